@@ -0,0 +1,69 @@
+//! Writes configuration and marker files so that they are never left half written.
+//!
+//! The agent can be killed at any point, e.g. if the user unplugs their Quest, and a config file truncated part way
+//! through would then fail to parse the next time it is read.
+
+use std::{ffi::OsString, fs::File, io::Write, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+
+/// Writes `data` to the file at `path`, replacing any existing file, so that a crash part way through leaves either
+/// the old contents or the new contents, never a mix of the two.
+///
+/// The data is written to a temporary file in the same directory, which is then renamed over `path`,
+/// as a rename within a directory replaces the file in one step.
+pub fn write_atomic(path: impl AsRef<Path>, data: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    let file_name = path
+        .file_name()
+        .ok_or(anyhow!("Cannot write to {path:?} as it has no file name"))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = write_and_sync(&temp_path, data)
+        .and_then(|_| std::fs::rename(&temp_path, path).context("Moving file into place"));
+    if result.is_err() && temp_path.exists() {
+        // The original file is left untouched, so only the temporary file needs tidying up.
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result.with_context(|| format!("Writing {path:?}"))
+}
+
+// Writes `data` to a new file at `path`, making sure it has reached the disk before returning.
+fn write_and_sync(path: &Path, data: &[u8]) -> Result<()> {
+    let mut file = File::create(path).context("Creating temporary file")?;
+    file.write_all(data).context("Writing temporary file")?;
+    file.sync_all().context("Syncing temporary file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_atomic;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn replaces_the_file_without_leaving_a_temporary_file() {
+        let dir = TempDir::new("atomic");
+        let path = dir.path().join("config.json");
+        write_atomic(&path, b"old").unwrap();
+        write_atomic(&path, b"new").unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        let names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["config.json"]);
+    }
+
+    #[test]
+    fn failed_write_leaves_nothing_behind() {
+        let dir = TempDir::new("atomic");
+        let path = dir.path().join("missing-folder").join("config.json");
+        assert!(write_atomic(&path, b"data").is_err());
+        assert!(!path.exists());
+        assert!(write_atomic(dir.path().join(".."), b"data").is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! Backs up the whole modded state of the game (the modloader folder, QMODs, custom levels and player data) to a single
+//! ZIP archive, and restores it, so that users can move their setup to another headset.
+//!
+//! Files are streamed into and out of the archive one at a time, so the backup is never held in memory.
+
+use std::{
+    fs::File,
+    io::Cursor,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use mbf_zip::{FileCompression, ZipFile};
+use serde::{Deserialize, Serialize};
+
+use crate::{cancellation, paths};
+
+/// The name of the entry identifying an archive as an MBF backup.
+const MARKER_ENTRY: &str = "mbf-backup.json";
+/// The name of the entry containing the player data.
+const PLAYER_DATA_ENTRY: &str = "player_data/PlayerData.dat";
+/// mbf-zip does not support ZIP64, so no archive can be larger than this.
+const MAX_BACKUP_BYTES: u64 = u32::MAX as u64;
+/// Files with these extensions are already compressed, so are stored without compressing them again.
+const COMPRESSED_EXTENSIONS: &[&str] = &["ogg", "egg", "png", "jpg", "jpeg", "zip", "qmod"];
+
+/// Identifies the app that a backup was made from.
+#[derive(Serialize, Deserialize)]
+struct BackupMarker {
+    apk_id: String,
+    // The version of the app when the backup was made, if it was installed.
+    game_version: Option<String>,
+}
+
+// The folders that are backed up, and the prefix of their entries within the archive.
+fn backed_up_dirs() -> [(&'static str, &'static str); 3] {
+    let paths = paths::default();
+    [
+        ("modloader", paths.modloader_dir()),
+        ("qmods", paths.qmods_root()),
+        ("custom_levels", paths.custom_levels()),
+    ]
+}
+
+/// Saves the modloader folder, QMODs, custom levels and player data to a ZIP archive at `dest`.
+/// The archive is marked with the ID of the app, so that it cannot be restored to a different app.
+///
+/// Fails without writing anything if the files would make an archive larger than 4 GiB, as ZIP64 is not supported.
+/// If writing the archive fails part way through, the partially written archive is deleted.
+pub fn export_moddata(dest: &Path) -> Result<()> {
+    let mut files = Vec::new();
+    for (prefix, dir) in backed_up_dirs() {
+        collect_files(Path::new(dir), prefix, &mut files)
+            .with_context(|| format!("Finding files to back up in {dir}"))?;
+    }
+    let player_data = Path::new(paths::default().player_data());
+    if player_data.exists() {
+        files.push((PLAYER_DATA_ENTRY.to_string(), player_data.to_path_buf()));
+    }
+
+    let total_bytes = files
+        .iter()
+        .map(|(_, path)| std::fs::metadata(path).map(|metadata| metadata.len()))
+        .sum::<std::io::Result<u64>>()
+        .context("Getting size of files to back up")?;
+    if total_bytes > MAX_BACKUP_BYTES {
+        return Err(anyhow!(
+            "Cannot back up {} MB of files, as backups can be at most {} MB. Try deleting some custom levels first",
+            total_bytes / (1024 * 1024),
+            MAX_BACKUP_BYTES / (1024 * 1024)
+        ));
+    }
+
+    info!(
+        "Backing up {} files ({} MB) to {dest:?}",
+        files.len(),
+        total_bytes / (1024 * 1024)
+    );
+    let result = write_backup(dest, &files);
+    if result.is_err() && dest.exists() {
+        if let Err(err) = std::fs::remove_file(dest) {
+            warn!("Failed to delete partially written backup: {err}");
+        }
+    }
+    result
+}
+
+fn write_backup(dest: &Path, files: &[(String, PathBuf)]) -> Result<()> {
+    let mut zip = ZipFile::create(File::create(dest).context("Creating backup file")?)
+        .context("Creating backup archive")?;
+
+    let marker = BackupMarker {
+        apk_id: crate::APK_ID.to_string(),
+        game_version: crate::installed_version_name()?,
+    };
+    zip.write_file(
+        MARKER_ENTRY,
+        &mut Cursor::new(serde_json::to_vec_pretty(&marker)?),
+        FileCompression::Deflate,
+    )?;
+
+    for (entry_name, path) in files {
+        cancellation::token().check()?;
+        let mut handle = File::open(path).with_context(|| format!("Opening {path:?}"))?;
+        zip.write_file(entry_name, &mut handle, compression_for(path))
+            .with_context(|| format!("Adding {path:?} to backup"))?;
+    }
+
+    zip.save().context("Saving backup archive")
+}
+
+// Adds each file within `dir` to `files`, along with its entry name, which is its path relative to `dir` after `prefix`.
+// Symbolic links are skipped, as are the files being staged for a mod install.
+fn collect_files(dir: &Path, prefix: &str, files: &mut Vec<(String, PathBuf)>) -> Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path == Path::new(paths::default().mod_staging()) {
+            continue;
+        }
+
+        let entry_name = format!("{prefix}/{}", entry.file_name().to_string_lossy());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(&path, &entry_name, files)?;
+        } else if file_type.is_file() {
+            files.push((entry_name, path));
+        }
+    }
+
+    Ok(())
+}
+
+fn compression_for(path: &Path) -> FileCompression {
+    let already_compressed = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| COMPRESSED_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+
+    if already_compressed {
+        FileCompression::Store
+    } else {
+        FileCompression::Deflate
+    }
+}
+
+/// Restores a backup made by [export_moddata], overwriting any existing files with the same paths.
+/// Files that are not in the backup are left in place.
+///
+/// Fails without extracting anything if the archive is not an MBF backup, is for a different app, or contains an
+/// entry that would be extracted outside of the backed up folders.
+pub fn import_moddata(src: &Path) -> Result<()> {
+    let mut zip = ZipFile::open(File::open(src).context("Opening backup")?)
+        .context("Backup was not a valid ZIP archive")?;
+    let marker: BackupMarker = serde_json::from_slice(
+        &zip.read_file(MARKER_ENTRY)
+            .context("Archive is not an MBF backup, as it has no marker")?,
+    )
+    .context("Parsing backup marker")?;
+    if marker.apk_id != crate::APK_ID {
+        return Err(anyhow!(
+            "This backup was made from {}, so cannot be restored to {}",
+            marker.apk_id,
+            crate::APK_ID
+        ));
+    }
+
+    let installed_version = crate::installed_version_name()?;
+    if marker.game_version.is_some() && marker.game_version != installed_version {
+        warn!(
+            "This backup was made with game version {:?}, but {:?} is installed. Mods may not load until the game is downgraded",
+            marker.game_version, installed_version
+        );
+    }
+
+    // Every destination is checked before anything is extracted, so a malicious archive cannot write anything.
+    let entries = zip
+        .iter_entry_names()
+        .filter(|name| *name != MARKER_ENTRY && !name.ends_with('/'))
+        .map(|name| entry_destination(name).map(|dest| (name.to_string(), dest)))
+        .collect::<Result<Vec<_>>>()?;
+
+    info!("Restoring {} files from backup", entries.len());
+    for (entry_name, dest) in entries {
+        cancellation::token().check()?;
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent).context("Creating directory to restore file to")?;
+        }
+
+        zip.extract_file_to(&entry_name, &dest)
+            .with_context(|| format!("Restoring {entry_name}"))?;
+    }
+
+    Ok(())
+}
+
+// Works out where an entry in a backup should be extracted to.
+// Gives an Err if the entry is not within one of the backed up folders, e.g. if its name contains `..` ("zip slip").
+fn entry_destination(entry_name: &str) -> Result<PathBuf> {
+    if entry_name == PLAYER_DATA_ENTRY {
+        return Ok(PathBuf::from(paths::default().player_data()));
+    }
+
+    let (prefix, relative) = entry_name.split_once('/').ok_or(anyhow!(
+        "Backup entry {entry_name} is not within a backed up folder"
+    ))?;
+    let relative = Path::new(relative);
+    if relative.as_os_str().is_empty()
+        || !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(anyhow!(
+            "Backup entry {entry_name} would be extracted outside of its folder"
+        ));
+    }
+
+    backed_up_dirs()
+        .into_iter()
+        .find(|(dir_prefix, _)| *dir_prefix == prefix)
+        .map(|(_, dir)| Path::new(dir).join(relative))
+        .ok_or(anyhow!(
+            "Backup entry {entry_name} is not within a backed up folder"
+        ))
+}
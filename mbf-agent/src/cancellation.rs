@@ -0,0 +1,144 @@
+//! Allows long-running operations, such as patching, to be cancelled by the frontend.
+//!
+//! The agent is started afresh for every request, so a `Cancel` request is handled by a different process to the operation
+//! being cancelled. Cancelling therefore creates a marker file at [crate::paths::Paths::cancel_marker], which the running
+//! operation checks for between its major steps and while downloading.
+
+use std::{
+    fmt::Display,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{atomic, paths};
+
+// The minimum time between checks for the marker file, so that checking inside download loops stays cheap.
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Returned by operations that stopped early because they were cancelled or ran out of time.
+#[derive(Debug)]
+pub struct Cancelled {
+    /// True if the operation took longer than its timeout, rather than being cancelled by the user.
+    pub timed_out: bool,
+}
+
+impl std::error::Error for Cancelled {}
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.timed_out {
+            write!(f, "The operation took too long and was stopped")
+        } else {
+            write!(f, "The operation was cancelled")
+        }
+    }
+}
+
+/// Tracks whether the operation carried out by this agent has been cancelled.
+pub struct CancellationToken {
+    cancelled: AtomicBool,
+    ignore_marker: AtomicBool,
+    // The time of the last check for the marker file, in milliseconds since the UNIX epoch.
+    last_checked: AtomicU64,
+    deadline: Mutex<Option<Instant>>,
+}
+
+static TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+
+/// Gets the cancellation token for the request being handled.
+/// As each agent process handles a single request, one token is shared by the whole process.
+pub fn token() -> &'static CancellationToken {
+    TOKEN.get_or_init(|| CancellationToken {
+        cancelled: AtomicBool::new(false),
+        ignore_marker: AtomicBool::new(false),
+        last_checked: AtomicU64::new(0),
+        deadline: Mutex::new(None),
+    })
+}
+
+/// Asks the operation running in another agent process to stop at its next check.
+pub fn request_cancel() -> Result<()> {
+    let marker_path = Path::new(paths::default().cancel_marker());
+    if let Some(parent) = marker_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    atomic::write_atomic(marker_path, &[]).context("Creating cancel marker")
+}
+
+impl CancellationToken {
+    /// Removes any cancel marker left by an earlier request, so that it doesn't cancel the request about to be handled.
+    pub fn reset(&self) -> Result<()> {
+        let marker_path = Path::new(paths::default().cancel_marker());
+        if marker_path.exists() {
+            std::fs::remove_file(marker_path).context("Removing old cancel marker")?;
+        }
+
+        Ok(())
+    }
+
+    /// Stops `Cancel` requests from affecting this agent process.
+    /// Used for requests that don't modify anything, as a `Cancel` request is only meant for the operation holding the
+    /// [agent lock](crate::lock).
+    pub fn ignore_cancel_requests(&self) {
+        self.ignore_marker.store(true, Ordering::SeqCst);
+    }
+
+    /// Makes the operation time out once `timeout` has passed from now.
+    pub fn set_timeout(&self, timeout: Duration) {
+        *self.deadline.lock().unwrap() = Some(Instant::now() + timeout);
+    }
+
+    /// Checks whether the operation should stop.
+    ///
+    /// # Returns
+    /// An `Err` containing [Cancelled] if the operation was cancelled or timed out, otherwise `Ok`.
+    pub fn check(&self) -> Result<()> {
+        if self
+            .deadline
+            .lock()
+            .unwrap()
+            .is_some_and(|deadline| Instant::now() > deadline)
+        {
+            return Err(Cancelled { timed_out: true }.into());
+        }
+
+        if self.is_cancelled() {
+            Err(Cancelled { timed_out: false }.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Gets whether a `Cancel` request has been made since the operation started.
+    /// The marker file is checked at most once every [CHECK_INTERVAL], so this can be called frequently.
+    pub fn is_cancelled(&self) -> bool {
+        if self.cancelled.load(Ordering::SeqCst) {
+            return true;
+        }
+        if self.ignore_marker.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis() as u64)
+            .unwrap_or(0);
+        let last_checked = self.last_checked.load(Ordering::SeqCst);
+        if now.saturating_sub(last_checked) < CHECK_INTERVAL.as_millis() as u64 {
+            return false;
+        }
+        self.last_checked.store(now, Ordering::SeqCst);
+
+        let cancelled = Path::new(paths::default().cancel_marker()).exists();
+        if cancelled {
+            self.cancelled.store(true, Ordering::SeqCst);
+        }
+        cancelled
+    }
+}
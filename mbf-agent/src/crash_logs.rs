@@ -0,0 +1,87 @@
+//! Reads the logs written by the modloader and mods into the game's files directory, so that users can share them
+//! when the game crashes instead of having to find them manually.
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use log::warn;
+
+use crate::{models::response::LogFile, paths};
+
+/// The maximum number of bytes read from each log file.
+/// Longer logs are truncated to their last bytes, as the lines just before a crash are the most useful.
+pub const MAX_LOG_FILE_BYTES: u64 = 256 * 1024;
+
+/// Reads the `max` most recently modified log files within [crate::paths::Paths::crash_logs].
+///
+/// # Returns
+/// The log files, most recently modified first. This is empty if the game has not written any logs.
+pub fn collect_crash_logs(max: usize) -> Result<Vec<LogFile>> {
+    let mut files = Vec::new();
+    find_log_files(Path::new(paths::default().crash_logs()), &mut files)
+        .context("Finding log files")?;
+    files.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    Ok(files
+        .into_iter()
+        .take(max)
+        .filter_map(|(modified, path)| match read_log_file(&path, modified) {
+            Ok(log_file) => Some(log_file),
+            Err(err) => {
+                warn!("Failed to read log file {path:?}: {err}");
+                None
+            }
+        })
+        .collect())
+}
+
+// Adds each file within `dir` (including those in subdirectories) to `files`, along with when it was last modified.
+fn find_log_files(dir: &Path, files: &mut Vec<(SystemTime, PathBuf)>) -> Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            find_log_files(&entry.path(), files)?;
+        } else if metadata.is_file() {
+            files.push((metadata.modified()?, entry.path()));
+        }
+    }
+
+    Ok(())
+}
+
+fn read_log_file(path: &Path, modified: SystemTime) -> Result<LogFile> {
+    let mut handle = File::open(path)?;
+    let length = handle.metadata()?.len();
+    let truncated = length > MAX_LOG_FILE_BYTES;
+    if truncated {
+        handle.seek(SeekFrom::End(-(MAX_LOG_FILE_BYTES as i64)))?;
+    }
+
+    let mut contents = Vec::new();
+    handle.take(MAX_LOG_FILE_BYTES).read_to_end(&mut contents)?;
+
+    Ok(LogFile {
+        name: path
+            .strip_prefix(paths::default().crash_logs())
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned(),
+        // Logs may be cut off part way through a character, either by truncating them or by the crash itself.
+        contents: String::from_utf8_lossy(&contents).into_owned(),
+        truncated,
+        modified: modified
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or(0),
+    })
+}
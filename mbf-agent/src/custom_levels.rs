@@ -0,0 +1,1162 @@
+//! Management of the custom levels (songs) that SongCore loads from [crate::paths::Paths::custom_levels].
+//!
+//! Both the v2 `Info.dat` format (with `_`-prefixed keys, also used by v3 levels) and the v4 format are understood.
+//! Each is read into the same [LevelInfo].
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use mbf_zip::ZipFile;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    atomic,
+    models::response::{
+        CustomLevel, DuplicateGroup, ImportedLevel, LevelDifficultySet, LevelMetadata,
+        SongCoreCacheStatus,
+    },
+    paths, storage,
+};
+
+/// The names that SongCore accepts for the info file of a level.
+const INFO_FILE_NAMES: [&str; 2] = ["Info.dat", "info.dat"];
+
+/// Returned when a folder cannot be imported as it is not a valid custom level.
+#[derive(Debug)]
+pub enum InvalidLevel {
+    /// The folder does not contain an `Info.dat` or `info.dat`.
+    MissingInfo,
+    /// The info file could not be parsed.
+    MalformedInfo(serde_json::Error),
+    /// The info file is for a version of the `Info.dat` format that MBF does not understand.
+    UnsupportedSchema { version: String },
+    /// A file referenced by the info file does not exist within the level folder.
+    MissingFile(String),
+}
+
+impl std::error::Error for InvalidLevel {}
+
+impl Display for InvalidLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingInfo => write!(f, "Level did not contain an Info.dat file"),
+            Self::MalformedInfo(e) => write!(f, "Level had an invalid Info.dat file: {e}"),
+            Self::UnsupportedSchema { version } => write!(
+                f,
+                "Level's Info.dat is for format version {version}, which is not supported"
+            ),
+            Self::MissingFile(name) => {
+                write!(f, "Level was missing {name}, which Info.dat refers to")
+            }
+        }
+    }
+}
+
+/// Returned when deleting a level if no installed level has the given hash.
+#[derive(Debug)]
+pub struct LevelNotFound {
+    pub hash: String,
+}
+
+impl std::error::Error for LevelNotFound {}
+
+impl Display for LevelNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No level with hash {} is installed", self.hash)
+    }
+}
+
+/// The parts of an `Info.dat` used by MBF, in any of the supported formats.
+pub struct LevelInfo {
+    pub song_name: String,
+    pub song_author: String,
+    /// The mappers of the level. For v4 levels, this is each mapper of any difficulty, separated by commas.
+    pub level_author: String,
+    pub bpm: f32,
+    pub song_filename: String,
+    pub difficulty_sets: Vec<DifficultySet>,
+    /// The files, other than the info file, that SongCore hashes to identify the level, in the order it hashes them.
+    /// For v2 levels, this is each difficulty file in the order they are listed.
+    /// For v4 levels, this is the audio data file, then the beatmap and lightshow file of each difficulty in the order
+    /// they are listed, which may differ from the order of [LevelInfo::difficulty_sets].
+    pub hashed_filenames: Vec<String>,
+}
+
+pub struct DifficultySet {
+    pub characteristic: String,
+    pub difficulties: Vec<Difficulty>,
+}
+
+pub struct Difficulty {
+    pub difficulty: String,
+}
+
+// Just the format version of an `Info.dat`, read first to work out how to parse the rest.
+// v2 files give the version as `_version`, v4 files as `version`.
+#[derive(Deserialize)]
+struct InfoSchema {
+    #[serde(rename = "_version")]
+    v2_version: Option<String>,
+    version: Option<String>,
+}
+
+// A v2 `Info.dat`.
+#[derive(Deserialize)]
+struct InfoV2 {
+    #[serde(rename = "_songName")]
+    song_name: String,
+    #[serde(rename = "_songAuthorName", default)]
+    song_author: String,
+    #[serde(rename = "_levelAuthorName", default)]
+    level_author: String,
+    #[serde(rename = "_beatsPerMinute", default)]
+    bpm: f32,
+    #[serde(rename = "_songFilename")]
+    song_filename: String,
+    #[serde(rename = "_difficultyBeatmapSets", default)]
+    difficulty_sets: Vec<DifficultySetV2>,
+}
+
+#[derive(Deserialize)]
+struct DifficultySetV2 {
+    #[serde(rename = "_beatmapCharacteristicName")]
+    characteristic: String,
+    #[serde(rename = "_difficultyBeatmaps", default)]
+    difficulties: Vec<DifficultyV2>,
+}
+
+#[derive(Deserialize)]
+struct DifficultyV2 {
+    #[serde(rename = "_difficulty")]
+    difficulty: String,
+    #[serde(rename = "_beatmapFilename")]
+    beatmap_filename: String,
+}
+
+// A v4 `Info.dat`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InfoV4 {
+    song: SongV4,
+    audio: AudioV4,
+    #[serde(default)]
+    difficulty_beatmaps: Vec<DifficultyV4>,
+}
+
+#[derive(Deserialize)]
+struct SongV4 {
+    title: String,
+    #[serde(default)]
+    author: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AudioV4 {
+    song_filename: String,
+    audio_data_filename: String,
+    #[serde(default)]
+    bpm: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DifficultyV4 {
+    characteristic: String,
+    difficulty: String,
+    #[serde(default)]
+    beatmap_authors: BeatmapAuthorsV4,
+    beatmap_data_filename: String,
+    lightshow_data_filename: String,
+}
+
+#[derive(Deserialize, Default)]
+struct BeatmapAuthorsV4 {
+    #[serde(default)]
+    mappers: Vec<String>,
+}
+
+impl From<InfoV2> for LevelInfo {
+    fn from(info: InfoV2) -> Self {
+        let hashed_filenames = info
+            .difficulty_sets
+            .iter()
+            .flat_map(|set| set.difficulties.iter())
+            .map(|difficulty| difficulty.beatmap_filename.clone())
+            .collect();
+
+        Self {
+            song_name: info.song_name,
+            song_author: info.song_author,
+            level_author: info.level_author,
+            bpm: info.bpm,
+            song_filename: info.song_filename,
+            hashed_filenames,
+            difficulty_sets: info
+                .difficulty_sets
+                .into_iter()
+                .map(|set| DifficultySet {
+                    characteristic: set.characteristic,
+                    difficulties: set
+                        .difficulties
+                        .into_iter()
+                        .map(|difficulty| Difficulty {
+                            difficulty: difficulty.difficulty,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<InfoV4> for LevelInfo {
+    fn from(info: InfoV4) -> Self {
+        let mut level_authors: Vec<String> = Vec::new();
+        let mut hashed_filenames = vec![info.audio.audio_data_filename];
+        // v4 lists every difficulty together, so they are grouped by characteristic in the order each first appears.
+        let mut difficulty_sets: Vec<DifficultySet> = Vec::new();
+        for difficulty in info.difficulty_beatmaps {
+            for mapper in difficulty.beatmap_authors.mappers {
+                if !level_authors.contains(&mapper) {
+                    level_authors.push(mapper);
+                }
+            }
+
+            // Several difficulties may share a lightshow file, in which case SongCore hashes it for each of them.
+            hashed_filenames.push(difficulty.beatmap_data_filename);
+            hashed_filenames.push(difficulty.lightshow_data_filename);
+            let entry = Difficulty {
+                difficulty: difficulty.difficulty,
+            };
+            match difficulty_sets
+                .iter_mut()
+                .find(|set| set.characteristic == difficulty.characteristic)
+            {
+                Some(set) => set.difficulties.push(entry),
+                None => difficulty_sets.push(DifficultySet {
+                    characteristic: difficulty.characteristic,
+                    difficulties: vec![entry],
+                }),
+            }
+        }
+
+        Self {
+            song_name: info.song.title,
+            song_author: info.song.author,
+            level_author: level_authors.join(", "),
+            bpm: info.audio.bpm,
+            song_filename: info.audio.song_filename,
+            difficulty_sets,
+            hashed_filenames,
+        }
+    }
+}
+
+// Parses the contents of an `Info.dat` file, working out which format it is in from its version.
+// Files with no version are assumed to be v2, as some older levels leave it out.
+fn parse_level_info(info_bytes: &[u8]) -> Result<LevelInfo, InvalidLevel> {
+    let schema: InfoSchema =
+        serde_json::from_slice(info_bytes).map_err(InvalidLevel::MalformedInfo)?;
+    match (schema.v2_version, schema.version) {
+        (Some(version), _) if version.starts_with("2.") => parse_info_as::<InfoV2>(info_bytes),
+        (None, Some(version)) if version.starts_with("4.") => parse_info_as::<InfoV4>(info_bytes),
+        (None, None) => parse_info_as::<InfoV2>(info_bytes),
+        (Some(version), _) | (None, Some(version)) => {
+            Err(InvalidLevel::UnsupportedSchema { version })
+        }
+    }
+}
+
+fn parse_info_as<'a, T: Deserialize<'a> + Into<LevelInfo>>(
+    info_bytes: &'a [u8],
+) -> Result<LevelInfo, InvalidLevel> {
+    serde_json::from_slice::<T>(info_bytes)
+        .map(Into::into)
+        .map_err(InvalidLevel::MalformedInfo)
+}
+
+/// A level folder that has been checked to contain all the files that its info file refers to.
+pub struct LoadedLevel {
+    pub info: LevelInfo,
+    /// The level hash, as computed by SongCore.
+    pub hash: String,
+}
+
+/// A previously computed level hash, saved to [crate::paths::Paths::level_hash_cache].
+#[derive(Serialize, Deserialize)]
+struct CachedHash {
+    /// The modification time and size of each file the hash was computed from when it was computed (see [level_fingerprint]).
+    files: Vec<(u64, u64)>,
+    hash: String,
+}
+
+/// Saves the hashes of installed levels, keyed by folder name, so that levels don't have to be re-hashed every time they are listed.
+/// A hash is only reused if none of the files it was computed from have been modified or resized since.
+#[derive(Default)]
+struct HashCache {
+    hashes: HashMap<String, CachedHash>,
+    changed: bool,
+}
+
+impl HashCache {
+    // Loads the saved hashes, starting with no hashes if none are saved or the saved hashes are invalid.
+    fn load() -> Self {
+        let hashes = File::open(paths::default().level_hash_cache())
+            .ok()
+            .and_then(|handle| serde_json::from_reader(BufReader::new(handle)).ok())
+            .unwrap_or_default();
+
+        Self {
+            hashes,
+            changed: false,
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+
+        let cache_path = Path::new(paths::default().level_hash_cache());
+        if let Some(parent) = cache_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        atomic::write_atomic(cache_path, &serde_json::to_vec(&self.hashes)?)
+            .context("Writing level hash cache")
+    }
+
+    // Gets the hash of a level from the cache, or computes (and caches) it if no up to date hash is saved.
+    fn get_or_compute(
+        &mut self,
+        level_dir: &Path,
+        info: &LevelInfo,
+        info_bytes: &[u8],
+    ) -> Result<String> {
+        let folder_name = level_dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let files = level_fingerprint(level_dir, info)?;
+        if let Some(cached) = self.hashes.get(&folder_name) {
+            if cached.files == files {
+                return Ok(cached.hash.clone());
+            }
+        }
+
+        let hash = hash_level_files(level_dir, info, info_bytes)?;
+        self.hashes.insert(
+            folder_name,
+            CachedHash {
+                files,
+                hash: hash.clone(),
+            },
+        );
+        self.changed = true;
+        Ok(hash)
+    }
+
+    // Removes the hashes of any levels that are not in `folder_names`.
+    fn retain_only(&mut self, folder_names: &HashSet<String>) {
+        let count = self.hashes.len();
+        self.hashes.retain(|name, _| folder_names.contains(name));
+        self.changed |= self.hashes.len() != count;
+    }
+}
+
+// Gives the modification time, in milliseconds since the UNIX epoch, and the size of the info file and each file in
+// [LevelInfo::hashed_filenames], in that order.
+// The files themselves are used rather than the level folder, as rewriting a file in place does not change the
+// modification time of the folder containing it.
+fn level_fingerprint(level_dir: &Path, info: &LevelInfo) -> Result<Vec<(u64, u64)>> {
+    let info_path = find_info_file(level_dir).ok_or(InvalidLevel::MissingInfo)?;
+    std::iter::once(info_path)
+        .chain(
+            info.hashed_filenames
+                .iter()
+                .map(|name| level_dir.join(name)),
+        )
+        .map(|path| {
+            let metadata = std::fs::metadata(&path)
+                .with_context(|| format!("Getting metadata of {path:?}"))?;
+            let modified = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_millis() as u64)
+                .unwrap_or(0);
+            Ok((modified, metadata.len()))
+        })
+        .collect()
+}
+
+/// Finds the info file within a level folder, if there is one.
+fn find_info_file(level_dir: &Path) -> Option<PathBuf> {
+    INFO_FILE_NAMES
+        .iter()
+        .map(|name| level_dir.join(name))
+        .find(|path| path.is_file())
+}
+
+// Gets the path of a file referenced by an info file, checking that it exists and is within the level folder.
+fn referenced_file(level_dir: &Path, name: &str) -> Result<PathBuf, InvalidLevel> {
+    let path = level_dir.join(name);
+    if name.contains(['/', '\\']) || name.contains("..") || !path.is_file() {
+        return Err(InvalidLevel::MissingFile(name.to_string()));
+    }
+
+    Ok(path)
+}
+
+// Parses the info file of a level and checks that the song and every file included in the level hash exist.
+// Gives the parsed info file and its raw contents, which are needed to compute the level hash.
+fn read_level_info(level_dir: &Path) -> Result<(LevelInfo, Vec<u8>)> {
+    let info_path = find_info_file(level_dir).ok_or(InvalidLevel::MissingInfo)?;
+    let info_bytes = std::fs::read(&info_path).context("Reading info file")?;
+    let info = parse_level_info(&info_bytes)?;
+
+    referenced_file(level_dir, &info.song_filename)?;
+    for filename in &info.hashed_filenames {
+        referenced_file(level_dir, filename)?;
+    }
+
+    Ok((info, info_bytes))
+}
+
+/// Computes the hash that SongCore gives the level in the given folder, which is used to identify the level
+/// in playlists, configs and by BeatSaver.
+///
+/// The hash is the SHA-1 of the bytes of the info file, followed by the bytes of each of [LevelInfo::hashed_filenames],
+/// given as upper case hex.
+/// For v2 levels, these are the difficulty files in the order they are listed in the info file (set by set, then difficulty
+/// by difficulty). For v4 levels, these are the audio data file, then the beatmap and lightshow files of each difficulty.
+/// Gives an `Err` if the folder is not a valid level.
+pub fn songcore_level_hash(level_dir: &Path) -> Result<String> {
+    let (info, info_bytes) = read_level_info(level_dir)?;
+    hash_level_files(level_dir, &info, &info_bytes)
+}
+
+// Computes the SongCore hash of a level (see [songcore_level_hash]) whose info file has already been read.
+// Each file is streamed into the hash rather than read into memory.
+fn hash_level_files(level_dir: &Path, info: &LevelInfo, info_bytes: &[u8]) -> Result<String> {
+    let mut hasher = Sha1::new();
+    hasher.update(info_bytes);
+    for filename in &info.hashed_filenames {
+        File::open(level_dir.join(filename))
+            .and_then(|mut handle| std::io::copy(&mut handle, &mut hasher))
+            .with_context(|| format!("Reading {filename}"))?;
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect())
+}
+
+/// Parses the info file of a level, checks that the audio and difficulty files it refers to exist,
+/// and computes the level hash.
+pub fn load_level(level_dir: &Path) -> Result<LoadedLevel> {
+    let (info, info_bytes) = read_level_info(level_dir)?;
+    let hash = hash_level_files(level_dir, &info, &info_bytes)?;
+    Ok(LoadedLevel { info, hash })
+}
+
+// Loads every folder in the custom levels directory as a level, using the hash cache to avoid re-hashing levels.
+// Gives the path of each folder and the level within it, or the reason it could not be loaded.
+fn scan_levels() -> Result<Vec<(PathBuf, Result<LoadedLevel>)>> {
+    let custom_levels = Path::new(paths::default().custom_levels());
+    if !custom_levels.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut cache = HashCache::load();
+    let mut levels = Vec::new();
+    let mut folder_names = HashSet::new();
+    for entry in std::fs::read_dir(custom_levels)? {
+        let level_dir = entry?.path();
+        if !level_dir.is_dir() {
+            continue;
+        }
+
+        let level = read_level_info(&level_dir).and_then(|(info, info_bytes)| {
+            let hash = cache.get_or_compute(&level_dir, &info, &info_bytes)?;
+            Ok(LoadedLevel { info, hash })
+        });
+        if let Some(folder_name) = level_dir.file_name() {
+            folder_names.insert(folder_name.to_string_lossy().to_string());
+        }
+        levels.push((level_dir, level));
+    }
+
+    cache.retain_only(&folder_names);
+    if let Err(err) = cache.save() {
+        warn!("Failed to save level hash cache: {err:?}");
+    }
+    Ok(levels)
+}
+
+/// Finds the folder of the installed level with the given hash, if any.
+/// Folders that are not valid levels are ignored.
+pub fn find_level_by_hash(hash: &str) -> Result<Option<PathBuf>> {
+    Ok(scan_levels()?
+        .into_iter()
+        .find(|(_, level)| {
+            level
+                .as_ref()
+                .is_ok_and(|level| level.hash.eq_ignore_ascii_case(hash))
+        })
+        .map(|(level_dir, _)| level_dir))
+}
+
+/// Lists all of the folders in the custom levels directory, with details of the level within each.
+/// Folders that do not contain a valid level are included with the reason they are invalid, so that they can be deleted.
+pub fn list_custom_levels() -> Vec<CustomLevel> {
+    let levels = match scan_levels() {
+        Ok(levels) => levels,
+        Err(err) => {
+            warn!("Failed to list custom levels: {err:?}");
+            return Vec::new();
+        }
+    };
+
+    levels
+        .into_iter()
+        .map(|(level_dir, level)| {
+            let folder_name = level_dir
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            match level {
+                Ok(LoadedLevel { info, hash }) => CustomLevel {
+                    folder_name,
+                    hash: Some(hash),
+                    metadata: Some(LevelMetadata {
+                        song_name: info.song_name,
+                        song_author: info.song_author,
+                        level_author: info.level_author,
+                        bpm: info.bpm,
+                        difficulty_sets: info
+                            .difficulty_sets
+                            .into_iter()
+                            .map(|set| LevelDifficultySet {
+                                characteristic: set.characteristic,
+                                difficulties: set
+                                    .difficulties
+                                    .into_iter()
+                                    .map(|difficulty| difficulty.difficulty)
+                                    .collect(),
+                            })
+                            .collect(),
+                    }),
+                    error: None,
+                },
+                Err(err) => CustomLevel {
+                    folder_name,
+                    hash: None,
+                    metadata: None,
+                    error: Some(format!("{err:#}")),
+                },
+            }
+        })
+        .collect()
+}
+
+/// Deletes the installed level with the given hash.
+///
+/// # Returns
+/// An `Err` variant containing [LevelNotFound] if no installed level has the hash.
+pub fn delete_custom_level(hash: &str) -> Result<()> {
+    let level_dir = find_level_by_hash(hash)?.ok_or_else(|| LevelNotFound {
+        hash: hash.to_string(),
+    })?;
+    remove_level_dir(&level_dir)
+}
+
+/// The result of removing duplicate custom levels with [dedupe_levels].
+pub struct DedupeReport {
+    pub removed_folders: usize,
+    /// The total size of the folders removed.
+    pub freed_bytes: u64,
+}
+
+/// Finds the custom level folders that contain the same level as another folder, grouped by the level hash.
+/// Folders are only ever grouped by their [songcore_level_hash], never by name, and folders that are not valid
+/// levels are ignored.
+///
+/// # Returns
+/// Each group of two or more folders with the same hash, sorted by hash.
+pub fn find_duplicate_levels() -> Vec<DuplicateGroup> {
+    let levels = match scan_levels() {
+        Ok(levels) => levels,
+        Err(err) => {
+            warn!("Failed to find duplicate levels: {err:?}");
+            return Vec::new();
+        }
+    };
+
+    let mut by_hash: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (level_dir, level) in levels {
+        if let (Ok(level), Some(folder_name)) = (level, level_dir.file_name()) {
+            by_hash
+                .entry(level.hash.to_uppercase())
+                .or_default()
+                .push(folder_name.to_string_lossy().to_string());
+        }
+    }
+
+    by_hash
+        .into_iter()
+        .filter(|(_, folder_names)| folder_names.len() > 1)
+        .map(|(hash, mut folder_names)| {
+            folder_names.sort();
+            DuplicateGroup { hash, folder_names }
+        })
+        .collect()
+}
+
+/// Removes every duplicate custom level found by [find_duplicate_levels], keeping the first folder in each group.
+/// Removal stops at the first folder that cannot be deleted.
+pub fn dedupe_levels() -> Result<DedupeReport> {
+    let custom_levels = Path::new(paths::default().custom_levels());
+    let mut report = DedupeReport {
+        removed_folders: 0,
+        freed_bytes: 0,
+    };
+    for group in find_duplicate_levels() {
+        for folder_name in group.folder_names.iter().skip(1) {
+            let level_dir = custom_levels.join(folder_name);
+            let is_link = std::fs::symlink_metadata(&level_dir)?
+                .file_type()
+                .is_symlink();
+            let size = if is_link {
+                0
+            } else {
+                storage::directory_size(&level_dir)
+            };
+
+            info!(
+                "{folder_name} is a duplicate of {} (hash {})",
+                group.folder_names[0], group.hash
+            );
+            remove_level_dir(&level_dir)?;
+            report.removed_folders += 1;
+            report.freed_bytes += size;
+        }
+    }
+
+    info!(
+        "Removed {} duplicate levels, freeing {} bytes",
+        report.removed_folders, report.freed_bytes
+    );
+    Ok(report)
+}
+
+// Deletes a level folder from the custom levels directory.
+fn remove_level_dir(level_dir: &Path) -> Result<()> {
+    // If the level folder is a symlink, only remove the link so that nothing outside the custom levels folder is deleted.
+    if std::fs::symlink_metadata(level_dir)?
+        .file_type()
+        .is_symlink()
+    {
+        info!("Removing link to level at {level_dir:?}");
+        std::fs::remove_file(level_dir).context("Removing level link")?;
+    } else {
+        // `remove_dir_all` does not follow symlinks within the folder.
+        info!("Deleting level at {level_dir:?}");
+        std::fs::remove_dir_all(level_dir).context("Deleting level")?;
+    }
+
+    Ok(())
+}
+
+// Replaces any characters that are not permitted in folder names on common file systems,
+// and removes leading/trailing periods and whitespace.
+fn sanitize_folder_name(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || r#"<>:"/\|?*"#.contains(c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    sanitized
+        .trim_matches(|c: char| c == '.' || c.is_whitespace())
+        .to_string()
+}
+
+// Copies the contents of `from` into `to`, creating `to` if it does not exist.
+// Symbolic links are skipped so that the copy cannot contain files from outside `from`.
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let dest_path = to.join(entry.file_name());
+
+        if file_type.is_dir() {
+            copy_dir(&entry.path(), &dest_path)?;
+        } else if file_type.is_file() {
+            std::fs::copy(entry.path(), dest_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies a song folder into the custom levels directory.
+///
+/// # Arguments
+/// * `src` - The folder containing the level. The name of this folder is used for the installed level, after removing any invalid characters.
+///
+/// # Returns
+/// Details of the imported level. If a level with the same hash is already installed, the level is not copied
+/// and the existing level is given instead.
+/// An `Err` variant containing an [InvalidLevel] is given if the folder is not a valid level.
+pub fn import_custom_level(src: &Path) -> Result<ImportedLevel> {
+    let folder_name = src
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    import_level_as(src, &folder_name)
+}
+
+/// Extracts a ZIP file containing a song and copies the song into the custom levels directory.
+///
+/// # Arguments
+/// * `zip_path` - The path to the ZIP file. The song may be at the root of the archive, or within a single folder.
+/// * `folder_name` - The name to give the folder of the installed level, after removing any invalid characters.
+///
+/// # Returns
+/// Details of the imported level, as with [import_custom_level].
+pub fn import_song_zip(zip_path: &Path, folder_name: &str) -> Result<ImportedLevel> {
+    let extract_dir = Path::new(paths::default().temp()).join("song-import");
+    if extract_dir.exists() {
+        std::fs::remove_dir_all(&extract_dir).context("Removing previous song extraction")?;
+    }
+
+    let result = extract_and_import(zip_path, &extract_dir, folder_name);
+    // The extracted files are no longer needed whether the import succeeded or not.
+    if let Err(err) = std::fs::remove_dir_all(&extract_dir) {
+        warn!("Failed to remove extracted song: {err}");
+    }
+
+    result
+}
+
+fn extract_and_import(
+    zip_path: &Path,
+    extract_dir: &Path,
+    folder_name: &str,
+) -> Result<ImportedLevel> {
+    let mut zip = ZipFile::open(File::open(zip_path)?).context("Song was invalid ZIP file")?;
+    std::fs::create_dir_all(extract_dir)?;
+    zip.extract_to_directory(extract_dir)
+        .context("Extracting song")?;
+
+    import_level_as(&find_level_dir(extract_dir)?, folder_name)
+}
+
+// Finds the folder containing the info file within an extracted song, which is either the extraction directory itself
+// or a single folder within it.
+fn find_level_dir(extract_dir: &Path) -> Result<PathBuf> {
+    if find_info_file(extract_dir).is_some() {
+        return Ok(extract_dir.to_owned());
+    }
+
+    let mut level_dirs = Vec::new();
+    for entry in std::fs::read_dir(extract_dir)? {
+        let path = entry?.path();
+        if path.is_dir() && find_info_file(&path).is_some() {
+            level_dirs.push(path);
+        }
+    }
+
+    match level_dirs.len() {
+        1 => Ok(level_dirs.remove(0)),
+        0 => Err(InvalidLevel::MissingInfo.into()),
+        _ => Err(anyhow!("ZIP file contained more than one song")),
+    }
+}
+
+fn import_level_as(src: &Path, folder_name: &str) -> Result<ImportedLevel> {
+    let level = load_level(src).context("Validating level")?;
+    if let Some(existing_path) = find_level_by_hash(&level.hash)? {
+        info!(
+            "Level {} is already installed at {existing_path:?}",
+            level.info.song_name
+        );
+        return Ok(ImportedLevel {
+            hash: level.hash,
+            path: existing_path.to_string_lossy().to_string(),
+            already_existed: true,
+        });
+    }
+
+    let mut folder_name = sanitize_folder_name(folder_name);
+    if folder_name.is_empty() {
+        folder_name = level.hash.clone();
+    }
+
+    // Avoid overwriting a different level that happens to have the same folder name.
+    let custom_levels = Path::new(paths::default().custom_levels());
+    let mut dest_path = custom_levels.join(&folder_name);
+    let mut i = 1;
+    while dest_path.exists() {
+        dest_path = custom_levels.join(format!("{folder_name} ({i})"));
+        i += 1;
+    }
+
+    // Rename is not used as the level may be on a separate volume.
+    info!("Copying level {} to {dest_path:?}", level.info.song_name);
+    if let Err(err) = copy_dir(src, &dest_path) {
+        // Don't leave a partial level behind, as this may prevent SongCore from loading.
+        let _ = std::fs::remove_dir_all(&dest_path);
+        return Err(err).context("Copying level");
+    }
+
+    // Check the copy is intact, since a level with a different hash will not match the playlists that refer to it.
+    match songcore_level_hash(&dest_path) {
+        Ok(copied_hash) if copied_hash == level.hash => {}
+        result => {
+            let _ = std::fs::remove_dir_all(&dest_path);
+            return Err(match result {
+                Ok(copied_hash) => anyhow!(
+                    "Copied level has hash {copied_hash}, expected {}",
+                    level.hash
+                ),
+                Err(err) => err,
+            })
+            .context("Verifying copied level");
+        }
+    }
+
+    Ok(ImportedLevel {
+        hash: level.hash,
+        path: dest_path.to_string_lossy().to_string(),
+        already_existed: false,
+    })
+}
+
+/// Deletes the cache that SongCore keeps of the hashes and details of levels, so that it loads every level again the next
+/// time the game starts. This fixes levels not appearing when the cache is out of date with the levels folder.
+/// Only the cache file is deleted, never the levels themselves.
+pub fn clear_songcore_cache() -> Result<()> {
+    let cache_path = Path::new(paths::default().songcore_cache());
+    if cache_path.exists() {
+        info!("Deleting SongCore cache at {cache_path:?}");
+        std::fs::remove_file(cache_path).context("Deleting SongCore cache")?;
+    }
+
+    Ok(())
+}
+
+/// Finds whether SongCore has cached the installed levels, and how long ago the cache was last updated.
+pub fn songcore_cache_status() -> SongCoreCacheStatus {
+    match std::fs::metadata(paths::default().songcore_cache()) {
+        Ok(metadata) => SongCoreCacheStatus {
+            exists: true,
+            age_secs: metadata
+                .modified()
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .map(|age| age.as_secs()),
+        },
+        Err(_) => SongCoreCacheStatus {
+            exists: false,
+            age_secs: None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        path::Path,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{parse_level_info, read_level_info, songcore_level_hash, HashCache, InvalidLevel};
+    use crate::test_utils::TempDir;
+
+    // A v2 level whose difficulties are not listed in alphabetical order, so that the order of hashing is checked.
+    const INFO_V2: &str = r#"{"_version":"2.0.0","_songName":"Test Song","_songAuthorName":"Artist","_levelAuthorName":"Mapper","_beatsPerMinute":120,"_songFilename":"song.ogg","_difficultyBeatmapSets":[{"_beatmapCharacteristicName":"Standard","_difficultyBeatmaps":[{"_difficulty":"Expert","_beatmapFilename":"Expert.dat"},{"_difficulty":"Easy","_beatmapFilename":"Easy.dat"}]}]}"#;
+
+    fn write_level(dir: &TempDir, files: &[(&str, &str)]) {
+        for (name, contents) in files {
+            std::fs::write(dir.path().join(name), contents).unwrap();
+        }
+    }
+
+    #[test]
+    fn level_hash_covers_info_then_difficulties_in_listed_order() {
+        let dir = TempDir::new("level-hash");
+        write_level(
+            &dir,
+            &[
+                ("Info.dat", INFO_V2),
+                ("Expert.dat", r#"{"_notes":[{"_time":1}]}"#),
+                ("Easy.dat", r#"{"_notes":[]}"#),
+                ("song.ogg", "OggS"),
+            ],
+        );
+
+        // SHA-1 of Info.dat, Expert.dat and Easy.dat concatenated, computed separately from this code.
+        assert_eq!(
+            songcore_level_hash(dir.path()).unwrap(),
+            "565A71BEED44FEEA9A560DDC118C94562FD96C42"
+        );
+    }
+
+    // An entry in test_fixtures/levels/levels.json.
+    #[derive(serde::Deserialize)]
+    struct FixtureLevel {
+        folder: String,
+        hash: String,
+        source: String,
+    }
+
+    // Each level in test_fixtures/levels must have the hash recorded for it in levels.json.
+    // A level downloaded from BeatSaver can be added as a folder, with the hash BeatSaver gives it as its source.
+    #[test]
+    fn fixture_levels_have_their_recorded_hashes() {
+        let fixtures_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("test_fixtures")
+            .join("levels");
+        let fixtures: Vec<FixtureLevel> =
+            serde_json::from_slice(&std::fs::read(fixtures_dir.join("levels.json")).unwrap())
+                .unwrap();
+        assert!(!fixtures.is_empty());
+
+        for fixture in fixtures {
+            assert_eq!(
+                songcore_level_hash(&fixtures_dir.join(&fixture.folder)).unwrap(),
+                fixture.hash,
+                "{} ({})",
+                fixture.folder,
+                fixture.source
+            );
+        }
+    }
+
+    #[test]
+    fn level_missing_a_difficulty_cannot_be_hashed() {
+        let dir = TempDir::new("level-hash");
+        write_level(
+            &dir,
+            &[
+                ("Info.dat", INFO_V2),
+                ("Expert.dat", r#"{"_notes":[]}"#),
+                ("song.ogg", "OggS"),
+            ],
+        );
+
+        assert!(songcore_level_hash(dir.path()).is_err());
+    }
+
+    #[test]
+    fn cached_hash_is_recomputed_when_a_difficulty_is_rewritten_in_place() {
+        let dir = TempDir::new("level-hash-cache");
+        write_level(
+            &dir,
+            &[
+                ("Info.dat", INFO_V2),
+                ("Expert.dat", r#"{"_notes":[{"_time":1}]}"#),
+                ("Easy.dat", r#"{"_notes":[]}"#),
+                ("song.ogg", "OggS"),
+            ],
+        );
+        let mut cache = HashCache::default();
+        let hash_with_cache = |cache: &mut HashCache| {
+            let (info, info_bytes) = read_level_info(dir.path()).unwrap();
+            cache
+                .get_or_compute(dir.path(), &info, &info_bytes)
+                .unwrap()
+        };
+
+        let original = hash_with_cache(&mut cache);
+        assert_eq!(original, songcore_level_hash(dir.path()).unwrap());
+        let folder_modified = std::fs::metadata(dir.path()).unwrap().modified().unwrap();
+
+        // Same size, so only the modification time of the file changes. The folder's modification time does not.
+        std::fs::write(dir.path().join("Expert.dat"), r#"{"_notes":[{"_time":2}]}"#).unwrap();
+        File::options()
+            .write(true)
+            .open(dir.path().join("Expert.dat"))
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(
+            std::fs::metadata(dir.path()).unwrap().modified().unwrap(),
+            folder_modified
+        );
+        let rewritten = hash_with_cache(&mut cache);
+        assert_ne!(rewritten, original);
+        assert_eq!(rewritten, songcore_level_hash(dir.path()).unwrap());
+
+        // A different size is noticed even if the modification time is unchanged.
+        let expert = File::options()
+            .write(true)
+            .open(dir.path().join("Expert.dat"))
+            .unwrap();
+        let expert_modified = expert.metadata().unwrap().modified().unwrap();
+        expert.set_len(4).unwrap();
+        expert.set_modified(expert_modified).unwrap();
+        let resized = hash_with_cache(&mut cache);
+        assert_ne!(resized, rewritten);
+        assert_eq!(resized, songcore_level_hash(dir.path()).unwrap());
+
+        // Unchanged files reuse the cached hash.
+        cache.changed = false;
+        assert_eq!(hash_with_cache(&mut cache), resized);
+        assert!(!cache.changed);
+    }
+
+    const INFO_V4: &str = r#"{
+        "version": "4.0.0",
+        "song": { "title": "Test Song", "subTitle": "", "author": "Artist" },
+        "audio": { "songFilename": "song.ogg", "songDuration": 120, "audioDataFilename": "BPMInfo.dat", "bpm": 128 },
+        "difficultyBeatmaps": [
+            { "characteristic": "Standard", "difficulty": "Easy", "beatmapAuthors": { "mappers": ["Mapper"], "lighters": [] }, "beatmapDataFilename": "Easy.dat", "lightshowDataFilename": "Lights.dat" },
+            { "characteristic": "OneSaber", "difficulty": "Hard", "beatmapAuthors": { "mappers": ["Other Mapper"], "lighters": [] }, "beatmapDataFilename": "OneSaberHard.dat", "lightshowDataFilename": "Lights.dat" },
+            { "characteristic": "Standard", "difficulty": "Expert", "beatmapAuthors": { "mappers": ["Mapper"], "lighters": [] }, "beatmapDataFilename": "Expert.dat", "lightshowDataFilename": "Lights.dat" }
+        ]
+    }"#;
+
+    // The files referenced by INFO_V4.
+    const V4_FILES: [(&str, &str); 6] = [
+        ("BPMInfo.dat", r#"{"version":"4.0.0","bpmEvents":[]}"#),
+        ("Easy.dat", r#"{"version":"4.0.0","colorNotes":[]}"#),
+        (
+            "OneSaberHard.dat",
+            r#"{"version":"4.0.0","colorNotes":[{"b":1}]}"#,
+        ),
+        (
+            "Expert.dat",
+            r#"{"version":"4.0.0","colorNotes":[{"b":2}]}"#,
+        ),
+        ("Lights.dat", r#"{"version":"4.0.0","basicEvents":[]}"#),
+        ("song.ogg", "OggS"),
+    ];
+
+    #[test]
+    fn v4_level_hash_covers_audio_data_then_beatmap_and_lightshow_of_each_difficulty() {
+        let dir = TempDir::new("level-hash-v4");
+        write_level(&dir, &[("Info.dat", INFO_V4)]);
+        write_level(&dir, &V4_FILES);
+
+        // SHA-1 of Info.dat, BPMInfo.dat, then Easy.dat, OneSaberHard.dat and Expert.dat each followed by Lights.dat,
+        // computed separately from this code.
+        assert_eq!(
+            songcore_level_hash(dir.path()).unwrap(),
+            "7A9E707AB7B33A6C3AD8712BEB6DFF2C42AC2517"
+        );
+    }
+
+    #[test]
+    fn v4_level_missing_its_audio_data_or_lightshow_cannot_be_hashed() {
+        for missing in ["BPMInfo.dat", "Lights.dat"] {
+            let dir = TempDir::new("level-hash-v4");
+            write_level(&dir, &[("Info.dat", INFO_V4)]);
+            write_level(&dir, &V4_FILES);
+            std::fs::remove_file(dir.path().join(missing)).unwrap();
+
+            let err = songcore_level_hash(dir.path()).unwrap_err();
+            match err.downcast_ref::<InvalidLevel>() {
+                Some(InvalidLevel::MissingFile(name)) => assert_eq!(name, missing),
+                _ => panic!("Expected {missing} to be missing, got {err}"),
+            }
+        }
+    }
+
+    #[test]
+    fn v2_info_is_parsed() {
+        let info = parse_level_info(INFO_V2.as_bytes()).unwrap();
+        assert_eq!(info.song_name, "Test Song");
+        assert_eq!(info.song_author, "Artist");
+        assert_eq!(info.level_author, "Mapper");
+        assert_eq!(info.bpm, 120.0);
+        assert_eq!(info.song_filename, "song.ogg");
+        assert_eq!(info.difficulty_sets.len(), 1);
+        assert_eq!(info.hashed_filenames, ["Expert.dat", "Easy.dat"]);
+    }
+
+    #[test]
+    fn v4_info_is_grouped_by_characteristic() {
+        let info = parse_level_info(INFO_V4.as_bytes()).unwrap();
+        assert_eq!(info.song_name, "Test Song");
+        assert_eq!(info.song_author, "Artist");
+        assert_eq!(info.level_author, "Mapper, Other Mapper");
+        assert_eq!(info.bpm, 128.0);
+        assert_eq!(info.song_filename, "song.ogg");
+        // Hashed in the order the difficulties are listed, not grouped by characteristic.
+        assert_eq!(
+            info.hashed_filenames,
+            [
+                "BPMInfo.dat",
+                "Easy.dat",
+                "Lights.dat",
+                "OneSaberHard.dat",
+                "Lights.dat",
+                "Expert.dat",
+                "Lights.dat"
+            ]
+        );
+
+        let sets: Vec<(&str, Vec<&str>)> = info
+            .difficulty_sets
+            .iter()
+            .map(|set| {
+                (
+                    set.characteristic.as_str(),
+                    set.difficulties
+                        .iter()
+                        .map(|difficulty| difficulty.difficulty.as_str())
+                        .collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            sets,
+            [
+                ("Standard", vec!["Easy", "Expert"]),
+                ("OneSaber", vec!["Hard"])
+            ]
+        );
+    }
+
+    #[test]
+    fn info_without_a_version_is_read_as_v2() {
+        let info =
+            parse_level_info(br#"{"_songName":"Old Song","_songFilename":"song.egg"}"#).unwrap();
+        assert_eq!(info.song_name, "Old Song");
+        assert!(info.difficulty_sets.is_empty());
+    }
+
+    #[test]
+    fn unknown_schema_versions_are_refused() {
+        for (info, expected) in [
+            (r#"{"version":"5.0.0","song":{"title":"Song"}}"#, "5.0.0"),
+            (r#"{"_version":"3.0.0","_songName":"Song"}"#, "3.0.0"),
+        ] {
+            match parse_level_info(info.as_bytes()) {
+                Err(InvalidLevel::UnsupportedSchema { version }) => assert_eq!(version, expected),
+                Err(err) => panic!("Expected an unsupported schema, got {err}"),
+                Ok(_) => panic!("Expected an unsupported schema, but {expected} was parsed"),
+            }
+        }
+    }
+}
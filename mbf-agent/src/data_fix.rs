@@ -1,10 +1,132 @@
 use anyhow::{anyhow, Context, Result};
+use log::warn;
 use std::{
     fs::{File, OpenOptions},
     io::{Read, Write},
     path::Path,
 };
 
+use crate::{
+    mod_man::ModManager,
+    models::response::{DatakeeperStatus, FileAge},
+    paths,
+};
+
+/// The ID of the mod that copies [paths::Paths::datakeeper_player_data] over the game's `PlayerData.dat` on launch.
+const DATAKEEPER_ID: &str = "datakeeper";
+
+/// The result of checking a `PlayerData.dat` file with [validate_player_data].
+#[derive(Debug, PartialEq, Eq)]
+pub enum PlayerDataStatus {
+    /// The file is valid player data.
+    Valid,
+    /// The file does not exist.
+    Missing,
+    /// The file exists but is empty, which typically happens if the game was killed while saving.
+    Empty,
+    /// The file ends before the player data is complete.
+    TruncatedAt { offset: usize },
+    /// The file is not valid JSON, or is not in the format of player data.
+    Malformed,
+}
+
+impl std::fmt::Display for PlayerDataStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlayerDataStatus::Valid => write!(f, "valid"),
+            PlayerDataStatus::Missing => write!(f, "missing"),
+            PlayerDataStatus::Empty => write!(f, "empty"),
+            PlayerDataStatus::TruncatedAt { offset } => write!(f, "truncated at byte {offset}"),
+            PlayerDataStatus::Malformed => write!(f, "malformed"),
+        }
+    }
+}
+
+// Checks whether the file at the given path is valid player data, i.e. a JSON object with a localPlayers array.
+// Files that cannot be read are treated as missing.
+pub fn validate_player_data(path: impl AsRef<Path>) -> PlayerDataStatus {
+    let data_file_buf = match std::fs::read(&path) {
+        Ok(buf) => buf,
+        Err(_) => return PlayerDataStatus::Missing,
+    };
+    if data_file_buf.iter().all(|byte| byte.is_ascii_whitespace()) {
+        return PlayerDataStatus::Empty;
+    }
+
+    match serde_json::from_slice::<serde_json::Value>(&data_file_buf) {
+        Ok(player_data) => match player_data.get("localPlayers") {
+            Some(serde_json::Value::Array(_)) => PlayerDataStatus::Valid,
+            _ => PlayerDataStatus::Malformed,
+        },
+        Err(err) if err.is_eof() => PlayerDataStatus::TruncatedAt {
+            offset: byte_offset(&data_file_buf, err.line(), err.column()),
+        },
+        Err(_) => PlayerDataStatus::Malformed,
+    }
+}
+
+// Converts a 1-based line and column from a serde_json error into a byte offset within `data`.
+fn byte_offset(data: &[u8], line: usize, column: usize) -> usize {
+    let line_start: usize = data
+        .split_inclusive(|byte| *byte == b'\n')
+        .take(line.saturating_sub(1))
+        .map(|line| line.len())
+        .sum();
+
+    (line_start + column).min(data.len())
+}
+
+/// Checks whether datakeeper is installed, and how its `PlayerData.dat` compares with the game's.
+/// When datakeeper is not installed, its `PlayerData.dat` is never copied to the game, so may be stale.
+///
+/// If the installed mods cannot be loaded, datakeeper is treated as not installed.
+pub fn datakeeper_status() -> DatakeeperStatus {
+    let installed = match datakeeper_installed() {
+        Ok(installed) => installed,
+        Err(err) => {
+            warn!("Could not check whether datakeeper is installed: {err}");
+            false
+        }
+    };
+
+    let datakeeper_data = Path::new(paths::default().datakeeper_player_data());
+    let live_data = Path::new(paths::default().player_data());
+    DatakeeperStatus {
+        installed,
+        player_data_exists: datakeeper_data.exists(),
+        compared_to_live: compare_modified(datakeeper_data, live_data),
+    }
+}
+
+fn datakeeper_installed() -> Result<bool> {
+    let version = match crate::installed_version_name()? {
+        Some(version) => version,
+        None => return Ok(false),
+    };
+    let res_cache = crate::load_res_cache()?;
+    let mut mod_manager = ModManager::new(version, &res_cache)?;
+    mod_manager.load_mods().context("Loading installed mods")?;
+
+    Ok(mod_manager
+        .get_mod(DATAKEEPER_ID)
+        .is_some_and(|mod_rc| mod_rc.borrow().installed()))
+}
+
+// Gives how recently `path` was modified compared with `other`, or `None` if either does not exist.
+fn compare_modified(path: &Path, other: &Path) -> Option<FileAge> {
+    let modified = std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+    let other_modified = std::fs::metadata(other)
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+    Some(match modified.cmp(&other_modified) {
+        std::cmp::Ordering::Greater => FileAge::Newer,
+        std::cmp::Ordering::Less => FileAge::Older,
+        std::cmp::Ordering::Equal => FileAge::Same,
+    })
+}
+
 // Fixes issues with player colour schemes from 1.28 loading incorrectly on v1.35.0 or newer.
 pub fn fix_colour_schemes(path: impl AsRef<Path>) -> Result<()> {
     let mut data_file_buf = Vec::with_capacity(8192);
@@ -43,3 +165,54 @@ pub fn fix_colour_schemes(path: impl AsRef<Path>) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_player_data, PlayerDataStatus};
+    use crate::test_utils::TempDir;
+
+    const SAMPLE: &str = r#"{"version":"2.0.26","localPlayers":[{"playerId":"1","playerName":"Player","shouldShowTutorialPrompt":false,"levelsStatsData":[{"levelId":"custom_level_ABC","difficulty":2,"highScore":123456,"validScore":true}]}],"onlinePlayers":[]}"#;
+
+    fn status_of(dir: &TempDir, contents: &[u8]) -> PlayerDataStatus {
+        let path = dir.path().join("PlayerData.dat");
+        std::fs::write(&path, contents).unwrap();
+        validate_player_data(&path)
+    }
+
+    #[test]
+    fn sample_player_data_is_valid() {
+        let dir = TempDir::new("player-data");
+        assert_eq!(status_of(&dir, SAMPLE.as_bytes()), PlayerDataStatus::Valid);
+    }
+
+    #[test]
+    fn empty_and_missing_player_data_are_detected() {
+        let dir = TempDir::new("player-data");
+        assert_eq!(status_of(&dir, b""), PlayerDataStatus::Empty);
+        assert_eq!(status_of(&dir, b" \n"), PlayerDataStatus::Empty);
+        assert_eq!(
+            validate_player_data(dir.path().join("missing.dat")),
+            PlayerDataStatus::Missing
+        );
+    }
+
+    #[test]
+    fn player_data_truncated_mid_record_gives_the_offset() {
+        let dir = TempDir::new("player-data");
+        let truncated = &SAMPLE.as_bytes()[..120];
+        assert_eq!(
+            status_of(&dir, truncated),
+            PlayerDataStatus::TruncatedAt { offset: 120 }
+        );
+    }
+
+    #[test]
+    fn json_that_is_not_player_data_is_malformed() {
+        let dir = TempDir::new("player-data");
+        assert_eq!(
+            status_of(&dir, br#"{"version":"2.0.26"}"#),
+            PlayerDataStatus::Malformed
+        );
+        assert_eq!(status_of(&dir, b"not json"), PlayerDataStatus::Malformed);
+    }
+}
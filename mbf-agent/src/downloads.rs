@@ -3,27 +3,164 @@
 //! - Resuming downloads if they fail partway through
 //! - Downloading to a Vec or to a file.
 //! - Multiple download attempts.
-//! - Progress reporting to the MBF logger
+//! - Progress reporting to the MBF logger and the frontend
+//! - Caching downloaded files, optionally verified by a SHA-256 hash.
+//! - Downloading several files to the cache at once.
+//! - Checking that a download is the expected type of file, rather than e.g. an HTML error page.
 
+use crate::{cancellation, hash};
 use anyhow::{anyhow, Context, Result};
 use log::{error, info, warn};
 use std::{
-    fs::OpenOptions,
+    fs::{File, OpenOptions},
     io::{self, Cursor, Read, Seek, Write},
-    path::Path,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
     time::Instant,
 };
 
+use crate::{
+    models::response::{ProgressEvent, Stage},
+    paths,
+};
+use mbf_res_man::{
+    res_cache::OfflineCacheMiss,
+    retry::{self, RetryConfig},
+};
+
+/// Returned when a file could not be downloaded, after retrying if the failure may have been temporary.
+#[derive(Debug)]
+pub struct DownloadError {
+    pub url: String,
+    /// Why the last attempt to download the file failed.
+    pub reason: String,
+}
+
+impl std::error::Error for DownloadError {}
+
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to download {}: {}", self.url, self.reason)
+    }
+}
+
+/// The type of file that a download is expected to be, which is checked from the magic bytes at the start of the file.
+#[derive(Debug, Clone, Copy)]
+pub enum ExpectedContent {
+    /// An ELF binary, such as a `.so` library.
+    Elf,
+    /// A ZIP archive, such as a QMOD or an APK.
+    Zip,
+}
+
+impl ExpectedContent {
+    fn magic(self) -> &'static [u8] {
+        match self {
+            ExpectedContent::Elf => b"\x7fELF",
+            ExpectedContent::Zip => b"PK\x03\x04",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            ExpectedContent::Elf => "an ELF binary",
+            ExpectedContent::Zip => "a ZIP archive",
+        }
+    }
+}
+
+/// Returned when a downloaded file is not the type of file expected.
+/// This usually means that a captive portal or proxy served a web page in place of the file.
+#[derive(Debug)]
+pub struct UnexpectedContent {
+    pub url: String,
+    pub expected: ExpectedContent,
+    /// A description of what the file appears to be instead.
+    pub detected: String,
+}
+
+impl std::error::Error for UnexpectedContent {}
+
+impl std::fmt::Display for UnexpectedContent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The file downloaded from {} should be {}, but is {}. Is the network blocking the download?",
+            self.url,
+            self.expected.description(),
+            self.detected
+        )
+    }
+}
+
+// The number of bytes read from the start of a file to work out what type of file it is.
+const CONTENT_SNIFF_LEN: u64 = 512;
+
+/// Checks that a file downloaded from `url` is the expected type of file.
+/// # Arguments
+/// * `contents_start` - The first bytes of the file. These can be the whole file.
+/// # Returns
+/// An `Err` with an [UnexpectedContent] if the file does not start with the magic bytes of `expected`.
+pub fn check_content(
+    url: &str,
+    contents_start: &[u8],
+    expected: ExpectedContent,
+) -> Result<(), UnexpectedContent> {
+    if contents_start.starts_with(expected.magic()) {
+        return Ok(());
+    }
+
+    Err(UnexpectedContent {
+        url: url.to_string(),
+        expected,
+        detected: describe_content(contents_start).to_string(),
+    })
+}
+
+/// Checks that the file at `path`, downloaded from `url`, is the expected type of file. See [check_content].
+pub fn check_file_content(url: &str, path: &Path, expected: ExpectedContent) -> Result<()> {
+    let mut contents_start = Vec::new();
+    File::open(path)
+        .context("Opening downloaded file")?
+        .take(CONTENT_SNIFF_LEN)
+        .read_to_end(&mut contents_start)
+        .context("Reading downloaded file")?;
+    Ok(check_content(url, &contents_start, expected)?)
+}
+
+// Describes the type of file that starts with the given bytes, for use in errors.
+fn describe_content(contents_start: &[u8]) -> &'static str {
+    let text_start = String::from_utf8_lossy(contents_start)
+        .trim_start()
+        .to_ascii_lowercase();
+    if contents_start.is_empty() {
+        "empty"
+    } else if text_start.starts_with("<!doctype html") || text_start.starts_with("<html") {
+        "an HTML page"
+    } else if text_start.starts_with('<') {
+        "an XML or HTML document"
+    } else if text_start.starts_with('{') || text_start.starts_with('[') {
+        "JSON"
+    } else if contents_start.starts_with(ExpectedContent::Elf.magic()) {
+        ExpectedContent::Elf.description()
+    } else if contents_start.starts_with(ExpectedContent::Zip.magic()) {
+        ExpectedContent::Zip.description()
+    } else {
+        "an unrecognised file"
+    }
+}
+
 /// Various configuration settings for the file downloader.
 pub struct DownloadConfig<'a> {
-    /// The number of times the connection can fail before the downloader will give up.
-    /// NB: If an unsuccessful status code is returned, the downloader will of course stop the request immediately instead of spamming the server.
-    pub max_disconnections: u32,
+    /// How the download is retried if the connection is lost or the server gives an error.
+    /// NB: If any other unsuccessful status code is returned, such as 404, the downloader will stop the request immediately instead of spamming the server.
+    pub retry: RetryConfig,
     /// If specified, the number of disconnections recorded thus far will be reset to 0
     /// if data is downloaded successfully for the provided period of time.
     pub disconnection_reset_time: Option<std::time::Duration>,
-    /// When the connection is lost, the downloader will wait for this specified period of time before trying again.
-    pub disconnect_wait_time: std::time::Duration,
     /// The amount of time between download progress updates. Set to None to disable.
     pub progress_update_interval: Option<std::time::Duration>,
     /// Specifies the ureq agent used to carry out the downloads
@@ -96,6 +233,7 @@ fn download_file_to_stream<T: FnMut(usize, Option<usize>) -> ()>(
 
 /// Copies bytes from the `from` stream to the `to` stream.
 /// As each buffer of data is copied, the `progress` function is called to update the caller on the number of bytes that have been copied thus far.
+/// Fails with [io::ErrorKind::Interrupted] if the operation is cancelled part way through.
 fn copy_stream_progress<T: FnMut(usize) -> ()>(
     from: &mut impl Read,
     mut to: impl Write,
@@ -105,6 +243,10 @@ fn copy_stream_progress<T: FnMut(usize) -> ()>(
 
     let mut total_read = 0;
     loop {
+        if let Err(err) = cancellation::token().check() {
+            break Err(io::Error::new(io::ErrorKind::Interrupted, err.to_string()));
+        }
+
         let bytes_read = from.read(&mut buffer)?;
         to.write_all(&buffer[0..bytes_read])?;
 
@@ -154,17 +296,49 @@ fn get_filename_from_headers(resp: &ureq::Response) -> Option<String> {
 /// Attempts to download a file with support for multiple attempts, continuing failed downloads,
 /// and progress reporting.
 /// Returns the filename, if it was provided within the response.
+/// Fails with [OfflineCacheMiss] if the agent is in offline mode.
 pub fn download_with_attempts(
+    cfg: &DownloadConfig,
+    to: impl Write + Seek,
+    url: &str,
+) -> Result<Option<String>> {
+    download_with_progress(cfg, to, url, &mut report_progress_for(url))
+}
+
+// Gives a progress callback that reports the progress of downloading `url` to the frontend.
+fn report_progress_for(url: &str) -> impl FnMut(u64, Option<u64>) + '_ {
+    move |done, total| {
+        crate::report_progress(ProgressEvent {
+            stage: Stage::Downloading,
+            done,
+            total,
+            message: format!("Downloading {url}"),
+        })
+    }
+}
+
+// The same as [download_with_attempts], but `progress` is called with the number of bytes downloaded and the total size
+// at each progress update, instead of the progress being reported to the frontend.
+fn download_with_progress(
     cfg: &DownloadConfig,
     mut to: impl Write + Seek,
     url: &str,
+    progress: &mut dyn FnMut(u64, Option<u64>),
 ) -> Result<Option<String>> {
+    if crate::is_offline() {
+        return Err(OfflineCacheMiss {
+            url: url.to_string(),
+        }
+        .into());
+    }
+
     let mut failed_attempts = 0;
     let mut bytes_valid: usize = 0; // The number of bytes successfully downloaded thus far.
     let mut file_name: Option<String> = None;
     let mut ranges_supported = false;
 
     loop {
+        cancellation::token().check()?;
         if failed_attempts > 0 {
             if ranges_supported {
                 info!("Continuing download");
@@ -202,6 +376,10 @@ pub fn download_with_attempts(
                     (Some(interval), Some(length)) => {
                         if now.duration_since(last_progress_update) > interval {
                             last_progress_update = now;
+                            progress(
+                                (bytes_written + bytes_valid_before_req) as u64,
+                                Some((bytes_valid_before_req + length) as u64),
+                            );
                             info!(
                                 "Progress: {:.2}%",
                                 ((bytes_written + bytes_valid_before_req) as f32
@@ -220,9 +398,11 @@ pub fn download_with_attempts(
         match result {
             Ok(_) => return Ok(file_name), // Full file successfully downloaded
             Err(err) => {
+                // There's no point trying again if the download failed because it was cancelled.
+                cancellation::token().check()?;
                 failed_attempts += 1;
                 // True if there is no next attempt
-                let dl_failed = failed_attempts > cfg.max_disconnections;
+                let dl_failed = failed_attempts >= cfg.retry.max_attempts;
 
                 // No support for ranges so we need to redownload the whole file.
                 if !ranges_supported {
@@ -230,34 +410,42 @@ pub fn download_with_attempts(
                 }
 
                 match err {
-                    DownloadFileError::InitialRequest(ureq_err) => match ureq_err {
+                    DownloadFileError::InitialRequest(ureq_err) => {
                         // Do not attempt to download again if the error is not network related
-                        ureq::Error::Status(code, _resp) => {
-                            return Err(anyhow!("Request failed as got status {code} from server."))
-                        }
-                        ureq::Error::Transport(transport_err) => {
-                            if dl_failed {
-                                return Err(transport_err)
-                                    .context("Downloading file: all attempts exhausted");
+                        if !retry::is_retryable(&ureq_err) || dl_failed {
+                            let reason = match ureq_err {
+                                ureq::Error::Status(code, _resp) => {
+                                    format!("Request failed as got status {code} from server.")
+                                }
+                                ureq::Error::Transport(transport_err) => {
+                                    format!("All attempts exhausted: {transport_err}")
+                                }
+                            };
+                            return Err(DownloadError {
+                                url: url.to_string(),
+                                reason,
                             }
-
-                            // Error occured due to internet connection, can make another attempt
-                            error!("Failed to make initial request: {transport_err}");
+                            .into());
                         }
-                    },
+
+                        // Error occured due to internet connection or the server, can make another attempt
+                        error!("Failed to make initial request: {ureq_err}");
+                    }
                     DownloadFileError::LostConnDuringDownload(io_error) => {
                         if dl_failed {
-                            return Err(io_error).context(
-                                "Lost connection mid download and ran out of download attempts",
-                            );
+                            return Err(DownloadError {
+                                url: url.to_string(),
+                                reason: format!("Lost connection mid download and ran out of download attempts: {io_error}"),
+                            }
+                            .into());
                         }
                         error!("Failed to complete file download: {io_error}");
                     }
                 };
 
-                // Wait a little bit in the hope that the connection loss is temporary
+                // Wait a little bit in the hope that the problem is temporary
                 info!("Waiting briefly for the connection to (hopefully) come back");
-                std::thread::sleep(cfg.disconnect_wait_time);
+                cfg.retry.wait_before_attempt(url, failed_attempts + 1);
             }
         }
     }
@@ -293,3 +481,171 @@ pub fn download_to_vec_with_attempts(cfg: &DownloadConfig, url: &str) -> Result<
 
     Ok(output)
 }
+
+/// Downloads the file at `url` to [paths::Paths::mbf_downloads], unless it has been downloaded already.
+/// Files are named by the SHA-256 hash of their URL, so this should only be used for URLs whose content does not change.
+///
+/// The file is downloaded to a `.part` file first, which is deleted if the download fails,
+/// so an incomplete download is never mistaken for a cached file.
+/// # Arguments
+/// * `expected_sha256` - If `Some`, the hex SHA-256 hash of the file. A cached file with a different hash is downloaded again,
+///   and the operation fails if the newly downloaded file also has a different hash.
+/// * `expected_content` - If `Some`, the type of file expected. The operation fails with an [UnexpectedContent],
+///   and nothing is cached, if the downloaded file is of another type.
+/// # Returns
+/// The path of the cached file.
+pub fn fetch_cached(
+    cfg: &DownloadConfig,
+    url: &str,
+    expected_sha256: Option<&str>,
+    expected_content: Option<ExpectedContent>,
+) -> Result<PathBuf> {
+    fetch_cached_with_progress(
+        cfg,
+        url,
+        expected_sha256,
+        expected_content,
+        &mut report_progress_for(url),
+    )
+}
+
+/// Downloads each of the given URLs using [fetch_cached], with up to `max_concurrent` downloads at once.
+/// The frontend is sent the combined progress of all the downloads.
+///
+/// A failed download does not stop the others, so that every failure can be reported at once.
+/// `expected_content` is the type that every file is expected to be, if known.
+/// # Returns
+/// The result of downloading each URL, in the same order as `urls`. Duplicate URLs are only downloaded once.
+pub fn fetch_all_cached(
+    cfg: &DownloadConfig,
+    urls: &[&str],
+    max_concurrent: usize,
+    expected_content: Option<ExpectedContent>,
+) -> Vec<(String, Result<PathBuf>)> {
+    // Downloading the same URL twice at once would write to the same `.part` file.
+    let mut unique_urls: Vec<&str> = Vec::new();
+    for url in urls {
+        if !unique_urls.contains(url) {
+            unique_urls.push(url);
+        }
+    }
+
+    let next_index = AtomicUsize::new(0);
+    // The bytes downloaded and total size of each URL, so that the combined progress can be reported.
+    let progress_by_url: Mutex<Vec<(u64, Option<u64>)>> =
+        Mutex::new(vec![(0, None); unique_urls.len()]);
+    let results: Mutex<Vec<Option<Result<PathBuf>>>> =
+        Mutex::new((0..unique_urls.len()).map(|_| None).collect());
+
+    std::thread::scope(|scope| {
+        for _ in 0..max_concurrent.clamp(1, unique_urls.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next_index.fetch_add(1, Ordering::SeqCst);
+                let url = match unique_urls.get(index) {
+                    Some(url) => *url,
+                    None => break,
+                };
+
+                let result = fetch_cached_with_progress(
+                    cfg,
+                    url,
+                    None,
+                    expected_content,
+                    &mut |done, total| {
+                        let mut progress = progress_by_url.lock().unwrap();
+                        progress[index] = (done, total);
+                        report_combined_progress(&progress, unique_urls.len());
+                    },
+                );
+                if let Err(err) = &result {
+                    error!("Failed to download {url}: {err:?}");
+                }
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    unique_urls
+        .into_iter()
+        .zip(results.into_inner().unwrap())
+        .map(|(url, result)| {
+            (
+                url.to_string(),
+                result.expect("All URLs should have been downloaded once the workers finish"),
+            )
+        })
+        .collect()
+}
+
+// Reports the total progress of several downloads to the frontend.
+fn report_combined_progress(progress_by_url: &[(u64, Option<u64>)], file_count: usize) {
+    let done = progress_by_url.iter().map(|(done, _)| done).sum();
+    // The total is only known once all downloads have started, and only if all have a known size.
+    let total = progress_by_url
+        .iter()
+        .map(|(_, total)| *total)
+        .sum::<Option<u64>>();
+    crate::report_progress(ProgressEvent {
+        stage: Stage::Downloading,
+        done,
+        total,
+        message: format!("Downloading {file_count} files"),
+    });
+}
+
+// The same as [fetch_cached], but `progress` is called at each progress update of the download.
+fn fetch_cached_with_progress(
+    cfg: &DownloadConfig,
+    url: &str,
+    expected_sha256: Option<&str>,
+    expected_content: Option<ExpectedContent>,
+    progress: &mut dyn FnMut(u64, Option<u64>),
+) -> Result<PathBuf> {
+    let downloads_dir = Path::new(paths::default().mbf_downloads());
+    std::fs::create_dir_all(downloads_dir).context("Creating downloads directory")?;
+    let cached_path = downloads_dir.join(hash::sha256_reader(url.as_bytes())?);
+
+    if cached_path.exists() {
+        match expected_sha256 {
+            None => return Ok(cached_path),
+            Some(expected) => {
+                if hash::sha256_file(&cached_path)?.eq_ignore_ascii_case(expected) {
+                    return Ok(cached_path);
+                }
+                warn!(
+                    "Cached download of {url} did not match the expected hash, downloading again"
+                );
+            }
+        }
+    }
+
+    let part_path = cached_path.with_extension("part");
+    let download_result = File::create(&part_path)
+        .context("Creating destination file")
+        .and_then(|part_file| download_with_progress(cfg, part_file, url, progress));
+    if let Err(err) = download_result {
+        // Ignore the error, as the download failing is the more important issue.
+        let _ = std::fs::remove_file(&part_path);
+        return Err(err);
+    }
+
+    if let Some(expected) = expected_content {
+        if let Err(err) = check_file_content(url, &part_path, expected) {
+            std::fs::remove_file(&part_path)?;
+            return Err(err);
+        }
+    }
+
+    if let Some(expected) = expected_sha256 {
+        let actual = hash::sha256_file(&part_path)?;
+        if !actual.eq_ignore_ascii_case(expected) {
+            std::fs::remove_file(&part_path)?;
+            return Err(anyhow!(
+                "Downloaded file from {url} had SHA-256 {actual}, but expected {expected}"
+            ));
+        }
+    }
+
+    std::fs::rename(&part_path, &cached_path).context("Moving completed download")?;
+    Ok(cached_path)
+}
@@ -0,0 +1,92 @@
+//! Classifies the errors that requests fail with, so that the frontend can react differently to each kind of failure,
+//! e.g. by offering to retry after a network error, rather than only showing the error message.
+
+use std::io;
+
+use mbf_res_man::res_cache::{JsonPullError, OfflineCacheMiss};
+use serde::Serialize;
+
+use crate::{
+    cancellation::Cancelled,
+    downloads::DownloadError,
+    handlers::{AppNotInstalled, UnknownApk},
+    mod_man::{DependencyError, IncompatibleMod},
+    models::response::Response,
+    storage::InsufficientSpace,
+};
+
+/// The kind of failure that caused a request to fail, given as the `code` of an `Error` response.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AgentError {
+    /// A file could not be downloaded, or a resource could not be fetched and was not cached.
+    Network,
+    /// There was not enough free space to carry out the request.
+    InsufficientSpace,
+    /// The request needs the game to be installed, but it is not.
+    AppNotInstalled,
+    /// The installed version of the game is not one that MBF knows how to mod.
+    UnknownApk,
+    /// A mod could not be installed or upgraded because of its dependencies, or the mods that depend on it.
+    DependencyError,
+    /// A mod is not made for the installed game.
+    IncompatibleMod,
+    /// Reading or writing a file failed.
+    Io,
+    /// The request was cancelled, or took longer than its timeout.
+    Cancelled,
+    /// Any other failure.
+    Other,
+}
+
+impl AgentError {
+    /// Works out the kind of failure from the causes of `err`.
+    /// The outermost cause with a specific kind is used. IO errors are only given as [AgentError::Io] if there is no such cause,
+    /// since most failures involve an IO error at some point.
+    pub fn classify(err: &anyhow::Error) -> Self {
+        let specific = err.chain().find_map(|cause| {
+            if cause.is::<Cancelled>() {
+                Some(Self::Cancelled)
+            } else if cause.is::<InsufficientSpace>() {
+                Some(Self::InsufficientSpace)
+            } else if cause.is::<AppNotInstalled>() {
+                Some(Self::AppNotInstalled)
+            } else if cause.is::<UnknownApk>() {
+                Some(Self::UnknownApk)
+            } else if cause.is::<DependencyError>() {
+                Some(Self::DependencyError)
+            } else if cause.is::<IncompatibleMod>() {
+                Some(Self::IncompatibleMod)
+            } else if cause.is::<DownloadError>()
+                || cause.is::<OfflineCacheMiss>()
+                || cause.is::<JsonPullError>()
+                || cause.is::<ureq::Error>()
+                || cause.is::<ureq::Transport>()
+            {
+                Some(Self::Network)
+            } else {
+                // Downloads that are cancelled part way through fail with an interrupted IO error.
+                cause
+                    .downcast_ref::<io::Error>()
+                    .filter(|io_err| io_err.kind() == io::ErrorKind::Interrupted)
+                    .map(|_| Self::Cancelled)
+            }
+        });
+
+        specific.unwrap_or_else(|| {
+            if err.chain().any(|cause| cause.is::<io::Error>()) {
+                Self::Io
+            } else {
+                Self::Other
+            }
+        })
+    }
+}
+
+/// Creates the `Error` response sent when a request fails with `err`.
+pub fn error_response(err: &anyhow::Error) -> Response {
+    Response::Error {
+        code: AgentError::classify(err),
+        message: err.to_string(),
+        detail: format!("{err:?}"),
+    }
+}
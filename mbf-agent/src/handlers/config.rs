@@ -0,0 +1,187 @@
+//! Handles requests for exporting the installed mods and custom levels as a portable configuration, and importing it again.
+
+use std::collections::HashSet;
+
+use crate::{
+    custom_levels,
+    mod_man::ModManager,
+    models::response::{ConfigExport, ExportedMod, Response},
+};
+use anyhow::{Context, Result};
+use log::{info, warn};
+use mbf_res_man::{models::CoreModSet, res_cache::ResCache};
+
+/// Handles `ExportConfig` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `ConfigExport`)
+pub(super) fn handle_export_config() -> Result<Response> {
+    Ok(Response::ConfigExport(export_config()?))
+}
+
+/// Handles `ImportConfig` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `ConfigImported`)
+pub(super) fn handle_import_config(config: ConfigExport) -> Result<Response> {
+    let res_cache = crate::load_res_cache()?;
+    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache)?;
+    mod_manager.load_mods().context("Loading installed mods")?;
+
+    let failed_mods = import_config(&mut mod_manager, &config);
+    let missing_levels = find_missing_levels(&config);
+
+    Ok(Response::ConfigImported {
+        installed_mods: super::mod_management::get_mod_models(mod_manager)?,
+        failed_mods,
+        missing_levels,
+    })
+}
+
+/// Describes the installed mods, their enabled state and the installed custom levels.
+///
+/// The download URL of each mod is found in the mod repo, or the core mod index for core mods.
+/// Mods that were installed from elsewhere will have no download URL.
+pub(super) fn export_config() -> Result<ConfigExport> {
+    let res_cache = crate::load_res_cache()?;
+    let game_version = super::get_app_version_only()?;
+    let mut mod_manager = ModManager::new(game_version.clone(), &res_cache)?;
+    mod_manager.load_mods().context("Loading installed mods")?;
+
+    let core_mod_urls = get_core_mod_urls(&res_cache, &game_version);
+
+    let installed: Vec<(String, semver::Version, bool)> = mod_manager
+        .get_mods()
+        .map(|mod_rc| {
+            let mod_ref = mod_rc.borrow();
+            (
+                mod_ref.manifest().id.clone(),
+                mod_ref.manifest().version.clone(),
+                mod_ref.installed(),
+            )
+        })
+        .collect();
+
+    let mods = installed
+        .into_iter()
+        .map(|(id, version, enabled)| {
+            let source_url = core_mod_urls
+                .iter()
+                .find(|(core_id, core_version, _)| core_id == &id && core_version == &version)
+                .map(|(_, _, url)| url.clone())
+                .or_else(|| mod_manager.find_mod_repo_url(&id, &version));
+            if source_url.is_none() {
+                warn!("No download URL found for {id} v{version}");
+            }
+
+            ExportedMod {
+                id,
+                version,
+                enabled,
+                source_url,
+            }
+        })
+        .collect();
+
+    Ok(ConfigExport {
+        game_version,
+        mods,
+        custom_level_hashes: custom_levels::list_custom_levels()
+            .into_iter()
+            .filter_map(|level| level.hash)
+            .collect(),
+    })
+}
+
+/// Installs each mod in `config` that isn't already installed with the same version,
+/// then enables or disables each mod to match the configuration.
+///
+/// # Arguments
+/// * `mod_manager` - A mod manager that has all existing mods loaded already.
+/// * `config` - The configuration to import.
+///
+/// # Returns
+/// A description of each mod that could not be imported.
+pub(super) fn import_config(mod_manager: &mut ModManager, config: &ConfigExport) -> Vec<String> {
+    let mut failures = Vec::new();
+    let current_version = mod_manager.game_version().to_string();
+    if config.game_version != current_version {
+        warn!(
+            "Configuration was exported from {} but {} is installed, so some mods may not work",
+            config.game_version, current_version
+        );
+    }
+
+    for exported in &config.mods {
+        let already_present = mod_manager
+            .get_mod(&exported.id)
+            .is_some_and(|existing| existing.borrow().manifest().version == exported.version);
+
+        if !already_present {
+            let url = match &exported.source_url {
+                Some(url) => url,
+                None => {
+                    failures.push(format!(
+                        "{} v{}: no download URL was exported",
+                        exported.id, exported.version
+                    ));
+                    continue;
+                }
+            };
+
+            if let Err(err) =
+                super::import::install_mod_from_url(mod_manager, url, None, false, false)
+            {
+                failures.push(format!("{} v{}: {err}", exported.id, exported.version));
+                continue;
+            }
+        }
+
+        let result = if exported.enabled {
+            mod_manager.install_mod(&exported.id, false)
+        } else {
+            mod_manager.uninstall_mod(&exported.id)
+        };
+        match result {
+            Ok(_) => info!("Imported {} v{}", exported.id, exported.version),
+            Err(err) => failures.push(format!("{} v{}: {err}", exported.id, exported.version)),
+        }
+    }
+
+    failures
+}
+
+// Gets the hashes of the custom levels in `config` that are not installed.
+fn find_missing_levels(config: &ConfigExport) -> Vec<String> {
+    let installed: HashSet<String> = custom_levels::list_custom_levels()
+        .into_iter()
+        .filter_map(|level| level.hash)
+        .collect();
+
+    config
+        .custom_level_hashes
+        .iter()
+        .filter(|hash| !installed.contains(&hash.to_uppercase()))
+        .cloned()
+        .collect()
+}
+
+// Gets the ID, version and download URL of each core mod for the given game version.
+// Gives an empty list if the core mod index could not be fetched, since the mod repo may still have the mods.
+fn get_core_mod_urls(
+    res_cache: &ResCache,
+    game_version: &str,
+) -> Vec<(String, semver::Version, String)> {
+    match mbf_res_man::external_res::load_core_mods(res_cache, None, game_version) {
+        Ok(CoreModSet::Supported(core_mods)) => core_mods
+            .mods
+            .into_iter()
+            .map(|core_mod| (core_mod.id, core_mod.version, core_mod.download_url))
+            .collect(),
+        Ok(CoreModSet::Unsupported { .. }) => Vec::new(),
+        Err(err) => {
+            warn!("Could not fetch core mod index: {err}");
+            Vec::new()
+        }
+    }
+}
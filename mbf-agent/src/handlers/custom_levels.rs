@@ -0,0 +1,76 @@
+//! Handles requests for managing the custom levels (songs) installed for SongCore, and the playlists containing them.
+
+use crate::{
+    custom_levels,
+    models::response::{Playlist, Response},
+    playlists,
+};
+use anyhow::Result;
+
+/// Handles `GetCustomLevels` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `CustomLevels`)
+pub(super) fn handle_get_custom_levels() -> Result<Response> {
+    Ok(Response::CustomLevels {
+        levels: custom_levels::list_custom_levels(),
+    })
+}
+
+/// Handles `DeleteCustomLevel` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `CustomLevels`)
+pub(super) fn handle_delete_custom_level(hash: String) -> Result<Response> {
+    custom_levels::delete_custom_level(&hash)?;
+
+    Ok(Response::CustomLevels {
+        levels: custom_levels::list_custom_levels(),
+    })
+}
+
+/// Handles `FindDuplicateLevels` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `DuplicateLevels`)
+pub(super) fn handle_find_duplicate_levels() -> Result<Response> {
+    Ok(Response::DuplicateLevels {
+        groups: custom_levels::find_duplicate_levels(),
+    })
+}
+
+/// Handles `DedupeLevels` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `LevelsDeduped`)
+pub(super) fn handle_dedupe_levels() -> Result<Response> {
+    let report = custom_levels::dedupe_levels()?;
+
+    Ok(Response::LevelsDeduped {
+        removed_folders: report.removed_folders,
+        freed_bytes: report.freed_bytes,
+        levels: custom_levels::list_custom_levels(),
+    })
+}
+
+/// Handles `GetPlaylists` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `Playlists`)
+pub(super) fn handle_get_playlists() -> Result<Response> {
+    Ok(Response::Playlists {
+        playlists: playlists::list_playlists()?,
+    })
+}
+
+/// Handles `SavePlaylist` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `Playlists`)
+pub(super) fn handle_save_playlist(playlist: Playlist) -> Result<Response> {
+    playlists::save_playlist(&playlist)?;
+
+    Ok(Response::Playlists {
+        playlists: playlists::list_playlists()?,
+    })
+}
@@ -1,22 +1,50 @@
 use std::path::{Path, PathBuf};
 
 use crate::{
-    downloads,
-    mod_man::ModManager,
-    models::response::{self, ImportResultType, Response},
-    paths,
+    custom_levels, downloads,
+    mod_man::{self, ModManager},
+    models::response::{self, Compatibility, ImportResultType, Response},
+    paths, uploads,
 };
 use anyhow::{anyhow, Context, Result};
 use log::{debug, info, warn};
-use mbf_zip::ZipFile;
 
-/// Handles `ImportUrl` [Requests](requests::Request).
+/// Handles `BeginUpload` [Requests](crate::models::request::Request).
 ///
 /// # Returns
-/// The [Response](requests::Response) to the request (variant `ImportResult`)
+/// The [Response](crate::models::response::Response) to the request (variant `Upload`)
+pub(super) fn handle_begin_upload(name: String, size: u64, sha256: String) -> Result<Response> {
+    Ok(Response::Upload(uploads::begin_upload(
+        &name, size, &sha256,
+    )?))
+}
+
+/// Handles `AppendChunk` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `Upload`)
+pub(super) fn handle_append_chunk(id: String, offset: u64, data: String) -> Result<Response> {
+    Ok(Response::Upload(uploads::append_chunk(&id, offset, &data)?))
+}
+
+/// Handles `FinishUpload` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `UploadFinished`)
+pub(super) fn handle_finish_upload(id: String) -> Result<Response> {
+    let path = uploads::finish_upload(&id)?;
+    Ok(Response::UploadFinished {
+        path: path.to_string_lossy().into_owned(),
+    })
+}
+
+/// Handles `ImportUrl` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `ImportResult`)
 pub(super) fn handle_import_mod_url(from_url: String) -> Result<Response> {
-    std::fs::create_dir_all(paths::MBF_DOWNLOADS)?;
-    let download_path = Path::new(paths::MBF_DOWNLOADS).join("import_from_url");
+    std::fs::create_dir_all(paths::default().mbf_downloads())?;
+    let download_path = Path::new(paths::default().mbf_downloads()).join("import_from_url");
 
     info!("Downloading {}", from_url);
     let filename: Option<String> =
@@ -26,17 +54,100 @@ pub(super) fn handle_import_mod_url(from_url: String) -> Result<Response> {
     handle_import(&download_path, filename)
 }
 
-/// Handles `Import` [Requests](requests::Request).
+/// Handles `InstallModFromUrl` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `ImportResult`)
+pub(super) fn handle_install_mod_from_url(
+    url: String,
+    expected_sha256: Option<String>,
+    allow_insecure: bool,
+    allow_version_mismatch: bool,
+) -> Result<Response> {
+    let res_cache = crate::load_res_cache()?;
+    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache)?;
+    mod_manager.load_mods()?;
+
+    let id = install_mod_from_url(
+        &mut mod_manager,
+        &url,
+        expected_sha256.as_deref(),
+        allow_insecure,
+        allow_version_mismatch,
+    )?;
+    let compatibility = get_compatibility(&mod_manager, &id);
+    Ok(Response::ImportResult {
+        result: ImportResultType::ImportedMod {
+            installed_mods: super::mod_management::get_mod_models(mod_manager)?,
+            imported_id: id,
+            compatibility,
+        },
+        used_filename: url,
+    })
+}
+
+/// Downloads the QMOD at the given URL, then loads it and installs it along with its dependencies.
+///
+/// # Arguments
+/// * `mod_manager` - A mod manager that has all existing mods loaded already.
+/// * `url` - The URL to download the QMOD from. This must be HTTPS unless `allow_insecure` is true.
+/// * `expected_sha256` - If `Some`, the hex SHA-256 hash of the QMOD. Installation fails if the downloaded QMOD has a different hash.
+/// * `allow_version_mismatch` - If false, installation fails if the mod is made for a different version of the game.
+///
+/// # Returns
+/// If successful, the ID of the installed mod.
+pub(super) fn install_mod_from_url(
+    mod_manager: &mut ModManager,
+    url: &str,
+    expected_sha256: Option<&str>,
+    allow_insecure: bool,
+    allow_version_mismatch: bool,
+) -> Result<String> {
+    let id = load_mod_from_url(mod_manager, url, expected_sha256, allow_insecure)?;
+    mod_manager.install_mod(&id, allow_version_mismatch)?;
+
+    Ok(id)
+}
+
+/// Downloads the QMOD at the given URL and loads it, without installing it.
+/// The arguments are the same as for [install_mod_from_url].
+///
+/// # Returns
+/// If successful, the ID of the loaded mod.
+pub(super) fn load_mod_from_url(
+    mod_manager: &mut ModManager,
+    url: &str,
+    expected_sha256: Option<&str>,
+    allow_insecure: bool,
+) -> Result<String> {
+    if !allow_insecure && !url.starts_with("https://") {
+        return Err(anyhow!(
+            "Refusing to download mod from {url} as it does not use HTTPS"
+        ));
+    }
+
+    info!("Downloading {url}");
+    let qmod_path = downloads::fetch_cached(
+        crate::get_dl_cfg(),
+        url,
+        expected_sha256,
+        Some(downloads::ExpectedContent::Zip),
+    )
+    .context("Downloading mod")?;
+    mod_manager.try_load_new_mod(std::fs::File::open(qmod_path)?)
+}
+
+/// Handles `Import` [Requests](crate::models::request::Request).
 ///
 /// # Returns
-/// The [Response](requests::Response) to the request (variant `ImportResult`)
+/// The [Response](crate::models::response::Response) to the request (variant `ImportResult`)
 pub(super) fn handle_import(
     from_path: impl AsRef<Path> + std::fmt::Debug,
     override_filename: Option<String>,
 ) -> Result<Response> {
     // Load the installed mods.
     let res_cache = crate::load_res_cache()?;
-    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache);
+    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache)?;
     mod_manager.load_mods()?;
 
     let filename = match override_filename {
@@ -52,6 +163,24 @@ pub(super) fn handle_import(
     let path = from_path.as_ref().to_owned();
     info!("Attempting to import from {filename}");
 
+    // Folders can only be songs.
+    if path.is_dir() {
+        let level = custom_levels::import_custom_level(&path)?;
+        // The folder may be an installed level, which must not be deleted, so only remove it if it was uploaded.
+        let upload_dirs = [
+            paths::default().pushed_files(),
+            paths::default().uploads(),
+            paths::default().temp(),
+        ];
+        if is_within_any(&path, &upload_dirs) && !is_same_path(&path, Path::new(&level.path)) {
+            std::fs::remove_dir_all(&path).context("Removing temporary upload folder")?;
+        }
+        return Ok(Response::ImportResult {
+            result: ImportResultType::ImportedLevel(level),
+            used_filename: filename,
+        });
+    }
+
     let file_ext = filename
         .split('.')
         .rev()
@@ -63,7 +192,7 @@ pub(super) fn handle_import(
     let import_result = if file_ext == "qmod" {
         handle_import_qmod(mod_manager, path.clone())
     } else if file_ext == "zip" {
-        attempt_song_import(path.clone())
+        attempt_song_import(path.clone(), &filename)
     } else if file_ext == "dll" {
         // This is a PC mod file, so delete it and return this fact to the importer.
         std::fs::remove_file(path.clone()).context("Removing temporary upload file")?;
@@ -104,14 +233,26 @@ fn handle_import_qmod(mut mod_manager: ModManager, from_path: PathBuf) -> Result
     let id = mod_manager.try_load_new_mod(std::fs::File::open(&from_path)?)?;
     std::fs::remove_file(from_path)?; // Delete temporary file.
 
+    let compatibility = get_compatibility(&mod_manager, &id);
     let installed_mods = super::mod_management::get_mod_models(mod_manager)?;
 
     Ok(ImportResultType::ImportedMod {
         imported_id: id,
         installed_mods,
+        compatibility,
     })
 }
 
+// Checks whether the loaded mod with the given ID is made for the installed game.
+fn get_compatibility(mod_manager: &ModManager, id: &str) -> Compatibility {
+    match mod_manager.get_mod(id) {
+        Some(mod_rc) => {
+            mod_man::check_mod_compatibility(mod_rc.borrow().manifest(), mod_manager.game_version())
+        }
+        None => Compatibility::Compatible,
+    }
+}
+
 /// Attempts to copy the given file as a mod file copy.
 /// If successful, the file will have been deleted, otherwise the file may still exist.
 ///
@@ -167,45 +308,86 @@ fn attempt_file_copy(
 
 /// Attempts to import a file as a song.
 ///
-/// This function will check that the file is a valid ZIP file and that it contains a file named `info.dat` or `Info.dat`.
-/// It will not do any further verification that the song file is valid.
+/// The file must be a ZIP file containing a valid level, either at the root of the archive or within a single folder.
 ///
 /// If successful, the file is deleted.
 ///
 /// # Arguments
 /// * `from_path` - The path to the song file.
+/// * `filename` - The name of the song file, the stem of which is used as the name of the installed level's folder.
 ///
 /// # Returns
-/// If successful, an [ImportResultType] of variant `ImportedFileCopy`, detailing the destination path the file was copied to
-/// and the mod that specified this destination path.
-fn attempt_song_import(from_path: PathBuf) -> Result<ImportResultType> {
-    let song_handle = std::fs::File::open(&from_path)?;
-    let mut zip = ZipFile::open(song_handle).context("Song was invalid ZIP file")?;
-
-    if zip.contains_file("info.dat") || zip.contains_file("Info.dat") {
-        let extract_path = Path::new(paths::CUSTOM_LEVELS)
-            .join(from_path.file_stem().expect("Must have file stem"));
-
-        if extract_path.exists() {
-            std::fs::remove_dir_all(&extract_path).context("Deleting existing song")?;
-        }
+/// If successful, an [ImportResultType] of variant `ImportedLevel`, detailing the hash of the level and where it was copied to.
+fn attempt_song_import(from_path: PathBuf, filename: &str) -> Result<ImportResultType> {
+    let folder_name = Path::new(filename)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let level = custom_levels::import_song_zip(&from_path, &folder_name)?;
 
-        std::fs::create_dir_all(&extract_path)?;
-        let entry_names = zip
-            .iter_entry_names()
-            // TODO: This is not nice for performance
-            .map(|s| s.to_string())
-            .collect::<Vec<String>>();
-        for entry_name in entry_names {
-            zip.extract_file_to(&entry_name, extract_path.join(&entry_name))?;
-        }
+    std::fs::remove_file(from_path)?;
+    Ok(ImportResultType::ImportedLevel(level))
+}
 
-        drop(zip);
-        std::fs::remove_file(from_path)?;
-        Ok(ImportResultType::ImportedSong)
-    } else {
-        Err(anyhow!(
-            "ZIP file was not a song; Unclear know how to import it"
-        ))
+// Whether `path` is inside one of `dirs`, after resolving any symbolic links and `..` components.
+// Gives false if `path` does not exist.
+fn is_within_any(path: &Path, dirs: &[&str]) -> bool {
+    let path = match path.canonicalize() {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+
+    dirs.iter().any(|dir| {
+        Path::new(dir)
+            .canonicalize()
+            .is_ok_and(|dir| path != dir && path.starts_with(dir))
+    })
+}
+
+// Whether the two paths refer to the same file or folder, after resolving any symbolic links and `..` components.
+fn is_same_path(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_same_path, is_within_any};
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn only_folders_inside_the_upload_folders_are_treated_as_uploads() {
+        let root = TempDir::new("import-uploads");
+        let uploads = root.path().join("uploads");
+        let custom_levels = root.path().join("CustomLevels");
+        std::fs::create_dir_all(uploads.join("Song")).unwrap();
+        std::fs::create_dir_all(custom_levels.join("Song")).unwrap();
+        let upload_dirs = [uploads.to_str().unwrap()];
+
+        assert!(is_within_any(&uploads.join("Song"), &upload_dirs));
+        assert!(!is_within_any(&custom_levels.join("Song"), &upload_dirs));
+        // The uploads folder itself is never removed.
+        assert!(!is_within_any(&uploads, &upload_dirs));
+        // `..` must not allow escaping the uploads folder.
+        assert!(!is_within_any(
+            &uploads.join("..").join("CustomLevels").join("Song"),
+            &upload_dirs
+        ));
+        assert!(!is_within_any(&uploads.join("Missing"), &upload_dirs));
+    }
+
+    #[test]
+    fn installed_level_is_the_same_path_as_its_folder() {
+        let root = TempDir::new("import-same-path");
+        let level = root.path().join("CustomLevels").join("Song");
+        std::fs::create_dir_all(&level).unwrap();
+
+        assert!(is_same_path(
+            &level,
+            &root.path().join("CustomLevels").join(".").join("Song")
+        ));
+        assert!(!is_same_path(&level, &root.path().join("CustomLevels")));
     }
 }
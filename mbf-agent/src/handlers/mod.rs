@@ -1,19 +1,22 @@
 //! Responsible for handling all requests sent to the backend (`mbf-agent`) from the frontend.
 
-use std::{io::Cursor, process::Command};
+use std::process::Command;
 
 use crate::{
-    downloads,
-    mod_man::ModManager,
+    cancellation, downloads, lock,
+    mod_man::{self, IncompatibleMod, ModManager},
     models::{
         request::Request,
         response::{self, Response},
     },
+    AppState,
 };
 use anyhow::{anyhow, Context, Result};
-use log::info;
-use mbf_res_man::res_cache::ResCache;
+use log::{debug, info, warn};
+use mbf_res_man::{models::CoreModSet, res_cache::ResCache};
 
+mod config;
+mod custom_levels;
 mod import;
 mod mod_management;
 mod mod_status;
@@ -28,7 +31,45 @@ mod utility;
 /// # Returns
 /// If successful, a [Response] to be sent back to the frontend.
 pub fn handle_request(request: Request) -> Result<Response> {
-    match request {
+    // Held until the request has been handled.
+    let held_lock = if request.modifies_game() {
+        crate::paths::check_storage_roots()?;
+        match lock::try_acquire()? {
+            Some(lock) => {
+                // A cancel marker left over from earlier is only meant for the operation that was running at the time.
+                cancellation::token().reset()?;
+                Some(lock)
+            }
+            None => return Ok(Response::Busy),
+        }
+    } else {
+        cancellation::token().ignore_cancel_requests();
+        None
+    };
+
+    // Without this check, these requests would fail with an unhelpful IO error when accessing the app's files.
+    // Some of these requests are read-only and so have no lock, in which case nothing here may write.
+    if request.requires_app() {
+        match crate::app_install_state(crate::APK_ID)? {
+            AppState::NotInstalled => {
+                return Ok(Response::AppNotInstalled {
+                    apk_id: crate::APK_ID.to_string(),
+                })
+            }
+            AppState::Installed { version } => debug!("Beat Saber v{version} is installed"),
+            AppState::InstalledModded { version } => {
+                debug!("Beat Saber v{version} is installed and modded");
+                // An outdated modloader may fail to load the mods about to be installed.
+                if held_lock.is_some() {
+                    if let Err(err) = crate::patching::upgrade_modloader_if_needed() {
+                        warn!("Failed to upgrade modloader: {err:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    let result = match request {
         Request::GetModStatus {
             override_core_mod_url,
         } => mod_status::handle_get_mod_status(override_core_mod_url),
@@ -39,6 +80,11 @@ pub fn handle_request(request: Request) -> Result<Response> {
             allow_no_core_mods,
             override_core_mod_url,
             vr_splash_path,
+            dry_run,
+            timeout_secs,
+            repack_tuning,
+            skip_core_mods,
+            manifest_flags,
         } => patching::handle_patch(
             downgrade_to,
             remodding,
@@ -46,19 +92,144 @@ pub fn handle_request(request: Request) -> Result<Response> {
             allow_no_core_mods,
             override_core_mod_url,
             vr_splash_path,
+            dry_run,
+            timeout_secs,
+            repack_tuning,
+            skip_core_mods,
+            manifest_flags,
         ),
+        Request::RestoreStockApk => patching::handle_restore_stock_apk(),
         Request::GetDowngradedManifest { version } => {
             patching::handle_get_downgraded_manifest(version)
         }
         Request::RemoveMod { id } => mod_management::handle_remove_mod(id),
-        Request::SetModsEnabled { statuses } => mod_management::handle_set_mods_enabled(statuses),
+        Request::PinMod { id, version } => mod_management::handle_pin_mod(id, version),
+        Request::UnpinMod { id } => mod_management::handle_unpin_mod(id),
+        Request::PreviewMod { from_path } => mod_management::handle_preview_mod(from_path),
+        Request::ValidateQmod { from_path } => mod_management::handle_validate_qmod(from_path),
+        Request::InstallMods {
+            from_paths,
+            allow_version_mismatch,
+        } => mod_management::handle_install_mods(from_paths, allow_version_mismatch),
+        Request::SyncMods {
+            mods,
+            allow_version_mismatch,
+            dry_run,
+        } => mod_management::handle_sync_mods(mods, allow_version_mismatch, dry_run),
+        Request::SetModsEnabled {
+            statuses,
+            allow_version_mismatch,
+        } => mod_management::handle_set_mods_enabled(statuses, allow_version_mismatch),
         Request::Import { from_path } => import::handle_import(from_path, None),
         Request::ImportUrl { from_url } => import::handle_import_mod_url(from_url),
+        Request::InstallModFromUrl {
+            url,
+            expected_sha256,
+            allow_insecure,
+            allow_version_mismatch,
+        } => import::handle_install_mod_from_url(
+            url,
+            expected_sha256,
+            allow_insecure,
+            allow_version_mismatch,
+        ),
         Request::FixPlayerData => utility::handle_fix_player_data(),
+        Request::RestorePlayerData {
+            source,
+            overwrite_newer,
+        } => utility::handle_restore_player_data(source, overwrite_newer),
         Request::QuickFix {
             override_core_mod_url,
             wipe_existing_mods,
         } => utility::handle_quick_fix(override_core_mod_url, wipe_existing_mods),
+        Request::GetCustomLevels => custom_levels::handle_get_custom_levels(),
+        Request::DeleteCustomLevel { hash } => custom_levels::handle_delete_custom_level(hash),
+        Request::FindDuplicateLevels => custom_levels::handle_find_duplicate_levels(),
+        Request::DedupeLevels => custom_levels::handle_dedupe_levels(),
+        Request::GetPlaylists => custom_levels::handle_get_playlists(),
+        Request::SavePlaylist { playlist } => custom_levels::handle_save_playlist(playlist),
+        Request::RunDiagnostics => utility::handle_run_diagnostics(),
+        Request::GetLogs => utility::handle_get_logs(),
+        Request::GetCrashLogs { max } => utility::handle_get_crash_logs(max),
+        Request::ExportConfig => config::handle_export_config(),
+        Request::ImportConfig { config } => config::handle_import_config(config),
+        Request::ResetMods { keep_core } => mod_management::handle_reset_mods(keep_core),
+        Request::ExportModData { to_path } => utility::handle_export_mod_data(to_path),
+        Request::ImportModData { from_path } => utility::handle_import_mod_data(from_path),
+        Request::GetAvailableMods => mod_status::handle_get_available_mods(),
+        Request::EnterSafeMode => mod_management::handle_enter_safe_mode(),
+        Request::ExitSafeMode => mod_management::handle_exit_safe_mode(),
+        Request::ClearCaches {
+            downloads,
+            temp,
+            res_cache,
+            songcore,
+        } => utility::handle_clear_caches(utility::CacheSelection {
+            downloads,
+            temp,
+            res_cache,
+            songcore,
+        }),
+        Request::GetGameVersion => mod_status::handle_get_game_version(),
+        Request::NetworkPreflight => utility::handle_network_preflight(),
+        Request::GetRecoveryState => patching::handle_get_recovery_state(),
+        Request::GetManifestFlags => patching::handle_get_manifest_flags(),
+        Request::VerifyInstallation => utility::handle_verify_installation(),
+        Request::GetStorageUsage => utility::handle_get_storage_usage(),
+        Request::GetLoaderConfig => utility::handle_get_loader_config(),
+        Request::SetLoaderConfig { config } => utility::handle_set_loader_config(config),
+        Request::GetModConfig { id } => utility::handle_get_mod_config(id),
+        Request::SetModConfig { id, config } => utility::handle_set_mod_config(id, config),
+        Request::BeginUpload { name, size, sha256 } => {
+            import::handle_begin_upload(name, size, sha256)
+        }
+        Request::AppendChunk { id, offset, data } => import::handle_append_chunk(id, offset, data),
+        Request::FinishUpload { id } => import::handle_finish_upload(id),
+        Request::Cancel => utility::handle_cancel(),
+    };
+
+    // Lets the frontend explain exactly why the mod couldn't be installed, rather than showing a generic error.
+    match result {
+        Err(err) => match err.downcast_ref::<IncompatibleMod>() {
+            Some(incompatible) => Ok(Response::IncompatibleMod {
+                mod_id: incompatible.mod_id.clone(),
+                compatibility: incompatible.compatibility.clone(),
+            }),
+            None => Err(err),
+        },
+        ok => ok,
+    }
+}
+
+/// Returned when a request needs Beat Saber to be installed, but it is not.
+#[derive(Debug)]
+pub(super) struct AppNotInstalled;
+
+impl std::error::Error for AppNotInstalled {}
+
+impl std::fmt::Display for AppNotInstalled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Beat Saber was not installed")
+    }
+}
+
+/// Returned when the installed version of Beat Saber has no core mods, so cannot be modded.
+#[derive(Debug)]
+pub(super) struct UnknownApk {
+    pub version: String,
+    /// The latest version of Beat Saber with core mods, if any.
+    pub latest_supported: Option<String>,
+}
+
+impl std::error::Error for UnknownApk {}
+
+impl std::fmt::Display for UnknownApk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No core mods existed for {}", self.version)?;
+        if let Some(latest) = &self.latest_supported {
+            write!(f, ". The latest version with core mods is {latest}")?;
+        }
+        Ok(())
     }
 }
 
@@ -78,7 +249,7 @@ fn get_app_version_only() -> Result<String> {
 
     let version_offset = match dumpsys_stdout.find("versionName=") {
         Some(offset) => offset,
-        None => return Err(anyhow!("Beat Saber was not installed")),
+        None => return Err(AppNotInstalled.into()),
     } + 12;
 
     let newline_offset = version_offset
@@ -91,6 +262,9 @@ fn get_app_version_only() -> Result<String> {
         .to_string())
 }
 
+/// The maximum number of core mods downloaded at once.
+const MAX_CONCURRENT_DOWNLOADS: usize = 4;
+
 /// Ensures that all core mods are installed and up to date.
 ///
 /// This includes downloading and installing the latest version of any missing or out-of-date core mods
@@ -102,23 +276,39 @@ fn get_app_version_only() -> Result<String> {
 /// * `app_info` - Details about the installed Beat Saber app.
 /// * `override_core_mod_url` - If this is a `Some` variant, the function will use this URL to download the core mod JSON, instead of using the default
 /// core mods URL. This can be useful for development purposes.
+///
+/// # Returns
+/// The IDs of the core mods that were missing, disabled or out of date before this was called.
 fn install_core_mods(
     res_cache: &ResCache,
     mod_manager: &mut ModManager,
     app_info: response::AppInfo,
     override_core_mod_url: Option<String>,
-) -> Result<()> {
+) -> Result<Vec<String>> {
     info!("Preparing core mods");
-    let core_mod_index =
-        mbf_res_man::external_res::fetch_core_mods(&res_cache, override_core_mod_url)?;
-
-    let core_mods = core_mod_index
-        .get(&app_info.version)
-        .ok_or(anyhow!("No core mods existed for {}", app_info.version))?;
+    let core_mods = match mbf_res_man::external_res::load_core_mods(
+        res_cache,
+        override_core_mod_url,
+        &app_info.version,
+    )? {
+        CoreModSet::Supported(core_mods) => core_mods,
+        CoreModSet::Unsupported { latest_supported } => {
+            return Err(UnknownApk {
+                version: app_info.version,
+                latest_supported,
+            }
+            .into())
+        }
+    };
+    let (_, needing_install) =
+        mod_status::get_core_mods_install_status(&core_mods.mods, mod_manager)?;
+    let pinned = mod_man::pinned_mods()?;
 
-    for core_mod in &core_mods.mods {
-        // Check if there is already an existing mod.
-        match mod_manager.get_mod(&core_mod.id) {
+    // Core mods that are already installed with a new enough version don't need downloading.
+    let to_download: Vec<&str> = core_mods
+        .mods
+        .iter()
+        .filter(|core_mod| match mod_manager.get_mod(&core_mod.id) {
             Some(existing) => {
                 let existing_ref = existing.borrow();
                 if existing_ref.manifest().version >= core_mod.version {
@@ -127,20 +317,51 @@ fn install_core_mods(
                         core_mod.id,
                         existing_ref.manifest().version
                     );
-                    continue;
+                    false
+                } else if pinned.contains_key(&core_mod.id) {
+                    info!(
+                        "Not updating core mod {} as it is pinned to {}",
+                        core_mod.id,
+                        existing_ref.manifest().version
+                    );
+                    false
+                } else {
+                    true
                 }
             }
-            None => {}
-        }
+            None => true,
+        })
+        .map(|core_mod| core_mod.download_url.as_str())
+        .collect();
 
-        info!("Downloading {} v{}", core_mod.id, core_mod.version);
+    // Core mod download links are for a particular release, so can safely be cached.
+    info!("Downloading {} core mods", to_download.len());
+    let mut failures = Vec::new();
+    let mut downloaded_paths = Vec::new();
+    for (url, result) in downloads::fetch_all_cached(
+        crate::get_dl_cfg(),
+        &to_download,
+        MAX_CONCURRENT_DOWNLOADS,
+        Some(downloads::ExpectedContent::Zip),
+    ) {
+        match result {
+            Ok(path) => downloaded_paths.push(path),
+            Err(err) => failures.push(format!("{url}: {err}")),
+        }
+    }
+    cancellation::token().check()?;
+    if !failures.is_empty() {
+        return Err(anyhow!(
+            "Failed to download {} core mods:\n{}",
+            failures.len(),
+            failures.join("\n")
+        ));
+    }
 
-        let core_mod_vec =
-            downloads::download_to_vec_with_attempts(&crate::get_dl_cfg(), &core_mod.download_url)
-                .context("Downloading core mod")?;
-        let result = mod_manager.try_load_new_mod(Cursor::new(core_mod_vec));
-        // Delete the temporary file either way
-        result?;
+    for core_mod_path in downloaded_paths {
+        mod_manager.try_load_new_mod(
+            std::fs::File::open(core_mod_path).context("Opening downloaded core mod")?,
+        )?;
     }
 
     info!("Installing core mods");
@@ -148,9 +369,11 @@ fn install_core_mods(
         .load_mods()
         .context("Loading core mods - is one invalid? If so, this is a BIG problem")?;
     for core_mod in &core_mods.mods {
-        mod_manager.install_mod(&core_mod.id)?;
+        // Core mods are chosen for the installed version, so are always compatible regardless of their manifest.
+        mod_manager.install_mod(&core_mod.id, true)?;
     }
     mod_status::mark_all_core_mods(&mod_manager, &core_mods.mods);
+    crate::patching::set_core_mods_skipped(false)?;
 
-    Ok(())
+    Ok(needing_install)
 }
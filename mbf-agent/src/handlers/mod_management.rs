@@ -1,23 +1,40 @@
 //! This file contains the request handlers relating to mod management (i.e. toggling or removing mods).
 //! Adding new mods is considered to be an "importing" operation - check the [Import Handlers](crate::handlers::import)
 
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 use crate::{
-    mod_man::ModManager,
-    models::response::{ModModel, Response},
+    atomic,
+    mod_man::{self, Abi, InstalledMod, ModManager},
+    models::{
+        request::DesiredMod,
+        response::{
+            ModModel, ModPreview, ProgressEvent, ResetReport, Response, SafeModeState, Skipped,
+            Stage, SyncStep,
+        },
+    },
+    patching, paths,
 };
-use anyhow::{Context, Result};
-use log::info;
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use mbf_res_man::models::CoreModSet;
+use mbf_zip::ZipFile;
+use serde::{Deserialize, Serialize};
 
-/// Handles `SetModsEnabled` [Requests](crate::requests::Request).
+/// Handles `SetModsEnabled` [Requests](crate::models::request::Request).
 ///
 /// # Returns
 /// The [Response] to the request (variant `ModSyncResult`)
-pub(super) fn handle_set_mods_enabled(statuses: HashMap<String, bool>) -> Result<Response> {
+pub(super) fn handle_set_mods_enabled(
+    statuses: HashMap<String, bool>,
+    allow_version_mismatch: bool,
+) -> Result<Response> {
     let res_cache = crate::load_res_cache()?;
 
-    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache);
+    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache)?;
     mod_manager.load_mods().context("Loading installed mods")?;
 
     let mut error = String::new();
@@ -33,7 +50,7 @@ pub(super) fn handle_set_mods_enabled(statuses: HashMap<String, bool>) -> Result
 
         let already_installed = mod_rc.borrow().installed();
         if new_status && !already_installed {
-            match mod_manager.install_mod(&id) {
+            match mod_manager.install_mod(&id, allow_version_mismatch) {
                 Ok(_) => info!("Installed {id}"),
                 Err(err) => error.push_str(&format!("Failed to install {id}: {err}\n")),
             }
@@ -47,25 +64,107 @@ pub(super) fn handle_set_mods_enabled(statuses: HashMap<String, bool>) -> Result
 
     Ok(Response::ModSyncResult {
         installed_mods: get_mod_models(mod_manager)?,
-        failures: if error.len() > 0 {
-            if error.ends_with('\n') {
-                error.pop();
+        failures: into_failures(error),
+    })
+}
+
+/// Handles `SyncMods` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `ModSyncResult`, or `SyncPlan` if `dry_run` is true)
+pub(super) fn handle_sync_mods(
+    desired: Vec<DesiredMod>,
+    allow_version_mismatch: bool,
+    dry_run: bool,
+) -> Result<Response> {
+    let res_cache = crate::load_res_cache()?;
+
+    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache)?;
+    mod_manager.load_mods().context("Loading installed mods")?;
+
+    let installed: Vec<InstalledMod> = mod_manager
+        .get_mods()
+        .map(|mod_rc| InstalledMod::from(&*mod_rc.borrow()))
+        .collect();
+    let plan = mod_man::plan_sync(&desired, &installed, &mod_man::pinned_mods()?);
+    if dry_run {
+        return Ok(Response::SyncPlan(plan));
+    }
+    for skipped in &plan.skipped {
+        match &skipped.reason {
+            Skipped::Pinned { version } => {
+                info!("Leaving {} as it is pinned to v{version}", skipped.id)
             }
+        }
+    }
 
-            Some(error)
-        } else {
-            None
-        },
+    // Each step is attempted even if an earlier one failed, so that as much of the plan as possible is carried out.
+    let mut error = String::new();
+    let total = plan.steps.len() as u64;
+    for (step_idx, step) in plan.steps.iter().enumerate() {
+        crate::report_progress(ProgressEvent {
+            stage: Stage::InstallingMods,
+            done: step_idx as u64,
+            total: Some(total),
+            message: step.to_string(),
+        });
+        match carry_out_sync_step(&mut mod_manager, step, allow_version_mismatch) {
+            Ok(_) => info!("{step}: done"),
+            Err(err) => error.push_str(&format!("{step} failed: {err}\n")),
+        }
+    }
+
+    Ok(Response::ModSyncResult {
+        installed_mods: get_mod_models(mod_manager)?,
+        failures: into_failures(error),
     })
 }
 
-/// Handles `RemoveMod` [Requests](crate::requests::Request).
+fn carry_out_sync_step(
+    mod_manager: &mut ModManager,
+    step: &SyncStep,
+    allow_version_mismatch: bool,
+) -> Result<()> {
+    match step {
+        SyncStep::Disable { id } => mod_manager.uninstall_mod(id),
+        SyncStep::Remove { id } => mod_manager.remove_mod(id),
+        SyncStep::Install {
+            id,
+            version,
+            source_url,
+        } => {
+            let url = source_url
+                .as_ref()
+                .ok_or(anyhow!("No download URL was given for {id} v{version}"))?;
+            let loaded_id = super::import::load_mod_from_url(mod_manager, url, None, false)?;
+            if &loaded_id != id {
+                return Err(anyhow!("The mod at {url} had ID {loaded_id}, not {id}"));
+            }
+            Ok(())
+        }
+        SyncStep::Enable { id } => mod_manager.install_mod(id, allow_version_mismatch),
+    }
+}
+
+// Gives the failures from a modding operation, or `None` if there were none.
+fn into_failures(mut error: String) -> Option<String> {
+    if error.is_empty() {
+        return None;
+    }
+
+    if error.ends_with('\n') {
+        error.pop();
+    }
+    Some(error)
+}
+
+/// Handles `RemoveMod` [Requests](crate::models::request::Request).
 ///
 /// # Returns
 /// The [Response] to the request (variant `Mods`)
 pub(super) fn handle_remove_mod(id: String) -> Result<Response> {
     let res_cache = crate::load_res_cache()?;
-    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache);
+    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache)?;
     mod_manager.load_mods()?;
     mod_manager.remove_mod(&id)?;
 
@@ -74,6 +173,329 @@ pub(super) fn handle_remove_mod(id: String) -> Result<Response> {
     })
 }
 
+/// Handles `PinMod` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `Mods`)
+pub(super) fn handle_pin_mod(id: String, version: Option<semver::Version>) -> Result<Response> {
+    let res_cache = crate::load_res_cache()?;
+    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache)?;
+    mod_manager.load_mods()?;
+
+    // Unless a version is given, the mod is pinned to the version currently loaded.
+    let version = match version {
+        Some(version) => version,
+        None => mod_manager
+            .get_mod(&id)
+            .map(|mod_rc| mod_rc.borrow().manifest().version.clone())
+            .ok_or(anyhow!(
+                "Cannot pin {id} as it is not loaded, and no version was given"
+            ))?,
+    };
+    mod_man::pin_mod(&id, version)?;
+
+    Ok(Response::Mods {
+        installed_mods: get_mod_models(mod_manager)?,
+    })
+}
+
+/// Handles `UnpinMod` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `Mods`)
+pub(super) fn handle_unpin_mod(id: String) -> Result<Response> {
+    if !mod_man::unpin_mod(&id)? {
+        warn!("{id} was not pinned");
+    }
+
+    let res_cache = crate::load_res_cache()?;
+    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache)?;
+    mod_manager.load_mods()?;
+    Ok(Response::Mods {
+        installed_mods: get_mod_models(mod_manager)?,
+    })
+}
+
+/// Handles `PreviewMod` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `ModPreview`)
+pub(super) fn handle_preview_mod(from_path: String) -> Result<Response> {
+    let res_cache = crate::load_res_cache()?;
+    let game_version = super::get_app_version_only()?;
+    let mod_manager = ModManager::new(game_version.clone(), &res_cache)?;
+    let manifest = mod_manager.peek_manifest(Path::new(&from_path))?;
+
+    Ok(Response::ModPreview(ModPreview {
+        compatibility: mod_man::check_mod_compatibility(&manifest, &game_version),
+        id: manifest.id,
+        name: manifest.name,
+        version: manifest.version,
+        author: manifest.author,
+        description: manifest.description,
+        game_version: manifest.package_version,
+        dependencies: manifest
+            .dependencies
+            .into_iter()
+            .map(|dep| dep.id)
+            .collect(),
+    }))
+}
+
+/// Handles `ValidateQmod` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `QmodValidation`)
+pub(super) fn handle_validate_qmod(from_path: String) -> Result<Response> {
+    let app_info = super::mod_status::get_app_info()?.ok_or(super::AppNotInstalled)?;
+    // All versions of the game supported by MBF are arm64, so this is only needed for APKs with no native libraries.
+    let game_abi = patching::get_apk_abi(&ZipFile::open(
+        std::fs::File::open(&app_info.path).context("Opening APK")?,
+    )?)
+    .unwrap_or(Abi::Arm64V8a);
+
+    let res_cache = crate::load_res_cache()?;
+    let mod_manager = ModManager::new(app_info.version, &res_cache)?;
+    let validation = mod_manager.validate_qmod(Path::new(&from_path), game_abi)?;
+    if !validation.issues.is_empty() {
+        warn!(
+            "Found {} problems with the QMOD at {from_path}",
+            validation.issues.len()
+        );
+    }
+
+    Ok(Response::QmodValidation(validation))
+}
+
+/// Handles `InstallMods` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `ModsInstalled`)
+pub(super) fn handle_install_mods(
+    from_paths: Vec<String>,
+    allow_version_mismatch: bool,
+) -> Result<Response> {
+    let res_cache = crate::load_res_cache()?;
+
+    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache)?;
+    mod_manager.load_mods().context("Loading installed mods")?;
+
+    let paths: Vec<PathBuf> = from_paths.into_iter().map(PathBuf::from).collect();
+    let report = mod_manager.install_mods(&paths, allow_version_mismatch)?;
+    let failed = report
+        .results
+        .iter()
+        .filter(|result| result.error.is_some())
+        .count();
+    if failed > 0 {
+        warn!("{failed} of {} mods could not be installed", paths.len());
+    }
+
+    Ok(Response::ModsInstalled {
+        report,
+        installed_mods: get_mod_models(mod_manager)?,
+    })
+}
+
+/// Handles `ResetMods` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `ModsReset`)
+pub(super) fn handle_reset_mods(keep_core: bool) -> Result<Response> {
+    Ok(Response::ModsReset(reset_mods(keep_core)?))
+}
+
+/// Deletes all installed mods and their QMODs, returning the game to a clean modded state without unpatching it.
+/// Custom levels and player data are left untouched.
+///
+/// # Arguments
+/// * `keep_core` - If true, the core mods for the installed version are reinstalled after deleting all mods.
+pub(super) fn reset_mods(keep_core: bool) -> Result<ResetReport> {
+    let app_info = super::mod_status::get_app_info()?
+        .ok_or(anyhow!("Cannot reset mods when app is not installed"))?;
+    let res_cache = crate::load_res_cache()?;
+    let mut mod_manager = ModManager::new(app_info.version.clone(), &res_cache)?;
+
+    info!("Wiping all existing mods");
+    let removed_files = mod_manager
+        .wipe_all_mods()
+        .context("Wiping existing mods")?;
+    mod_manager.load_mods()?; // Should load no mods.
+
+    let reinstalled_core_mods = if keep_core {
+        super::install_core_mods(&res_cache, &mut mod_manager, app_info, None)
+            .context("Reinstalling core mods")?
+            .len()
+    } else {
+        0
+    };
+
+    info!("Removed {removed_files} files and reinstalled {reinstalled_core_mods} core mods");
+    Ok(ResetReport {
+        installed_mods: get_mod_models(mod_manager)?,
+        removed_files,
+        reinstalled_core_mods,
+    })
+}
+
+/// Recorded on entering safe mode, so that exiting it can put each mod back how it was.
+#[derive(Serialize, Deserialize)]
+struct SafeModeRecord {
+    // The non-core mods that were enabled, and so were disabled by safe mode.
+    enabled: Vec<String>,
+    // The non-core mods that were already disabled.
+    disabled: Vec<String>,
+}
+
+/// Handles `EnterSafeMode` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `SafeMode`)
+pub(super) fn handle_enter_safe_mode() -> Result<Response> {
+    Ok(Response::SafeMode(enter_safe_mode()?))
+}
+
+/// Handles `ExitSafeMode` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response] to the request (variant `SafeMode`)
+pub(super) fn handle_exit_safe_mode() -> Result<Response> {
+    Ok(Response::SafeMode(exit_safe_mode()?))
+}
+
+/// Disables every mod that is not a core mod for the installed version or a dependency of one, so that users can check
+/// whether the game boots with only the core mods. The QMODs of disabled mods are kept, so none of them are lost.
+///
+/// The enabled state of each mod is recorded in [crate::paths::Paths::safe_mode_record] first, for [exit_safe_mode].
+/// Fails if safe mode is already active, as the record of the mods enabled beforehand would otherwise be overwritten.
+pub(super) fn enter_safe_mode() -> Result<SafeModeState> {
+    let record_path = Path::new(paths::default().safe_mode_record());
+    if record_path.exists() {
+        return Err(anyhow!(
+            "Safe mode is already active. Exit safe mode before entering it again"
+        ));
+    }
+
+    let app_info = super::mod_status::get_app_info()?.ok_or(super::AppNotInstalled)?;
+    let res_cache = crate::load_res_cache()?;
+    let mut mod_manager = ModManager::new(app_info.version.clone(), &res_cache)?;
+    mod_manager.load_mods().context("Loading installed mods")?;
+
+    // Without knowing which mods are core, they would be disabled too and the game would boot unmodded.
+    let core_mods =
+        match mbf_res_man::external_res::load_core_mods(&res_cache, None, &app_info.version)? {
+            CoreModSet::Supported(core_mods) => core_mods,
+            CoreModSet::Unsupported { latest_supported } => {
+                return Err(super::UnknownApk {
+                    version: app_info.version,
+                    latest_supported,
+                }
+                .into())
+            }
+        };
+    super::mod_status::mark_all_core_mods(&mod_manager, &core_mods.mods);
+
+    let mut record = SafeModeRecord {
+        enabled: Vec::new(),
+        disabled: Vec::new(),
+    };
+    for mod_rc in mod_manager.get_mods() {
+        let mod_ref = mod_rc.borrow();
+        if mod_ref.is_core() {
+            continue;
+        }
+
+        let id = mod_ref.manifest().id.clone();
+        if mod_ref.installed() {
+            record.enabled.push(id);
+        } else {
+            record.disabled.push(id);
+        }
+    }
+
+    // Recorded before anything is disabled, so that a failure part way through still leaves a way back.
+    std::fs::create_dir_all(record_path.parent().expect("Safe mode record has a parent"))?;
+    atomic::write_atomic(record_path, &serde_json::to_vec(&record)?)
+        .context("Recording enabled mods before entering safe mode")?;
+
+    let mut error = String::new();
+    for id in &record.enabled {
+        match mod_manager.uninstall_mod(id) {
+            Ok(_) => info!("Disabled {id} for safe mode"),
+            Err(err) => error.push_str(&format!("Failed to disable {id}: {err}\n")),
+        }
+    }
+
+    Ok(SafeModeState {
+        active: true,
+        changed_mods: record.enabled,
+        installed_mods: get_mod_models(mod_manager)?,
+        failures: into_failures(error),
+    })
+}
+
+/// Puts each mod back how it was before [enter_safe_mode] was called: mods disabled by safe mode are re-enabled, and
+/// mods enabled while in safe mode (e.g. to find which mod stops the game from booting) are disabled again.
+/// Mods that were removed or imported while in safe mode are left as they are.
+///
+/// The record of safe mode is removed even if some mods could not be re-enabled, as these are given in the failures.
+pub(super) fn exit_safe_mode() -> Result<SafeModeState> {
+    let record_path = Path::new(paths::default().safe_mode_record());
+    let record: SafeModeRecord = match std::fs::read(record_path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).context("Parsing safe mode record")?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(anyhow!("Safe mode is not active"))
+        }
+        Err(err) => return Err(err).context("Reading safe mode record"),
+    };
+
+    let res_cache = crate::load_res_cache()?;
+    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache)?;
+    mod_manager.load_mods().context("Loading installed mods")?;
+
+    let mut error = String::new();
+    for id in &record.disabled {
+        let enabled = mod_manager
+            .get_mod(id)
+            .is_some_and(|mod_rc| mod_rc.borrow().installed());
+        if enabled {
+            match mod_manager.uninstall_mod(id) {
+                Ok(_) => info!("Disabled {id}, which was enabled while in safe mode"),
+                Err(err) => error.push_str(&format!("Failed to disable {id}: {err}\n")),
+            }
+        }
+    }
+
+    let mut changed_mods = Vec::new();
+    for id in record.enabled {
+        match mod_manager.get_mod(&id) {
+            Some(mod_rc) if mod_rc.borrow().installed() => continue,
+            Some(_) => {}
+            None => {
+                warn!("{id} was removed while in safe mode, so cannot be re-enabled");
+                continue;
+            }
+        }
+
+        // These mods were enabled before, so are put back even if they were enabled despite a version mismatch.
+        match mod_manager.install_mod(&id, true) {
+            Ok(_) => {
+                info!("Re-enabled {id}");
+                changed_mods.push(id);
+            }
+            Err(err) => error.push_str(&format!("Failed to re-enable {id}: {err}\n")),
+        }
+    }
+
+    std::fs::remove_file(record_path).context("Removing safe mode record")?;
+    Ok(SafeModeState {
+        active: false,
+        changed_mods,
+        installed_mods: get_mod_models(mod_manager)?,
+        failures: into_failures(error),
+    })
+}
+
 /// Consumes a [ModManager] and converts the loaded mods into [ModModels](ModModel) which can be serialized
 /// to JSON and sent back to the frontend.
 pub(super) fn get_mod_models(mut mod_manager: ModManager) -> Result<Vec<ModModel>> {
@@ -82,8 +504,13 @@ pub(super) fn get_mod_models(mut mod_manager: ModManager) -> Result<Vec<ModModel
     // Therefore, check dependencies of mods again to double-check which are really installed.
     mod_manager.check_mods_installed()?;
 
+    let pinned = mod_man::pinned_mods()?;
     Ok(mod_manager
         .get_mods()
-        .map(|mod_info| ModModel::from(&*(**mod_info).borrow()))
+        .map(|mod_info| {
+            let mut model = ModModel::from(&*(**mod_info).borrow());
+            model.is_pinned = pinned.contains_key(&model.id);
+            model
+        })
         .collect())
 }
@@ -1,40 +1,69 @@
 //! Handler for the GetModStatus request.
 
-use std::{fs::File, io::Cursor};
+use std::{collections::HashMap, fs::File, io::Cursor};
 
 use log::{error, info, warn};
 use mbf_res_man::{
-    models::{CoreMod, VersionDiffs},
+    models::{CoreMod, CoreModSet, ModRepoMod, VersionDiffs},
     res_cache::{self, ResCache},
+    version,
 };
 use mbf_zip::ZipFile;
 
 use crate::{
     axml::{self, AxmlReader},
     manifest::ManifestInfo,
-    mod_man::ModManager,
-    models::response::{self, CoreModsInfo, Response},
+    mod_man::{self, InstalledMod, ModManager},
+    models::response::{self, AvailableMod, AvailableModStatus, CoreModsInfo, Response},
     patching,
 };
 use anyhow::{Context, Result};
 
+/// The maximum size of the resource cache, in bytes, before old files are deleted.
+const RES_CACHE_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
 /// Handles `GetModStatus` [Requests](response::Request).
 ///
 /// # Returns
 /// The [Response](response::Response) to the request (variant `ModStatus`)
 pub(super) fn handle_get_mod_status(override_core_mod_url: Option<String>) -> Result<Response> {
-    crate::try_delete_legacy_dirs();
+    crate::purge_legacy_dirs();
 
     info!("Searching for Beat Saber app");
     let app_info = get_app_info()?;
     let res_cache = crate::load_res_cache()?;
+    // The cached files cannot be downloaded again if offline, so are only removed when online.
+    if !crate::is_offline() {
+        match res_cache.enforce_cache_limit(RES_CACHE_MAX_BYTES, &[]) {
+            Ok(report) if !report.removed.is_empty() => info!(
+                "Freed {} bytes by removing {} old files from the resource cache",
+                report.freed_bytes,
+                report.removed.len()
+            ),
+            Ok(_) => {}
+            Err(err) => warn!("Failed to limit size of resource cache: {err}"),
+        }
+    }
 
-    let (core_mods, installed_mods) = match &app_info {
+    let (core_mods, installed_mods, conflicts) = match &app_info {
         Some(app_info) => {
             info!("Loading installed mods");
-            let mut mod_manager = ModManager::new(app_info.version.clone(), &res_cache);
+            let mut mod_manager = ModManager::new(app_info.version.clone(), &res_cache)?;
             mod_manager.load_mods().context("Loading installed mods")?;
 
+            let loaded_mods: Vec<InstalledMod> = mod_manager
+                .get_mods()
+                .map(|mod_rc| InstalledMod::from(&*mod_rc.borrow()))
+                .collect();
+            let conflicts = mod_man::detect_conflicts(&loaded_mods);
+            for conflict in &conflicts {
+                warn!(
+                    "{} mods provide different copies of {}",
+                    conflict.mods.len(),
+                    conflict.library
+                );
+            }
+
             (
                 get_core_mods_info(
                     &app_info.version,
@@ -44,19 +73,116 @@ pub(super) fn handle_get_mod_status(override_core_mod_url: Option<String>) -> Re
                     app_info.loader_installed.is_some(),
                 )?,
                 super::mod_management::get_mod_models(mod_manager)?,
+                conflicts,
             )
         }
         None => {
             warn!("Beat Saber is not installed!");
-            (None, Vec::new())
+            (None, Vec::new(), Vec::new())
         }
     };
 
     Ok(Response::ModStatus {
         app_info,
         core_mods,
+        core_mods_intentionally_skipped: patching::core_mods_skipped(),
         modloader_install_status: patching::get_modloader_status()?,
         installed_mods,
+        conflicts,
+    })
+}
+
+/// Handles `GetAvailableMods` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `AvailableMods`)
+pub(super) fn handle_get_available_mods() -> Result<Response> {
+    Ok(Response::AvailableMods {
+        mods: available_mods(&super::get_app_version_only()?)?,
+    })
+}
+
+/// Gets the latest version of each mod in the mod repo, from the mods for `game_version` and the global mods.
+/// Each mod is compared with the installed mods to find whether it is installed or outdated.
+///
+/// # Returns
+/// The mods, sorted by name. This is empty if the mod repo has no mods for `game_version`.
+pub(super) fn available_mods(game_version: &str) -> Result<Vec<AvailableMod>> {
+    let res_cache = crate::load_res_cache()?;
+    let mod_repo =
+        mbf_res_man::external_res::get_mod_repo(&res_cache).context("Getting mod repo")?;
+
+    let mut mod_manager = ModManager::new(game_version.to_string(), &res_cache)?;
+    mod_manager.load_mods().context("Loading installed mods")?;
+    // Without the core mods, a core mod could be given as outdated before its update is in the core mod index.
+    match mbf_res_man::external_res::load_core_mods(&res_cache, None, game_version) {
+        Ok(CoreModSet::Supported(core_mods)) => mark_all_core_mods(&mod_manager, &core_mods.mods),
+        Ok(CoreModSet::Unsupported { .. }) => {}
+        Err(err) => warn!("Failed to load core mods, so core mods may be given as outdated: {err}"),
+    }
+
+    let mut latest_versions: HashMap<&str, &ModRepoMod> = HashMap::new();
+    for repo_mod in ["global", game_version]
+        .into_iter()
+        .filter_map(|key| mod_repo.get(key))
+        .flatten()
+    {
+        let latest = latest_versions.entry(&repo_mod.id).or_insert(repo_mod);
+        if latest.version < repo_mod.version {
+            *latest = repo_mod;
+        }
+    }
+
+    let mut available: Vec<AvailableMod> = latest_versions
+        .into_values()
+        .map(|repo_mod| {
+            let (status, installed_version) = match mod_manager.get_mod(&repo_mod.id) {
+                Some(existing) => {
+                    let existing = existing.borrow();
+                    let status =
+                        if existing.manifest().version < repo_mod.version && !existing.is_core() {
+                            AvailableModStatus::Outdated
+                        } else {
+                            AvailableModStatus::Installed
+                        };
+                    (status, Some(existing.manifest().version.clone()))
+                }
+                None => (AvailableModStatus::NotInstalled, None),
+            };
+
+            AvailableMod {
+                id: repo_mod.id.clone(),
+                name: repo_mod.name.clone(),
+                version: repo_mod.version.clone(),
+                author: repo_mod.author.clone(),
+                description: repo_mod.description.clone(),
+                cover: repo_mod.cover.clone(),
+                download_url: repo_mod.download.clone(),
+                status,
+                installed_version,
+            }
+        })
+        .collect();
+    available.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(available)
+}
+
+/// Handles `GetGameVersion` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `GameVersion`)
+pub(super) fn handle_get_game_version() -> Result<Response> {
+    let version = crate::installed_version_name()?;
+    let (semver, build) = match crate::installed_game_version()? {
+        Some(parsed) => (parsed.semver, parsed.build),
+        None => (None, None),
+    };
+
+    Ok(Response::GameVersion {
+        version,
+        semver,
+        build,
     })
 }
 
@@ -137,9 +263,9 @@ fn get_core_mods_info(
         };
 
     // Check that all core mods are installed with an appropriate version
-    let all_core_mods_installed = match core_mods.get(apk_version) {
-        Some(core_mods) => get_core_mods_install_status(&core_mods.mods, mod_manager),
-        None => response::InstallStatus::Missing,
+    let (all_core_mods_installed, missing_core_mods) = match core_mods.get(apk_version) {
+        Some(core_mods) => get_core_mods_install_status(&core_mods.mods, mod_manager)?,
+        None => (response::InstallStatus::Missing, Vec::new()),
     };
 
     let supported_versions: Vec<String> = core_mods
@@ -182,6 +308,7 @@ fn get_core_mods_info(
     Ok(Some(CoreModsInfo {
         supported_versions,
         core_mod_install_status: all_core_mods_installed,
+        missing_core_mods,
         downgrade_versions,
         is_awaiting_diff: newer_than_latest_diff && !is_version_supported,
     }))
@@ -218,26 +345,26 @@ fn is_version_newer_than_latest_diff(apk_version: &str, diffs: &[VersionDiffs])
 }
 
 fn try_parse_bs_ver_as_semver(version: &str) -> Option<semver::Version> {
-    let version_segment = version
-        .split('_')
-        .next()
-        .expect("Split iterator always returns at least one string");
-    semver::Version::parse(version_segment).ok()
+    version::parse_bs_version(version).semver
 }
 
 // Checks whether all the core mods in the provided slice are present within the mod manager given.
 // Will give InstallStatus::Ready if all core mods are installed and up to date,
 // InstallStatus::NeedUpdate if any core mods are out of date but all are installed, and InstallStatus::Missing if any
 // of the core mods are not installed or not even present.
-fn get_core_mods_install_status(
+// Also gives the IDs of all core mods that are missing or out of date.
+// Core mods that are pinned are never considered out of date, as they will not be updated.
+pub(super) fn get_core_mods_install_status(
     core_mods: &[CoreMod],
     mod_man: &ModManager,
-) -> response::InstallStatus {
+) -> Result<(response::InstallStatus, Vec<String>)> {
     info!("Checking if core mods installed and up to date");
     mark_all_core_mods(mod_man, core_mods);
+    let pinned = mod_man::pinned_mods()?;
 
     let mut missing_core_mods = false;
     let mut outdated_core_mods = false;
+    let mut needing_install = Vec::new();
     for core_mod in core_mods {
         match mod_man.get_mod(&core_mod.id) {
             Some(existing_mod) => {
@@ -248,6 +375,15 @@ fn get_core_mods_install_status(
                 if !mod_ref.installed() {
                     warn!("Core mod {} was present (ver {}) but is not installed: needs to be installed", core_mod.id, mod_ref.manifest().version);
                     missing_core_mods = true;
+                    needing_install.push(core_mod.id.clone());
+                } else if mod_ref.manifest().version < core_mod.version
+                    && pinned.contains_key(&core_mod.id)
+                {
+                    info!(
+                        "Core mod {} is outdated, but is pinned to {}",
+                        core_mod.id,
+                        mod_ref.manifest().version
+                    );
                 } else if mod_ref.manifest().version < core_mod.version {
                     warn!(
                         "Core mod {} is outdated, latest version: {}, installed version: {}",
@@ -256,19 +392,24 @@ fn get_core_mods_install_status(
                         mod_ref.manifest().version
                     );
                     outdated_core_mods = true;
+                    needing_install.push(core_mod.id.clone());
                 }
             }
-            None => missing_core_mods = true,
+            None => {
+                missing_core_mods = true;
+                needing_install.push(core_mod.id.clone());
+            }
         }
     }
 
-    if missing_core_mods {
+    let status = if missing_core_mods {
         response::InstallStatus::Missing
     } else if outdated_core_mods {
         response::InstallStatus::NeedUpdate
     } else {
         response::InstallStatus::Ready
-    }
+    };
+    Ok((status, needing_install))
 }
 
 /// Marks all of the mods with IDs matching mods in `core_mods` and all of their dependencies as core within the provided ModManager
@@ -1,16 +1,35 @@
 //! Handles requests related to the patching of the game.
 
-use std::path::Path;
+use std::{path::Path, time::Duration};
 
 use log::{info, warn};
 
-use crate::{mod_man::ModManager, models::response::Response, patching, paths};
+use crate::{
+    cancellation,
+    mod_man::ModManager,
+    models::{
+        request::RepackTuning,
+        response::{
+            AppInfo, ManifestFlags, ModLoader, ObbMatch, PatchPlan, ProgressEvent, Response, Stage,
+            TimedStage,
+        },
+    },
+    patching::{self, StockApkError},
+    paths,
+    storage::{self, SpaceStatus},
+    timings,
+};
 use anyhow::{anyhow, Context, Result};
+use mbf_res_man::{
+    external_res,
+    models::{StockApkIndex, VersionDiffs},
+    res_cache::ResCache,
+};
 
-/// Handles `GetDowngradedManifest` [Requests](requests::Request).
+/// Handles `GetDowngradedManifest` [Requests](crate::models::request::Request).
 ///
 /// # Returns
-/// The [Response](requests::Response) to the request (variant `DowngradedManifest`)
+/// The [Response](crate::models::response::Response) to the request (variant `DowngradedManifest`)
 pub(super) fn handle_get_downgraded_manifest(version: String) -> Result<Response> {
     info!("Downloading manifest AXML file");
     let manifest_bytes = mbf_res_man::external_res::get_manifest_axml(
@@ -24,10 +43,37 @@ pub(super) fn handle_get_downgraded_manifest(version: String) -> Result<Response
     Ok(Response::DowngradedManifest { manifest_xml })
 }
 
-/// Handles `Patch` [Requests](requests::Request).
+/// Handles `GetRecoveryState` [Requests](crate::models::request::Request).
 ///
 /// # Returns
-/// The [Response](requests::Response) to the request (variant `Mods`)
+/// The [Response](crate::models::response::Response) to the request (variant `RecoveryState`)
+pub(super) fn handle_get_recovery_state() -> Result<Response> {
+    Ok(Response::RecoveryState {
+        recovery: patching::patch_recovery_state(),
+    })
+}
+
+/// Handles `GetManifestFlags` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `ManifestFlags`)
+pub(super) fn handle_get_manifest_flags() -> Result<Response> {
+    let flags = match crate::get_apk_path()? {
+        Some(apk_path) => Some(
+            crate::manifest::manifest_flags(Path::new(&apk_path))
+                .context("Reading manifest flags")?,
+        ),
+        None => None,
+    };
+
+    Ok(Response::ManifestFlags { flags })
+}
+
+/// Handles `Patch` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `Patched`, or `PatchPlan` if `dry_run` is true)
+#[allow(clippy::too_many_arguments)] // Each argument is a field of the request.
 pub(super) fn handle_patch(
     downgrade_to: Option<String>,
     repatch: bool,
@@ -35,60 +81,167 @@ pub(super) fn handle_patch(
     allow_no_core_mods: bool,
     override_core_mod_url: Option<String>,
     vr_splash_path: Option<String>,
+    dry_run: bool,
+    timeout_secs: Option<u64>,
+    repack_tuning: RepackTuning,
+    skip_core_mods: bool,
+    manifest_flags: ManifestFlags,
 ) -> Result<Response> {
-    let app_info =
-        super::mod_status::get_app_info()?.ok_or(anyhow!("Cannot patch when app not installed"))?;
+    let app_info = super::mod_status::get_app_info()?.ok_or(super::AppNotInstalled)?;
+    // Patching the game again for the same version would give the same APK, so only the mods need checking.
+    // Fixing the mods installs any missing core mods, so this isn't done if core mods are to be skipped.
+    // If the manifest, its flags or the splash screen are to be changed, the APK must be patched again,
+    // but this only needs a repatch.
+    let already_patched = downgrade_to
+        .as_ref()
+        .is_none_or(|to_version| *to_version == app_info.version)
+        && matches!(app_info.loader_installed, Some(ModLoader::Scotland2));
+    let changes_apk = vr_splash_path.is_some()
+        || manifest_flags != ManifestFlags::default()
+        || !patching::manifests_equivalent(&manifest_mod, &app_info.manifest_xml);
+    if !repatch && !dry_run && !skip_core_mods && already_patched && !changes_apk {
+        info!("App is already patched, so fixing mods instead of patching again");
+        let report = super::utility::quick_fix(override_core_mod_url, false)?;
+        return Ok(Response::AlreadyPatched {
+            installed_mods: report.installed_mods,
+            mods_fixed: report.repairs,
+            binary_issues: report.binary_issues,
+        });
+    }
+
+    let repatch = repatch || (already_patched && changes_apk);
+    if repatch {
+        info!("Repatching to change the manifest, its flags or the splash screen");
+    }
+
+    // Patching an APK that is already modded would add a second copy of the modloader,
+    // and the diffs used to downgrade only apply to unmodified APKs.
+    if !repatch && app_info.loader_installed.is_some() {
+        return Err(anyhow!(
+            "Cannot patch as the app is already modded. Reinstall the game to patch it again, or repatch instead"
+        ));
+    }
     let res_cache = crate::load_res_cache()?;
+    let version_diffs = match &downgrade_to {
+        Some(to_version) => Some(find_version_diffs(&res_cache, &app_info, to_version)?),
+        None => None,
+    };
 
-    std::fs::create_dir_all(paths::TEMP)?;
+    let plan = patching::plan_patch(
+        &app_info,
+        version_diffs.as_ref(),
+        repatch,
+        vr_splash_path.as_deref(),
+        &res_cache,
+    )
+    .context("Planning patch")?;
+    if dry_run {
+        return Ok(Response::PatchPlan(plan));
+    }
 
-    // Either downgrade or just patch the current APK depending on the caller's choice.
-    let patching_result = if let Some(to_version) = &downgrade_to {
-        let diff_index = mbf_res_man::external_res::get_diff_index(&res_cache)
-            .context("Getting diff index to downgrade")?;
-        let version_diffs = diff_index
-            .into_iter()
-            .filter(|diff| diff.from_version == app_info.version && &diff.to_version == to_version)
-            .next()
-            .ok_or(anyhow!(
-                "No diff existed to go from {} to {}",
-                app_info.version,
-                to_version
-            ))?;
+    // Fail now rather than running out of space part way through patching.
+    let required_space = estimate_required_space(&plan, &app_info, !repatch)?;
+    match storage::check_free_space(required_space)? {
+        SpaceStatus::Sufficient { available } => info!(
+            "{} MB free, ~{} MB needed to patch",
+            available / (1024 * 1024),
+            required_space / (1024 * 1024)
+        ),
+        SpaceStatus::Insufficient(insufficient) => return Err(insufficient.into()),
+    }
+
+    if let Some(timeout_secs) = timeout_secs {
+        cancellation::token().set_timeout(Duration::from_secs(timeout_secs));
+    }
+    patching::set_repack_tuning(repack_tuning);
+    patching::set_manifest_flags(manifest_flags);
+
+    // Catch a corrupt APK before spending time patching it.
+    if !repatch {
+        let stock_apks = match external_res::get_stock_apk_index(&res_cache) {
+            Ok(index) => index,
+            Err(err) => {
+                warn!("Failed to get the hashes of official APKs, so the APK cannot be checked to be official: {err}");
+                StockApkIndex::new()
+            }
+        };
+        let known_version = patching::known_version(&stock_apks, &app_info.version);
+        if let Err(err) = patching::verify_stock_apk(Path::new(&app_info.path), &known_version) {
+            match err.downcast_ref::<StockApkError>() {
+                Some(StockApkError::UnknownApk { sha256 }) => warn!(
+                    "Installed APK (SHA-256 {sha256}) is not a known official release of {}, so it may not patch correctly",
+                    app_info.version
+                ),
+                _ => return Err(err).context("Checking installed APK before patching"),
+            }
+        }
+    }
 
+    let mut progress = crate::report_progress;
+    std::fs::create_dir_all(paths::default().temp())?;
+    // Checked above that the APK is unmodded, unless repatching.
+    if !repatch {
+        patching::backup_stock_apk(&app_info).context("Backing up unmodded APK")?;
+    }
+
+    // Either downgrade or just patch the current APK depending on the caller's choice.
+    let patching_result = if let Some(version_diffs) = version_diffs {
         patching::downgrade_and_mod_apk(
-            Path::new(paths::TEMP),
+            Path::new(paths::default().temp()),
             &app_info,
             version_diffs,
             manifest_mod,
             vr_splash_path.as_deref(),
             &res_cache,
+            &mut progress,
         )
         .context("Downgrading and patching APK")
     } else {
         patching::mod_current_apk(
-            Path::new(paths::TEMP),
+            Path::new(paths::default().temp()),
             &app_info,
             manifest_mod,
             repatch,
             vr_splash_path.as_deref(),
             &res_cache,
+            &mut progress,
         )
         .context("Patching APK")
         .map(|_| false) // Modding the currently installed APK will never remove DLC as they are restored automatically.
     };
 
     // No matter what, make sure that all temporary files are gone.
-    std::fs::remove_dir_all(paths::TEMP)?;
+    // If patching was cancelled, this includes the partially patched APK, leaving the installed app as it was.
+    std::fs::remove_dir_all(paths::default().temp())?;
     if let Some(splash_path) = vr_splash_path {
         std::fs::remove_file(splash_path)?;
     }
 
+    // If patching failed part way through reinstalling the app, the OBB files may still be stashed.
+    if patching_result.is_err() {
+        if let Err(err) = patching::recover_orphaned_obb() {
+            warn!("Failed to restore stashed OBB files: {err:?}");
+        }
+    }
+    patching::clear_patch_progress()?;
+
     let removed_dlc = patching_result?;
+    // An OBB left at the newer version after downgrading stops the game from loading.
+    if downgrade_to.is_some() {
+        match patching::check_obb_matches_apk() {
+            Ok(ObbMatch::Mismatch { apk, obb }) => warn!(
+                "The OBB files are for version code {obb}, but the downgraded APK is version code {apk}. The game may fail to load"
+            ),
+            Ok(ObbMatch::ObbMissing) => warn!("No OBB files were found after downgrading"),
+            Ok(ObbMatch::Match) => info!("OBB files match the downgraded APK"),
+            Err(err) => warn!("Failed to check OBB files match the APK: {err:?}"),
+        }
+    }
     patching::install_modloader().context("Installing external modloader")?;
+    patching::ensure_nomedia()?;
 
     let new_app_version = downgrade_to.unwrap_or(app_info.version);
-    let mut mod_manager = ModManager::new(new_app_version, &res_cache);
+    let mut mod_manager = ModManager::new(new_app_version, &res_cache)?;
 
     if !repatch {
         info!("Wiping all existing mods");
@@ -96,7 +249,19 @@ pub(super) fn handle_patch(
             .wipe_all_mods()
             .context("Wiping existing mods")?;
         mod_manager.load_mods()?; // Should load no mods.
+    }
 
+    if !repatch && skip_core_mods {
+        info!("Not installing core mods, as requested");
+        patching::set_core_mods_skipped(true)?;
+    } else if !repatch {
+        // Clear any marker left by an earlier patch, in case installing the core mods fails below.
+        patching::set_core_mods_skipped(false)?;
+        progress(ProgressEvent::new(
+            Stage::InstallingMods,
+            "Installing core mods",
+        ));
+        let core_mods_timer = timings::start(TimedStage::InstallCoreMods);
         match super::install_core_mods(
             &res_cache,
             &mut mod_manager,
@@ -104,7 +269,10 @@ pub(super) fn handle_patch(
                 .ok_or(anyhow!("Beat Saber should be installed after patching"))?,
             override_core_mod_url,
         ) {
-            Ok(_) => info!("Successfully installed all core mods"),
+            Ok(_) => {
+                core_mods_timer.finish(None);
+                info!("Successfully installed all core mods")
+            }
             Err(err) => {
                 if allow_no_core_mods {
                     warn!("Failed to install core mods: {err}")
@@ -115,8 +283,71 @@ pub(super) fn handle_patch(
         }
     }
 
+    progress(ProgressEvent::new(Stage::Finalizing, "Patching complete"));
     Ok(Response::Patched {
         installed_mods: super::mod_management::get_mod_models(mod_manager)?,
         did_remove_dlc: removed_dlc,
+        timings: timings::take(),
     })
 }
+
+// Finds the diffs needed to downgrade the installed app to the given version.
+fn find_version_diffs(
+    res_cache: &ResCache,
+    app_info: &AppInfo,
+    to_version: &str,
+) -> Result<VersionDiffs> {
+    let diff_index = mbf_res_man::external_res::get_diff_index(res_cache)
+        .context("Getting diff index to downgrade")?;
+    diff_index
+        .into_iter()
+        .find(|diff| diff.from_version == app_info.version && diff.to_version == to_version)
+        .ok_or(anyhow!(
+            "No diff existed to go from {} to {}",
+            app_info.version,
+            to_version
+        ))
+}
+
+// The space allowed for each file that needs to be downloaded during patching, as their sizes are not known in advance.
+const DOWNLOAD_SPACE_ALLOWANCE: u64 = 100 * 1024 * 1024;
+
+// Estimates the number of bytes of temporary files that carrying out the given plan will need.
+// Patching rewrites the APK within the temporary folder, so an extra copy of the APK is allowed for,
+// and another if the unmodded APK is to be backed up.
+fn estimate_required_space(plan: &PatchPlan, app_info: &AppInfo, backup_apk: bool) -> Result<u64> {
+    let apk_size = std::fs::metadata(&app_info.path)
+        .context("Getting APK size")?
+        .len();
+    let apk_copies = if backup_apk { 2 } else { 1 };
+
+    Ok(plan.estimated_bytes
+        + apk_copies * apk_size
+        + plan.downloads_needed.len() as u64 * DOWNLOAD_SPACE_ALLOWANCE)
+}
+
+/// Handles `RestoreStockApk` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `StockApkRestored`)
+pub(super) fn handle_restore_stock_apk() -> Result<Response> {
+    let result = patching::restore_stock_apk();
+    // If reinstalling failed part way through, the OBB files may still be stashed.
+    if result.is_err() {
+        if let Err(err) = patching::recover_orphaned_obb() {
+            warn!("Failed to restore stashed OBB files: {err:?}");
+        }
+    }
+    let version = result?;
+
+    match patching::check_obb_matches_apk() {
+        Ok(ObbMatch::Mismatch { apk, obb }) => warn!(
+            "The OBB files are for version code {obb}, but the restored APK is version code {apk}. Reinstall the game from the store if it fails to load"
+        ),
+        Ok(ObbMatch::ObbMissing) => warn!("No OBB files were found after restoring the APK"),
+        Ok(ObbMatch::Match) => {}
+        Err(err) => warn!("Failed to check OBB files match the APK: {err:?}"),
+    }
+
+    Ok(Response::StockApkRestored { version })
+}
@@ -1,73 +1,740 @@
 //! Handles requests relating to some buttons in the options page of MBF.
 
-use std::path::Path;
+use std::{fs::File, path::Path};
 
-use crate::{data_fix, mod_man::ModManager, models::response::Response, patching, paths};
+use crate::{
+    backup, cancellation, crash_logs, custom_levels,
+    data_fix::{self, PlayerDataStatus},
+    loader_config, lock, logging, mod_config,
+    mod_man::{self, Abi, InstalledMod, ModManager},
+    models::{
+        request::RestoreSource,
+        response::{
+            BinaryIssue, ClearReport, ClearedCache, Conflict, DiagnosticsReport, FileAge,
+            InstallStatus, InstallationReport, LoaderConfig, ModModel, ObbMatch, PathCheck,
+            Response,
+        },
+    },
+    network,
+    patching::{self, PatchVerificationFailed},
+    paths, storage, AppState,
+};
 use anyhow::{anyhow, Context, Result};
 use log::{debug, info, warn};
+use mbf_zip::ZipFile;
 
-/// Handles `QuickFix` [Requests](requests::Request).
+/// Handles `QuickFix` [Requests](crate::models::request::Request).
 ///
 /// # Returns
-/// The [Response](requests::Response) to the request (variant `Mods`)
+/// The [Response](crate::models::response::Response) to the request (variant `QuickFixed`)
 pub(super) fn handle_quick_fix(
     override_core_mod_url: Option<String>,
     wipe_existing_mods: bool,
 ) -> Result<Response> {
+    let report = quick_fix(override_core_mod_url, wipe_existing_mods)?;
+    Ok(Response::QuickFixed {
+        installed_mods: report.installed_mods,
+        repairs: report.repairs,
+        binary_issues: report.binary_issues,
+    })
+}
+
+/// The outcome of [quick_fix].
+pub(super) struct QuickFixReport {
+    pub installed_mods: Vec<ModModel>,
+    /// A description of each repair that was carried out.
+    pub repairs: Vec<String>,
+    /// Problems with installed mod binaries that could not be repaired.
+    pub binary_issues: Vec<BinaryIssue>,
+}
+
+/// Reinstalls any missing core mods and the modloader, and repairs the permissions and binaries of installed mods,
+/// without modifying the APK.
+pub(super) fn quick_fix(
+    override_core_mod_url: Option<String>,
+    wipe_existing_mods: bool,
+) -> Result<QuickFixReport> {
     let app_info = super::mod_status::get_app_info()?
         .ok_or(anyhow!("Cannot quick fix when app is not installed"))?;
     let res_cache = crate::load_res_cache()?;
+    let mut repairs = Vec::new();
+    // Checked first, as loading mods will also create the file.
+    if patching::ensure_nomedia()? {
+        repairs.push("Recreated .nomedia file".to_string());
+    }
+
+    let game_abi = patching::get_apk_abi(&ZipFile::open(
+        std::fs::File::open(&app_info.path).context("Opening APK")?,
+    )?);
 
-    let mut mod_manager = ModManager::new(app_info.version.clone(), &res_cache);
+    let mut mod_manager = ModManager::new(app_info.version.clone(), &res_cache)?;
     if wipe_existing_mods {
         info!("Wiping all existing mods");
         mod_manager
             .wipe_all_mods()
             .context("Wiping existing mods")?;
+        repairs.push("Removed all existing mods".to_string());
     }
     mod_manager.load_mods()?; // Should load no mods.
 
-    // Reinstall missing core mods and overwrite the modloader with the one contained within the executable.
-    super::install_core_mods(
+    // Reinstall missing core mods and replace the modloader if it is missing or doesn't match the one contained within the executable.
+    let reinstalled_core_mods = super::install_core_mods(
         &res_cache,
         &mut mod_manager,
         app_info,
         override_core_mod_url,
     )?;
-    patching::install_modloader()?;
-    Ok(Response::Mods {
+    repairs.extend(
+        reinstalled_core_mods
+            .into_iter()
+            .map(|id| format!("Installed core mod {id}")),
+    );
+
+    match patching::get_modloader_status()? {
+        InstallStatus::Ready => {}
+        InstallStatus::NeedUpdate => {
+            patching::install_modloader()?;
+            repairs.push("Replaced outdated or corrupt modloader".to_string());
+        }
+        InstallStatus::Missing => {
+            patching::install_modloader()?;
+            repairs.push("Reinstalled missing modloader".to_string());
+        }
+    }
+
+    // Libraries left behind by removed mods are not needed and may take up a lot of space.
+    let pruned_libs = mod_manager
+        .prune_orphaned_libs()
+        .context("Removing unused libraries")?;
+    if !pruned_libs.is_empty() {
+        info!("Removed {} unused libraries", pruned_libs.len());
+        repairs.push(format!("Removed {} unused libraries", pruned_libs.len()));
+    }
+
+    // Files that the game cannot read cause mods to silently fail to load.
+    patching::repair_moddata_permissions().context("Repairing ModData permissions")?;
+
+    // Corrupted or wrong-ABI binaries are otherwise only noticed when the game crashes on launch.
+    let binary_issues = match game_abi {
+        Some(abi) => repair_mod_binaries(&mut mod_manager, abi, &mut repairs)?,
+        None => {
+            warn!("Could not work out the ABI of the game, so mod binaries were not checked");
+            Vec::new()
+        }
+    };
+
+    for repair in &repairs {
+        info!("Quick fix: {repair}");
+    }
+    Ok(QuickFixReport {
         installed_mods: super::mod_management::get_mod_models(mod_manager)?,
+        repairs,
+        binary_issues,
     })
 }
 
-/// Handles `FixPlayerData` [Requests](requests::Request).
+// Checks the binaries of each installed mod, copying the mod's files again if any are invalid.
+// Each mod that was repaired is added to `repairs`.
+// Gives the issues that copying the files again did not fix, which means that the extracted QMOD itself is invalid.
+fn repair_mod_binaries(
+    mod_manager: &mut ModManager,
+    abi: Abi,
+    repairs: &mut Vec<String>,
+) -> Result<Vec<BinaryIssue>> {
+    mod_manager.check_mods_installed()?;
+    let installed_ids: Vec<String> = mod_manager
+        .get_mods()
+        .map(|mod_rc| mod_rc.borrow())
+        .filter(|mod_ref| mod_ref.installed())
+        .map(|mod_ref| mod_ref.manifest().id.clone())
+        .collect();
+
+    let mut remaining_issues = Vec::new();
+    for id in installed_ids {
+        if mod_manager.verify_mod_binaries(&id, abi)?.is_empty() {
+            continue;
+        }
+
+        match mod_manager.repair_mod(&id) {
+            Ok(_) => {
+                let issues = mod_manager.verify_mod_binaries(&id, abi)?;
+                if issues.is_empty() {
+                    repairs.push(format!("Replaced invalid binaries of {id}"));
+                }
+                remaining_issues.extend(issues);
+            }
+            Err(err) => {
+                warn!("Failed to repair {id}: {err:?}");
+                remaining_issues.extend(mod_manager.verify_mod_binaries(&id, abi)?);
+            }
+        }
+    }
+
+    Ok(remaining_issues)
+}
+
+/// Handles `FixPlayerData` [Requests](crate::models::request::Request).
 ///
 /// # Returns
-/// The [Response](requests::Response) to the request (variant `FixedPlayerData`)
+/// The [Response](crate::models::response::Response) to the request (variant `FixedPlayerData`)
 pub(super) fn handle_fix_player_data() -> Result<Response> {
     patching::kill_app()?; // Kill app, in case it's still stuck in a hanging state
 
     let mut did_work = false;
-    if Path::new(paths::DATAKEEPER_PLAYER_DATA).exists() {
-        info!("Fixing color scheme issues");
-        data_fix::fix_colour_schemes(paths::DATAKEEPER_PLAYER_DATA)?;
-        did_work = true;
-    }
-
-    if Path::new(paths::PLAYER_DATA).exists() {
-        info!("Backing up player data");
-        patching::backup_player_data()?;
+    let datakeeper = data_fix::datakeeper_status();
+    if Path::new(paths::default().player_data()).exists() {
+        // Backing up corrupt player data could overwrite a good backup, so this is skipped.
+        match data_fix::validate_player_data(paths::default().player_data()) {
+            PlayerDataStatus::Valid => {
+                info!("Backing up player data");
+                patching::backup_player_data(false)?;
+                // Datakeeper's copy replaces the game's on the next launch, so is only used to recover from if it is newer.
+                if datakeeper.player_data_exists
+                    && datakeeper.compared_to_live != Some(FileAge::Newer)
+                {
+                    info!("Replacing older datakeeper PlayerData.dat with the game's");
+                    std::fs::copy(
+                        paths::default().player_data(),
+                        paths::default().datakeeper_player_data(),
+                    )
+                    .context("Updating datakeeper player data")?;
+                }
+            }
+            status => warn!("Not backing up PlayerData.dat as it was {status}"),
+        }
 
         info!("Removing (potentially faulty) PlayerData.dat in game files");
-        debug!("(removing {})", paths::PLAYER_DATA);
-        std::fs::remove_file(paths::PLAYER_DATA).context("Deleting faulty player data")?;
-        if Path::new(paths::PLAYER_DATA_BAK).exists() {
-            std::fs::remove_file(paths::PLAYER_DATA_BAK)?;
+        debug!("(removing {})", paths::default().player_data());
+        std::fs::remove_file(paths::default().player_data())
+            .context("Deleting faulty player data")?;
+        if Path::new(paths::default().player_data_bak()).exists() {
+            std::fs::remove_file(paths::default().player_data_bak())?;
         }
         did_work = true;
     } else {
         warn!("No player data found to \"fix\"");
     }
 
+    let datakeeper_data_status =
+        data_fix::validate_player_data(paths::default().datakeeper_player_data());
+    if datakeeper_data_status == PlayerDataStatus::Valid {
+        info!("Fixing color scheme issues");
+        data_fix::fix_colour_schemes(paths::default().datakeeper_player_data())?;
+        did_work = true;
+        if !datakeeper.installed {
+            warn!("Datakeeper is not installed, so its PlayerData.dat will not be copied to the game until it is");
+        }
+    } else if datakeeper_data_status != PlayerDataStatus::Missing {
+        warn!("Datakeeper PlayerData.dat was {datakeeper_data_status}");
+    }
+
+    // If the datakeeper player data was lost or corrupted, fall back to the backup made by MBF.
+    if datakeeper_data_status != PlayerDataStatus::Valid
+        && data_fix::validate_player_data(paths::default().aux_data_backup())
+            == PlayerDataStatus::Valid
+    {
+        info!(
+            "Restoring player data from {}",
+            paths::default().aux_data_backup()
+        );
+        patching::restore_player_data(RestoreSource::AuxBackup, true)?;
+        did_work = true;
+    }
+
     Ok(Response::FixedPlayerData { existed: did_work })
 }
+
+/// Handles `RestorePlayerData` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `RestoredPlayerData`)
+pub(super) fn handle_restore_player_data(
+    source: RestoreSource,
+    overwrite_newer: bool,
+) -> Result<Response> {
+    patching::kill_app()?; // The game may overwrite the restored data if left running
+
+    let restored = patching::restore_player_data(source, overwrite_newer)?;
+    Ok(Response::RestoredPlayerData { restored })
+}
+
+/// Handles `GetLogs` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `Logs`)
+pub(super) fn handle_get_logs() -> Result<Response> {
+    Ok(Response::Logs {
+        entries: logging::take_log_snapshot(),
+    })
+}
+
+/// Handles `GetCrashLogs` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `CrashLogs`)
+pub(super) fn handle_get_crash_logs(max: usize) -> Result<Response> {
+    Ok(Response::CrashLogs {
+        files: crash_logs::collect_crash_logs(max)?,
+    })
+}
+
+/// Handles `Cancel` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `CancelRequested`)
+pub(super) fn handle_cancel() -> Result<Response> {
+    // If the lock can be taken, nothing is running, and a marker would only cancel the next request.
+    if lock::try_acquire()?.is_some() {
+        info!("No operation is running, so there is nothing to cancel");
+    } else {
+        info!("Asking the running operation to stop");
+        cancellation::request_cancel()?;
+    }
+
+    Ok(Response::CancelRequested)
+}
+
+/// Handles `GetLoaderConfig` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `LoaderConfig`)
+pub(super) fn handle_get_loader_config() -> Result<Response> {
+    Ok(Response::LoaderConfig(loader_config::get_loader_config()?))
+}
+
+/// Handles `SetLoaderConfig` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `LoaderConfig`)
+pub(super) fn handle_set_loader_config(config: LoaderConfig) -> Result<Response> {
+    loader_config::set_loader_config(&config)?;
+    info!("Updated modloader config");
+    Ok(Response::LoaderConfig(config))
+}
+
+/// Handles `GetModConfig` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `ModConfig`)
+pub(super) fn handle_get_mod_config(id: String) -> Result<Response> {
+    let config = mod_config::read_mod_config(&id)?;
+    Ok(Response::ModConfig { id, config })
+}
+
+/// Handles `SetModConfig` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `ModConfig`)
+pub(super) fn handle_set_mod_config(id: String, config: String) -> Result<Response> {
+    let config = mod_config::write_mod_config(&id, &config)?;
+    Ok(Response::ModConfig {
+        id,
+        config: Some(config),
+    })
+}
+
+/// Handles `NetworkPreflight` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `NetworkStatus`)
+pub(super) fn handle_network_preflight() -> Result<Response> {
+    Ok(Response::NetworkStatus(network::network_preflight()))
+}
+
+/// Handles `GetStorageUsage` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `StorageUsage`)
+pub(super) fn handle_get_storage_usage() -> Result<Response> {
+    Ok(Response::StorageUsage(storage::storage_usage()))
+}
+
+/// Handles `ExportModData` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `ModDataExported`)
+pub(super) fn handle_export_mod_data(to_path: String) -> Result<Response> {
+    backup::export_moddata(Path::new(&to_path))?;
+    Ok(Response::ModDataExported {
+        bytes: std::fs::metadata(&to_path)
+            .context("Getting size of backup")?
+            .len(),
+    })
+}
+
+/// Handles `ImportModData` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `Mods`)
+pub(super) fn handle_import_mod_data(from_path: String) -> Result<Response> {
+    backup::import_moddata(Path::new(&from_path))?;
+
+    let res_cache = crate::load_res_cache()?;
+    let mut mod_manager = ModManager::new(super::get_app_version_only()?, &res_cache)?;
+    mod_manager.load_mods().context("Loading restored mods")?;
+    Ok(Response::Mods {
+        installed_mods: super::mod_management::get_mod_models(mod_manager)?,
+    })
+}
+
+/// The caches to delete the contents of in [clear_caches].
+pub(super) struct CacheSelection {
+    pub downloads: bool,
+    pub temp: bool,
+    pub res_cache: bool,
+    pub songcore: bool,
+}
+
+/// Handles `ClearCaches` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `CachesCleared`)
+pub(super) fn handle_clear_caches(which: CacheSelection) -> Result<Response> {
+    Ok(Response::CachesCleared(clear_caches(which)))
+}
+
+/// Deletes the contents of the selected caches, but not the cache folders themselves.
+/// None of the caches contain mods, custom levels or player data, so these are never removed.
+/// The uploads folder is within the downloads cache, but is kept so that uploads in progress can still be resumed.
+///
+/// Any file that cannot be deleted is skipped with a warning, so the report only counts the files that were removed.
+/// This must only be called while holding the [agent lock](crate::lock), so that no other request is using the caches.
+pub(super) fn clear_caches(which: CacheSelection) -> ClearReport {
+    let paths = paths::default();
+    let keep = [Path::new(paths.uploads())];
+    let mut caches = Vec::new();
+    for (selected, name, path) in [
+        (which.downloads, "downloads", paths.mbf_downloads()),
+        (which.temp, "temp", paths.temp()),
+        (which.res_cache, "res_cache", paths.res_cache()),
+    ] {
+        if !selected {
+            continue;
+        }
+
+        let mut cleared = ClearedCache {
+            name: name.to_string(),
+            path: path.to_string(),
+            files_removed: 0,
+            bytes_freed: 0,
+        };
+        if Path::new(path).exists() {
+            if let Err(err) = remove_contents(Path::new(path), &keep, &mut cleared) {
+                warn!("Failed to clear {name} cache: {err:?}");
+            }
+        }
+        info!(
+            "Cleared {name} cache: removed {} files, freeing {} bytes",
+            cleared.files_removed, cleared.bytes_freed
+        );
+        caches.push(cleared);
+    }
+
+    if which.songcore {
+        let path = paths.songcore_cache();
+        let mut cleared = ClearedCache {
+            name: "songcore".to_string(),
+            path: path.to_string(),
+            files_removed: 0,
+            bytes_freed: 0,
+        };
+        let cache_len = std::fs::metadata(path).map(|metadata| metadata.len());
+        match custom_levels::clear_songcore_cache() {
+            Ok(()) => {
+                if let Ok(cache_len) = cache_len {
+                    cleared.files_removed = 1;
+                    cleared.bytes_freed = cache_len;
+                }
+                info!("Cleared SongCore cache");
+            }
+            Err(err) => warn!("Failed to clear SongCore cache: {err:?}"),
+        }
+        caches.push(cleared);
+    }
+
+    ClearReport { caches }
+}
+
+// Deletes everything within `dir` apart from the paths in `keep`, adding each deleted file to `cleared`.
+fn remove_contents(dir: &Path, keep: &[&Path], cleared: &mut ClearedCache) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if keep.contains(&path.as_path()) {
+            continue;
+        }
+
+        let metadata = std::fs::symlink_metadata(&path)?;
+        if metadata.is_dir() {
+            remove_contents(&path, keep, cleared)?;
+            if let Err(err) = std::fs::remove_dir(&path) {
+                warn!("Failed to remove {path:?}: {err}");
+            }
+        } else {
+            match std::fs::remove_file(&path) {
+                Ok(_) => {
+                    cleared.files_removed += 1;
+                    cleared.bytes_freed += metadata.len();
+                }
+                Err(err) => warn!("Failed to remove {path:?}: {err}"),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles `VerifyInstallation` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `InstallationReport`)
+pub(super) fn handle_verify_installation() -> Result<Response> {
+    let game_version = super::get_app_version_only()?;
+    Ok(Response::InstallationReport(verify_installation(
+        &game_version,
+    )))
+}
+
+/// Checks every part of the modded game, without repairing anything.
+/// Failures are recorded in the report rather than returned, so that a check failing does not stop the others.
+pub(super) fn verify_installation(game_version: &str) -> InstallationReport {
+    let mut errors = Vec::new();
+
+    let modded = match record_failure(
+        &mut errors,
+        "checking app state",
+        crate::app_install_state(crate::APK_ID),
+    ) {
+        Some(AppState::NotInstalled) => {
+            errors.push("Beat Saber is not installed".to_string());
+            None
+        }
+        Some(AppState::Installed { .. }) => Some(false),
+        Some(AppState::InstalledModded { .. }) => Some(true),
+        None => None,
+    };
+
+    let game_abi = record_failure(&mut errors, "reading APK", installed_apk()).and_then(|apk| {
+        record_failure(
+            &mut errors,
+            "choosing ABI",
+            patching::choose_patch_abi(&apk),
+        )
+    });
+    let mut patch_issues = Vec::new();
+    if modded == Some(true) {
+        if let Some(abi) = game_abi {
+            // The libil2cpp.so of the unmodded APK is not known, so it is not checked.
+            match crate::get_apk_path().and_then(|apk_path| {
+                let apk_path = apk_path.ok_or(anyhow!("Beat Saber is not installed"))?;
+                patching::verify_patched(Path::new(&apk_path), abi, true, None)
+            }) {
+                Ok(()) => {}
+                Err(err) => match err.downcast::<PatchVerificationFailed>() {
+                    Ok(failed) => patch_issues = failed.missing,
+                    Err(err) => errors.push(format!("Failed verifying patched APK: {err:#}")),
+                },
+            }
+        }
+    }
+
+    let modloader_version = patching::modloader_version();
+    let modloader_status = record_failure(
+        &mut errors,
+        "checking modloader",
+        patching::get_modloader_status(),
+    );
+    let mods = record_failure(
+        &mut errors,
+        "checking mods",
+        verify_mods(game_version, game_abi),
+    );
+    let obb = record_failure(
+        &mut errors,
+        "checking OBB files",
+        patching::check_obb_matches_apk(),
+    );
+
+    let (missing_core_mods, binary_issues, conflicts) = match mods {
+        Some(mods) => (
+            record_failure(&mut errors, "checking core mods", mods.missing_core_mods),
+            mods.binary_issues,
+            mods.conflicts,
+        ),
+        None => (None, Vec::new(), Vec::new()),
+    };
+
+    let healthy = errors.is_empty()
+        && modded == Some(true)
+        && patch_issues.is_empty()
+        && matches!(modloader_status, Some(InstallStatus::Ready))
+        && missing_core_mods.as_ref().is_some_and(Vec::is_empty)
+        && binary_issues.is_empty()
+        && conflicts.is_empty()
+        && obb == Some(ObbMatch::Match);
+    if healthy {
+        info!("Installation is healthy");
+    } else {
+        warn!("Installation has problems");
+    }
+
+    InstallationReport {
+        healthy,
+        game_version: game_version.to_string(),
+        modded,
+        patch_issues,
+        modloader_status,
+        modloader_release: modloader_version
+            .as_deref()
+            .and_then(patching::find_modloader_release),
+        modloader_version,
+        missing_core_mods,
+        binary_issues,
+        conflicts,
+        obb,
+        errors,
+    }
+}
+
+// The findings of [verify_mods].
+struct ModsVerification {
+    // An `Err` if the core mod index could not be fetched, or has no core mods for the game version.
+    missing_core_mods: Result<Vec<String>>,
+    binary_issues: Vec<BinaryIssue>,
+    conflicts: Vec<Conflict>,
+}
+
+// Checks the installed mods for missing core mods, invalid binaries and conflicts.
+// Binaries are only checked if the ABI of the game is known.
+fn verify_mods(game_version: &str, game_abi: Option<Abi>) -> Result<ModsVerification> {
+    let res_cache = crate::load_res_cache()?;
+    let mut mod_manager = ModManager::new(game_version.to_string(), &res_cache)?;
+    mod_manager.load_mods().context("Loading installed mods")?;
+    mod_manager.check_mods_installed()?;
+
+    let loaded_mods: Vec<InstalledMod> = mod_manager
+        .get_mods()
+        .map(|mod_rc| InstalledMod::from(&*mod_rc.borrow()))
+        .collect();
+    let conflicts = mod_man::detect_conflicts(&loaded_mods);
+
+    let mut binary_issues = Vec::new();
+    if let Some(abi) = game_abi {
+        let installed_ids: Vec<String> = mod_manager
+            .get_mods()
+            .map(|mod_rc| mod_rc.borrow())
+            .filter(|mod_ref| mod_ref.installed())
+            .map(|mod_ref| mod_ref.manifest().id.clone())
+            .collect();
+        for id in installed_ids {
+            binary_issues.extend(mod_manager.verify_mod_binaries(&id, abi)?);
+        }
+    }
+
+    let missing_core_mods = mbf_res_man::external_res::fetch_core_mods(&res_cache, None)
+        .context("Fetching core mod index")
+        .and_then(|core_mods| {
+            let core_mods = core_mods.get(game_version).ok_or(anyhow!(
+                "No core mods are available for Beat Saber {game_version}"
+            ))?;
+            super::mod_status::get_core_mods_install_status(&core_mods.mods, &mod_manager)
+                .map(|(_, missing)| missing)
+        });
+
+    Ok(ModsVerification {
+        missing_core_mods,
+        binary_issues,
+        conflicts,
+    })
+}
+
+// Opens the installed APK.
+fn installed_apk() -> Result<ZipFile<File>> {
+    let apk_path = crate::get_apk_path()?.ok_or(anyhow!("Beat Saber is not installed"))?;
+    ZipFile::open(File::open(apk_path).context("Opening APK")?).context("Reading APK as ZIP")
+}
+
+// Gives the value of `result`, or records that the check it came from failed and gives `None`.
+fn record_failure<T>(errors: &mut Vec<String>, check: &str, result: Result<T>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(err) => {
+            warn!("Failed {check}: {err:?}");
+            errors.push(format!("Failed {check}: {err:#}"));
+            None
+        }
+    }
+}
+
+/// Handles `RunDiagnostics` [Requests](crate::models::request::Request).
+///
+/// # Returns
+/// The [Response](crate::models::response::Response) to the request (variant `Diagnostics`)
+pub(super) fn handle_run_diagnostics() -> Result<Response> {
+    Ok(Response::Diagnostics(run_diagnostics()))
+}
+
+/// Checks that each of the paths used by MBF can be written to, and gathers details of the installed app.
+/// Failures are recorded in the report rather than returned, so that as much information as possible is gathered.
+pub(super) fn run_diagnostics() -> DiagnosticsReport {
+    let game_version = match super::get_app_version_only() {
+        Ok(version) => Some(version),
+        Err(err) => {
+            warn!("Could not get game version: {err}");
+            None
+        }
+    };
+
+    let mut paths_to_check = vec![
+        (
+            "modloader_dir",
+            paths::default().modloader_dir().to_string(),
+        ),
+        (
+            "custom_levels",
+            paths::default().custom_levels().to_string(),
+        ),
+        (
+            "mbf_downloads",
+            paths::default().mbf_downloads().to_string(),
+        ),
+        ("temp", paths::default().temp().to_string()),
+        (
+            "android_app_files",
+            paths::default().android_app_files().to_string(),
+        ),
+    ];
+    if let Some(version) = &game_version {
+        match paths::default().qmods_dir_for_version(version) {
+            Ok(qmods_dir) => paths_to_check.insert(0, ("qmods", qmods_dir)),
+            Err(err) => warn!("Could not get QMODs directory: {err}"),
+        }
+    }
+
+    let paths = paths_to_check
+        .into_iter()
+        .map(|(name, path)| {
+            let probe_result = paths::probe_writable(&path);
+            if let Err(err) = &probe_result {
+                warn!("{name} ({path}) is not writable: {err:?}");
+            }
+
+            PathCheck {
+                name: name.to_string(),
+                free_space: storage::available_space(&path).ok(),
+                writable: probe_result.is_ok(),
+                error: probe_result.err().map(|err| format!("{err:#}")),
+                path,
+            }
+        })
+        .collect();
+
+    DiagnosticsReport {
+        apk_id: crate::APK_ID.to_string(),
+        game_version,
+        paths,
+        tmp_root: paths::tmp_root_status(),
+        datakeeper: data_fix::datakeeper_status(),
+        songcore_cache: custom_levels::songcore_cache_status(),
+    }
+}
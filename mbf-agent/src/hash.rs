@@ -0,0 +1,33 @@
+//! Computes the SHA-256 hashes used to check downloaded and installed files.
+//!
+//! Data is hashed as it is read, so that hashing large files such as the APK doesn't require loading them into memory.
+
+use std::{fs::File, io::Read, path::Path};
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+/// Calculates the SHA-256 hash of the file at the given path.
+///
+/// # Returns
+/// The hash as a lowercase hex string.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let file = File::open(path).with_context(|| format!("Opening {path:?} to hash"))?;
+    sha256_reader(file).with_context(|| format!("Hashing {path:?}"))
+}
+
+/// Calculates the SHA-256 hash of all of the data read from `r`, until it reaches EOF.
+///
+/// # Returns
+/// The hash as a lowercase hex string.
+pub fn sha256_reader(mut r: impl Read) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut r, &mut hasher).context("Reading data to hash")?;
+
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Converts the given bytes into a lowercase hex string.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
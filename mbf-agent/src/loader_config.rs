@@ -0,0 +1,36 @@
+//! Reads and writes the modloader's configuration file, which controls how verbose its logging is and whether the game
+//! waits for a debugger to attach when it is launched.
+//! This gives mod developers a way to change these settings without editing the file by hand over ADB.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use log::info;
+
+use crate::{atomic, models::response::LoaderConfig, paths};
+
+/// Gets the modloader's configuration, creating the configuration file with the default settings if it does not exist.
+pub fn get_loader_config() -> Result<LoaderConfig> {
+    let config_path = Path::new(paths::default().loader_config());
+    if !config_path.exists() {
+        info!("Creating modloader config with default settings");
+        let config = LoaderConfig::default();
+        set_loader_config(&config)?;
+        return Ok(config);
+    }
+
+    serde_json::from_slice(&std::fs::read(config_path).context("Reading modloader config")?)
+        .context("Parsing modloader config")
+}
+
+/// Saves the modloader's configuration, replacing the existing settings.
+/// The new settings are used the next time the game is launched.
+pub fn set_loader_config(config: &LoaderConfig) -> Result<()> {
+    std::fs::create_dir_all(paths::default().modloader_dir())
+        .context("Creating modloader directory")?;
+    atomic::write_atomic(
+        paths::default().loader_config(),
+        &serde_json::to_vec_pretty(config)?,
+    )
+    .context("Writing modloader config")
+}
@@ -0,0 +1,42 @@
+//! Prevents two agent processes from modifying the game or its mods at the same time.
+//!
+//! If the frontend sends two requests at almost the same time, they are handled by separate agent processes
+//! that would otherwise race on the temporary files, the mod folders and the APK.
+//! Requests that modify anything therefore take an exclusive `flock` on [crate::paths::Paths::agent_lock] first.
+
+use std::{fs::File, os::fd::AsRawFd, path::Path};
+
+use anyhow::{Context, Result};
+
+use crate::paths;
+
+/// Holds the agent lock. The lock is released when this is dropped, including while unwinding from a panic.
+/// The OS also releases the lock if the agent process is killed.
+pub struct AgentLock {
+    // Closing the file releases the lock.
+    _file: File,
+}
+
+/// Attempts to take the agent lock, without waiting if another process holds it.
+///
+/// # Returns
+/// The lock, or `None` if another agent process already holds it.
+pub fn try_acquire() -> Result<Option<AgentLock>> {
+    let lock_path = Path::new(paths::default().agent_lock());
+    if let Some(parent) = lock_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let file = File::create(lock_path).context("Opening agent lock file")?;
+
+    // SAFETY: The file descriptor is valid for as long as `file` is open.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(Some(AgentLock { _file: file }));
+    }
+
+    let err = std::io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::EWOULDBLOCK) {
+        Ok(None)
+    } else {
+        Err(err).context("Locking agent lock file")
+    }
+}
@@ -0,0 +1,102 @@
+//! Keeps a record of the most recent log messages so that the frontend can retrieve them, e.g. to let users copy diagnostics
+//! when reporting a problem.
+//!
+//! The agent is started afresh for every request, so the buffer is saved to [crate::paths::Paths::agent_log] once a request is complete
+//! and loaded again when the next request starts.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::{Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    atomic,
+    models::response::{LogEntry, LogLevel},
+    paths,
+};
+
+/// The maximum number of entries kept in the buffer. Once full, the oldest entries are discarded.
+pub const MAX_LOG_ENTRIES: usize = 1000;
+
+static LOG_BUFFER: Mutex<VecDeque<LogEntry>> = Mutex::new(VecDeque::new());
+
+// Locks the buffer, ignoring poisoning since a panic while logging shouldn't prevent future logging.
+fn lock_buffer() -> MutexGuard<'static, VecDeque<LogEntry>> {
+    LOG_BUFFER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn push_bounded(buffer: &mut VecDeque<LogEntry>, entry: LogEntry) {
+    if buffer.len() >= MAX_LOG_ENTRIES {
+        buffer.pop_front();
+    }
+    buffer.push_back(entry);
+}
+
+/// Adds an entry to the log buffer.
+///
+/// # Arguments
+/// * `level` - The level of the message.
+/// * `stage` - The part of MBF that the message came from, normally the module path, e.g. `mbf_agent::patching`.
+/// * `message` - The message to record.
+pub fn record(level: LogLevel, stage: &str, message: String) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as u64)
+        .unwrap_or(0);
+
+    push_bounded(
+        &mut lock_buffer(),
+        LogEntry {
+            timestamp,
+            level,
+            stage: stage.to_string(),
+            message,
+        },
+    );
+}
+
+/// Gets a copy of all entries currently in the log buffer, oldest first.
+pub fn take_log_snapshot() -> Vec<LogEntry> {
+    lock_buffer().iter().cloned().collect()
+}
+
+/// Loads the entries saved by the previous request into the buffer, before any entries logged so far.
+/// Does nothing if no log has been saved yet.
+pub fn load_saved_log() -> Result<()> {
+    let log_path = Path::new(paths::default().agent_log());
+    if !log_path.exists() {
+        return Ok(());
+    }
+
+    let saved: Vec<LogEntry> = serde_json::from_reader(BufReader::new(
+        File::open(log_path).context("Opening saved log")?,
+    ))
+    .context("Parsing saved log")?;
+
+    let mut buffer = lock_buffer();
+    let current: Vec<LogEntry> = buffer.drain(..).collect();
+    for entry in saved.into_iter().chain(current) {
+        push_bounded(&mut buffer, entry);
+    }
+
+    Ok(())
+}
+
+/// Saves the contents of the log buffer so that it can be loaded by the next request.
+pub fn save_log() -> Result<()> {
+    let log_path = Path::new(paths::default().agent_log());
+    if let Some(parent) = log_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let snapshot = take_log_snapshot();
+    atomic::write_atomic(log_path, &serde_json::to_vec(&snapshot)?).context("Writing saved log")
+}
@@ -1,17 +1,37 @@
+mod atomic;
 mod axml;
+mod backup;
+mod cancellation;
+mod crash_logs;
+mod custom_levels;
 mod data_fix;
 mod downloads;
+mod error;
 mod handlers;
+mod hash;
+mod loader_config;
+mod lock;
+mod logging;
 mod manifest;
+mod mod_config;
 mod mod_man;
 mod models;
+mod network;
 mod patching;
 mod paths;
+mod playlists;
+mod storage;
+#[cfg(test)]
+mod test_utils;
+mod timings;
+mod uploads;
 
 use anyhow::{Context, Result};
 use downloads::DownloadConfig;
-use log::{debug, error, warn, Level};
-use mbf_res_man::res_cache::ResCache;
+use log::{debug, error, info, warn, Level};
+use manifest::ManifestInfo;
+use mbf_res_man::{res_cache::ResCache, retry::RetryConfig, version::BsVersion};
+use mbf_zip::ZipFile;
 use models::{request, response};
 use serde::{Deserialize, Serialize};
 use std::{
@@ -25,22 +45,47 @@ use std::{
 /// The ID of the APK file that MBF manages.
 pub const APK_ID: &str = "com.beatgames.beatsaber";
 
-#[cfg(feature = "request_timing")]
-use log::info;
 #[cfg(feature = "request_timing")]
 use std::time::Instant;
 
+/// The outcome of attempting to delete one of the legacy directories no longer used by MBF.
+pub enum PurgeResult {
+    /// The directory existed and was deleted.
+    Removed,
+    /// The directory did not exist, so there was nothing to delete.
+    NotPresent,
+    /// The directory existed but could not be deleted.
+    Failed { error: std::io::Error },
+}
+
 /// Attempts to delete legacy directories no longer used by MBF to free up space
 /// Logs on failure
-pub fn try_delete_legacy_dirs() {
-    for dir in paths::LEGACY_DIRS {
-        if Path::new(dir).exists() {
+///
+/// The legacy QMODs directory is not included, as the mods within it are instead migrated when mods are loaded.
+///
+/// # Returns
+/// The outcome for each directory in [paths::Paths::legacy_dirs], in the same order.
+pub fn purge_legacy_dirs() -> Vec<PurgeResult> {
+    let mut results = Vec::new();
+    for dir in paths::default().legacy_dirs() {
+        let result = if Path::new(dir).exists() {
             match std::fs::remove_dir_all(dir) {
-                Ok(_) => debug!("Successfully removed legacy dir {dir}"),
-                Err(err) => warn!("Failed to remove legacy dir {dir}: {err}"),
+                Ok(_) => PurgeResult::Removed,
+                Err(error) => PurgeResult::Failed { error },
             }
+        } else {
+            PurgeResult::NotPresent
+        };
+
+        match &result {
+            PurgeResult::Removed => debug!("Successfully removed legacy dir {dir}"),
+            PurgeResult::Failed { error } => warn!("Failed to remove legacy dir {dir}: {error}"),
+            PurgeResult::NotPresent => {}
         }
+        results.push(result);
     }
+
+    results
 }
 
 static DOWNLOAD_CFG: sync::OnceLock<DownloadConfig> = sync::OnceLock::new();
@@ -49,29 +94,71 @@ static DOWNLOAD_CFG: sync::OnceLock<DownloadConfig> = sync::OnceLock::new();
 pub fn get_dl_cfg() -> &'static DownloadConfig<'static> {
     DOWNLOAD_CFG.get_or_init(|| {
         DownloadConfig {
-            max_disconnections: 10,
+            retry: get_retry_cfg(),
             // If downloads data successfully for 10 seconds, reset disconnection attempts
             disconnection_reset_time: Some(std::time::Duration::from_secs_f32(10.0)),
-            disconnect_wait_time: std::time::Duration::from_secs_f32(5.0),
             progress_update_interval: Some(std::time::Duration::from_secs_f32(2.0)),
             ureq_agent: mbf_res_man::default_agent::get_agent(),
         }
     })
 }
 
+/// Gets the policy used to retry failed downloads and requests made by the resource cache.
+pub fn get_retry_cfg() -> RetryConfig {
+    RetryConfig {
+        max_attempts: 10,
+        on_retry: Some(report_retry),
+        ..Default::default()
+    }
+}
+
+// Lets the frontend know that a request is being retried, so that MBF doesn't appear to have hung.
+fn report_retry(url: &str, attempt: u32, delay: std::time::Duration) {
+    report_progress(response::ProgressEvent::new(
+        response::Stage::Downloading,
+        format!(
+            "Retrying {url} in {:.1}s (attempt {attempt} of {})",
+            delay.as_secs_f32(),
+            get_retry_cfg().max_attempts
+        ),
+    ));
+}
+
+/// Environment variable that, if set to `1`, makes the agent use only cached files instead of accessing the internet.
+const OFFLINE_VAR: &str = "MBF_OFFLINE";
+
+static OFFLINE: sync::OnceLock<bool> = sync::OnceLock::new();
+
+/// Gets whether the agent is in offline mode, in which all files must be served from the resource cache
+/// or the downloads directory.
+/// Offline mode is enabled by setting the `MBF_OFFLINE` environment variable to `1`.
+pub fn is_offline() -> bool {
+    *OFFLINE.get_or_init(|| std::env::var(OFFLINE_VAR).is_ok_and(|value| value == "1"))
+}
+
 /// Creates a ResCache for downloading files using mbf_res_man
 /// This should be reused where possible.
 pub fn load_res_cache() -> Result<ResCache<'static>> {
-    std::fs::create_dir_all(paths::RES_CACHE).expect("Failed to create resource cache folder");
-    Ok(ResCache::new(
-        paths::RES_CACHE.into(),
+    std::fs::create_dir_all(paths::default().res_cache())
+        .expect("Failed to create resource cache folder");
+    let mut res_cache = ResCache::new(
+        paths::default().res_cache().into(),
         mbf_res_man::default_agent::get_agent(),
-    ))
+    );
+    res_cache.set_offline(is_offline());
+    res_cache.set_retry_config(get_retry_cfg());
+    Ok(res_cache)
 }
 
 pub fn get_apk_path() -> Result<Option<String>> {
+    find_apk_path(APK_ID)
+}
+
+// Asks the package manager for the path of the APK of the app with the given ID.
+// Gives None if the app is not installed.
+fn find_apk_path(apk_id: &str) -> Result<Option<String>> {
     let pm_output = Command::new("pm")
-        .args(["path", APK_ID])
+        .args(["path", apk_id])
         .output()
         .context("Working out APK path")?;
     if 8 > pm_output.stdout.len() {
@@ -86,6 +173,101 @@ pub fn get_apk_path() -> Result<Option<String>> {
     }
 }
 
+/// Whether an app is installed, and whether it has been patched to support mods.
+pub enum AppState {
+    NotInstalled,
+    Installed {
+        version: String,
+    },
+    /// The APK contains a mod tag, or another indication that it has been patched.
+    InstalledModded {
+        version: String,
+    },
+}
+
+/// Works out whether the app with the given ID is installed and patched.
+///
+/// # Returns
+/// The state of the app, or an `Err` if it is installed but its APK could not be read.
+pub fn app_install_state(apk_id: &str) -> Result<AppState> {
+    let apk_path = match find_apk_path(apk_id)? {
+        Some(path) => path,
+        None => return Ok(AppState::NotInstalled),
+    };
+
+    let mut apk = ZipFile::open(std::fs::File::open(&apk_path).context("Opening APK")?)
+        .context("Reading APK as ZIP")?;
+    let version = ManifestInfo::read_from_apk(&mut apk)?.package_version;
+
+    Ok(match patching::get_modloader_installed(&mut apk)? {
+        Some(_) => AppState::InstalledModded { version },
+        None => AppState::Installed { version },
+    })
+}
+
+// The version of the installed APK, saved to [paths::Paths::game_version_cache] so that the APK need not be read
+// again until it changes.
+#[derive(Serialize, Deserialize)]
+struct CachedGameVersion {
+    apk_path: String,
+    // The modification time of the APK, in nanoseconds since the Unix epoch.
+    apk_modified: u128,
+    version: String,
+}
+
+/// Gets the version name of the installed Beat Saber APK, e.g. `1.37.0_9064817954`, as given in its manifest.
+/// The version is cached until the APK is replaced, so this is cheap to call repeatedly.
+///
+/// # Returns
+/// The version, or `None` if Beat Saber is not installed.
+pub fn installed_version_name() -> Result<Option<String>> {
+    let apk_path = match get_apk_path()? {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let apk_modified = std::fs::metadata(&apk_path)
+        .and_then(|metadata| metadata.modified())
+        .context("Getting APK modification time")?
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+
+    let cache_path = Path::new(paths::default().game_version_cache());
+    // A missing or unreadable cache just means that the APK must be read.
+    let cached = std::fs::read(cache_path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<CachedGameVersion>(&bytes).ok());
+    if let Some(cached) = cached {
+        if cached.apk_path == apk_path && cached.apk_modified == apk_modified {
+            return Ok(Some(cached.version));
+        }
+    }
+
+    let mut apk = ZipFile::open(std::fs::File::open(&apk_path).context("Opening APK")?)
+        .context("Reading APK as ZIP")?;
+    let version = ManifestInfo::read_from_apk(&mut apk)?.package_version;
+
+    let cached = CachedGameVersion {
+        apk_path,
+        apk_modified,
+        version,
+    };
+    if let Err(err) = std::fs::create_dir_all(cache_path.parent().expect("Cache has a parent"))
+        .map_err(anyhow::Error::from)
+        .and_then(|_| atomic::write_atomic(cache_path, &serde_json::to_vec(&cached)?))
+    {
+        warn!("Failed to cache game version: {err}");
+    }
+    Ok(Some(cached.version))
+}
+
+/// Gets the parsed version of the installed Beat Saber APK. See [installed_version_name].
+pub fn installed_game_version() -> Result<Option<BsVersion>> {
+    Ok(installed_version_name()?
+        .as_deref()
+        .map(mbf_res_man::version::parse_bs_version))
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 struct ModTag {
@@ -114,17 +296,21 @@ impl log::Log for ResponseLogger {
             None => return,
         }
 
+        let level = match record.level() {
+            Level::Debug => response::LogLevel::Debug,
+            Level::Info => response::LogLevel::Info,
+            Level::Warn => response::LogLevel::Warn,
+            Level::Error => response::LogLevel::Error,
+            Level::Trace => response::LogLevel::Trace,
+        };
+        let message = format!("{}", record.args());
+
+        #[cfg(feature = "log_stderr")]
+        eprintln!("[{}] {}: {message}", record.level(), record.target());
+
+        logging::record(level, record.target(), message.clone());
         // Ignore errors, logging should be infallible and we don't want to panic
-        let _result = write_response(response::Response::LogMsg {
-            message: format!("{}", record.args()),
-            level: match record.level() {
-                Level::Debug => response::LogLevel::Debug,
-                Level::Info => response::LogLevel::Info,
-                Level::Warn => response::LogLevel::Warn,
-                Level::Error => response::LogLevel::Error,
-                Level::Trace => response::LogLevel::Trace,
-            },
-        });
+        let _result = write_response(response::Response::LogMsg { message, level });
     }
 
     fn flush(&self) {
@@ -139,6 +325,12 @@ fn write_response(response: response::Response) -> Result<()> {
     Ok(())
 }
 
+/// Writes a [response::ProgressEvent] to stdout so that the frontend can display the progress of an operation.
+/// Failing to write the event is ignored, since progress updates are not essential.
+pub fn report_progress(event: response::ProgressEvent) {
+    let _result = write_response(response::Response::Progress(event));
+}
+
 static LOGGER: ResponseLogger = ResponseLogger {};
 
 fn main() -> Result<()> {
@@ -147,6 +339,29 @@ fn main() -> Result<()> {
 
     log::set_logger(&LOGGER).expect("Failed to set up logging");
     log::set_max_level(log::LevelFilter::Debug);
+    if let Err(err) = logging::load_saved_log() {
+        warn!("Failed to load log from previous requests: {err:?}");
+    }
+    let tmp_root = paths::tmp_root_status();
+    match &tmp_root.fallback_reason {
+        Some(reason) => warn!(
+            "Could not use {} for temporary files ({reason}), so using {} instead",
+            tmp_root.default_root, tmp_root.root
+        ),
+        None => debug!("Using {} for temporary files", tmp_root.root),
+    }
+
+    // A previous request may have been interrupted part way through patching, e.g. while the OBB files were moved out of the way.
+    // If another agent process holds the lock, it may still be patching, so its files must be left where they are.
+    match lock::try_acquire() {
+        Ok(Some(_lock)) => {
+            if let Some(action) = patching::recover_interrupted_patch() {
+                info!("Recovered from an interrupted patch: {action:?}");
+            }
+        }
+        Ok(None) => {}
+        Err(err) => error!("Failed to take agent lock: {err:?}"),
+    }
 
     let mut reader = BufReader::new(std::io::stdin());
     let mut line = String::new();
@@ -170,10 +385,17 @@ fn main() -> Result<()> {
 
                 write_response(resp)?;
             }
-            Err(err) => error!("{err:?}"),
+            Err(err) => {
+                error!("{err:?}");
+                write_response(error::error_response(&err))?;
+            }
         },
         Err(_) => {} // Panic will be outputted above
     };
 
+    if let Err(err) = logging::save_log() {
+        warn!("Failed to save log: {err:?}");
+    }
+
     Ok(())
 }
@@ -1,24 +1,62 @@
 //! Module containing convenience functions for modifying AndroidManifest.xml
 
-use std::io::{Read, Seek};
+use std::{
+    fs::File,
+    io::{Cursor, Read, Seek},
+    path::Path,
+};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use mbf_zip::ZipFile;
 
-use crate::axml::{AttributeValue, AxmlReader, Event};
+use crate::{
+    axml::{Attribute, AttributeValue, AxmlReader, AxmlWriter, Event, ResourceIds, ANDROID_NS_URI},
+    models::response::ManifestFlags,
+};
+
+// The names of the `android:` attributes of the `application` element that hold each of the [ManifestFlags].
+const DEBUGGABLE_ATTR: &str = "debuggable";
+const EXTRACT_NATIVE_LIBS_ATTR: &str = "extractNativeLibs";
+const LEGACY_STORAGE_ATTR: &str = "requestLegacyExternalStorage";
 
 /// Useful struct to read key details from the APK manifest.
 pub struct ManifestInfo {
     pub package_version: String,
+    /// The `versionCode` of the APK, which is also part of the name of its OBB files.
+    /// This is `None` if the manifest has no version code.
+    pub version_code: Option<u32>,
+    /// The names of the permissions requested with `uses-permission` elements, e.g. `android.permission.INTERNET`.
+    pub permissions: Vec<String>,
 }
 
 impl ManifestInfo {
+    /// Reads the manifest of the given APK.
+    pub fn read_from_apk<T: Read + Seek>(apk: &mut ZipFile<T>) -> Result<Self> {
+        let manifest = apk
+            .read_file("AndroidManifest.xml")
+            .context("Reading manifest file from APK")?;
+        Self::read(&mut AxmlReader::new(&mut Cursor::new(&manifest))?)
+            .context("Parsing manifest from AXML")
+    }
+
     pub fn read<T: Read + Seek>(reader: &mut AxmlReader<T>) -> Result<Self> {
         let mut version: Option<String> = None;
+        let mut version_code: Option<u32> = None;
+        let mut permissions = Vec::new();
         while let Some(event) = reader.read_next_event()? {
             match event {
                 Event::StartElement {
                     attributes, name, ..
                 } => {
+                    if &*name == "uses-permission" {
+                        permissions.extend(attributes.iter().find_map(|attr| {
+                            match (&*attr.name, &attr.value) {
+                                ("name", AttributeValue::String(s)) => Some(s.to_string()),
+                                _ => None,
+                            }
+                        }));
+                        continue;
+                    }
                     if &*name != "manifest" {
                         continue;
                     }
@@ -35,14 +73,191 @@ impl ManifestInfo {
                         },
                         None => return Err(anyhow!("No package version attribute")),
                     }
+
+                    version_code = attributes
+                        .iter()
+                        .find(|attr| &*attr.name == "versionCode")
+                        .and_then(|attr| match &attr.value {
+                            AttributeValue::Integer(code) => u32::try_from(*code).ok(),
+                            AttributeValue::String(s) => s.parse().ok(),
+                            _ => None,
+                        });
                 }
                 _ => {}
             }
         }
 
         match version {
-            Some(package_version) => Ok(Self { package_version }),
+            Some(package_version) => Ok(Self {
+                package_version,
+                version_code,
+                permissions,
+            }),
             None => Err(anyhow!("No useful information found in the manifest")),
         }
     }
 }
+
+/// Reads the [ManifestFlags] set in the manifest of the APK at the given path.
+pub fn manifest_flags(apk: &Path) -> Result<ManifestFlags> {
+    let mut apk = ZipFile::open(File::open(apk).context("Opening APK")?)
+        .context("APK was invalid ZIP archive")?;
+    let manifest = apk
+        .read_file("AndroidManifest.xml")
+        .context("Reading manifest file from APK")?;
+    read_flags(&manifest).context("Parsing manifest from AXML")
+}
+
+/// Reads the [ManifestFlags] set on the `application` element of a binary (AXML) manifest.
+pub fn read_flags(manifest: &[u8]) -> Result<ManifestFlags> {
+    let mut manifest = Cursor::new(manifest);
+    let mut reader = AxmlReader::new(&mut manifest)?;
+    while let Some(event) = reader.read_next_event()? {
+        if let Event::StartElement {
+            attributes, name, ..
+        } = event
+        {
+            if name == "application" {
+                return Ok(ManifestFlags {
+                    debuggable: bool_attribute(&attributes, DEBUGGABLE_ATTR)?,
+                    extract_native_libs: bool_attribute(&attributes, EXTRACT_NATIVE_LIBS_ATTR)?,
+                    legacy_storage: bool_attribute(&attributes, LEGACY_STORAGE_ATTR)?,
+                });
+            }
+        }
+    }
+
+    Err(anyhow!("Manifest had no application element"))
+}
+
+/// Sets the given [ManifestFlags] on the `application` element of a binary (AXML) manifest.
+/// Flags that are `None` are left as they are.
+///
+/// The manifest is parsed and saved again, rather than patched in place, so that flags which are not yet set can be added.
+/// # Returns
+/// The modified manifest.
+pub fn set_flags(manifest: &[u8], flags: &ManifestFlags) -> Result<Vec<u8>> {
+    let res_ids = ResourceIds::load().context("Loading resource IDs")?;
+    let mut manifest = Cursor::new(manifest);
+    let mut reader = AxmlReader::new(&mut manifest)?;
+
+    let mut output = Cursor::new(Vec::new());
+    let mut writer = AxmlWriter::new(&mut output);
+    let mut found_application = false;
+    while let Some(mut event) = reader.read_next_event()? {
+        if let Event::StartElement {
+            attributes, name, ..
+        } = &mut event
+        {
+            if name == "application" {
+                found_application = true;
+                for (attr_name, value) in [
+                    (DEBUGGABLE_ATTR, flags.debuggable),
+                    (EXTRACT_NATIVE_LIBS_ATTR, flags.extract_native_libs),
+                    (LEGACY_STORAGE_ATTR, flags.legacy_storage),
+                ] {
+                    if let Some(value) = value {
+                        set_bool_attribute(attributes, attr_name, value, &res_ids);
+                    }
+                }
+            }
+        }
+        writer.write_event(event);
+    }
+
+    if !found_application {
+        return Err(anyhow!("Manifest had no application element"));
+    }
+    writer.finish().context("Saving AXML (binary) manifest")?;
+    Ok(output.into_inner())
+}
+
+fn is_android_attribute(attr: &Attribute, name: &str) -> bool {
+    attr.name == name && attr.namespace.as_deref() == Some(ANDROID_NS_URI)
+}
+
+// Gets the value of the `android:` attribute with the given name, or `None` if it is not set.
+fn bool_attribute(attributes: &[Attribute], name: &str) -> Result<Option<bool>> {
+    match attributes
+        .iter()
+        .find(|attr| is_android_attribute(attr, name))
+        .map(|attr| &attr.value)
+    {
+        None => Ok(None),
+        Some(AttributeValue::Boolean(value)) => Ok(Some(*value)),
+        Some(AttributeValue::String(value)) => value
+            .parse()
+            .map(Some)
+            .with_context(|| format!("android:{name} was not true or false")),
+        Some(_) => Err(anyhow!("android:{name} was not a boolean")),
+    }
+}
+
+// Sets the `android:` attribute with the given name to `value`, adding the attribute if it is not set.
+fn set_bool_attribute(
+    attributes: &mut Vec<Attribute>,
+    name: &str,
+    value: bool,
+    res_ids: &ResourceIds,
+) {
+    match attributes
+        .iter_mut()
+        .find(|attr| is_android_attribute(attr, name))
+    {
+        Some(attr) => attr.value = AttributeValue::Boolean(value),
+        None => attributes.push(Attribute {
+            name: name.to_string(),
+            namespace: Some(ANDROID_NS_URI.to_string()),
+            resource_id: res_ids.get_res_id_or_none(name),
+            value: AttributeValue::Boolean(value),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{read_flags, set_flags};
+    use crate::{
+        axml::{self, AxmlWriter},
+        models::response::ManifestFlags,
+    };
+
+    // Converts a manifest given as XML into AXML.
+    fn axml_manifest(xml: &str) -> Vec<u8> {
+        let mut output = Cursor::new(Vec::new());
+        let mut writer = AxmlWriter::new(&mut output);
+        axml::xml_to_axml(
+            &mut writer,
+            &mut xml::EventReader::new(Cursor::new(xml.as_bytes())),
+        )
+        .unwrap();
+        writer.finish().unwrap();
+        output.into_inner()
+    }
+
+    #[test]
+    fn set_flags_can_be_read_back() {
+        let manifest = axml_manifest(
+            r#"<manifest xmlns:android="http://schemas.android.com/apk/res/android" package="com.beatgames.beatsaber">
+                <application android:label="Beat Saber" android:debuggable="false"/>
+            </manifest>"#,
+        );
+        assert_eq!(
+            read_flags(&manifest).unwrap(),
+            ManifestFlags {
+                debuggable: Some(false),
+                ..Default::default()
+            }
+        );
+
+        let flags = ManifestFlags {
+            debuggable: Some(true),
+            extract_native_libs: Some(false),
+            legacy_storage: None,
+        };
+        let modified = set_flags(&manifest, &flags).unwrap();
+        assert_eq!(read_flags(&modified).unwrap(), flags);
+    }
+}
@@ -0,0 +1,71 @@
+//! Reads and writes the JSON configuration files of individual mods, so that their settings can be changed from MBF.
+//!
+//! By convention, mods save their configuration to [crate::paths::Paths::mod_configs], in a file named after the mod's ID.
+
+use std::{fmt::Display, path::PathBuf};
+
+use anyhow::{Context, Result};
+use log::info;
+
+use crate::{atomic, mod_man::sanitize_mod_id, paths};
+
+/// Returned when the new configuration given for a mod is not valid JSON.
+#[derive(Debug)]
+pub struct InvalidModConfig {
+    pub mod_id: String,
+    pub error: serde_json::Error,
+}
+
+impl std::error::Error for InvalidModConfig {}
+
+impl Display for InvalidModConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Refusing to save the configuration of {}, as it is not valid JSON: {}",
+            self.mod_id, self.error
+        )
+    }
+}
+
+/// Reads the configuration of the mod with the given ID.
+///
+/// # Returns
+/// The configuration, or `None` if the mod has not saved a configuration file.
+/// Gives an `Err` if the configuration file exists but is not valid JSON.
+pub fn read_mod_config(mod_id: &str) -> Result<Option<serde_json::Value>> {
+    let config_path = config_path(mod_id)?;
+    if !config_path.exists() {
+        return Ok(None);
+    }
+
+    let config =
+        serde_json::from_slice(&std::fs::read(&config_path).context("Reading mod configuration")?)
+            .with_context(|| format!("Parsing configuration of {mod_id}"))?;
+    Ok(Some(config))
+}
+
+/// Replaces the configuration of the mod with the given ID, creating the configuration file if it does not exist.
+/// `contents` is written exactly as given, so that formatting chosen by the user is kept, but must be valid JSON.
+///
+/// # Returns
+/// The new configuration, or an `Err` with an [InvalidModConfig] if `contents` is not valid JSON.
+pub fn write_mod_config(mod_id: &str, contents: &str) -> Result<serde_json::Value> {
+    let config: serde_json::Value =
+        serde_json::from_str(contents).map_err(|error| InvalidModConfig {
+            mod_id: mod_id.to_string(),
+            error,
+        })?;
+
+    let config_path = config_path(mod_id)?;
+    std::fs::create_dir_all(paths::default().mod_configs())
+        .context("Creating mod configuration directory")?;
+    info!("Saving configuration of {mod_id}");
+    atomic::write_atomic(config_path, contents.as_bytes()).context("Writing mod configuration")?;
+    Ok(config)
+}
+
+fn config_path(mod_id: &str) -> Result<PathBuf> {
+    let id = sanitize_mod_id(mod_id)?;
+    Ok(PathBuf::from(paths::default().mod_configs()).join(format!("{id}.json")))
+}
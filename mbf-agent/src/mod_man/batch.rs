@@ -0,0 +1,240 @@
+//! Installs several QMODs at once, working out the order to install them in across the whole set, rather than
+//! resolving the dependencies of each mod separately.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+
+use super::{ModInfo, ModManager};
+use crate::models::response::{BatchInstallReport, BatchInstallResult, ProgressEvent, Stage};
+
+impl ModManager<'_> {
+    /// Loads and installs every QMOD in `paths`, making a single pass in dependency order.
+    ///
+    /// All of the manifests are read first. If more than one QMOD has the same ID, only the newest version is installed,
+    /// so that mods sharing a dependency all use the same version of it. A mod in the set that needs a version of another
+    /// mod in the set other than the one chosen is not installed, rather than being given a conflicting version.
+    /// Dependencies that are not in the set are downloaded as usual, once each.
+    ///
+    /// # Arguments
+    /// * `paths` - The QMODs to install. Each QMOD that is installed is deleted, as with a single import.
+    /// * `allow_version_mismatch` - If false, mods made for a different version of the game are not installed.
+    /// # Returns
+    /// The outcome for each QMOD, in the same order as `paths`.
+    /// A mod failing to install does not stop the others, unless they depend on it.
+    pub fn install_mods(
+        &mut self,
+        paths: &[PathBuf],
+        allow_version_mismatch: bool,
+    ) -> Result<BatchInstallReport> {
+        let mut results: Vec<BatchInstallResult> = paths
+            .iter()
+            .map(|path| BatchInstallResult {
+                path: path.to_string_lossy().to_string(),
+                id: None,
+                version: None,
+                error: None,
+            })
+            .collect();
+
+        // The index in `paths` of the QMOD chosen for each mod ID.
+        let mut chosen: HashMap<String, usize> = HashMap::new();
+        let mut manifests: Vec<Option<ModInfo>> = Vec::new();
+        for (idx, path) in paths.iter().enumerate() {
+            let manifest = match self.peek_manifest(path) {
+                Ok(manifest) => manifest,
+                Err(err) => {
+                    results[idx].error = Some(format!("{err:#}"));
+                    manifests.push(None);
+                    continue;
+                }
+            };
+            results[idx].id = Some(manifest.id.clone());
+            results[idx].version = Some(manifest.version.clone());
+
+            if let Some(&other_idx) = chosen.get(&manifest.id) {
+                let other_version = &results[other_idx].version;
+                if other_version.as_ref() >= Some(&manifest.version) {
+                    results[idx].error = Some(format!(
+                        "Another QMOD in the batch has the same or a newer version of {}",
+                        manifest.id
+                    ));
+                    manifests.push(None);
+                    continue;
+                }
+                results[other_idx].error = Some(format!(
+                    "Another QMOD in the batch has v{} of {}",
+                    manifest.version, manifest.id
+                ));
+                manifests[other_idx] = None;
+            }
+            chosen.insert(manifest.id.clone(), idx);
+            manifests.push(Some(manifest));
+        }
+
+        let order = resolve_install_order(&manifests, &mut results);
+        let total = order.len() as u64;
+        let mut failed_ids: HashSet<String> = HashSet::new();
+        for (step_idx, idx) in order.into_iter().enumerate() {
+            let manifest = manifests[idx]
+                .as_ref()
+                .expect("Only mods with a manifest are ordered");
+            crate::report_progress(ProgressEvent {
+                stage: Stage::InstallingMods,
+                done: step_idx as u64,
+                total: Some(total),
+                message: format!("Installing {} v{}", manifest.id, manifest.version),
+            });
+
+            let failed_dep = manifest
+                .dependencies
+                .iter()
+                .find(|dep| dep.required && failed_ids.contains(&dep.id));
+            let result = match failed_dep {
+                Some(dep) => Err(anyhow!("Dependency {} could not be installed", dep.id)),
+                None => self.load_and_install(&paths[idx], &manifest.id, allow_version_mismatch),
+            };
+            match result {
+                Ok(()) => {
+                    info!("Installed {} v{}", manifest.id, manifest.version);
+                    if let Err(err) = std::fs::remove_file(&paths[idx]) {
+                        warn!("Failed to delete {:?}: {err}", paths[idx]);
+                    }
+                }
+                Err(err) => {
+                    warn!("Failed to install {}: {err:?}", manifest.id);
+                    failed_ids.insert(manifest.id.clone());
+                    results[idx].error = Some(format!("{err:#}"));
+                }
+            }
+        }
+
+        Ok(BatchInstallReport { results })
+    }
+
+    // Loads the QMOD at `path`, checking that it has the expected ID, and then installs it.
+    fn load_and_install(
+        &mut self,
+        path: &Path,
+        expected_id: &str,
+        allow_version_mismatch: bool,
+    ) -> Result<()> {
+        let loaded_id = self.try_load_new_mod(std::fs::File::open(path)?)?;
+        if loaded_id != expected_id {
+            return Err(anyhow!(
+                "QMOD changed while installing, now has ID {loaded_id}"
+            ));
+        }
+        self.install_mod(&loaded_id, allow_version_mismatch)
+    }
+}
+
+// Orders the mods in `manifests` so that every mod comes after the mods in the set that it depends on.
+// Gives the indices of the mods to install, in order.
+//
+// A mod is left out, with the reason recorded in `results`, if it needs a version of another mod in the set other than
+// the one in the set, or is part of a dependency cycle.
+fn resolve_install_order(
+    manifests: &[Option<ModInfo>],
+    results: &mut [BatchInstallResult],
+) -> Vec<usize> {
+    let by_id: HashMap<&str, usize> = manifests
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, manifest)| {
+            manifest
+                .as_ref()
+                .map(|manifest| (manifest.id.as_str(), idx))
+        })
+        .collect();
+
+    let mut order = Vec::new();
+    let mut visited = HashSet::new();
+    // Mods are visited in the order given, so that the install order is the same each time.
+    let mut indices: Vec<usize> = by_id.values().copied().collect();
+    indices.sort_unstable();
+    for idx in indices {
+        visit(
+            idx,
+            manifests,
+            &by_id,
+            results,
+            &mut visited,
+            &mut Vec::new(),
+            &mut order,
+        );
+    }
+    order
+}
+
+// Adds the mod at `idx` to `order` after the mods in the set that it depends on, giving `false` if it cannot be installed.
+// `visiting` contains the mods whose dependencies are currently being ordered, to detect cycles.
+fn visit(
+    idx: usize,
+    manifests: &[Option<ModInfo>],
+    by_id: &HashMap<&str, usize>,
+    results: &mut [BatchInstallResult],
+    visited: &mut HashSet<usize>,
+    visiting: &mut Vec<usize>,
+    order: &mut Vec<usize>,
+) -> bool {
+    if visited.contains(&idx) {
+        return results[idx].error.is_none();
+    }
+    let manifest = manifests[idx]
+        .as_ref()
+        .expect("Only mods with a manifest are visited");
+    if let Some(cycle_start) = visiting
+        .iter()
+        .position(|&visiting_idx| visiting_idx == idx)
+    {
+        let involved: Vec<String> = visiting[cycle_start..]
+            .iter()
+            .chain(std::iter::once(&idx))
+            .map(|&cycle_idx| results[cycle_idx].id.clone().unwrap_or_default())
+            .collect();
+        results[idx].error = Some(format!("Dependency cycle: {}", involved.join(" -> ")));
+        return false;
+    }
+
+    visiting.push(idx);
+    let mut error = None;
+    for dep in &manifest.dependencies {
+        let Some(&dep_idx) = by_id.get(dep.id.as_str()) else {
+            continue;
+        };
+        let dep_version = &manifests[dep_idx]
+            .as_ref()
+            .expect("Only mods with a manifest are indexed")
+            .version;
+        if !dep.version_range.matches(dep_version) {
+            error = Some(format!(
+                "Needs {} {}, but the batch contains v{dep_version}",
+                dep.id, dep.version_range
+            ));
+            break;
+        }
+        if !visit(dep_idx, manifests, by_id, results, visited, visiting, order) && dep.required {
+            error = Some(format!("Dependency {} could not be installed", dep.id));
+            break;
+        }
+    }
+    visiting.pop();
+    visited.insert(idx);
+
+    match error {
+        Some(error) => {
+            results[idx].error.get_or_insert(error);
+            false
+        }
+        None if results[idx].error.is_none() => {
+            order.push(idx);
+            true
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,70 @@
+//! Detects enabled mods that provide different copies of the same library.
+//!
+//! Libraries are installed by file name, so only one copy of each library can be installed at once.
+//! If two mods are built against incompatible versions of the same library, one of them will crash the game.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::Path,
+};
+
+use log::warn;
+
+use super::InstalledMod;
+use crate::{
+    hash,
+    models::response::{Conflict, ConflictingMod},
+};
+
+/// Finds each library that more than one of the enabled mods in `installed` provides, with differing contents.
+/// A library provided by several mods is not a conflict if every copy is identical.
+///
+/// The copies of a library are only hashed if more than one mod provides it.
+/// Copies that cannot be read are left out with a warning, so may hide a conflict.
+///
+/// # Returns
+/// The conflicts, in order of library file name.
+pub fn detect_conflicts(installed: &[InstalledMod]) -> Vec<Conflict> {
+    let mut providers: BTreeMap<&str, Vec<(&InstalledMod, &Path)>> = BTreeMap::new();
+    for installed_mod in installed
+        .iter()
+        .filter(|installed_mod| installed_mod.enabled)
+    {
+        for lib_file in &installed_mod.library_files {
+            providers
+                .entry(&lib_file.soname)
+                .or_default()
+                .push((installed_mod, &lib_file.path));
+        }
+    }
+
+    providers
+        .into_iter()
+        .filter(|(_, lib_providers)| lib_providers.len() > 1)
+        .filter_map(|(soname, lib_providers)| {
+            let mut hashes = HashSet::new();
+            for (provider, path) in &lib_providers {
+                match hash::sha256_file(path) {
+                    Ok(hash) => {
+                        hashes.insert(hash);
+                    }
+                    Err(err) => warn!(
+                        "Failed to hash {soname} from {} to check for conflicts: {err}",
+                        provider.id
+                    ),
+                }
+            }
+
+            (hashes.len() > 1).then(|| Conflict {
+                library: soname.to_string(),
+                mods: lib_providers
+                    .iter()
+                    .map(|(provider, _)| ConflictingMod {
+                        id: provider.id.clone(),
+                        version: provider.version.clone(),
+                    })
+                    .collect(),
+            })
+        })
+        .collect()
+}
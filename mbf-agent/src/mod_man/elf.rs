@@ -0,0 +1,127 @@
+//! Checks that mod binaries are ELF shared objects for the ABI used by the game, so that corrupted downloads and
+//! libraries built for the wrong ABI can be found before they crash the game on launch.
+
+use std::{fs::File, io::Read, path::Path};
+
+use anyhow::{Context, Result};
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const EM_ARM: u16 = 40;
+const EM_AARCH64: u16 = 183;
+
+/// An Android ABI that mod binaries can be built for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Abi {
+    /// `arm64-v8a`, used by all versions of Beat Saber supported by MBF.
+    Arm64V8a,
+    /// `armeabi-v7a`, used by older versions of Beat Saber.
+    ArmeabiV7a,
+}
+
+impl Abi {
+    /// Gets the ABI with the given name, as used for the `lib` folders within an APK, e.g. `arm64-v8a`.
+    pub fn from_name(name: &str) -> Option<Abi> {
+        match name {
+            "arm64-v8a" => Some(Abi::Arm64V8a),
+            "armeabi-v7a" => Some(Abi::ArmeabiV7a),
+            _ => None,
+        }
+    }
+
+    /// Gets the name of the ABI, as used for the `lib` folders within an APK.
+    pub fn name(self) -> &'static str {
+        match self {
+            Abi::Arm64V8a => "arm64-v8a",
+            Abi::ArmeabiV7a => "armeabi-v7a",
+        }
+    }
+
+    // The e_machine value of ELF files built for this ABI.
+    fn machine(self) -> u16 {
+        match self {
+            Abi::Arm64V8a => EM_AARCH64,
+            Abi::ArmeabiV7a => EM_ARM,
+        }
+    }
+
+    // Whether ELF files built for this ABI use the 64 bit format.
+    fn is_64_bit(self) -> bool {
+        match self {
+            Abi::Arm64V8a => true,
+            Abi::ArmeabiV7a => false,
+        }
+    }
+}
+
+/// Reads the ELF header of the file at `path` and checks that it is a little-endian ELF file for `expected_abi`.
+/// The file is also checked to be long enough to contain the section headers, which are normally at the end of the file,
+/// so that most truncated files are detected.
+///
+/// # Returns
+/// A description of the problem with the file, or `None` if it is valid.
+pub(super) fn check_elf(path: impl AsRef<Path>, expected_abi: Abi) -> Result<Option<String>> {
+    let file = File::open(path).context("Opening binary")?;
+    let file_len = file.metadata()?.len();
+    let mut header = Vec::with_capacity(64);
+    file.take(64)
+        .read_to_end(&mut header)
+        .context("Reading ELF header")?;
+
+    Ok(check_header(&header, file_len, expected_abi))
+}
+
+/// Checks an ELF file that has been read into memory, in the same way as [check_elf].
+pub(super) fn check_elf_bytes(contents: &[u8], expected_abi: Abi) -> Option<String> {
+    check_header(
+        &contents[..contents.len().min(64)],
+        contents.len() as u64,
+        expected_abi,
+    )
+}
+
+// Checks the first 64 bytes (or fewer, if the file is shorter) of an ELF file that is `file_len` bytes long.
+fn check_header(header: &[u8], file_len: u64, expected_abi: Abi) -> Option<String> {
+    if header.len() < 20 || !header.starts_with(ELF_MAGIC) {
+        return Some("Not an ELF file".to_string());
+    }
+    if header[5] != 1 {
+        return Some("ELF file is not little-endian".to_string());
+    }
+
+    let machine = u16::from_le_bytes([header[18], header[19]]);
+    if machine != expected_abi.machine() || (header[4] == 2) != expected_abi.is_64_bit() {
+        return Some(format!(
+            "Built for the wrong ABI (machine type {machine}), expected {expected_abi:?}"
+        ));
+    }
+
+    // The offsets of e_shoff, e_shentsize and e_shnum differ between the 32 and 64 bit formats.
+    let (header_len, section_headers_end) = if expected_abi.is_64_bit() {
+        (64, read_section_headers_end(header, 0x28, 8, 0x3A))
+    } else {
+        (52, read_section_headers_end(header, 0x20, 4, 0x2E))
+    };
+    match section_headers_end {
+        Some(end) if header.len() >= header_len && end <= file_len => None,
+        _ => Some("ELF file is truncated".to_string()),
+    }
+}
+
+// Gives the offset of the end of the section header table, or None if the header is too short to contain it.
+fn read_section_headers_end(
+    header: &[u8],
+    shoff_offset: usize,
+    shoff_len: usize,
+    shentsize_offset: usize,
+) -> Option<u64> {
+    let mut shoff_bytes = [0u8; 8];
+    shoff_bytes[..shoff_len].copy_from_slice(header.get(shoff_offset..shoff_offset + shoff_len)?);
+    let shoff = u64::from_le_bytes(shoff_bytes);
+
+    // e_shnum immediately follows e_shentsize.
+    let sizes = header.get(shentsize_offset..shentsize_offset + 4)?;
+    let shentsize = u16::from_le_bytes([sizes[0], sizes[1]]) as u64;
+    let shnum = u16::from_le_bytes([sizes[2], sizes[3]]) as u64;
+
+    Some(shoff + shentsize * shnum)
+}
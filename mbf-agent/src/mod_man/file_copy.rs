@@ -0,0 +1,124 @@
+//! Installs and uninstalls the file copies declared in mod manifests.
+//!
+//! A file copy may overwrite a file that already exists, e.g. a config file created by the game.
+//! Such files are moved into [crate::paths::Paths::file_copy_backups] first, and moved back when the mod is uninstalled.
+
+use std::{
+    fmt::Display,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+
+use super::{sanitize_mod_id, util};
+use crate::{hash, paths};
+
+/// Returned when a mod has a file copy with a destination outside of the directories that mods are allowed to write to.
+#[derive(Debug)]
+pub struct UnsafeFileCopy {
+    /// The destination given in the mod manifest.
+    pub dest: String,
+}
+
+impl std::error::Error for UnsafeFileCopy {}
+
+impl Display for UnsafeFileCopy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Refusing to copy a file to {}, as mods may only copy files into ModData or the game's files directory",
+            self.dest
+        )
+    }
+}
+
+/// Checks that a file copy destination is within ModData or the game's files directory.
+/// The check is carried out on the path as written, so destinations containing `..` are always refused.
+pub(super) fn check_destination(dest: &str) -> Result<(), UnsafeFileCopy> {
+    let dest_path = Path::new(dest);
+    let paths = paths::default();
+    let is_safe = dest_path.is_absolute()
+        && !dest_path
+            .components()
+            .any(|component| component == Component::ParentDir)
+        && [paths.moddata_root(), paths.android_app_files()]
+            .iter()
+            .any(|root| dest_path.starts_with(root) && dest_path != Path::new(root));
+
+    if is_safe {
+        Ok(())
+    } else {
+        Err(UnsafeFileCopy {
+            dest: dest.to_string(),
+        })
+    }
+}
+
+/// Copies `source` to `dest`, creating the parent directories of `dest` if necessary.
+///
+/// Nothing is written if the file at `dest` is already identical to `source`.
+/// Otherwise, if a file already exists at `dest`, it is backed up so that [uninstall] can put it back, unless
+/// a backup was already made for this mod (i.e. the existing file was copied by an earlier install of the mod).
+pub(super) fn install(mod_id: &str, source: &Path, dest: &str) -> Result<()> {
+    check_destination(dest)?;
+    let dest_path = Path::new(dest);
+    if let Some(parent) = dest_path.parent() {
+        std::fs::create_dir_all(parent).context("Creating destination directory for file copy")?;
+    }
+
+    if dest_path.exists() && util::files_identical(source, dest_path)? {
+        debug!("File copy at {dest} is already up to date");
+        return Ok(());
+    }
+
+    let backup_path = backup_path(mod_id, dest)?;
+    if dest_path.exists() && !backup_path.exists() {
+        info!("Backing up {dest} as it would be overwritten by a file copy");
+        std::fs::create_dir_all(backup_path.parent().expect("Backup path has a parent"))
+            .context("Creating file copy backup directory")?;
+        std::fs::rename(dest_path, &backup_path).context("Backing up existing file")?;
+    }
+
+    debug!("Installing file copy {source:?} to {dest}");
+    std::fs::copy(source, dest_path).context("Copying stated file copy to destination")?;
+    Ok(())
+}
+
+/// Removes the file copied to `dest` by the given mod, restoring the file it overwrote if there is one.
+/// Destinations refused by [check_destination] are left untouched, as they cannot have been installed.
+pub(super) fn uninstall(mod_id: &str, dest: &str) -> Result<()> {
+    if let Err(err) = check_destination(dest) {
+        warn!("Not removing file copy: {err}");
+        return Ok(());
+    }
+
+    let dest_path = Path::new(dest);
+    let backup_path = backup_path(mod_id, dest)?;
+    if backup_path.exists() {
+        debug!("Restoring backup of {dest}");
+        std::fs::rename(&backup_path, dest_path).context("Restoring backed up file")?;
+    } else if dest_path.exists() {
+        debug!("Removing file copy at destination {dest_path:?}");
+        std::fs::remove_file(dest_path).context("Deleting copied file")?;
+    }
+
+    Ok(())
+}
+
+/// Deletes the backup folder of the given mod if no backups remain within it.
+pub(super) fn remove_empty_backups(mod_id: &str) -> Result<()> {
+    let backup_dir = Path::new(paths::default().file_copy_backups()).join(sanitize_mod_id(mod_id)?);
+    if backup_dir.exists() && std::fs::read_dir(&backup_dir)?.next().is_none() {
+        std::fs::remove_dir(&backup_dir).context("Removing file copy backup directory")?;
+    }
+
+    Ok(())
+}
+
+// Backups are named by the hash of their destination, so each destination has a single backup per mod.
+fn backup_path(mod_id: &str, dest: &str) -> Result<PathBuf> {
+    Ok(Path::new(paths::default().file_copy_backups())
+        .join(sanitize_mod_id(mod_id)?)
+        .join(hash::sha256_reader(dest.as_bytes())?))
+}
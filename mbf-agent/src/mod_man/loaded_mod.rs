@@ -2,13 +2,13 @@
 //! This module is responsible for installing/removing the mod files and representing the loaded mods
 //! but does not handle mod dependencies and other responsibilities - this is the job of the [ModManager](super::ModManager)
 
-use std::{collections::HashSet, ffi::{OsStr, OsString}, path::{Path, PathBuf}};
+use std::{collections::HashSet, ffi::OsString, path::{Path, PathBuf}};
 
 use crate::paths;
 
-use super::{util, ModInfo};
+use super::{file_copy, sanitize_mod_id, util, ModInfo};
 use anyhow::{Result, Context};
-use log::{debug, warn};
+use log::warn;
 
 
 /// Represents a mod (in QMOD format).
@@ -79,23 +79,18 @@ impl Mod {
     /// the modloader folders and marking it as installed.
     /// 
     /// Does not install dependencies, hence the "unchecked".
-    pub(super) fn install_unchecked(&mut self) -> Result<()> {
+    /// Library files with file names in `kept_libs` are not copied, so that a newer copy of the library provided by another mod is kept.
+    pub(super) fn install_unchecked(&mut self, kept_libs: &HashSet<OsString>) -> Result<()> {
         // Copy early mods, late mods and library binaries.
-        util::copy_files_from_mod_folder(
-            &self.loaded_from,
-            &self.manifest().mod_files,
-            paths::EARLY_MODS,
-        )?;
-        util::copy_files_from_mod_folder(
-            &self.loaded_from,
-            &self.manifest().library_files,
-            paths::LIBS,
-        )?;
-        util::copy_files_from_mod_folder(
-            &self.loaded_from,
-            &self.manifest().late_mod_files,
-            paths::LATE_MODS,
-        )?;
+        let staging_dir = Path::new(paths::default().mod_staging()).join(sanitize_mod_id(&self.manifest().id)?);
+        let install_result = self.install_binaries_via(&staging_dir, kept_libs);
+        // If successful, all staged files have been moved out, so this only removes files left by a failed install.
+        if staging_dir.exists() {
+            if let Err(err) = std::fs::remove_dir_all(&staging_dir) {
+                warn!("Failed to remove staging directory {staging_dir:?}: {err}");
+            }
+        }
+        install_result.context("Installing mod binaries")?;
 
         self.copy_file_copies().context("Copying auxillary files")?;
 
@@ -114,30 +109,30 @@ impl Mod {
         // Delete all mod binary files.
         util::remove_file_names_from_folder(
             self.manifest().mod_files.iter(),
-            paths::EARLY_MODS,
+            paths::default().early_mods(),
         )?;
         util::remove_file_names_from_folder(
             self.manifest().late_mod_files.iter(),
-            paths::LATE_MODS,
+            paths::default().late_mods(),
         )?;
         util::remove_file_names_from_folder(
             // Only delete libraries not in use (!)
+            // Libraries are copied by file name, so this must also be used to check if they are retained.
             self
                 .manifest()
                 .library_files
                 .iter()
-                .filter(|lib_file| !retained_libs.contains(OsStr::new(lib_file))),
-            paths::LIBS,
+                .filter(|lib_file| !Path::new(lib_file)
+                    .file_name()
+                    .is_some_and(|file_name| retained_libs.contains(file_name))),
+            paths::default().libs(),
         )?;
 
-        // Delete all file copies.
+        // Delete all file copies, putting back any files they overwrote.
         for copy in &self.manifest().file_copies {
-            let dest_path = Path::new(&copy.destination);
-            if dest_path.exists() {
-                debug!("Removing file copy at destination {dest_path:?}");
-                std::fs::remove_file(dest_path).context("Deleting copied file")?;
-            }
+            file_copy::uninstall(&self.manifest().id, &copy.destination)?;
         }
+        file_copy::remove_empty_backups(&self.manifest().id)?;
 
         // Mark as uninstalled.
         self.installed = Some(false);
@@ -146,44 +141,86 @@ impl Mod {
         Ok(())
     }
 
+    /// Gets the paths that the mod's early mod, late mod and library files are installed to.
+    pub(super) fn installed_binary_paths(&self) -> Vec<PathBuf> {
+        let paths = paths::default();
+        let manifest = self.manifest();
+        [
+            (&manifest.mod_files, paths.early_mods()),
+            (&manifest.late_mod_files, paths.late_mods()),
+            (&manifest.library_files, paths.libs()),
+        ]
+        .into_iter()
+        .flat_map(|(files, dest)| files
+            .iter()
+            .filter_map(|file| Path::new(file).file_name())
+            .map(move |file_name| Path::new(dest).join(file_name)))
+        .collect()
+    }
+
+    /// Gets the path of one of the mod's files within the extracted QMOD.
+    pub(super) fn path_in_mod(&self, file: impl AsRef<Path>) -> PathBuf {
+        self.loaded_from.join(file)
+    }
+
     /// Deletes the mod, and will not check first whether it needs to be uninstalled.
     pub(super) fn delete_unchecked(self) -> Result<()> {
         std::fs::remove_dir_all(self.loaded_from).context("Deleting mod extract directory")?;
         Ok(())
     } 
 
+    /// Copies the early mods, late mods and library binaries of the mod to `staging_dir`, then moves them into the modloader folders.
+    /// The modloader folders are not modified until every file has been staged, so if staging fails (or MBF is killed),
+    /// the previously installed files are left untouched rather than being replaced by partially written files.
+    fn install_binaries_via(&self, staging_dir: &Path, kept_libs: &HashSet<OsString>) -> Result<()> {
+        if staging_dir.exists() {
+            std::fs::remove_dir_all(staging_dir).context("Removing previous staging directory")?;
+        }
+
+        let library_files: Vec<&String> = self.manifest().library_files
+            .iter()
+            .filter(|lib_file| !Path::new(lib_file)
+                .file_name()
+                .is_some_and(|file_name| kept_libs.contains(file_name)))
+            .collect();
+
+        let paths = paths::default();
+        let mut staged = Vec::new();
+        // Each type of file is staged separately, as an early mod and a late mod could have the same file name.
+        for (files, category, dest) in [
+            (self.manifest().mod_files.iter().collect(), "early_mods", paths.early_mods()),
+            (library_files, "libs", paths.libs()),
+            (self.manifest().late_mod_files.iter().collect(), "mods", paths.late_mods()),
+        ] {
+            staged.extend(util::stage_files_from_mod_folder(
+                &self.loaded_from,
+                &files,
+                staging_dir.join(category),
+                dest,
+            )?);
+        }
+
+        util::move_staged_files(&staged)
+    }
+
     /// Copies all auxillary file copies in the manifest from the extracted mod to the required destination.
+    /// Fails with [UnsafeFileCopy](super::file_copy::UnsafeFileCopy), without copying anything, if any destination is outside of the allowed directories.
     fn copy_file_copies(&self) -> Result<()> {
-        // TODO: Deny certain file copy destinations.
+        for copy in &self.manifest().file_copies {
+            file_copy::check_destination(&copy.destination)?;
+        }
 
-        for file_copy in &self.manifest().file_copies {
-            let file_path_in_mod = self.loaded_from.join(&file_copy.name);
+        for copy in &self.manifest().file_copies {
+            let file_path_in_mod = self.loaded_from.join(&copy.name);
             if !file_path_in_mod.exists() {
                 warn!(
                     "Could not install file copy {} as it did not exist in the QMOD",
-                    file_copy.name
+                    copy.name
                 );
                 continue;
             }
 
-            let dest_path = Path::new(&file_copy.destination);
-            match dest_path.parent() {
-                Some(parent) => std::fs::create_dir_all(parent)
-                    .context("Creating destination directory for file copy")?,
-                None => {}
-            }
-
-            if Path::new(&file_copy.destination).exists() {
-                std::fs::remove_file(&file_copy.destination)
-                    .context("Removing existing copied file")?;
-            }
-
-            debug!(
-                "Installing file copy {file_path_in_mod:?} to {}",
-                file_copy.destination
-            );
-            std::fs::copy(file_path_in_mod, &file_copy.destination)
-                .context("Copying stated file copy to destination")?;
+            file_copy::install(&self.manifest().id, &file_path_in_mod, &copy.destination)?;
         }
 
         Ok(())
@@ -197,10 +234,11 @@ impl Mod {
     /// `true` if and only if all early mod files, late mod files, library files and file copies exist in their expected
     /// destinations.
     fn check_if_files_copied(manifest: &ModInfo) -> Result<bool> {
+        let paths = paths::default();
         Ok(
-            util::files_exist_in_dir(paths::EARLY_MODS, manifest.mod_files.iter())?
-                && util::files_exist_in_dir(paths::LATE_MODS, manifest.late_mod_files.iter())?
-                && util::files_exist_in_dir(paths::LIBS, manifest.library_files.iter())?
+            util::files_exist_in_dir(paths.early_mods(), manifest.mod_files.iter())?
+                && util::files_exist_in_dir(paths.late_mods(), manifest.late_mod_files.iter())?
+                && util::files_exist_in_dir(paths.libs(), manifest.library_files.iter())?
                 && manifest
                     .file_copies
                     .iter()
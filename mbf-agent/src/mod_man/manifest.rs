@@ -5,9 +5,15 @@
 //! This code is under the GNU General Public License version 3, found here:
 //! https://github.com/QuestPackageManager/QPM.qmod/blob/main/LICENSE
 
+use std::fmt::Display;
+
+use anyhow::{Context, Result};
+use mbf_res_man::version;
 use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
 
+use crate::models::response::Compatibility;
+
 /// Model for the `mod.json` manifest within a QMOD.
 #[derive(Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -83,6 +89,115 @@ impl Default for ModInfo {
     }
 }
 
+impl ModInfo {
+    /// Checks that the package this mod is made for can be modded by MBF.
+    /// # Returns
+    /// An `Err` if `package_id` is not [crate::APK_ID], or if `package_version` is not a valid version.
+    /// Both fields are optional, and a missing field is always valid.
+    pub fn validate_package(&self) -> Result<()> {
+        if let compatibility @ Compatibility::WrongPackage { .. } = check_package_id(self) {
+            return Err(IncompatibleMod {
+                mod_id: self.id.clone(),
+                compatibility,
+            }
+            .into());
+        }
+
+        if let Some(package_version) = self
+            .package_version
+            .as_deref()
+            .filter(|ver| !ver.is_empty())
+        {
+            // Game versions may have a build number after an underscore, which is not part of the semver.
+            version::parse_bs_version(package_version).semver.with_context(|| {
+                format!(
+                    "Mod {} specified package version {package_version}, which is not a valid version",
+                    self.id
+                )
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returned when installing a mod that is not made for the installed game.
+#[derive(Debug)]
+pub struct IncompatibleMod {
+    pub mod_id: String,
+    /// Why the mod is incompatible. This is never [Compatibility::Compatible].
+    pub compatibility: Compatibility,
+}
+
+impl std::error::Error for IncompatibleMod {}
+
+impl Display for IncompatibleMod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.compatibility {
+            Compatibility::Compatible => write!(f, "Mod {} is compatible", self.mod_id),
+            Compatibility::WrongPackage { expected } => write!(
+                f,
+                "Mod {} is made for a different app, not {expected}",
+                self.mod_id
+            ),
+            Compatibility::VersionMismatch { mod_target, game } => write!(
+                f,
+                "Mod {} is made for v{mod_target} of the game, but v{game} is installed",
+                self.mod_id
+            ),
+        }
+    }
+}
+
+/// Checks whether a mod is made for the installed game, using the package ID and version in its manifest.
+/// A manifest with no package ID or version is assumed to be compatible.
+///
+/// # Arguments
+/// * `manifest` - The manifest of the mod.
+/// * `game_version` - The full version of the installed game. Only the semver portion of the versions is compared,
+///   as mods are not made for a particular build.
+pub fn check_mod_compatibility(manifest: &ModInfo, game_version: &str) -> Compatibility {
+    if let compatibility @ Compatibility::WrongPackage { .. } = check_package_id(manifest) {
+        return compatibility;
+    }
+
+    let mod_target = match manifest
+        .package_version
+        .as_deref()
+        .filter(|ver| !ver.is_empty())
+    {
+        Some(mod_target) => mod_target,
+        None => return Compatibility::Compatible,
+    };
+
+    let same_version = match (
+        version::parse_bs_version(mod_target).semver,
+        version::parse_bs_version(game_version).semver,
+    ) {
+        (Some(target_semver), Some(game_semver)) => target_semver == game_semver,
+        _ => mod_target == game_version,
+    };
+    if same_version {
+        Compatibility::Compatible
+    } else {
+        Compatibility::VersionMismatch {
+            mod_target: mod_target.to_string(),
+            game: game_version.to_string(),
+        }
+    }
+}
+
+fn check_package_id(manifest: &ModInfo) -> Compatibility {
+    match manifest.package_id.as_deref() {
+        Some(package_id) if !package_id.is_empty() && package_id != crate::APK_ID => {
+            Compatibility::WrongPackage {
+                expected: crate::APK_ID.to_string(),
+            }
+        }
+        _ => Compatibility::Compatible,
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct ModDependency {
@@ -3,12 +3,19 @@
 mod manifest;
 mod util;
 mod loaded_mod;
+mod elf;
+mod file_copy;
+mod sync;
+mod conflicts;
+mod mod_id;
+mod pins;
+mod batch;
+mod resolve;
 
 use std::{
     cell::RefCell,
     collections::{HashMap, HashSet},
     ffi::OsString,
-    fs::OpenOptions,
     io::{Cursor, Read, Seek},
     path::{Path, PathBuf},
     rc::Rc,
@@ -18,6 +25,12 @@ use jsonschema::JSONSchema;
 use log::{debug, error, info, warn};
 pub use manifest::*;
 pub use loaded_mod::Mod;
+pub use elf::Abi;
+pub use sync::{plan_sync, InstalledMod};
+pub use conflicts::detect_conflicts;
+pub use mod_id::{sanitize_mod_id, ModId};
+pub use pins::{pin_mod, pinned_mods, unpin_mod};
+pub use resolve::{resolve_install_order, DependencyError};
 
 use anyhow::{anyhow, Context, Result};
 use mbf_res_man::{
@@ -28,7 +41,7 @@ use mbf_res_man::{
 use mbf_zip::ZipFile;
 use semver::Version;
 
-use crate::{downloads, paths};
+use crate::{downloads, models::response::{BinaryIssue, Compatibility, QmodIssue, QmodValidation}, paths};
 
 /// The JSON schema for the `mod.json` file within a qmod.
 /// This is the same schema used by QuestPatcher.
@@ -40,6 +53,34 @@ const QMOD_SCHEMA: &str = include_str!("qmod_schema.json");
 /// than "schema validation failed."
 const MAX_SCHEMA_VERSION: Version = Version::new(1, 2, 0);
 
+/// The result of migrating the QMODs in the legacy ModsBeforeFriday directory.
+/// See [ModManager::migrate_legacy_qmods].
+#[derive(Default)]
+pub struct MigrationReport {
+    /// The number of QMODs that were extracted to the current mods directory.
+    pub migrated: usize,
+    /// The number of QMODs that were skipped as the same version of the mod, with identical files, was already present.
+    pub skipped: usize,
+    /// The number of QMODs with the same ID and version as a mod that was already present, but with different files.
+    /// These were left in the legacy directory, as either copy may be the one the user wants.
+    pub mismatched: usize,
+    /// The number of QMODs that could not be migrated, and were left in the legacy directory.
+    pub failed: usize,
+}
+
+// The outcome of migrating a single legacy QMOD, see [ModManager::migrate_legacy_qmods].
+enum LegacyMigration {
+    Migrated,
+    Skipped,
+    Mismatched,
+}
+
+impl std::fmt::Display for MigrationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} migrated, {} skipped, {} mismatched, {} failed", self.migrated, self.skipped, self.mismatched, self.failed)
+    }
+}
+
 /// A structure to manage QMODs installed on Beat Saber.
 pub struct ModManager<'cache> {
     /// A map of mod IDs to mods.
@@ -66,8 +107,9 @@ impl<'cache> ModManager<'cache> {
     /// # Returns
     /// A new [ModManager] with specified parameters. The instance returned *will not* have mods loaded
     /// yet, and this must be done by invoking [ModManager::load_mods].
-    pub fn new(game_version: String, res_cache: &'cache ResCache) -> Self {
-        Self {
+    /// Gives an `Err` if `game_version` is not suitable for use in the QMODs directory path.
+    pub fn new(game_version: String, res_cache: &'cache ResCache) -> Result<Self> {
+        Ok(Self {
             mods: HashMap::new(),
             schema: JSONSchema::options()
                 .compile(
@@ -76,35 +118,41 @@ impl<'cache> ModManager<'cache> {
                 )
                 .expect("QMOD schema should be a valid JSON schema"),
             // Each game version stores its QMODs in a different directory.
-            qmods_dir: paths::QMODS.replace('$', &game_version),
+            qmods_dir: paths::default()
+                .qmods_dir_for_version(&game_version)
+                .context("Finding QMODs directory")?,
             game_version,
             res_cache,
             mod_repo: None,
-        }
+        })
     }
 
     /// Removes ALL mod/early-mod and library files, ensuring that all installed mods are removed from the game.
-    pub fn wipe_all_mods(&mut self) -> Result<()> {
+    /// # Returns
+    /// The number of files that were removed.
+    pub fn wipe_all_mods(&mut self) -> Result<usize> {
         self.mods.clear();
 
         // Wipe all mod directories, if they exist.
         let to_remove = [
-            paths::OLD_QMODS,
-            paths::LATE_MODS,
-            paths::EARLY_MODS,
-            paths::LIBS,
+            paths::default().old_qmods(),
+            paths::default().late_mods(),
+            paths::default().early_mods(),
+            paths::default().libs(),
             &self.qmods_dir,
         ];
+        let mut removed_files = 0;
         for path in to_remove {
             let path = Path::new(path);
             if path.exists() {
-                std::fs::remove_dir(path).context("Failed to delete mod folder")?;
+                removed_files += util::count_files(path)?;
+                std::fs::remove_dir_all(path).context("Failed to delete mod folder")?;
             }
         }
 
         // Ensure that the mods directories exist after the operation.
         self.create_mods_dir()?;
-        Ok(())
+        Ok(removed_files)
     }
 
     /// Gets all loaded mods.
@@ -123,10 +171,18 @@ impl<'cache> ModManager<'cache> {
         self.mods.get(id)
     }
 
-    /// Loads the installed mods from the [paths::QMODS] directory in ModData.
+    /// Gets the version of the game that this [ModManager] manages mods for.
+    /// # Returns
+    /// The full `versionName` of the game, including the build number suffix.
+    pub fn game_version(&self) -> &str {
+        &self.game_version
+    }
+
+    /// Loads the installed mods from the [paths::Paths::qmods_dir_for_version] directory in ModData.
     ///
-    /// Also loads any legacy (non-extracted) mods found in the [paths::OLD_QMODS] directory,
-    /// if the directory exists, and extracts them to the new path. [paths::OLD_QMODS] is then deleted.
+    /// Also loads any legacy (non-extracted) mods found in the [paths::Paths::old_qmods] directory,
+    /// if the directory exists, and extracts them to the new path. [paths::Paths::old_qmods] is then deleted
+    /// unless any of the mods within it could not be migrated.
     pub fn load_mods(&mut self) -> Result<()> {
         self.create_mods_dir()?;
         self.mods.clear();
@@ -172,12 +228,16 @@ impl<'cache> ModManager<'cache> {
         // so the code above would lead to the mods being loaded again.
         self.check_mods_installed()
             .context("Checking if mods are installed")?;
-        match self.load_old_qmods() {
+        match self.migrate_legacy_qmods() {
             // If we had old QMODs loaded in this stage, then recheck if all mods are installed again, since the random load order of the legacy QMODs may mean
             // that if a dependency of a mod existed, it might not have been loaded
             // when the dependant mod was loaded.
-            Ok(had_old_qmods) => {
-                if had_old_qmods {
+            Ok(report) => {
+                if report.migrated > 0 || report.failed > 0 {
+                    info!("Legacy mod migration: {report}");
+                }
+
+                if report.migrated > 0 {
                     self.check_mods_installed()?;
                 }
             }
@@ -213,65 +273,269 @@ impl<'cache> ModManager<'cache> {
     /// If the mod is already installed (see [Mod::installed]), this does nothing.
     /// # Arguments
     /// * `id` the ID of the mod to install.
+    /// * `allow_version_mismatch` - If false, the mod is not installed if it is made for a different version of the game.
     /// # Returns
     /// `Ok` if all mod files were copied successfully and all dependencies were installed correctly.
     /// Reasons for failure could include:
+    /// - The mod is not made for the installed game (see [check_mod_compatibility]), in which case an [IncompatibleMod] is given.
     /// - A dependency conflict (a dependency needs to be upgraded to install the mod but another mod will not allow
     /// the newer version to be installed.)
     /// - `id` is not the ID of an installed mod.
+    /// - The mod depends on itself, directly or indirectly.
     ///
     /// This function will NOT fail if the mod is missing one of its stated mod/lib/late_mod files, but will instead
     /// log a warning.
-    pub fn install_mod(&mut self, id: &str) -> Result<()> {
-        // Install the mod's dependencies if applicable
-        let mod_rc = self
-            .mods
-            .get(id)
-            .ok_or(anyhow!(
-                "Could not install mod with ID {id} as it did not exist"
-            ))?
-            .clone();
-
-        let to_install = (*mod_rc).borrow();
-        if to_install.installed() {
-            return Ok(());
+    pub fn install_mod(&mut self, id: &str, allow_version_mismatch: bool) -> Result<()> {
+        // Mods that are already installed are left alone, so don't need checking.
+        if let Some(mod_rc) = self.mods.get(id).filter(|mod_rc| !mod_rc.borrow().installed()) {
+            let compatibility = check_mod_compatibility(mod_rc.borrow().manifest(), &self.game_version);
+            let refused = match compatibility {
+                Compatibility::Compatible => false,
+                Compatibility::WrongPackage { .. } => true,
+                Compatibility::VersionMismatch { .. } => !allow_version_mismatch,
+            };
+            if refused {
+                return Err(IncompatibleMod {
+                    mod_id: id.to_string(),
+                    compatibility,
+                }.into());
+            }
         }
 
-        info!(
-            "Installing {} v{}",
-            to_install.manifest().id, to_install.manifest().version
-        );
+        self.install_mod_internal(id)
+    }
 
-        for dep in &to_install.manifest().dependencies {
-            match self.mods.get(&dep.id) {
-                Some(existing_dep) => {
-                    let dep_ref = (**existing_dep).borrow();
-                    if !dep.version_range.matches(&dep_ref.manifest().version) {
-                        info!(
-                            "Dependency {} is out of date, got version {} but need {}",
-                            dep.id, dep_ref.manifest().version, dep.version_range
-                        );
-                        drop(dep_ref);
-                        self.install_dependency(&dep)?;
-                    } else if !dep_ref.installed() && dep.required {
-                        // Must install the dependency
-                        info!("Dependency {} was not installed, reinstalling", dep.id);
-                        drop(dep_ref);
-                        self.install_mod(&dep.id)?;
-                    }
+    // Does the actual installation of a mod and its dependencies.
+    // The order to install in is worked out by [resolve_install_order] from the loaded mods. Each dependency that is
+    // missing, or out of date, is downloaded and loaded before resolving again.
+    fn install_mod_internal(&mut self, id: &str) -> Result<()> {
+        // The IDs of the dependencies downloaded so far, so that a download that does not satisfy the dependency is not
+        // repeated forever.
+        let mut fetched: HashSet<String> = HashSet::new();
+        let order = loop {
+            let target = {
+                let mod_ref = self
+                    .mods
+                    .get(id)
+                    .ok_or(anyhow!("Could not install mod with ID {id} as it did not exist"))?
+                    .borrow();
+                if mod_ref.installed() {
+                    return Ok(());
                 }
-                None => {
-                    if dep.required {
-                        info!("Dependency {} was not found: installing now", dep.id);
-                        self.install_dependency(&dep)?;
+                mod_ref.manifest().clone()
+            };
+            let available: Vec<ModInfo> = self.mods.values()
+                .map(|mod_rc| mod_rc.borrow().manifest().clone())
+                .collect();
+
+            let to_fetch = match resolve_install_order(&target, &available) {
+                Ok(order) => match self.outdated_optional_dependency(&order) {
+                    Some(dep) => dep,
+                    None => break order,
+                },
+                Err(err) => match err.downcast::<DependencyError>() {
+                    Ok(DependencyError::MissingDependency { id: dep_id, required_range }) => {
+                        // The dependency may be given more than once with the same range, so any copy will do.
+                        let dep = available.iter()
+                            .chain(std::iter::once(&target))
+                            .flat_map(|manifest| manifest.dependencies.iter())
+                            .find(|dep| dep.id == dep_id && dep.version_range == required_range)
+                            .cloned()
+                            .expect("A missing dependency is a dependency of a mod that was resolved");
+                        info!("Dependency {dep_id} {required_range} was not found: downloading now");
+                        dep
                     }
+                    Ok(dep_err) => return Err(anyhow!(dep_err).context(format!("Could not install {id}"))),
+                    Err(err) => return Err(err),
+                },
+            };
+
+            if !fetched.insert(to_fetch.id.clone()) {
+                return Err(DependencyError::MissingDependency {
+                    id: to_fetch.id,
+                    required_range: to_fetch.version_range,
+                }.into());
+            }
+            self.fetch_dependency(&to_fetch)?;
+        };
+
+        for mod_id in order {
+            let mod_rc = self.mods.get(&mod_id.to_string())
+                .expect("Resolved mods are all loaded")
+                .clone();
+            let to_install = mod_rc.borrow();
+            if to_install.installed() {
+                continue;
+            }
+
+            info!(
+                "Installing {} v{}",
+                to_install.manifest().id, to_install.manifest().version
+            );
+            let kept_libs = self.get_newer_lib_files(&to_install)?;
+            drop(to_install);
+
+            mod_rc.borrow_mut().install_unchecked(&kept_libs)?;
+        }
+        Ok(())
+    }
+
+    // Finds an optional dependency of one of the mods in `order` that is loaded, but not within the range needed.
+    // Optional dependencies are not resolved, but one that is present must still be a version the dependant accepts.
+    fn outdated_optional_dependency(&self, order: &[ModId]) -> Option<ModDependency> {
+        order.iter()
+            .filter_map(|id| self.mods.get(&id.to_string()))
+            .flat_map(|mod_rc| mod_rc.borrow().manifest().dependencies.clone())
+            .filter(|dep| !dep.required)
+            .find(|dep| self.mods.get(&dep.id).is_some_and(|dep_rc| {
+                let dep_ref = dep_rc.borrow();
+                let dep_version = &dep_ref.manifest().version;
+                if dep.version_range.matches(dep_version) {
+                    return false;
+                }
+
+                info!("Dependency {} is out of date, got version {dep_version} but need {}", dep.id, dep.version_range);
+                true
+            }))
+    }
+
+    /// Used to avoid replacing a library with an older copy when installing a mod.
+    /// # Arguments
+    /// * `installing` - The mod being installed.
+    /// # Returns
+    /// A HashSet of the file name (with extension) of each library file of `installing` that is installed with different contents,
+    /// where the installed copy is kept if it is a newer version of the library than the copy in
+    /// `installing`, or if the version of either copy cannot be worked out.
+    /// The installed copies of these libraries should be kept.
+    fn get_newer_lib_files(&self, installing: &Mod) -> Result<HashSet<OsString>> {
+        let mut kept_libs = HashSet::new();
+        for lib_path in &installing.manifest().library_files {
+            let file_name = match Path::new(lib_path).file_name() {
+                Some(file_name) => file_name,
+                None => continue,
+            };
+            let installed_path = Path::new(paths::default().libs()).join(file_name);
+            let new_path = installing.path_in_mod(lib_path);
+            if !installed_path.exists() || !new_path.exists()
+                || util::files_identical(&new_path, &installed_path)? {
+                continue;
+            }
+
+            // The installed copy came from whichever installed mod ships an identical file.
+            let mut installed_version = None;
+            for other_mod in self.mods.values().map(|other_mod| other_mod.borrow()) {
+                if other_mod.manifest().id == installing.manifest().id || !other_mod.installed() {
+                    continue;
+                }
+                let other_lib = match other_mod.manifest().library_files.iter()
+                    .find(|other_lib| Path::new(other_lib).file_name() == Some(file_name)) {
+                    Some(other_lib) => other_lib,
+                    None => continue,
+                };
+                let other_path = other_mod.path_in_mod(other_lib);
+                if other_path.exists() && util::files_identical(&other_path, &installed_path)? {
+                    installed_version = self.library_version(&other_mod, other_lib)?;
+                    break;
                 }
             }
+            let installing_version = self.library_version(installing, lib_path)?;
+
+            if keep_installed_library(installed_version.as_ref(), installing_version.as_ref()) {
+                match (installed_version, installing_version) {
+                    (Some(installed), Some(new)) => warn!("Not replacing {file_name:?} v{installed} with the older v{new} from {} v{}",
+                        installing.manifest().id, installing.manifest().version),
+                    _ => warn!("Not replacing {file_name:?} with the copy from {} v{}, as the version of one of the copies is unknown",
+                        installing.manifest().id, installing.manifest().version),
+                }
+                kept_libs.insert(file_name.to_owned());
+            }
         }
-        drop(to_install);
 
-        mod_rc.borrow_mut().install_unchecked()?;
-        Ok(())
+        Ok(kept_libs)
+    }
+
+    // Works out the version of the copy of a library that `provider` ships at `lib_path`.
+    // A library belongs to the mod it is named after, e.g. `libbeatsaber-hook_5_1_9.so` belongs to `beatsaber-hook`.
+    // If that is `provider`, the version is `provider`'s version. If it is a loaded dependency of `provider`, the version
+    // of the dependency is used, but only if `provider` ships an identical copy, since it may bundle a different version.
+    // Otherwise, gives `None`.
+    fn library_version(&self, provider: &Mod, lib_path: &str) -> Result<Option<Version>> {
+        let file_name = match Path::new(lib_path).file_name().and_then(|file_name| file_name.to_str()) {
+            Some(file_name) => file_name,
+            None => return Ok(None),
+        };
+        if is_library_of(&provider.manifest().id, file_name) {
+            return Ok(Some(provider.manifest().version.clone()));
+        }
+
+        for dep in &provider.manifest().dependencies {
+            if !is_library_of(&dep.id, file_name) {
+                continue;
+            }
+            let dep_ref = match self.mods.get(&dep.id) {
+                Some(dep_rc) => dep_rc.borrow(),
+                None => continue,
+            };
+            let dep_lib = match dep_ref.manifest().library_files.iter().chain(&dep_ref.manifest().mod_files)
+                .find(|dep_lib| Path::new(dep_lib).file_name() == Some(file_name.as_ref())) {
+                Some(dep_lib) => dep_lib,
+                None => continue,
+            };
+
+            let dep_path = dep_ref.path_in_mod(dep_lib);
+            let provider_path = provider.path_in_mod(lib_path);
+            if dep_path.exists() && provider_path.exists() && util::files_identical(&dep_path, &provider_path)? {
+                return Ok(Some(dep_ref.manifest().version.clone()));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Checks that each installed early mod, late mod and library file of a mod is a valid ELF file for `expected_abi`.
+    /// # Arguments
+    /// * `id` - The ID of the mod to check.
+    /// * `expected_abi` - The ABI of the game.
+    /// # Returns
+    /// Every problem found, so that they can all be shown at once, or an empty `Vec` if there were none.
+    /// Missing files are included, so this should only be called for installed mods.
+    pub fn verify_mod_binaries(&self, id: &str, expected_abi: Abi) -> Result<Vec<BinaryIssue>> {
+        let mod_ref = self.mods.get(id)
+            .ok_or(anyhow!("Could not verify mod with ID {id} as it did not exist"))?
+            .borrow();
+
+        let mut issues = Vec::new();
+        for path in mod_ref.installed_binary_paths() {
+            let problem = if path.exists() {
+                elf::check_elf(&path, expected_abi).context(format!("Checking {path:?}"))?
+            } else {
+                Some("File is missing".to_string())
+            };
+
+            if let Some(problem) = problem {
+                warn!("Problem with {path:?} from {id}: {problem}");
+                issues.push(BinaryIssue {
+                    mod_id: id.to_string(),
+                    path: path.to_string_lossy().into_owned(),
+                    problem,
+                });
+            }
+        }
+
+        Ok(issues)
+    }
+
+    /// Copies the early mod, late mod and library files of an installed mod to the modloader folders again,
+    /// replacing any installed copies that differ from the files in the extracted QMOD.
+    /// Libraries provided by a newer version of another mod are not replaced.
+    /// # Arguments
+    /// * `id` - The ID of the mod to repair.
+    pub fn repair_mod(&self, id: &str) -> Result<()> {
+        let mod_rc = self.mods.get(id)
+            .ok_or(anyhow!("Could not repair mod with ID {id} as it did not exist"))?;
+
+        let kept_libs = self.get_newer_lib_files(&mod_rc.borrow())?;
+        mod_rc.borrow_mut().install_unchecked(&kept_libs)
     }
 
     /// Uninstalls the mod with the given ID.
@@ -328,7 +592,7 @@ impl<'cache> ModManager<'cache> {
     }
 
     /// Attempts to load a new QMOD from a stream.
-    /// This will load the mod as a ZIP and validate its manifest.
+    /// This will load the mod as a ZIP and validate its manifest, including that it is made for the app being modded.
     ///
     /// If a mod with this mod's ID already exists in the [ModManager], this function checks, for all mods that are dependant
     /// on the ID, that the new mod version matches their dependency constraints.
@@ -349,6 +613,7 @@ impl<'cache> ModManager<'cache> {
         let loaded_mod_manifest = self
             .load_manifest_from_slice(&json_data)
             .context("Parsing manifest")?;
+        loaded_mod_manifest.validate_package()?;
 
         debug!(
             "Early load of new mod, ID {}, version: {}, author: {}",
@@ -358,18 +623,36 @@ impl<'cache> ModManager<'cache> {
         // Check that upgrading the mod to the new version is actually safe...
         let id = loaded_mod_manifest.id.clone();
         if let Err(msg) = self.check_dependency_compatibility(&id, &loaded_mod_manifest.version) {
-            return Err(anyhow!(
-                "Could not upgrade {} to v{}: {}",
-                id,
-                loaded_mod_manifest.version,
-                msg
-            ));
+            return Err(DependencyError::Other {
+                message: format!("Could not upgrade {} to v{}: {}", id, loaded_mod_manifest.version, msg),
+            }.into());
         }
 
         // Remove the existing version of the mod,
         // unchecked as we don't want to nuke any dependant mods or any of its dependencies; we have established that the upgrade is safe.
         // by allowing remove_mod to run a regular uninstall
-        if let Some(existing_mod) = self.mods.get(&id) {
+        let existing_unchanged = match self.mods.get(&id) {
+            Some(existing_mod) => {
+                let existing_ref = existing_mod.borrow();
+                existing_ref.installed()
+                    && existing_ref.manifest().version == loaded_mod_manifest.version
+                    && util::same_files(existing_ref.manifest(), &loaded_mod_manifest)
+                    && util::installed_files_match(&loaded_mod_manifest, &mut zip)?
+            },
+            None => false
+        };
+
+        if existing_unchanged {
+            // Reinstalling an identical QMOD would rewrite every installed file for no reason, so they are kept,
+            // and only the extracted copy of the existing QMOD is replaced.
+            info!("Existing version of mod is identical and installed, keeping its installed files");
+            let existing_mod = self.mods.remove(&id).unwrap();
+            Rc::try_unwrap(existing_mod)
+                .expect("Should be only one reference")
+                .into_inner()
+                .delete_unchecked()
+                .context("Deleting existing mod")?;
+        } else if let Some(existing_mod) = self.mods.get(&id) {
             info!("Removing existing version of mod");
             existing_mod.borrow_mut().uninstall_unchecked(self.get_retained_lib_files(&id))
                 .context("Uninstalling existing mod")?;
@@ -381,7 +664,7 @@ impl<'cache> ModManager<'cache> {
             "Extracting {} v{}",
             loaded_mod_manifest.id, loaded_mod_manifest.version
         );
-        let extract_path = self.get_mod_extract_path(&loaded_mod_manifest);
+        let extract_path = self.get_mod_extract_path(&loaded_mod_manifest)?;
         debug!("Extract path: {extract_path:?}");
         std::fs::create_dir_all(&extract_path).context("Creating extract directory")?;
         zip.extract_to_directory(&extract_path)
@@ -418,6 +701,52 @@ impl<'cache> ModManager<'cache> {
         Ok(())
     }
 
+    /// Finds the library files that are not used by any installed mod.
+    /// These are generally left behind by mods that were removed without uninstalling them first.
+    ///
+    /// Only the [paths::Paths::libs] directory is checked, so the libraries of the modloader itself are never included.
+    /// # Returns
+    /// The paths of each library file in [paths::Paths::libs] not listed in the `library_files` of an installed mod.
+    pub fn find_orphaned_libs(&self) -> Result<Vec<PathBuf>> {
+        let libs_dir = Path::new(paths::default().libs());
+        if !libs_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut claimed_libs: HashSet<OsString> = HashSet::new();
+        for installed_mod in self.mods.values().map(|mod_rc| mod_rc.borrow()).filter(|m| m.installed()) {
+            for lib_path in installed_mod.manifest().library_files.iter() {
+                if let Some(file_name) = Path::new(lib_path).file_name() {
+                    claimed_libs.insert(file_name.to_owned());
+                }
+            }
+        }
+
+        let mut orphaned = Vec::new();
+        for stat_result in std::fs::read_dir(libs_dir).context("Reading libs directory")? {
+            let entry = stat_result?;
+            if entry.file_type()?.is_file() && !claimed_libs.contains(&entry.file_name()) {
+                orphaned.push(entry.path());
+            }
+        }
+
+        Ok(orphaned)
+    }
+
+    /// Deletes all library files that are not used by any installed mod, as found by [ModManager::find_orphaned_libs].
+    /// Each deleted library is logged.
+    /// # Returns
+    /// The paths of the libraries that were deleted.
+    pub fn prune_orphaned_libs(&self) -> Result<Vec<PathBuf>> {
+        let orphaned = self.find_orphaned_libs()?;
+        for lib_path in &orphaned {
+            info!("Removing unused library {:?}", lib_path.file_name().unwrap_or_default());
+            std::fs::remove_file(lib_path).context("Deleting unused library")?;
+        }
+
+        Ok(orphaned)
+    }
+
     /// Sets a particular mod ID as being a core mod.
     /// This will also make all required dependencies of the mod core, transitively.
     /// Does nothing if the mod with the given ID doesn't exist or is already marked as core.
@@ -444,39 +773,144 @@ impl<'cache> ModManager<'cache> {
         }
     }
 
-    /// Attempts to load QMODs found in the legacy ModsBeforeFriday directory.
-    /// This will extract them in the new mods directory.
-    /// The QMODs and the directory itself are then deleted.
-    /// Will do nothing if the old mods directory does not exist.
-    /// Returns true if any old QMODs were found
-    fn load_old_qmods(&mut self) -> Result<bool> {
-        if !Path::new(paths::OLD_QMODS).exists() {
-            return Ok(false);
+    /// Migrates QMODs found in the legacy ModsBeforeFriday directory.
+    /// Each `.qmod` file is extracted to the mods directory for the current game version, and the legacy file
+    /// is only deleted once the mod has been loaded successfully from its new location.
+    /// Legacy QMODs with the same ID, version and files as a mod that is already loaded are skipped, and deleted.
+    /// If the ID and version match but the files differ, the legacy QMOD is kept and counted as mismatched.
+    /// The legacy directory itself is deleted if every QMOD was migrated or skipped.
+    ///
+    /// Does nothing if the old mods directory does not exist.
+    /// # Returns
+    /// A [MigrationReport] giving the number of QMODs that were migrated, skipped or failed.
+    pub fn migrate_legacy_qmods(&mut self) -> Result<MigrationReport> {
+        let mut report = MigrationReport::default();
+        let old_qmods = paths::default().old_qmods();
+        if !Path::new(old_qmods).exists() {
+            return Ok(report);
         }
 
         warn!("Migrating mods from legacy folder");
-        let mut found_qmod = false;
-        for stat_result in
-            std::fs::read_dir(paths::OLD_QMODS).context("Reading old QMODs directory")?
-        {
-            let stat = stat_result?;
+        for stat_result in std::fs::read_dir(old_qmods).context("Reading old QMODs directory")? {
+            let legacy_path = stat_result?.path();
+            if !legacy_path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("qmod")) {
+                continue;
+            }
+            debug!("Migrating {legacy_path:?}");
+
+            match self.migrate_legacy_qmod(&legacy_path) {
+                Ok(LegacyMigration::Migrated) => report.migrated += 1,
+                Ok(LegacyMigration::Skipped) => report.skipped += 1,
+                Ok(LegacyMigration::Mismatched) => {
+                    report.mismatched += 1;
+                    continue;
+                }
+                Err(err) => {
+                    // Keep the file so that the mod isn't lost.
+                    warn!("Failed to migrate legacy mod at {legacy_path:?}: {err}");
+                    report.failed += 1;
+                    continue;
+                }
+            }
 
-            let mod_stream = std::fs::File::open(stat.path()).context("Opening legacy mod")?;
-            debug!("Migrating {:?}", stat.path());
+            if let Err(err) = std::fs::remove_file(&legacy_path) {
+                warn!("Failed to delete migrated legacy mod at {legacy_path:?}: {err}");
+            }
+        }
 
-            // Attempt to load a mod from each file
-            match self.try_load_new_mod(mod_stream) {
-                Ok(new_mod) => info!("Successfully migrated legacy mod {new_mod}"),
-                Err(err) => warn!("Failed to migrate legacy mod at {:?}: {}", stat.path(), err),
+        if report.failed == 0 && report.mismatched == 0 {
+            if let Err(err) = std::fs::remove_dir(old_qmods) {
+                warn!("Failed to delete legacy mods folder: {err}");
             }
+        } else {
+            warn!("{} legacy mod(s) could not be migrated and have been left in {old_qmods}", report.failed + report.mismatched);
+        }
+
+        Ok(report)
+    }
 
-            // Delete the file either way
-            found_qmod = true;
-            std::fs::remove_file(stat.path()).context("Deleting legacy mod")?;
+    /// Reads and validates the manifest of the QMOD at the given path, without extracting any of its other files.
+    /// Nothing is written to disk, so this can be used to preview a mod before it is installed.
+    pub fn peek_manifest(&self, qmod_path: &Path) -> Result<ModInfo> {
+        let mut zip = ZipFile::open(std::fs::File::open(qmod_path).context("Opening mod")?)
+            .context("Mod was invalid ZIP archive")?;
+        self.load_manifest_from_slice(&zip.read_file("mod.json").context("Mod had no mod.json manifest")?)
+            .context("Parsing manifest")
+    }
+
+    /// Checks that the QMOD at the given path could be installed, without extracting any of its files.
+    /// Unlike [ModManager::peek_manifest], every problem is given rather than just the first, so they can all be shown at once.
+    /// # Arguments
+    /// * `qmod_path` - The QMOD to check.
+    /// * `expected_abi` - The ABI of the game, which the binaries of the mod must be built for.
+    /// # Returns
+    /// The problems found, or an `Err` if the file could not be opened as a ZIP archive.
+    pub fn validate_qmod(&self, qmod_path: &Path, expected_abi: Abi) -> Result<QmodValidation> {
+        let mut zip = ZipFile::open(std::fs::File::open(qmod_path).context("Opening mod")?)
+            .context("Mod was invalid ZIP archive")?;
+        let manifest_result = zip.read_file("mod.json")
+            .context("Mod had no mod.json manifest")
+            .and_then(|manifest_slice| self.load_manifest_from_slice(&manifest_slice));
+        let manifest = match manifest_result {
+            Ok(manifest) => manifest,
+            // None of the other checks can be made without the manifest.
+            Err(err) => return Ok(QmodValidation {
+                id: None,
+                version: None,
+                issues: vec![QmodIssue::BadManifest { error: format!("{err:#}") }],
+            }),
+        };
+
+        let mut issues = Vec::new();
+        let binaries = manifest.mod_files.iter()
+            .chain(&manifest.late_mod_files)
+            .chain(&manifest.library_files);
+        for entry in binaries {
+            if !zip.contains_file(entry) {
+                issues.push(QmodIssue::MissingEntry { entry: entry.clone() });
+                continue;
+            }
+
+            let contents = zip.read_file(entry).context(format!("Reading {entry}"))?;
+            if let Some(problem) = elf::check_elf_bytes(&contents, expected_abi) {
+                issues.push(QmodIssue::NoAbiMatch { entry: entry.clone(), problem });
+            }
         }
-        std::fs::remove_dir(paths::OLD_QMODS)?;
+        for file_copy in &manifest.file_copies {
+            if !zip.contains_file(&file_copy.name) {
+                issues.push(QmodIssue::MissingEntry { entry: file_copy.name.clone() });
+            }
+        }
+
+        Ok(QmodValidation {
+            id: Some(manifest.id),
+            version: Some(manifest.version),
+            issues,
+        })
+    }
+
+    // Migrates a single legacy QMOD file.
+    fn migrate_legacy_qmod(&mut self, legacy_path: &Path) -> Result<LegacyMigration> {
+        let manifest = self.peek_manifest(legacy_path)?;
+
+        if let Some(existing) = self.mods.get(&manifest.id) {
+            let existing = existing.borrow();
+            if existing.manifest().version == manifest.version {
+                let mut zip = ZipFile::open(std::fs::File::open(legacy_path).context("Opening legacy mod")?)
+                    .context("Legacy mod was invalid ZIP archive")?;
+                if util::extracted_files_match(&mut zip, &existing.path_in_mod(""))? {
+                    info!("Legacy mod {} v{} already exists, skipping", manifest.id, manifest.version);
+                    return Ok(LegacyMigration::Skipped);
+                }
 
-        Ok(found_qmod)
+                warn!("Legacy mod {} v{} has different files to the existing copy, so it was not migrated", manifest.id, manifest.version);
+                return Ok(LegacyMigration::Mismatched);
+            }
+        }
+
+        let new_mod = self.try_load_new_mod(std::fs::File::open(legacy_path).context("Opening legacy mod")?)?;
+        info!("Successfully migrated legacy mod {new_mod}");
+        Ok(LegacyMigration::Migrated)
     }
 
     fn load_mod_from_directory(&self, from: PathBuf) -> Result<Mod> {
@@ -529,8 +963,11 @@ impl<'cache> ModManager<'cache> {
             return Err(anyhow!("QMOD schema validation failed: \n{log_builder}"));
         }
 
-        Ok(serde_json::from_value(manifest_value)
-            .expect("Failed to parse as QMOD manifest, despite being valid according to schema. This is a bug"))
+        let manifest: ModInfo = serde_json::from_value(manifest_value)
+            .expect("Failed to parse as QMOD manifest, despite being valid according to schema. This is a bug");
+        // The ID is used in the names of the folders the mod is extracted and installed through.
+        sanitize_mod_id(&manifest.id)?;
+        Ok(manifest)
     }
 
     /// Used to avoid removing library files that are still in use by another mod when uninstalling a mod.
@@ -563,7 +1000,8 @@ impl<'cache> ModManager<'cache> {
         retained_libs
     }
 
-    fn install_dependency(&mut self, dep: &ModDependency) -> Result<()> {
+    // Downloads a version of the given dependency and loads it, without installing it.
+    fn fetch_dependency(&mut self, dep: &ModDependency) -> Result<()> {
         // First check if we can find a copy of the dependency in the mod repo, since this is the preferred option
         // The mod repo will likely have a more up-to-date version of the dependency than the dependency downloadIfMissing
         let link = if let Some(dep_url) = self.try_get_dep_from_mod_repo(dep) {
@@ -571,7 +1009,9 @@ impl<'cache> ModManager<'cache> {
         } else {
             match &dep.mod_link {
                 Some(link) => link.clone(),
-                None => return Err(anyhow!("Could not download dependency {}: no link given and could not find in mod repo", dep.id))
+                None => return Err(DependencyError::Other {
+                    message: format!("Could not download dependency {} (version range {}): no link given and could not find in mod repo", dep.id, dep.version_range),
+                }.into())
             }
         };
 
@@ -579,9 +1019,9 @@ impl<'cache> ModManager<'cache> {
         let dependency_bytes =
             downloads::download_to_vec_with_attempts(&crate::get_dl_cfg(), &link)
                 .context("Downloading dependency")?;
+        downloads::check_content(&link, &dependency_bytes, downloads::ExpectedContent::Zip)?;
 
         self.try_load_new_mod(Cursor::new(dependency_bytes))?;
-        self.install_mod(&dep.id)?;
         Ok(())
     }
 
@@ -649,13 +1089,37 @@ impl<'cache> ModManager<'cache> {
         }
     }
 
+    /// Finds the URL that the given version of a mod can be downloaded from in the mod repo.
+    /// Both the global mods and the mods for the current game version are checked.
+    /// # Returns
+    /// The download URL, or `None` if the mod repo could not be accessed or did not contain this exact version of the mod.
+    pub fn find_mod_repo_url(&mut self, id: &str, version: &Version) -> Option<String> {
+        let game_ver_clone = self.game_version.clone();
+        let mod_repo = match self.get_or_load_mod_repo() {
+            Ok(mod_repo) => mod_repo,
+            Err(err) => {
+                warn!("Could not check for {id} v{version} in mod repo: {err}");
+                return None;
+            }
+        };
+
+        let url = ["global", &game_ver_clone].into_iter()
+            .filter_map(|key| mod_repo.get(key))
+            .flatten()
+            .find(|repo_mod| repo_mod.id == id && &repo_mod.version == version)
+            .map(|repo_mod| repo_mod.download.clone());
+        url
+    }
+
     /// Updates whether the mod with the given ID is currently installed.
     /// This will recursively check whether any dependent mods are installed.
     // `checked_in_path` is used to detect recursive dependencies - which are not allowed and will trigger an error.
     // Returns the new state of Mod#installed for the mod.
     fn check_mod_installed(&self, id: &str, checked_in_pass: &mut HashSet<String>) -> Result<bool> {
         if !checked_in_pass.insert(id.to_string()) {
-            return Err(anyhow!("Recursive dependency detected. Mod with ID {id} depends on itself, directly or indirectly. This is not permitted"));
+            return Err(DependencyError::Other {
+                message: format!("Recursive dependency detected. Mod with ID {id} depends on itself, directly or indirectly. This is not permitted"),
+            }.into());
         }
 
         let mod_rc = self
@@ -715,10 +1179,11 @@ impl<'cache> ModManager<'cache> {
 
     // Finds a path to extract the mod with the given manifest.
     // This will have folder name {ID}_v{VERSION} unless a folder of this name already exists (which it shouldn't really )
-    fn get_mod_extract_path(&self, manifest: &ModInfo) -> PathBuf {
+    fn get_mod_extract_path(&self, manifest: &ModInfo) -> Result<PathBuf> {
+        let id = sanitize_mod_id(&manifest.id)?;
         let mut i = 1;
         loop {
-            let mut folder_name = format!("{}_v{}", manifest.id, manifest.version);
+            let mut folder_name = format!("{id}_v{}", manifest.version);
             if i > 1 {
                 warn!("When finding path to extract {} v{}, the folder name {folder_name} was already occupied,
                     \n... despite no mod existing with the ID and version in the folder name. 
@@ -730,7 +1195,7 @@ impl<'cache> ModManager<'cache> {
 
             let extract_path = Path::new(&self.qmods_dir).join(folder_name);
             if !extract_path.exists() {
-                break extract_path;
+                break Ok(extract_path);
             }
 
             i += 1;
@@ -741,14 +1206,10 @@ impl<'cache> ModManager<'cache> {
     /// and the Packages directory that stores the extracted QMODs for the current game version.
     fn create_mods_dir(&self) -> Result<()> {
         std::fs::create_dir_all(&self.qmods_dir)?;
-        std::fs::create_dir_all(paths::LATE_MODS)?;
-        std::fs::create_dir_all(paths::EARLY_MODS)?;
-        std::fs::create_dir_all(paths::LIBS)?;
-        OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(paths::MODDATA_NOMEDIA)
-            .context("Creating nomedia file")?;
+        std::fs::create_dir_all(paths::default().late_mods())?;
+        std::fs::create_dir_all(paths::default().early_mods())?;
+        std::fs::create_dir_all(paths::default().libs())?;
+        crate::patching::ensure_nomedia()?;
 
         Ok(())
     }
@@ -816,3 +1277,55 @@ impl<'cache> ModManager<'cache> {
         Ok(self.mod_repo.as_ref().expect("Just loaded mod repo"))
     }
 }
+
+// Checks whether the library with file name `file_name` is named after the mod with ID `mod_id`,
+// i.e. it is `lib{mod_id}.so` or `lib{mod_id}_{version}.so`.
+fn is_library_of(mod_id: &str, file_name: &str) -> bool {
+    let stem = match file_name.strip_prefix("lib").and_then(|name| name.strip_suffix(".so")) {
+        Some(stem) => stem,
+        None => return false,
+    };
+
+    match stem.strip_prefix(mod_id) {
+        Some(rest) => rest.is_empty() || rest.starts_with('_'),
+        None => false,
+    }
+}
+
+// Decides whether to keep an installed library rather than replacing it with a different copy.
+// The installed copy is only replaced if both versions are known and it is not newer.
+fn keep_installed_library(installed: Option<&Version>, installing: Option<&Version>) -> bool {
+    match (installed, installing) {
+        (Some(installed), Some(installing)) => installed > installing,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+
+    use super::{is_library_of, keep_installed_library};
+
+    #[test]
+    fn libraries_belong_to_the_mod_they_are_named_after() {
+        assert!(is_library_of("beatsaber-hook", "libbeatsaber-hook.so"));
+        assert!(is_library_of("beatsaber-hook", "libbeatsaber-hook_5_1_9.so"));
+        assert!(!is_library_of("beatsaber-hook", "libbeatsaber-hook-extras.so"));
+        assert!(!is_library_of("paper", "libpaper2_scotland2.so"));
+        assert!(!is_library_of("custom-types", "libbeatsaber-hook.so"));
+    }
+
+    #[test]
+    fn installed_libraries_are_only_replaced_by_known_newer_or_equal_versions() {
+        let older = Version::new(5, 1, 0);
+        let newer = Version::new(5, 1, 9);
+
+        assert!(keep_installed_library(Some(&newer), Some(&older)));
+        assert!(!keep_installed_library(Some(&older), Some(&newer)));
+        assert!(!keep_installed_library(Some(&newer), Some(&newer)));
+        assert!(keep_installed_library(None, Some(&newer)));
+        assert!(keep_installed_library(Some(&older), None));
+        assert!(keep_installed_library(None, None));
+    }
+}
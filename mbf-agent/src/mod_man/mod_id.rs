@@ -0,0 +1,103 @@
+//! Validation of mod IDs, which are used as file and folder names when installing mods.
+//!
+//! A mod ID comes from the manifest of a QMOD, so could be anything. Without checking it first, an ID such as
+//! `../../foo` would let a QMOD write outside of the folders that MBF creates for each mod.
+
+use std::{fmt::Display, path::Path};
+
+/// A mod ID that has been checked by [sanitize_mod_id], and so is safe to use as a single path component.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModId(String);
+
+impl AsRef<Path> for ModId {
+    fn as_ref(&self) -> &Path {
+        Path::new(&self.0)
+    }
+}
+
+impl Display for ModId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Returned when a mod ID cannot safely be used as a file or folder name.
+#[derive(Debug)]
+pub struct InvalidModId {
+    pub id: String,
+    /// Why the ID was refused.
+    pub reason: &'static str,
+}
+
+impl std::error::Error for InvalidModId {}
+
+impl Display for InvalidModId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Mod ID {:?} is not allowed: {}", self.id, self.reason)
+    }
+}
+
+/// Checks that a mod ID only contains ASCII letters, digits, `.`, `_` and `-`, and is not empty, `.` or `..`.
+/// Path separators are therefore refused, so the ID can always be joined onto a folder without escaping it.
+pub fn sanitize_mod_id(id: &str) -> Result<ModId, InvalidModId> {
+    let reason = if id.is_empty() {
+        Some("it is empty")
+    } else if id == "." || id == ".." {
+        Some("it refers to a folder")
+    } else if !id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        Some("it may only contain letters, numbers, '.', '_' and '-'")
+    } else {
+        None
+    };
+
+    match reason {
+        Some(reason) => Err(InvalidModId {
+            id: id.to_string(),
+            reason,
+        }),
+        None => Ok(ModId(id.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sanitize_mod_id;
+
+    #[test]
+    fn ordinary_mod_ids_are_allowed() {
+        for id in [
+            "beatsaber-hook",
+            "custom-types",
+            "paper2_scotland2",
+            "bs-utils",
+            "MappingExtensions",
+            "qosmetics.core",
+            "..a",
+        ] {
+            let mod_id = sanitize_mod_id(id).unwrap();
+            assert_eq!(mod_id.to_string(), id);
+        }
+    }
+
+    #[test]
+    fn ids_that_could_escape_a_folder_are_refused() {
+        for id in [
+            "",
+            ".",
+            "..",
+            "../../foo",
+            "foo/bar",
+            "/etc",
+            "foo\\bar",
+            "foo\0bar",
+            "mod id",
+            "caf\u{e9}",
+        ] {
+            let err = sanitize_mod_id(id).unwrap_err();
+            assert_eq!(err.id, id);
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! Keeps a list of mods that the user has pinned to a particular version.
+//!
+//! A pinned mod is never changed to another version, or removed, by syncing mods or by updating core mods, so that
+//! a setup that works is not broken by an update. The pins are saved within the modloader directory.
+
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{Context, Result};
+use log::info;
+use semver::Version;
+
+use super::sanitize_mod_id;
+use crate::{atomic, paths};
+
+/// Gets the pinned mods, by ID, along with the version that each is pinned to.
+/// Gives an empty map if no mods have been pinned.
+pub fn pinned_mods() -> Result<BTreeMap<String, Version>> {
+    let pins_path = Path::new(paths::default().mod_pins());
+    if !pins_path.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    serde_json::from_slice(&std::fs::read(pins_path).context("Reading pinned mods")?)
+        .context("Parsing pinned mods")
+}
+
+/// Pins the mod with the given ID to `version`, replacing any existing pin for the mod.
+pub fn pin_mod(id: &str, version: Version) -> Result<()> {
+    sanitize_mod_id(id)?;
+    let mut pins = pinned_mods()?;
+    info!("Pinning {id} to v{version}");
+    pins.insert(id.to_string(), version);
+    save_pins(&pins)
+}
+
+/// Unpins the mod with the given ID, so that it can be updated again.
+/// Gives `false` if the mod was not pinned.
+pub fn unpin_mod(id: &str) -> Result<bool> {
+    let mut pins = pinned_mods()?;
+    if pins.remove(id).is_none() {
+        return Ok(false);
+    }
+
+    info!("Unpinning {id}");
+    save_pins(&pins)?;
+    Ok(true)
+}
+
+fn save_pins(pins: &BTreeMap<String, Version>) -> Result<()> {
+    std::fs::create_dir_all(paths::default().modloader_dir())
+        .context("Creating modloader directory")?;
+    atomic::write_atomic(
+        paths::default().mod_pins(),
+        &serde_json::to_vec_pretty(pins)?,
+    )
+    .context("Writing pinned mods")
+}
@@ -0,0 +1,210 @@
+//! Works out which mods need installing to install a mod, and in what order, before anything is installed.
+
+use std::{collections::HashMap, fmt::Display};
+
+use anyhow::Result;
+use semver::VersionReq;
+
+use super::{sanitize_mod_id, ModId, ModInfo};
+
+/// Returned when a mod cannot be installed or upgraded because of its dependencies, or the mods depending on it.
+#[derive(Debug)]
+pub enum DependencyError {
+    /// The mods depend on each other in a cycle.
+    /// `involved` gives the IDs of the mods in the cycle, in order, with the first mod repeated at the end.
+    Cycle { involved: Vec<String> },
+    /// No available mod with ID `id` has a version within `required_range`.
+    /// This is also given if two mods need versions of the same dependency that no one version satisfies.
+    MissingDependency {
+        id: String,
+        required_range: VersionReq,
+    },
+    /// Another problem, such as an installed mod not allowing one of its dependencies to be upgraded.
+    Other { message: String },
+}
+
+impl std::error::Error for DependencyError {}
+
+impl Display for DependencyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cycle { involved } => {
+                write!(f, "Dependency cycle: {}", involved.join(" -> "))
+            }
+            Self::MissingDependency { id, required_range } => {
+                write!(
+                    f,
+                    "Could not find dependency {id} with version {required_range}"
+                )
+            }
+            Self::Other { message } => write!(f, "{message}"),
+        }
+    }
+}
+
+/// Works out the order to install `target` and the dependencies it needs in.
+///
+/// For each required dependency, the newest mod in `available` with that ID and a version in the required range is used.
+/// Once a version of a dependency has been chosen, every other mod depending on it must accept that version.
+/// Optional dependencies are not followed.
+///
+/// # Arguments
+/// * `target` - The mod to install.
+/// * `available` - The mods that can be installed, which may include several versions of the same mod.
+///   `target` need not be included.
+/// # Returns
+/// The IDs of the mods to install, with each mod after the mods it depends on and `target` last.
+/// Mods that are already installed are included, so should be skipped by the caller.
+/// Gives a [DependencyError] if a dependency is missing or the dependencies form a cycle.
+pub fn resolve_install_order(target: &ModInfo, available: &[ModInfo]) -> Result<Vec<ModId>> {
+    let mut resolver = Resolver {
+        available,
+        chosen: HashMap::from([(target.id.as_str(), target)]),
+        visiting: Vec::new(),
+        order: Vec::new(),
+    };
+    resolver.visit(target)?;
+
+    resolver
+        .order
+        .into_iter()
+        .map(|id| Ok(sanitize_mod_id(id)?))
+        .collect()
+}
+
+struct Resolver<'a> {
+    available: &'a [ModInfo],
+    // The version of each mod that will be installed, by ID.
+    chosen: HashMap<&'a str, &'a ModInfo>,
+    // The IDs of the mods whose dependencies are currently being resolved, to detect cycles.
+    visiting: Vec<&'a str>,
+    // The IDs of the mods that have been resolved, in the order to install them.
+    order: Vec<&'a str>,
+}
+
+impl<'a> Resolver<'a> {
+    // Adds `manifest` to the order after the mods it depends on.
+    fn visit(&mut self, manifest: &'a ModInfo) -> Result<(), DependencyError> {
+        if self.order.contains(&manifest.id.as_str()) {
+            return Ok(());
+        }
+        if let Some(cycle_start) = self
+            .visiting
+            .iter()
+            .position(|&visiting| visiting == manifest.id)
+        {
+            let mut involved: Vec<String> = self.visiting[cycle_start..]
+                .iter()
+                .map(|id| id.to_string())
+                .collect();
+            involved.push(manifest.id.clone());
+            return Err(DependencyError::Cycle { involved });
+        }
+
+        self.visiting.push(&manifest.id);
+        for dep in manifest.dependencies.iter().filter(|dep| dep.required) {
+            let missing = || DependencyError::MissingDependency {
+                id: dep.id.clone(),
+                required_range: dep.version_range.clone(),
+            };
+            let dep_manifest = match self.chosen.get(dep.id.as_str()) {
+                Some(chosen) if dep.version_range.matches(&chosen.version) => *chosen,
+                Some(_) => return Err(missing()),
+                None => {
+                    let newest = self
+                        .available
+                        .iter()
+                        .filter(|candidate| {
+                            candidate.id == dep.id && dep.version_range.matches(&candidate.version)
+                        })
+                        .max_by(|a, b| a.version.cmp(&b.version))
+                        .ok_or_else(missing)?;
+                    self.chosen.insert(&newest.id, newest);
+                    newest
+                }
+            };
+            self.visit(dep_manifest)?;
+        }
+        self.visiting.pop();
+
+        self.order.push(&manifest.id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use semver::{Version, VersionReq};
+
+    use super::{resolve_install_order, DependencyError};
+    use crate::mod_man::{ModDependency, ModInfo};
+
+    fn manifest(id: &str, version: &str, dependencies: &[(&str, &str)]) -> ModInfo {
+        ModInfo {
+            id: id.to_string(),
+            version: Version::parse(version).unwrap(),
+            dependencies: dependencies
+                .iter()
+                .map(|(dep_id, range)| ModDependency {
+                    id: dep_id.to_string(),
+                    version_range: VersionReq::parse(range).unwrap(),
+                    mod_link: None,
+                    required: true,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn diamond_dependency_is_installed_once_before_its_dependants() {
+        let target = manifest("app", "1.0.0", &[("left", "^1.0.0"), ("right", "^1.0.0")]);
+        let available = [
+            manifest("left", "1.0.0", &[("base", "^1.0.0")]),
+            manifest("right", "1.0.0", &[("base", ">=1.2.0")]),
+            manifest("base", "1.1.0", &[]),
+            manifest("base", "1.3.0", &[]),
+        ];
+
+        let order: Vec<String> = resolve_install_order(&target, &available)
+            .unwrap()
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+        assert_eq!(order, ["base", "left", "right", "app"]);
+    }
+
+    #[test]
+    fn incompatible_ranges_of_the_same_dependency_are_refused() {
+        let target = manifest("app", "1.0.0", &[("left", "^1.0.0"), ("right", "^1.0.0")]);
+        let available = [
+            manifest("left", "1.0.0", &[("base", "^1.0.0")]),
+            manifest("right", "1.0.0", &[("base", "^2.0.0")]),
+            manifest("base", "1.3.0", &[]),
+            manifest("base", "2.0.0", &[]),
+        ];
+
+        let err = resolve_install_order(&target, &available).unwrap_err();
+        match err.downcast_ref::<DependencyError>() {
+            Some(DependencyError::MissingDependency { id, required_range }) => {
+                assert_eq!(id, "base");
+                assert_eq!(required_range, &VersionReq::parse("^2.0.0").unwrap());
+            }
+            other => panic!("Expected a missing dependency, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn cycles_are_detected() {
+        let target = manifest("app", "1.0.0", &[("lib", "*")]);
+        let available = [manifest("lib", "1.0.0", &[("app", "*")])];
+
+        let err = resolve_install_order(&target, &available).unwrap_err();
+        match err.downcast_ref::<DependencyError>() {
+            Some(DependencyError::Cycle { involved }) => {
+                assert_eq!(involved, &["app", "lib", "app"]);
+            }
+            other => panic!("Expected a cycle, got {other:?}"),
+        }
+    }
+}
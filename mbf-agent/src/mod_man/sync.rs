@@ -0,0 +1,205 @@
+//! Works out the steps needed to make the installed mods match a desired set of mods, e.g. from a mod profile.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use semver::Version;
+
+use super::Mod;
+use crate::models::{
+    request::DesiredMod,
+    response::{Skipped, SkippedMod, SyncPlan, SyncStep},
+};
+
+/// The state of a mod that is currently loaded, as needed by [plan_sync] and [detect_conflicts](super::detect_conflicts).
+pub struct InstalledMod {
+    pub id: String,
+    pub version: Version,
+    /// Whether the mod is installed (see [Mod::installed]).
+    pub enabled: bool,
+    /// The IDs of the mods that this mod has a required dependency on.
+    pub required_dependencies: Vec<String>,
+    /// The library files of the mod.
+    pub library_files: Vec<LibraryFile>,
+}
+
+/// A library file provided by a mod.
+pub struct LibraryFile {
+    /// The file name that the library is installed with, and so loaded by.
+    pub soname: String,
+    /// The path of the library within the extracted QMOD.
+    pub path: PathBuf,
+}
+
+impl From<&Mod> for InstalledMod {
+    fn from(value: &Mod) -> Self {
+        Self {
+            id: value.manifest().id.clone(),
+            version: value.manifest().version.clone(),
+            enabled: value.installed(),
+            required_dependencies: value
+                .manifest()
+                .dependencies
+                .iter()
+                .filter(|dep| dep.required)
+                .map(|dep| dep.id.clone())
+                .collect(),
+            library_files: value
+                .manifest()
+                .library_files
+                .iter()
+                .filter_map(|lib_file| {
+                    Path::new(lib_file).file_name().map(|soname| LibraryFile {
+                        soname: soname.to_string_lossy().into_owned(),
+                        path: value.path_in_mod(lib_file),
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Works out the fewest steps needed to go from the `installed` mods to the `desired` mods.
+///
+/// Any installed mod not in `desired` is removed, unless a mod that will be enabled needs it, transitively.
+/// Likewise, a mod that `desired` gives as disabled is left enabled if another enabled mod needs it.
+/// The dependencies of a mod that is not installed yet are not known, so the dependencies of the currently installed
+/// version of the mod are used instead. Any other missing dependencies are downloaded when the mod is enabled.
+///
+/// Mods in `pinned` are never changed to a version other than the one they are pinned to, and are never removed.
+/// These mods are given in [SyncPlan::skipped] instead, though they may still be enabled or disabled.
+///
+/// # Returns
+/// The steps, which must be carried out in order. Steps are given in the order disable, remove, install, then enable,
+/// so every mod needed by a mod has been installed by the time the mod is enabled.
+pub fn plan_sync(
+    desired: &[DesiredMod],
+    installed: &[InstalledMod],
+    pinned: &BTreeMap<String, Version>,
+) -> SyncPlan {
+    let installed_by_id: HashMap<&str, &InstalledMod> = installed
+        .iter()
+        .map(|installed_mod| (installed_mod.id.as_str(), installed_mod))
+        .collect();
+    let desired_by_id: HashMap<&str, &DesiredMod> = desired
+        .iter()
+        .map(|desired_mod| (desired_mod.id.as_str(), desired_mod))
+        .collect();
+
+    // Find every mod needed by an enabled mod, including the enabled mods themselves.
+    let mut needed: HashSet<&str> = desired
+        .iter()
+        .filter(|desired_mod| desired_mod.enabled)
+        .map(|desired_mod| desired_mod.id.as_str())
+        .collect();
+    let mut to_check: Vec<&str> = needed.iter().copied().collect();
+    while let Some(id) = to_check.pop() {
+        if let Some(installed_mod) = installed_by_id.get(id) {
+            for dep_id in &installed_mod.required_dependencies {
+                if needed.insert(dep_id) {
+                    to_check.push(dep_id);
+                }
+            }
+        }
+    }
+
+    let mut disables = Vec::new();
+    let mut removals = Vec::new();
+    let mut installs = Vec::new();
+    let mut enables = Vec::new();
+    let mut skipped = Vec::new();
+    // Gives the version a mod is pinned to, if it is pinned to a version other than `version`.
+    let pinned_away_from = |id: &str, version: &Version| {
+        pinned
+            .get(id)
+            .filter(|pinned_version| *pinned_version != version)
+    };
+
+    for installed_mod in installed {
+        let id = installed_mod.id.clone();
+        let desired_mod = desired_by_id.get(installed_mod.id.as_str());
+        if let Some(version) = pinned.get(&id) {
+            let pin_blocks = match desired_mod {
+                None => !needed.contains(id.as_str()),
+                Some(desired_mod) => {
+                    desired_mod.version != installed_mod.version
+                        && pinned_away_from(&id, &desired_mod.version).is_some()
+                }
+            };
+            if pin_blocks {
+                skipped.push(pinned_skip(&id, version));
+            }
+        }
+
+        match desired_mod {
+            None if needed.contains(installed_mod.id.as_str()) => {}
+            None if pinned.contains_key(&id) => {}
+            None => removals.push(SyncStep::Remove { id }),
+            Some(desired_mod)
+                if desired_mod.version != installed_mod.version
+                    && pinned_away_from(&id, &desired_mod.version).is_none() =>
+            {
+                installs.push(install_step(desired_mod));
+                if desired_mod.enabled {
+                    enables.push(SyncStep::Enable { id });
+                }
+            }
+            Some(desired_mod) if desired_mod.enabled && !installed_mod.enabled => {
+                enables.push(SyncStep::Enable { id })
+            }
+            Some(desired_mod)
+                if !desired_mod.enabled
+                    && installed_mod.enabled
+                    && !needed.contains(installed_mod.id.as_str()) =>
+            {
+                disables.push(SyncStep::Disable { id })
+            }
+            Some(_) => {}
+        }
+    }
+
+    for desired_mod in desired {
+        if !installed_by_id.contains_key(desired_mod.id.as_str()) {
+            if let Some(version) = pinned_away_from(&desired_mod.id, &desired_mod.version) {
+                skipped.push(pinned_skip(&desired_mod.id, version));
+                continue;
+            }
+
+            installs.push(install_step(desired_mod));
+            if desired_mod.enabled {
+                enables.push(SyncStep::Enable {
+                    id: desired_mod.id.clone(),
+                });
+            }
+        }
+    }
+
+    SyncPlan {
+        steps: disables
+            .into_iter()
+            .chain(removals)
+            .chain(installs)
+            .chain(enables)
+            .collect(),
+        skipped,
+    }
+}
+
+fn pinned_skip(id: &str, version: &Version) -> SkippedMod {
+    SkippedMod {
+        id: id.to_string(),
+        reason: Skipped::Pinned {
+            version: version.clone(),
+        },
+    }
+}
+
+fn install_step(desired_mod: &DesiredMod) -> SyncStep {
+    SyncStep::Install {
+        id: desired_mod.id.clone(),
+        version: desired_mod.version.clone(),
+        source_url: desired_mod.source_url.clone(),
+    }
+}
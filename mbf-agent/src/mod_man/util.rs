@@ -1,8 +1,15 @@
 //! Utilities for mod management.
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use log::{debug, warn};
-use std::path::Path;
+use mbf_zip::ZipFile;
+use std::{
+    io::{Read, Seek},
+    path::{Path, PathBuf},
+};
+
+use super::ModInfo;
+use crate::{hash, paths};
 
 /// Checks if all files with the specified file names exist within a directory.
 ///
@@ -31,18 +38,35 @@ pub(super) fn files_exist_in_dir(
     }))
 }
 
-/// Used to copy stated mods/libs/early_mod files from a mod folder to the modloader folder.
+/// A file that has been copied to a staging directory, ready to be moved to its destination by [move_staged_files].
+pub(super) struct StagedFile {
+    staged_path: PathBuf,
+    dest_path: PathBuf,
+}
+
+/// Used to copy stated mods/libs/early_mod files from a mod folder to a staging directory, so that they can then be moved
+/// to the modloader folder with [move_staged_files].
+/// Each copied file is checked to be the same size as the file in the mod folder.
 /// # Arguments
 /// * `mod_folder` - The folder that contains the SO files for the mod being installed.
 /// * `files` - The paths of the mods/libs/early_mod files within `mod_folder`.
-/// These paths will be appended to `mod_folder` to get the full file path.
-/// * `modloader_folder` - The destination to copy the files to.
-/// For each file in `files`, the file name of the file (i.e. last path segment) is joined after `modloader_folder` to get the destination path.
-pub(super) fn copy_files_from_mod_folder(
+///   These paths will be appended to `mod_folder` to get the full file path.
+/// * `staging_folder` - The directory to copy the files to, which must be on the same file system as `modloader_folder`.
+/// * `modloader_folder` - The final destination of the files.
+///   For each file in `files`, the file name of the file (i.e. last path segment) is joined after `modloader_folder` to get the destination path.
+///
+/// # Returns
+/// The files that were copied to `staging_folder`. Files that are not found in `mod_folder`, or are identical to the file
+/// already at their destination, are skipped.
+pub(super) fn stage_files_from_mod_folder(
     mod_folder: impl AsRef<Path>,
     files: &[impl AsRef<Path>],
+    staging_folder: impl AsRef<Path>,
     modloader_folder: impl AsRef<Path>,
-) -> Result<()> {
+) -> Result<Vec<StagedFile>> {
+    std::fs::create_dir_all(&staging_folder).context("Creating staging directory")?;
+
+    let mut staged = Vec::new();
     for file in files {
         let file = file.as_ref();
         let file_location = mod_folder.as_ref().join(file);
@@ -55,14 +79,162 @@ pub(super) fn copy_files_from_mod_folder(
         let file_name = file
             .file_name()
             .context("Mod file should have a file name")?;
-        let copy_to = modloader_folder.as_ref().join(file_name);
+        let staged_path = staging_folder.as_ref().join(file_name);
+        let dest_path = modloader_folder.as_ref().join(file_name);
+
+        // Many mods ship the same libraries, so avoid rewriting files that are already up to date.
+        if dest_path.exists() && files_identical(&file_location, &dest_path)? {
+            debug!("{file_name:?} is already up to date");
+            continue;
+        }
+
+        debug!("Staging {file_name:?}");
+        let copied_bytes =
+            std::fs::copy(&file_location, &staged_path).context("Copying SO for mod")?;
+        let expected_bytes = std::fs::metadata(&file_location)?.len();
+        if copied_bytes != expected_bytes {
+            return Err(anyhow!(
+                "Copy of {file_name:?} was {copied_bytes} bytes, expected {expected_bytes}"
+            ));
+        }
+
+        staged.push(StagedFile {
+            staged_path,
+            dest_path,
+        });
+    }
+
+    Ok(staged)
+}
+
+/// Checks if two files have the same contents, by comparing their sizes and then their SHA-256 hashes.
+pub(super) fn files_identical(a: impl AsRef<Path>, b: impl AsRef<Path>) -> Result<bool> {
+    if std::fs::metadata(&a)?.len() != std::fs::metadata(&b)?.len() {
+        return Ok(false);
+    }
+
+    Ok(hash::sha256_file(a.as_ref())? == hash::sha256_file(b.as_ref())?)
+}
+
+/// Checks whether every early mod, late mod, library and file copy of a QMOD is already installed with identical contents.
+/// The hashes of the files within the QMOD are compared with the installed files, so nothing is extracted.
+///
+/// # Arguments
+/// * `manifest` - The manifest of the QMOD.
+/// * `qmod` - The QMOD, opened as a ZIP file.
+///
+/// # Returns
+/// `Ok(true)` if installing the QMOD would not change any installed file, or `Ok(false)` if it would,
+/// including if any file is not installed.
+pub(super) fn installed_files_match(
+    manifest: &ModInfo,
+    qmod: &mut ZipFile<impl Read + Seek>,
+) -> Result<bool> {
+    let paths = paths::default();
+    let binaries = [
+        (&manifest.mod_files, paths.early_mods()),
+        (&manifest.late_mod_files, paths.late_mods()),
+        (&manifest.library_files, paths.libs()),
+    ]
+    .into_iter()
+    .flat_map(|(files, dest)| {
+        files.iter().filter_map(move |file| {
+            Path::new(file)
+                .file_name()
+                .map(|file_name| (file.as_str(), Path::new(dest).join(file_name)))
+        })
+    });
+    let file_copies = manifest
+        .file_copies
+        .iter()
+        .map(|copy| (copy.name.as_str(), PathBuf::from(&copy.destination)));
 
-        debug!("Copying {file_name:?} to {copy_to:?}");
+    for (name_in_qmod, installed_path) in binaries.chain(file_copies) {
+        if !installed_path.exists() || !qmod.contains_file(name_in_qmod) {
+            return Ok(false);
+        }
 
-        if copy_to.exists() {
-            std::fs::remove_file(&copy_to).context("Removing existing mod file")?;
+        let contents = qmod
+            .read_file(name_in_qmod)
+            .context("Reading file from QMOD")?;
+        if std::fs::metadata(&installed_path)?.len() != contents.len() as u64
+            || hash::sha256_reader(contents.as_slice())? != hash::sha256_file(&installed_path)?
+        {
+            return Ok(false);
         }
-        std::fs::copy(file_location, copy_to).context("Copying SO for mod")?;
+    }
+
+    Ok(true)
+}
+
+/// Checks whether every file within a QMOD has the same contents as the file at the same path within `dir`,
+/// e.g. the folder that a loaded mod was extracted to. The hashes of the files are compared, so nothing is extracted.
+///
+/// # Returns
+/// `Ok(true)` if every file matches, or `Ok(false)` if any file differs or is missing from `dir`.
+pub(super) fn extracted_files_match(
+    qmod: &mut ZipFile<impl Read + Seek>,
+    dir: &Path,
+) -> Result<bool> {
+    let names: Vec<String> = qmod
+        .iter_entry_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(str::to_string)
+        .collect();
+    for name in names {
+        let extracted_path = dir.join(&name);
+        if !extracted_path.is_file() {
+            return Ok(false);
+        }
+
+        let contents = qmod.read_file(&name).context("Reading file from QMOD")?;
+        if std::fs::metadata(&extracted_path)?.len() != contents.len() as u64
+            || hash::sha256_reader(contents.as_slice())? != hash::sha256_file(&extracted_path)?
+        {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Checks whether two manifests list the same early mods, late mods, libraries and file copies.
+pub(super) fn same_files(a: &ModInfo, b: &ModInfo) -> bool {
+    let copies = |manifest: &ModInfo| -> Vec<(String, String)> {
+        manifest
+            .file_copies
+            .iter()
+            .map(|copy| (copy.name.clone(), copy.destination.clone()))
+            .collect()
+    };
+
+    a.mod_files == b.mod_files
+        && a.late_mod_files == b.late_mod_files
+        && a.library_files == b.library_files
+        && copies(a) == copies(b)
+}
+
+/// Counts the files within a directory, including those in subdirectories.
+pub(super) fn count_files(dir: &Path) -> Result<usize> {
+    let mut count = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            count += count_files(&entry.path())?;
+        } else {
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Moves files staged by [stage_files_from_mod_folder] to their destinations.
+/// Files are renamed into place, so an existing file at a destination is replaced atomically and is never left partially written.
+pub(super) fn move_staged_files(files: &[StagedFile]) -> Result<()> {
+    for file in files {
+        debug!("Moving {:?} to {:?}", file.staged_path, file.dest_path);
+        std::fs::rename(&file.staged_path, &file.dest_path).context("Moving staged mod file")?;
     }
 
     Ok(())
@@ -4,6 +4,8 @@ use std::collections::HashMap;
 
 use serde::Deserialize;
 
+use super::response::{ConfigExport, LoaderConfig, ManifestFlags, Playlist};
+
 #[derive(Deserialize)]
 #[serde(tag = "type")]
 pub enum Request {
@@ -25,6 +27,33 @@ pub enum Request {
     /// Returns a `ModSyncResult` response.
     SetModsEnabled {
         statuses: HashMap<String, bool>,
+        // Unless this is true, mods made for a different version of the game will not be enabled.
+        #[serde(default)]
+        allow_version_mismatch: bool,
+    },
+    /// Installs, removes, enables and disables mods so that the installed mods match the given set of mods.
+    /// Installed mods that are not in `mods` are removed, unless another mod that will be enabled depends on them.
+    ///
+    /// Returns a `ModSyncResult` response, or a `SyncPlan` response if `dry_run` is true.
+    SyncMods {
+        mods: Vec<DesiredMod>,
+        // Unless this is true, mods made for a different version of the game will not be enabled.
+        #[serde(default)]
+        allow_version_mismatch: bool,
+        // If this is true, nothing is changed and a `SyncPlan` response giving the steps that would be carried out is given instead.
+        #[serde(default)]
+        dry_run: bool,
+    },
+
+    /// Installs the QMODs at the given paths, resolving the dependencies of all of them together and installing them
+    /// in one pass. This is faster than importing them one at a time, and gives every mod the same version of a shared dependency.
+    /// Each QMOD that is installed is deleted.
+    /// Returns a `ModsInstalled` response giving the outcome for each QMOD.
+    InstallMods {
+        from_paths: Vec<String>,
+        // Unless this is true, mods made for a different version of the game will not be installed.
+        #[serde(default)]
+        allow_version_mismatch: bool,
     },
 
     // TODO: Make these lists to allow importing multiple mods at once?
@@ -33,25 +62,78 @@ pub enum Request {
     RemoveMod {
         id: String,
     },
+    /// Pins the mod with the given ID to a version, so that syncing mods and updating core mods will not change it.
+    /// If `version` is null, the mod is pinned to the version currently loaded.
+    /// Returns a Mods message containing the mods now installed.
+    PinMod {
+        id: String,
+        #[serde(default)]
+        version: Option<semver::Version>,
+    },
+    /// Unpins the mod with the given ID, so that it can be updated again.
+    /// Returns a Mods message containing the mods now installed.
+    UnpinMod {
+        id: String,
+    },
     /// Imports a mod or file copy from the given path on the quest.
     /// Returns an ImportedMod message containing the mods now installed, and the ID of the one that was imported, if importing a mod.
     /// Returns an ImportedFileCopy message if the file type was copied by a mod copy extension.
-    /// Returns an ImportedSong message if the file type was copied to the songs folder.
+    /// Returns an ImportedLevel message if the path is a song folder or ZIP file, which was copied to the songs folder.
+    /// A song folder is only deleted afterwards if it is inside one of the folders that uploads are written to.
     Import {
         from_path: String,
     },
+    /// Starts uploading a file in chunks, or resumes the upload if a file with the same name, size and hash was being uploaded.
+    /// Returns an `Upload` response giving the ID of the upload and where the next chunk should start.
+    BeginUpload {
+        name: String,
+        size: u64,
+        sha256: String,
+    },
+    /// Writes the next chunk of an upload, which must start at the number of bytes received so far.
+    /// `data` is encoded with base64.
+    /// Returns an `Upload` response.
+    AppendChunk {
+        id: String,
+        offset: u64,
+        data: String,
+    },
+    /// Checks that all of an upload was received with the correct hash.
+    /// Returns an `UploadFinished` response giving the path of the file, which can then be given to `Import`.
+    FinishUpload {
+        id: String,
+    },
     /// Downloads the file from the given URL and then attempts to import it.
     /// Returns an ImportResult message.
     ImportUrl {
         from_url: String,
     },
+    /// Downloads the QMOD from the given URL, then loads it and installs it along with its dependencies.
+    /// Downloaded QMODs are cached, so the URL should be for a particular release of the mod.
+    /// Returns an ImportResult message with the `ImportedMod` result.
+    InstallModFromUrl {
+        url: String,
+        // If not null, the hex SHA-256 hash of the QMOD, which is checked after downloading.
+        expected_sha256: Option<String>,
+        // Unless this is true, URLs that do not use HTTPS are rejected.
+        #[serde(default)]
+        allow_insecure: bool,
+        // Unless this is true, the mod will not be installed if it is made for a different version of the game.
+        // An `IncompatibleMod` response is given instead.
+        #[serde(default)]
+        allow_version_mismatch: bool,
+    },
 
     /// - Patches Beat Saber to add support for modloaders.
     /// - Optionally, downgrades the game to the given version if downgrade_to is Some
     /// - Saves the modloader to the appropriate locatioon on the Quest.
     /// - Wipes any existing mods.
     /// - Installs the core mods for the current version.
+    ///
     /// Returns a `Mods` response to update the frontend with the newly installed core mods.
+    /// If the game is already patched for the version, the APK is left alone and the mods are fixed as with `QuickFix` instead,
+    /// giving an `AlreadyPatched` response. If the manifest, its flags or the splash screen would change,
+    /// the game is repatched instead.
     Patch {
         downgrade_to: Option<String>,
         // The contents of the manifest of the patched app, as XML
@@ -72,21 +154,66 @@ pub enum Request {
         // If not null, this specifies a core mod JSON to use instead of the default core mods source.
         // This is useful for developers testing a core mod update.
         override_core_mod_url: Option<String>,
+        // If this is true, nothing is patched and a `PatchPlan` response describing what patching would do is given instead.
+        #[serde(default)]
+        dry_run: bool,
+        // If not null, patching is stopped if it takes longer than this many seconds.
+        // As with a `Cancel` request, the installed app is only left untouched if it had not yet been reinstalled.
+        #[serde(default)]
+        timeout_secs: Option<u64>,
+        // Controls how file data is copied into the APK when repacking it.
+        #[serde(default)]
+        repack_tuning: RepackTuning,
+        // If this is true, the modloader is added but no core mods are installed, giving a modded game with no mods.
+        // This is useful for developers testing their own mods. Ignored when remodding.
+        #[serde(default)]
+        skip_core_mods: bool,
+        // Flags to set in the manifest of the patched app, after applying `manifest_mod`. Flags that are null are left unchanged.
+        // Setting any flag for an app that is already modded repatches it, as with `remodding`, instead of only fixing the mods.
+        #[serde(default)]
+        manifest_flags: ManifestFlags,
     },
+    /// Reads the manifest of the QMOD at the given path without installing it, so that its details can be shown first.
+    /// Returns a `ModPreview` response.
+    PreviewMod {
+        from_path: String,
+    },
+    /// Checks that the QMOD at the given path has a valid manifest, contains every file its manifest references,
+    /// and that its binaries are built for the ABI of the game, without installing it.
+    /// Returns a `QmodValidation` response listing any problems.
+    ValidateQmod {
+        from_path: String,
+    },
+    /// Reinstalls the unmodded APK that was backed up when the game was last patched, keeping the installed OBB files.
+    /// If the game was downgraded when patching, the OBB files will be for the downgraded version.
+    /// Returns a `StockApkRestored` response.
+    RestoreStockApk,
 
     // Attempts to fix a blackscreen issue by removing PlayerData.dat from `/sdcard/...../files/`.
     // (and copying it to /sdcard/ModsBeforeFriday so it isn't lost. It will also be copied to the datakeeper directory iff there isn't already one there)
     // (This occurs when the permissions set by MBF copying the file lead to the game not being able to open it, typically on Quest 3,
     // unfortunately chmod 777 doesn't seem to fix the issue.)
+    // If the datakeeper PlayerData.dat is missing or corrupt, the backup in /sdcard/ModsBeforeFriday is restored there instead.
     // Gives a `FixedPlayerData` response.
     FixPlayerData,
+    /// Restores a backup of PlayerData.dat to the datakeeper directory, which is where the modded game loads it from.
+    /// (it is not restored to the game's `files` directory, as the game cannot open PlayerData.dat if copied there by MBF)
+    /// The backup is checked to be valid player data before it is restored.
+    /// Gives a `RestoredPlayerData` response.
+    RestorePlayerData {
+        source: RestoreSource,
+        // If false, the backup will not be restored if the existing datakeeper PlayerData.dat was modified more recently.
+        // This allows the frontend to ask the user to confirm before overwriting newer data.
+        overwrite_newer: bool,
+    },
     /// Gets a copy of the AndroidManifest.xml for the given Beat Saber version, converted from AXML into an XML string.
     GetDowngradedManifest {
         version: String,
     },
     /// Reinstalls any core mods that are misssing/out of date and overwrites the modloader in case it is corrupt.
     /// Should fix most issues with any installation.
-    /// Returns a `Mods` response containing the newly installed mods.
+    /// Also recreates the `.nomedia` file in ModData if it is missing.
+    /// Returns a `QuickFixed` response containing the newly installed mods and the repairs that were carried out.
     QuickFix {
         // If not null, this specifies a core mod JSON to use instead of the default core mods source.
         // This is useful for developers testing a core mod update.
@@ -94,4 +221,260 @@ pub enum Request {
         // If true, this request will delete ALL mods before reinstalling only the core mods.
         wipe_existing_mods: bool,
     },
+    /// Lists the custom levels installed for SongCore, including any folders that do not contain a valid level.
+    /// Returns a `CustomLevels` response.
+    GetCustomLevels,
+    /// Deletes the custom level with the given hash.
+    /// Returns a `CustomLevels` response containing the levels that remain installed.
+    DeleteCustomLevel {
+        hash: String,
+    },
+    /// Finds custom level folders that contain the same level, e.g. as it was imported twice under different names.
+    /// Returns a `DuplicateLevels` response.
+    FindDuplicateLevels,
+    /// Removes every duplicate custom level folder, keeping the first folder (by name) for each level.
+    /// Returns a `LevelsDeduped` response.
+    DedupeLevels,
+    /// Gets the playlists in the PlaylistManager playlists folder, marking which of their songs are installed.
+    /// Returns a `Playlists` response.
+    GetPlaylists,
+    /// Saves the given playlist, replacing any existing playlist with the same file name.
+    /// Returns a `Playlists` response containing all of the playlists.
+    SavePlaylist {
+        playlist: Playlist,
+    },
+    /// Checks that each of the paths used by MBF can be written to, and gathers details of the installed app.
+    /// Returns a `Diagnostics` response.
+    RunDiagnostics,
+    /// Gets the most recent log messages from this and previous requests, so that they can be shared when reporting a problem.
+    /// Returns a `Logs` response.
+    GetLogs,
+    /// Gets the contents of the `max` most recent log files written by the modloader and mods, e.g. from when the game crashed.
+    /// Returns a `CrashLogs` response.
+    GetCrashLogs {
+        max: usize,
+    },
+    /// Describes the installed mods and custom levels, so that the same setup can be reproduced on another headset.
+    /// Returns a `ConfigExport` response.
+    ExportConfig,
+    /// Installs each mod in a configuration from a previous `ExportConfig` request, and enables or disables it to match.
+    /// Returns a `ConfigImported` response, which lists the custom levels the user still needs to supply.
+    ImportConfig {
+        config: ConfigExport,
+    },
+    /// Deletes every mod, including QMODs and library files, without unpatching the game.
+    /// If `keep_core` is true, the core mods for the installed version are then reinstalled.
+    /// Custom levels and player data are not touched.
+    /// Returns a `ModsReset` response.
+    ResetMods {
+        keep_core: bool,
+    },
+    /// Disables every mod that is not a core mod or a dependency of one, so that the game can be booted with as few mods as possible.
+    /// The mods that were enabled beforehand are recorded, so that `ExitSafeMode` can re-enable them.
+    /// Returns a `SafeMode` response.
+    EnterSafeMode,
+    /// Puts every mod disabled or enabled by `EnterSafeMode` back how it was before safe mode was entered.
+    /// Returns a `SafeMode` response.
+    ExitSafeMode,
+    /// Saves the modloader folder, QMODs, custom levels and player data to a single ZIP archive at the given path on the quest,
+    /// so that they can be moved to another headset.
+    /// Returns a `ModDataExported` response.
+    ExportModData {
+        to_path: String,
+    },
+    /// Restores an archive made by `ExportModData`, overwriting any existing files with the same paths.
+    /// Returns a `Mods` response containing the mods now installed.
+    ImportModData {
+        from_path: String,
+    },
+    /// Works out how much space is used by each category of files that MBF manages, e.g. custom levels.
+    /// Returns a `StorageUsage` response.
+    GetStorageUsage,
+    /// Deletes the contents of each of the selected caches, leaving the cache folders themselves in place.
+    /// Mods, custom levels and player data are never touched.
+    /// Returns a `CachesCleared` response.
+    ClearCaches {
+        // The files downloaded by MBF, e.g. QMODs and diffs. Uploads in progress are kept so they can be resumed.
+        downloads: bool,
+        // The temporary files used during patching.
+        temp: bool,
+        // The cached core mod index, diff index and other resources.
+        res_cache: bool,
+        // The cache SongCore keeps of the installed levels. Clearing this makes SongCore load every level again
+        // the next time the game starts, which can fix levels not appearing. The levels themselves are not touched.
+        #[serde(default)]
+        songcore: bool,
+    },
+    /// Gets the version of Beat Saber that is installed, read from its APK without modifying anything.
+    /// Returns a `GameVersion` response.
+    GetGameVersion,
+    /// Checks whether a patch was interrupted part way through, and how far it got, without changing anything.
+    /// Returns a `RecoveryState` response.
+    GetRecoveryState,
+    /// Reads the debuggable, extractNativeLibs and requestLegacyExternalStorage flags from the manifest of the installed APK.
+    /// These can be changed with the `manifest_flags` of a `Patch` request.
+    /// Returns a `ManifestFlags` response.
+    GetManifestFlags,
+    /// Runs every check of the installed game at once: whether the APK is correctly patched, the modloader, core mods,
+    /// the binaries of installed mods, conflicts between mods and the OBB files. Nothing is changed or repaired.
+    /// Returns an `InstallationReport` response.
+    VerifyInstallation,
+    /// Checks that the servers MBF downloads core mods, diffs and mods from can be reached, and how quickly they respond.
+    /// This should be used before patching, so that users can be told about network problems before anything is changed.
+    /// Returns a `NetworkStatus` response.
+    NetworkPreflight,
+    /// Gets the latest version of each mod in the mod repo for the installed game version, and whether each is installed.
+    /// Returns an `AvailableMods` response, which lists no mods if the mod repo has none for the installed version.
+    GetAvailableMods,
+    /// Gets the modloader's configuration, creating it with the default settings if it does not exist.
+    /// Returns a `LoaderConfig` response.
+    GetLoaderConfig,
+    /// Replaces the modloader's configuration. The new settings are used the next time the game is launched.
+    /// Returns a `LoaderConfig` response containing the new configuration.
+    SetLoaderConfig {
+        config: LoaderConfig,
+    },
+    /// Reads the JSON configuration file of the mod with the given ID.
+    /// Returns a `ModConfig` response, with a null config if the mod has not saved one.
+    GetModConfig {
+        id: String,
+    },
+    /// Replaces the JSON configuration file of the mod with the given ID.
+    /// `config` is saved exactly as given, and is refused if it is not valid JSON.
+    /// Returns a `ModConfig` response containing the new configuration.
+    SetModConfig {
+        id: String,
+        config: String,
+    },
+    /// Asks the operation currently being carried out by another agent process, e.g. patching, to stop as soon as it safely can.
+    /// The cancelled request fails with an error, and any temporary files it created are removed.
+    /// Returns a `CancelRequested` response immediately, without waiting for the operation to stop.
+    Cancel,
+}
+
+impl Request {
+    /// Gets whether handling this request may modify the game, its mods or MBF's temporary files.
+    /// Only one such request can be handled at once, as [handle_request](crate::handlers::handle_request) holds the
+    /// agent lock while handling it.
+    ///
+    /// Requests that give `false` are handled without the lock, possibly while another agent is patching.
+    /// Nothing run for these requests may write to the game or its mods, including the checks done for [Request::requires_app].
+    pub fn modifies_game(&self) -> bool {
+        match self {
+            Request::Patch { dry_run, .. } | Request::SyncMods { dry_run, .. } => !dry_run,
+            Request::GetModStatus { .. }
+            | Request::GetDowngradedManifest { .. }
+            | Request::GetCustomLevels
+            | Request::FindDuplicateLevels
+            | Request::GetPlaylists
+            | Request::RunDiagnostics
+            | Request::GetLogs
+            | Request::GetCrashLogs { .. }
+            | Request::ExportConfig
+            | Request::GetLoaderConfig
+            | Request::GetModConfig { .. }
+            | Request::GetGameVersion
+            | Request::GetRecoveryState
+            | Request::GetManifestFlags
+            | Request::VerifyInstallation
+            | Request::NetworkPreflight
+            | Request::GetStorageUsage
+            | Request::GetAvailableMods
+            | Request::PreviewMod { .. }
+            | Request::ValidateQmod { .. }
+            | Request::BeginUpload { .. }
+            | Request::AppendChunk { .. }
+            | Request::FinishUpload { .. }
+            | Request::Cancel => false,
+            _ => true,
+        }
+    }
+
+    /// Gets whether handling this request requires Beat Saber to be installed.
+    /// Requests that can also deal with files left over from a previous installation, such as custom levels, give `false`.
+    ///
+    /// This is independent of [Request::modifies_game], so some requests that need the app are read-only and handled
+    /// without the lock. Any repair done when checking for the app must therefore only be done if the lock is held.
+    pub fn requires_app(&self) -> bool {
+        matches!(
+            self,
+            Request::Patch { .. }
+                | Request::RestoreStockApk
+                | Request::RemoveMod { .. }
+                | Request::PinMod { .. }
+                | Request::UnpinMod { .. }
+                | Request::SetModsEnabled { .. }
+                | Request::SyncMods { .. }
+                | Request::InstallMods { .. }
+                | Request::InstallModFromUrl { .. }
+                | Request::FixPlayerData
+                | Request::RestorePlayerData { .. }
+                | Request::QuickFix { .. }
+                | Request::VerifyInstallation
+                | Request::ExportConfig
+                | Request::ImportConfig { .. }
+                | Request::ResetMods { .. }
+                | Request::EnterSafeMode
+                | Request::ExitSafeMode
+                | Request::ImportModData { .. }
+                | Request::GetAvailableMods
+                | Request::PreviewMod { .. }
+                | Request::ValidateQmod { .. }
+        )
+    }
+}
+
+/// A mod that should be present after a `SyncMods` request.
+#[derive(Deserialize)]
+pub struct DesiredMod {
+    pub id: String,
+    pub version: semver::Version,
+    /// Whether the mod should be enabled, i.e. installed to the modloader.
+    pub enabled: bool,
+    /// The URL to download the mod from if this version of it is not already loaded.
+    #[serde(default)]
+    pub source_url: Option<String>,
+}
+
+/// Controls how file data is copied into the APK while it is repacked.
+/// Larger buffers copy faster on headsets with fast storage, but use more memory.
+#[derive(Deserialize, Copy, Clone)]
+#[serde(default)]
+pub struct RepackTuning {
+    /// The size of the buffer used to copy each file, in bytes.
+    /// Values outside of 4 KiB to 16 MiB are clamped to that range.
+    pub copy_buffer_bytes: usize,
+    /// If true, each file is read back after being copied and its CRC-32 checked against the source.
+    pub verify: bool,
+}
+
+impl RepackTuning {
+    const MIN_BUFFER_BYTES: usize = 4 * 1024;
+    const MAX_BUFFER_BYTES: usize = 16 * 1024 * 1024;
+
+    /// Gets the copy buffer size, clamped to a range that is neither too slow nor uses too much memory.
+    pub fn buffer_bytes(&self) -> usize {
+        self.copy_buffer_bytes
+            .clamp(Self::MIN_BUFFER_BYTES, Self::MAX_BUFFER_BYTES)
+    }
+}
+
+impl Default for RepackTuning {
+    fn default() -> Self {
+        Self {
+            copy_buffer_bytes: 64 * 1024,
+            verify: false,
+        }
+    }
+}
+
+/// A location that a backup of PlayerData.dat can be restored from.
+#[derive(Deserialize, Copy, Clone)]
+pub enum RestoreSource {
+    /// The backup made by MBF in `/sdcard/ModsBeforeFriday`, which survives the game being uninstalled.
+    AuxBackup,
+    /// The PlayerData.dat of the vanilla game.
+    Vanilla,
+    /// The backup PlayerData.dat made by the vanilla game.
+    VanillaBak,
 }
@@ -2,7 +2,7 @@
 
 use serde::{Deserialize, Serialize};
 
-use crate::mod_man;
+use crate::{error::AgentError, mod_man};
 
 #[derive(Serialize)]
 pub struct AppInfo {
@@ -25,6 +25,8 @@ pub struct CoreModsInfo {
     /// In these circumstances, the user needs to wait for a diff to be generated.
     pub is_awaiting_diff: bool,
     pub core_mod_install_status: InstallStatus,
+    /// The IDs of the core mods for the current version that are not installed or are out of date.
+    pub missing_core_mods: Vec<String>,
 }
 
 /// An enum that represents whether a particular piece of the modded game is:
@@ -38,7 +40,7 @@ pub enum InstallStatus {
     Missing,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Copy, Clone)]
 pub enum LogLevel {
     Error,
     Warn,
@@ -61,6 +63,8 @@ pub enum ImportResultType {
     ImportedMod {
         installed_mods: Vec<ModModel>,
         imported_id: String,
+        // Whether the mod is made for the installed game, so that the frontend can warn before the mod is enabled.
+        compatibility: Compatibility,
     },
     ImportedFileCopy {
         // The full path where the file was copied to.
@@ -68,11 +72,103 @@ pub enum ImportResultType {
         // The mod ID that the file copy belonged to
         mod_id: String,
     },
-    ImportedSong,
+    // A folder or ZIP file was imported as a custom level.
+    ImportedLevel(ImportedLevel),
     // A non-quest mod was detected (i.e. `.DLL`) and so the import failed.
     NonQuestModDetected,
 }
 
+/// Whether a mod is made for the installed game, as given by the package ID and version in its manifest.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum Compatibility {
+    Compatible,
+    // The mod is for a different app. Mods for a different app are never installed.
+    WrongPackage {
+        // The ID of the app that MBF mods.
+        expected: String,
+    },
+    // The mod is for a different version of the game.
+    // The mod is only installed if the request explicitly allows version mismatches.
+    VersionMismatch {
+        mod_target: String,
+        game: String,
+    },
+}
+
+/// Details of a custom level that was imported into the SongCore custom levels folder.
+#[derive(Serialize, Deserialize)]
+pub struct ImportedLevel {
+    /// The level hash, as computed by SongCore.
+    pub hash: String,
+    /// The full path of the folder containing the level.
+    pub path: String,
+    /// True if a level with the same hash was already installed, in which case the level was not copied again.
+    pub already_existed: bool,
+}
+
+/// A folder within the SongCore custom levels folder.
+#[derive(Serialize)]
+pub struct CustomLevel {
+    /// The name of the folder containing the level.
+    pub folder_name: String,
+    /// The level hash, as computed by SongCore, or `None` if the folder does not contain a valid level.
+    pub hash: Option<String>,
+    /// Details of the level, or `None` if the folder does not contain a valid level.
+    pub metadata: Option<LevelMetadata>,
+    /// The reason the folder does not contain a valid level, if it doesn't.
+    pub error: Option<String>,
+}
+
+/// Custom level folders that all contain the same level, i.e. have the same hash.
+#[derive(Serialize)]
+pub struct DuplicateGroup {
+    pub hash: String,
+    /// The names of the folders containing the level, sorted by name.
+    /// When removing duplicates, the first folder is kept.
+    pub folder_names: Vec<String>,
+}
+
+/// A playlist of custom levels, stored as a `.bplist` file.
+#[derive(Serialize, Deserialize)]
+pub struct Playlist {
+    /// The name of the file within the playlists folder, including the `.bplist` extension.
+    pub file_name: String,
+    pub title: String,
+    pub author: String,
+    pub description: Option<String>,
+    /// The cover image of the playlist, as base64, if it has one.
+    pub image: Option<String>,
+    pub songs: Vec<PlaylistSong>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PlaylistSong {
+    /// The level hash, as computed by SongCore.
+    pub hash: String,
+    pub song_name: Option<String>,
+    /// True if a level with this hash is installed. This is ignored when saving a playlist.
+    #[serde(default)]
+    pub installed: bool,
+}
+
+/// Details of a custom level, taken from its `Info.dat`.
+#[derive(Serialize)]
+pub struct LevelMetadata {
+    pub song_name: String,
+    pub song_author: String,
+    pub level_author: String,
+    pub bpm: f32,
+    pub difficulty_sets: Vec<LevelDifficultySet>,
+}
+
+/// The difficulties available for one characteristic of a custom level, e.g. `Standard` or `OneSaber`.
+#[derive(Serialize)]
+pub struct LevelDifficultySet {
+    pub characteristic: String,
+    pub difficulties: Vec<String>,
+}
+
 /// The trimmed version of the ModInfo type that is sent to the web client.
 #[derive(Serialize, Deserialize)]
 pub struct ModModel {
@@ -84,6 +180,9 @@ pub struct ModModel {
     pub is_enabled: bool,
     // True if the mod is core or if it is a required dependency of another core mod (potentially indirectly.)
     pub is_core: bool,
+    // True if the mod is pinned to its version, so will not be updated.
+    #[serde(default)]
+    pub is_pinned: bool,
 }
 
 impl From<&mod_man::Mod> for ModModel {
@@ -96,10 +195,67 @@ impl From<&mod_man::Mod> for ModModel {
             description: value.manifest().description.clone(),
             is_enabled: value.installed(),
             is_core: value.is_core(),
+            // Pins are saved separately to the mod, so are filled in afterwards.
+            is_pinned: false,
         }
     }
 }
 
+/// The result of checking a QMOD before it is imported.
+#[derive(Serialize)]
+pub struct QmodValidation {
+    /// The ID of the mod, or `None` if its manifest could not be read.
+    pub id: Option<String>,
+    pub version: Option<semver::Version>,
+    /// Every problem found with the QMOD. The QMOD can be imported if this is empty.
+    pub issues: Vec<QmodIssue>,
+}
+
+/// A problem that would stop a QMOD from being installed, or from loading once installed.
+#[derive(Serialize)]
+#[serde(tag = "type")]
+pub enum QmodIssue {
+    /// A file referenced by the manifest is not within the QMOD.
+    MissingEntry { entry: String },
+    /// The `mod.json` manifest is missing or invalid.
+    BadManifest { error: String },
+    /// A binary within the QMOD is not an ELF file built for the ABI of the game.
+    NoAbiMatch { entry: String, problem: String },
+}
+
+/// The outcome of installing several QMODs at once.
+#[derive(Serialize)]
+pub struct BatchInstallReport {
+    /// The outcome for each QMOD, in the order the QMODs were given.
+    pub results: Vec<BatchInstallResult>,
+}
+
+/// The outcome of installing one QMOD of a batch.
+#[derive(Serialize)]
+pub struct BatchInstallResult {
+    pub path: String,
+    /// The ID of the mod, or `None` if its manifest could not be read.
+    pub id: Option<String>,
+    pub version: Option<semver::Version>,
+    /// Why the mod was not installed, or `None` if it was installed successfully.
+    pub error: Option<String>,
+}
+
+/// The details of a QMOD that has not been installed, read from its manifest.
+#[derive(Serialize)]
+pub struct ModPreview {
+    pub id: String,
+    pub name: String,
+    pub version: semver::Version,
+    pub author: String,
+    pub description: Option<String>,
+    pub game_version: Option<String>,
+    /// Whether the mod is made for the installed game.
+    pub compatibility: Compatibility,
+    /// The IDs of the mods that the mod depends on.
+    pub dependencies: Vec<String>,
+}
+
 #[derive(Serialize)]
 #[serde(tag = "type")]
 pub enum Response {
@@ -113,12 +269,25 @@ pub enum Response {
         // Information about the status of core mods.
         // None if an internet connection could not be established.
         core_mods: Option<CoreModsInfo>,
+        // True if the game was patched with `skip_core_mods`, so the core mods are missing on purpose.
+        core_mods_intentionally_skipped: bool,
 
         modloader_install_status: InstallStatus,
+
+        // Libraries provided by more than one enabled mod with differing contents, which may crash the game.
+        conflicts: Vec<Conflict>,
     },
     Mods {
         installed_mods: Vec<ModModel>,
     },
+    QuickFixed {
+        installed_mods: Vec<ModModel>,
+        // A description of each repair that was carried out. Empty if nothing needed fixing.
+        repairs: Vec<String>,
+        // Problems with installed mod binaries that could not be repaired.
+        // These mods should be reinstalled.
+        binary_issues: Vec<BinaryIssue>,
+    },
     ModSyncResult {
         // The new state of the installed mods after the operation
         installed_mods: Vec<ModModel>,
@@ -129,6 +298,32 @@ pub enum Response {
     Patched {
         installed_mods: Vec<ModModel>,
         did_remove_dlc: bool,
+        // How long each stage of patching took, so that slow patches can be diagnosed.
+        timings: Timings,
+    },
+    ModPreview(ModPreview),
+    QmodValidation(QmodValidation),
+    ModsInstalled {
+        report: BatchInstallReport,
+        installed_mods: Vec<ModModel>,
+    },
+    // Given instead of `Patched` when the game was already patched for the requested version,
+    // in which case the APK is left untouched and only the mods are fixed, as with a `QuickFix` request.
+    AlreadyPatched {
+        installed_mods: Vec<ModModel>,
+        // A description of each repair that was carried out. Empty if nothing needed fixing.
+        mods_fixed: Vec<String>,
+        // Problems with installed mod binaries that could not be repaired.
+        binary_issues: Vec<BinaryIssue>,
+    },
+    Upload(UploadStatus),
+    UploadFinished {
+        // The path the uploaded file was saved to, which can then be imported.
+        path: String,
+    },
+    StockApkRestored {
+        // The version of the game that was reinstalled.
+        version: String,
     },
     ImportResult {
         result: ImportResultType, // The result of importing the file.
@@ -144,7 +339,607 @@ pub enum Response {
         // True if a PlayerData.dat existed to fix, false if the request did nothing.
         existed: bool,
     },
+    RestoredPlayerData {
+        // False if the backup was not restored as the existing PlayerData.dat was newer.
+        restored: bool,
+    },
     DowngradedManifest {
         manifest_xml: String,
     },
+    // Sent to update the frontend on the progress of a long-running operation.
+    // As with `LogMsg`, this will NOT be the final message sent.
+    Progress(ProgressEvent),
+    // Sent in response to a `Patch` request with `dry_run` set.
+    PatchPlan(PatchPlan),
+    Playlists {
+        // Sorted by file name.
+        playlists: Vec<Playlist>,
+    },
+    CustomLevels {
+        levels: Vec<CustomLevel>,
+    },
+    DuplicateLevels {
+        groups: Vec<DuplicateGroup>,
+    },
+    LevelsDeduped {
+        removed_folders: usize,
+        freed_bytes: u64,
+        // The levels that remain installed.
+        levels: Vec<CustomLevel>,
+    },
+    Diagnostics(DiagnosticsReport),
+    Logs {
+        // The most recent log messages, oldest first.
+        entries: Vec<LogEntry>,
+    },
+    // Sent instead of the usual response if the request needs Beat Saber to be installed and it isn't.
+    AppNotInstalled {
+        apk_id: String,
+    },
+    ConfigExport(ConfigExport),
+    ConfigImported {
+        installed_mods: Vec<ModModel>,
+        // A description of each mod in the configuration that could not be installed.
+        failed_mods: Vec<String>,
+        // The hashes of the custom levels in the configuration that are not installed.
+        // These must be supplied by the user.
+        missing_levels: Vec<String>,
+    },
+    ModsReset(ResetReport),
+    SafeMode(SafeModeState),
+    CrashLogs {
+        // Most recently modified first.
+        files: Vec<LogFile>,
+    },
+    AvailableMods {
+        // Sorted by name.
+        mods: Vec<AvailableMod>,
+    },
+    ModDataExported {
+        // The size of the archive written, in bytes.
+        bytes: u64,
+    },
+    CachesCleared(ClearReport),
+    StorageUsage(StorageUsage),
+    SyncPlan(SyncPlan),
+    LoaderConfig(LoaderConfig),
+    NetworkStatus(NetworkStatus),
+    ModConfig {
+        id: String,
+        // The configuration of the mod, or null if the mod has not saved one.
+        config: Option<serde_json::Value>,
+    },
+    RecoveryState {
+        // The state left by an interrupted patch, or null if there is nothing to recover.
+        recovery: Option<PatchRecovery>,
+    },
+    ManifestFlags {
+        // The flags set in the manifest of the installed APK, or null if Beat Saber is not installed.
+        flags: Option<ManifestFlags>,
+    },
+    InstallationReport(InstallationReport),
+    GameVersion {
+        // The version name of the installed APK, e.g. `1.37.0_9064817954`, or null if Beat Saber is not installed.
+        version: Option<String>,
+        // The semver portion of the version, or null if it is not valid semver.
+        semver: Option<semver::Version>,
+        // The build number after the underscore, if there is one.
+        build: Option<u64>,
+    },
+    // Sent instead of the usual response if the request failed.
+    Error {
+        // The kind of failure, so that the frontend can react to each differently.
+        code: AgentError,
+        // The outermost error message.
+        message: String,
+        // The full chain of errors that caused the failure.
+        detail: String,
+    },
+    // Sent instead of the usual response if a mod could not be installed as it is not made for the installed game.
+    IncompatibleMod {
+        mod_id: String,
+        compatibility: Compatibility,
+    },
+    CancelRequested,
+    // Sent instead of the usual response if another request that modifies the game is still being handled.
+    Busy,
+}
+
+/// A library that more than one enabled mod provides, with different contents in each.
+/// Only one copy can be installed, so the other mods will use a copy of the library they were not built with.
+#[derive(Serialize)]
+pub struct Conflict {
+    /// The file name of the library.
+    pub library: String,
+    /// The mods that provide the library.
+    pub mods: Vec<ConflictingMod>,
+}
+
+#[derive(Serialize)]
+pub struct ConflictingMod {
+    pub id: String,
+    pub version: semver::Version,
+}
+
+/// A mod in the mod repo that can be installed on the installed game version.
+#[derive(Serialize)]
+pub struct AvailableMod {
+    pub id: String,
+    pub name: String,
+    /// The latest version of the mod in the mod repo.
+    pub version: semver::Version,
+    pub author: String,
+    pub description: String,
+    pub cover: Option<String>,
+    pub download_url: String,
+    pub status: AvailableModStatus,
+    /// The version of the mod that is installed, if any.
+    pub installed_version: Option<semver::Version>,
+}
+
+#[derive(Serialize)]
+pub enum AvailableModStatus {
+    NotInstalled,
+    /// The mod is installed with the latest version in the mod repo or newer.
+    Installed,
+    /// An older version of the mod is installed.
+    /// Core mods are never outdated, as their updates come from the core mod index instead.
+    Outdated,
+}
+
+/// The result of resetting the installed mods.
+#[derive(Serialize)]
+pub struct ResetReport {
+    /// The mods that remain after the reset, i.e. the reinstalled core mods.
+    pub installed_mods: Vec<ModModel>,
+    /// The number of mod, library and QMOD files that were deleted.
+    pub removed_files: usize,
+    /// The number of core mods that were reinstalled.
+    pub reinstalled_core_mods: usize,
+}
+
+/// The state of the installed mods after entering or exiting safe mode.
+#[derive(Serialize)]
+pub struct SafeModeState {
+    /// Whether safe mode is now active.
+    pub active: bool,
+    /// The IDs of the mods that were disabled on entering safe mode, or re-enabled on exiting it.
+    pub changed_mods: Vec<String>,
+    pub installed_mods: Vec<ModModel>,
+    /// The mods that could not be disabled or re-enabled and why, or `None` if there were no failures.
+    pub failures: Option<String>,
+}
+
+/// Details of the device and the paths MBF uses, which can be attached to bug reports.
+#[derive(Serialize)]
+pub struct DiagnosticsReport {
+    pub apk_id: String,
+    /// The version of the installed app, or `None` if it is not installed.
+    pub game_version: Option<String>,
+    pub paths: Vec<PathCheck>,
+    pub tmp_root: &'static TmpRootStatus,
+    pub datakeeper: DatakeeperStatus,
+    pub songcore_cache: SongCoreCacheStatus,
+}
+
+/// The findings of checking every part of the modded game, which is the first thing to look at when the game or mods
+/// do not work. A check that could not be run is recorded in `errors`, and the result of that check is `None`.
+#[derive(Serialize)]
+pub struct InstallationReport {
+    /// True if every check passed and none failed to run.
+    pub healthy: bool,
+    pub game_version: String,
+    /// Whether the installed APK is patched to support mods.
+    pub modded: Option<bool>,
+    /// Descriptions of each part of the patched APK that is missing or invalid, e.g. the modloader or signature.
+    pub patch_issues: Vec<String>,
+    /// Whether the installed modloader is present and the one bundled with MBF.
+    pub modloader_status: Option<InstallStatus>,
+    /// The SHA-256 hash of the installed modloader, or `None` if it is missing.
+    pub modloader_version: Option<String>,
+    /// The release of Scotland2 that the installed modloader is, or `None` if it is missing or not a known release.
+    /// The modloader is only reported, never replaced, by a `VerifyInstallation` request.
+    pub modloader_release: Option<semver::Version>,
+    /// The IDs of the core mods that are missing or outdated.
+    pub missing_core_mods: Option<Vec<String>>,
+    /// Files of installed mods that are corrupt or built for the wrong ABI.
+    pub binary_issues: Vec<BinaryIssue>,
+    /// Libraries that more than one installed mod provides, different copies of.
+    pub conflicts: Vec<Conflict>,
+    /// Whether the OBB files are for the installed APK.
+    pub obb: Option<ObbMatch>,
+    /// Why each check that could not be run failed.
+    pub errors: Vec<String>,
+}
+
+/// Whether SongCore has cached the details of the installed levels.
+/// If levels do not appear in game, the cache may be out of date and can be cleared with a `ClearCaches` request.
+#[derive(Serialize)]
+pub struct SongCoreCacheStatus {
+    pub exists: bool,
+    /// The number of seconds since the cache was last updated, if it exists.
+    pub age_secs: Option<u64>,
+}
+
+/// Whether datakeeper is installed, and the state of the `PlayerData.dat` it keeps in ModData.
+#[derive(Serialize)]
+pub struct DatakeeperStatus {
+    pub installed: bool,
+    /// Whether datakeeper's `PlayerData.dat` exists.
+    pub player_data_exists: bool,
+    /// How recently datakeeper's `PlayerData.dat` was modified compared with the game's,
+    /// or `None` if either does not exist.
+    pub compared_to_live: Option<FileAge>,
+}
+
+/// How recently a file was modified compared with another.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum FileAge {
+    Newer,
+    Older,
+    Same,
+}
+
+/// The space used by each category of files, in bytes.
+#[derive(Serialize)]
+pub struct StorageUsage {
+    pub categories: Vec<CategoryUsage>,
+    /// The sum of the space used by every category.
+    pub total_bytes: u64,
+}
+
+/// The space used by one category of files.
+#[derive(Serialize)]
+pub struct CategoryUsage {
+    /// The category, e.g. `custom_levels` or `res_cache`.
+    pub name: String,
+    pub bytes: u64,
+}
+
+/// The result of clearing caches.
+#[derive(Serialize)]
+pub struct ClearReport {
+    pub caches: Vec<ClearedCache>,
+}
+
+/// The result of clearing one cache.
+#[derive(Serialize)]
+pub struct ClearedCache {
+    /// Which cache was cleared, e.g. `downloads`.
+    pub name: String,
+    pub path: String,
+    /// The number of files deleted.
+    pub files_removed: u64,
+    /// The total size of the files deleted, in bytes.
+    pub bytes_freed: u64,
+}
+
+/// The result of checking that MBF can write to one of the paths it uses.
+#[derive(Serialize)]
+pub struct PathCheck {
+    /// What the path is used for, e.g. `temp`.
+    pub name: String,
+    pub path: String,
+    /// True if a file could be written, read back and deleted within the path.
+    pub writable: bool,
+    /// The reason the path is not writable, if it isn't.
+    pub error: Option<String>,
+    /// The free space on the file system containing the path, in bytes, or `None` if it could not be found.
+    pub free_space: Option<u64>,
+}
+
+/// Whether the servers that MBF downloads from could be reached.
+#[derive(Serialize)]
+#[serde(tag = "state")]
+pub enum NetworkStatus {
+    /// At least one server could be reached. Some may still be unreachable, so check each host.
+    Online { hosts: Vec<HostReachability> },
+    /// None of the servers could be reached, or the agent is in offline mode.
+    Offline { hosts: Vec<HostReachability> },
+}
+
+/// The result of checking that one of the servers MBF downloads from can be reached.
+#[derive(Serialize)]
+pub struct HostReachability {
+    /// What the server is used for, e.g. `core_mod_index`.
+    pub name: String,
+    pub url: String,
+    pub reachable: bool,
+    /// How long the server took to respond, in milliseconds, if it could be reached.
+    pub latency_millis: Option<u64>,
+    /// Why the server could not be reached, if it couldn't.
+    pub error: Option<String>,
+}
+
+/// The major steps of patching, saved to [crate::paths::Paths::patch_progress] as each one begins.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PatchStep {
+    /// Downloading files and patching the APK within [crate::paths::Paths::temp]. The installed app is untouched.
+    Preparing,
+    /// Moving the OBB files to [crate::paths::Paths::obb_stash].
+    StashingObb,
+    /// Uninstalling the app and installing the patched APK.
+    Reinstalling,
+    /// The patched APK is installed, but the OBB files may not have been restored yet.
+    Reinstalled,
+}
+
+/// The result of [check_obb_matches_apk](crate::patching::check_obb_matches_apk).
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(tag = "state")]
+pub enum ObbMatch {
+    /// Every OBB file is for the installed APK version.
+    Match,
+    /// An OBB file is for a different version of the APK, so the game will fail to load.
+    /// Contains the version codes of the APK and of the first mismatched OBB file.
+    Mismatch { apk: u32, obb: u32 },
+    /// There are no OBB files.
+    ObbMissing,
+}
+
+/// The state left behind by a patch that was interrupted part way through.
+#[derive(Serialize)]
+pub struct PatchRecovery {
+    /// The step that patching had reached, or `None` if the record of it is missing or unreadable.
+    pub step: Option<PatchStep>,
+    /// The version of the game that was being patched to, if known.
+    pub target_version: Option<String>,
+    /// Whether the OBB files are stashed, and so missing from the OBB directory until they are restored.
+    pub obb_stashed: bool,
+}
+
+/// Flags set on the `application` element of the APK manifest.
+/// Each flag is `None` if it is not set in the manifest, in which case Android uses its default.
+/// When given in a `Patch` request, flags that are `None` are left unchanged.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct ManifestFlags {
+    /// `android:debuggable`, which allows a debugger to attach to the game. Defaults to false.
+    pub debuggable: Option<bool>,
+    /// `android:extractNativeLibs`, which controls whether libraries are extracted from the APK when installing.
+    /// Defaults to true. If false, libraries must be stored uncompressed and page aligned within the APK.
+    pub extract_native_libs: Option<bool>,
+    /// `android:requestLegacyExternalStorage`, which gives the game access to all of external storage on Android 10.
+    /// Defaults to false.
+    pub legacy_storage: Option<bool>,
+}
+
+/// Where MBF keeps its temporary files, including downloads and the resource cache.
+#[derive(Serialize)]
+pub struct TmpRootStatus {
+    /// The root that temporary files are kept within.
+    pub root: String,
+    /// The root that would normally be used, i.e. `/data/local/tmp`.
+    pub default_root: String,
+    /// Why the default root could not be used, if it couldn't.
+    /// This is common on firmware where `/data/local/tmp` is restricted, in which case files are kept in ModData instead.
+    pub fallback_reason: Option<String>,
+}
+
+/// A log message recorded by the agent, which can be retrieved later with a `GetLogs` request.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LogEntry {
+    /// The time the message was logged, in milliseconds since the UNIX epoch.
+    pub timestamp: u64,
+    pub level: LogLevel,
+    /// The part of MBF that logged the message, e.g. `mbf_agent::patching`.
+    pub stage: String,
+    pub message: String,
+}
+
+/// The progress of a file being uploaded in chunks.
+#[derive(Serialize)]
+pub struct UploadStatus {
+    /// Identifies the upload in later requests. This is the SHA-256 hash of the file.
+    pub id: String,
+    /// The number of bytes received so far, which is the offset that the next chunk should start at.
+    pub received: u64,
+    /// The total size of the file, in bytes.
+    pub size: u64,
+}
+
+/// A log file written by the modloader or a mod, retrieved with a `GetCrashLogs` request.
+#[derive(Serialize)]
+pub struct LogFile {
+    /// The path of the file, relative to the logs directory.
+    pub name: String,
+    pub contents: String,
+    /// True if the file was too long, so only the end of it is given in `contents`.
+    pub truncated: bool,
+    /// The time the file was last modified, in milliseconds since the UNIX epoch.
+    pub modified: u64,
+}
+
+/// A problem with one of the binary files installed by a mod.
+#[derive(Serialize)]
+pub struct BinaryIssue {
+    pub mod_id: String,
+    /// The path of the installed file.
+    pub path: String,
+    pub problem: String,
+}
+
+/// A portable description of the mods and custom levels installed, which can be used to reproduce the same setup on another headset.
+#[derive(Serialize, Deserialize)]
+pub struct ConfigExport {
+    /// The version of the game the configuration was exported from.
+    pub game_version: String,
+    pub mods: Vec<ExportedMod>,
+    /// The SongCore hashes of each installed custom level.
+    pub custom_level_hashes: Vec<String>,
+}
+
+/// A mod within a [ConfigExport].
+#[derive(Serialize, Deserialize)]
+pub struct ExportedMod {
+    pub id: String,
+    pub version: semver::Version,
+    pub enabled: bool,
+    /// The URL the QMOD can be downloaded from, or `None` if no download was found for this version of the mod.
+    pub source_url: Option<String>,
+}
+
+/// The steps needed to make the installed mods match a desired set of mods, in the order they must be carried out.
+#[derive(Serialize)]
+pub struct SyncPlan {
+    pub steps: Vec<SyncStep>,
+    /// The mods that are left as they are, rather than being made to match the desired mods.
+    pub skipped: Vec<SkippedMod>,
+}
+
+/// A mod that a [SyncPlan] does not change, even though it differs from the desired mods.
+#[derive(Serialize)]
+pub struct SkippedMod {
+    pub id: String,
+    pub reason: Skipped,
+}
+
+/// Why a mod was given as a [SkippedMod].
+#[derive(Serialize)]
+#[serde(tag = "type")]
+pub enum Skipped {
+    /// The mod has been pinned to a version with a `PinMod` request.
+    Pinned { version: semver::Version },
+}
+
+/// A step within a [SyncPlan].
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum SyncStep {
+    /// Uninstalls a mod from the modloader, keeping its QMOD.
+    Disable { id: String },
+    /// Uninstalls and deletes a mod.
+    Remove { id: String },
+    /// Downloads and loads the given version of a mod, replacing any other version of it. The mod is not enabled.
+    Install {
+        id: String,
+        version: semver::Version,
+        source_url: Option<String>,
+    },
+    /// Installs a mod, and its dependencies, to the modloader.
+    Enable { id: String },
+}
+
+impl std::fmt::Display for SyncStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyncStep::Disable { id } => write!(f, "Disable {id}"),
+            SyncStep::Remove { id } => write!(f, "Remove {id}"),
+            SyncStep::Install { id, version, .. } => write!(f, "Install {id} v{version}"),
+            SyncStep::Enable { id } => write!(f, "Enable {id}"),
+        }
+    }
+}
+
+/// The settings of the modloader, saved within the modloader directory.
+#[derive(Serialize, Deserialize, Default)]
+pub struct LoaderConfig {
+    /// The least severe level of log message that the modloader and mods should write to logcat.
+    #[serde(default)]
+    pub log_level: LoaderLogLevel,
+    /// Whether the game should wait for a debugger to be attached when launched, before loading any mods.
+    #[serde(default)]
+    pub wait_for_debugger: bool,
+}
+
+/// The verbosity of the modloader's logging.
+#[derive(Serialize, Deserialize, Copy, Clone, Default)]
+pub enum LoaderLogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Verbose,
+}
+
+/// A description of what patching the app would do, without anything being modified.
+#[derive(Serialize)]
+pub struct PatchPlan {
+    /// The steps that would be carried out, in order.
+    pub steps: Vec<PlannedStep>,
+    /// The URLs of the files that would need to be downloaded.
+    pub downloads_needed: Vec<String>,
+    /// The approximate number of bytes of temporary files that would be written, excluding downloads.
+    pub estimated_bytes: u64,
+}
+
+/// A single step of a [PatchPlan].
+#[derive(Serialize)]
+pub struct PlannedStep {
+    /// A description of what the step does.
+    pub action: String,
+    /// The path of the file or directory the step modifies, if any.
+    pub target: Option<String>,
+}
+
+/// The stages of long-running operations that progress is reported for.
+#[derive(Copy, Clone, Serialize)]
+pub enum Stage {
+    Downloading,
+    Patching,
+    Signing,
+    InstallingMods,
+    Finalizing,
+}
+
+/// The time taken by each stage of an operation.
+#[derive(Serialize)]
+pub struct Timings {
+    /// The stages that completed, in the order they finished.
+    pub stages: Vec<StageTiming>,
+    /// The sum of the time taken by every stage, in milliseconds.
+    pub total_millis: u64,
+}
+
+/// The time taken by one stage of an operation.
+#[derive(Serialize)]
+pub struct StageTiming {
+    pub stage: TimedStage,
+    pub millis: u64,
+    /// The number of bytes downloaded or written by the stage, so that throughput can be worked out.
+    /// `None` if the stage does not deal with a known number of bytes.
+    pub bytes: Option<u64>,
+}
+
+/// A stage of patching that is timed.
+#[derive(Serialize, Clone, Copy)]
+pub enum TimedStage {
+    /// Downloading libunity.so and any diffs.
+    Download,
+    /// Applying the diffs to downgrade the APK and OBB files.
+    Downgrade,
+    /// Adding the modloader and other files to the APK.
+    Repack,
+    /// Signing the APK and saving its central directory.
+    Sign,
+    /// Uninstalling the original app and installing the modded APK.
+    Reinstall,
+    /// Downloading and installing the core mods.
+    InstallCoreMods,
+}
+
+/// An update on the progress of a long-running operation.
+#[derive(Serialize)]
+pub struct ProgressEvent {
+    /// The stage of the operation currently being carried out.
+    pub stage: Stage,
+    /// The number of steps (or bytes) of the current stage completed thus far.
+    pub done: u64,
+    /// The total number of steps (or bytes) in the current stage, if known.
+    pub total: Option<u64>,
+    /// A description of what is currently happening.
+    pub message: String,
+}
+
+impl ProgressEvent {
+    /// Creates a [ProgressEvent] for the start of a stage with no known number of steps.
+    pub fn new(stage: Stage, message: impl Into<String>) -> Self {
+        Self {
+            stage,
+            done: 0,
+            total: None,
+            message: message.into(),
+        }
+    }
 }
@@ -0,0 +1,80 @@
+//! Checks that the servers MBF downloads from can be reached, so that the frontend can warn about network problems
+//! before starting an operation such as patching, rather than it failing part way through.
+
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use mbf_res_man::{default_agent, external_res};
+
+use crate::models::response::{HostReachability, NetworkStatus};
+
+// Checks are only meant to be quick, so servers that take longer than this to respond are considered unreachable.
+const PREFLIGHT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends a HEAD request to each server that MBF fetches resources from, recording whether it responded and how long it took.
+/// The requests are made at the same time, so this takes at most a few seconds even if no server responds.
+///
+/// Any response, even an error status, means that the server could be reached.
+/// In offline mode, no requests are made and every server is reported as unreachable.
+pub fn network_preflight() -> NetworkStatus {
+    let hosts: Vec<HostReachability> = if crate::is_offline() {
+        external_res::resource_hosts()
+            .into_iter()
+            .map(|(name, url)| HostReachability {
+                name: name.to_string(),
+                url,
+                reachable: false,
+                latency_millis: None,
+                error: Some("MBF is in offline mode".to_string()),
+            })
+            .collect()
+    } else {
+        std::thread::scope(|scope| {
+            let checks: Vec<_> = external_res::resource_hosts()
+                .into_iter()
+                .map(|(name, url)| scope.spawn(move || check_host(name, url)))
+                .collect();
+            checks
+                .into_iter()
+                .map(|check| check.join().expect("Host check should not panic"))
+                .collect()
+        })
+    };
+
+    if hosts.iter().any(|host| host.reachable) {
+        info!(
+            "{} of {} resource servers are reachable",
+            hosts.iter().filter(|host| host.reachable).count(),
+            hosts.len()
+        );
+        NetworkStatus::Online { hosts }
+    } else {
+        warn!("No resource servers could be reached");
+        NetworkStatus::Offline { hosts }
+    }
+}
+
+fn check_host(name: &str, url: String) -> HostReachability {
+    let started = Instant::now();
+    let result = default_agent::get_agent()
+        .head(&url)
+        .timeout(PREFLIGHT_TIMEOUT)
+        .call();
+    let latency_millis = started.elapsed().as_millis().try_into().unwrap_or(u64::MAX);
+
+    let error = match result {
+        Ok(_) | Err(ureq::Error::Status(_, _)) => None,
+        Err(ureq::Error::Transport(err)) => {
+            warn!("Could not reach {name} ({url}): {err}");
+            Some(err.to_string())
+        }
+    };
+
+    HostReachability {
+        name: name.to_string(),
+        url,
+        reachable: error.is_none(),
+        latency_millis: error.is_none().then_some(latency_millis),
+        error,
+    }
+}
@@ -1,39 +1,93 @@
 use std::{
-    fs::{File, OpenOptions},
-    io::{BufReader, BufWriter, Cursor, Read, Write},
+    fs::{File, OpenOptions, Permissions},
+    io::{BufReader, BufWriter, Cursor, ErrorKind, Read, Write},
+    os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
     process::Command,
+    sync::OnceLock,
 };
 
 use crate::{
+    atomic,
     axml::{self, AxmlWriter},
-    data_fix::fix_colour_schemes,
-    downloads,
-    models::response::{AppInfo, InstallStatus, ModLoader},
-    paths, ModTag, APK_ID,
+    cancellation,
+    data_fix::{fix_colour_schemes, validate_player_data, PlayerDataStatus},
+    downloads, hash,
+    manifest::ManifestInfo,
+    mod_man::Abi,
+    models::{
+        request::{RepackTuning, RestoreSource},
+        response::{
+            AppInfo, InstallStatus, ManifestFlags, ModLoader, ObbMatch, PatchPlan, PatchRecovery,
+            PatchStep, PlannedStep, ProgressEvent, Stage, TimedStage,
+        },
+    },
+    paths, timings, ModTag, APK_ID,
 };
 use anyhow::{anyhow, Context, Result};
-use log::{info, warn};
+use log::{error, info, warn};
 use mbf_res_man::{
     external_res,
-    models::{Diff, VersionDiffs},
+    models::{Diff, StockApkIndex, VersionDiffs},
     res_cache::ResCache,
 };
 use mbf_zip::{signing, FileCompression, ZipFile, ZIP_CRC};
+use semver::Version;
+use serde::{Deserialize, Serialize};
 
 const DEBUG_CERT_PEM: &[u8] = include_bytes!("debug_cert.pem");
 const LIB_MAIN: &[u8] = include_bytes!("../libs/libmain.so");
 const MODLOADER: &[u8] = include_bytes!("../libs/libsl2.so");
+// The release of Scotland2 that [MODLOADER] is. This must be updated whenever libsl2.so is.
+const BUNDLED_MODLOADER_RELEASE: Version = Version::new(0, 1, 6);
+// Earlier releases of Scotland2, so that an installed modloader can be recognised as older than the bundled one.
+// Each is recognised by the hash of the libsl2.so published with the release. Any other modloader, apart from the bundled
+// one, is of an unknown release and may be newer.
+const EARLIER_MODLOADER_RELEASES: [Version; 6] = [
+    Version::new(0, 1, 0),
+    Version::new(0, 1, 1),
+    Version::new(0, 1, 2),
+    Version::new(0, 1, 3),
+    Version::new(0, 1, 4),
+    Version::new(0, 1, 5),
+];
 
 const MODLOADER_NAME: &str = "libsl2.so";
 const MOD_TAG_PATH: &str = "modded.json";
+// The permission the modloader needs to load mods from ModData, which the frontend adds to the manifest.
+const MANAGE_EXTERNAL_STORAGE: &str = "android.permission.MANAGE_EXTERNAL_STORAGE";
 
-const LIB_MAIN_PATH: &str = "lib/arm64-v8a/libmain.so";
+// The unstripped libunity.so is only available for arm64.
 const LIB_UNITY_PATH: &str = "lib/arm64-v8a/libunity.so";
 
 // Aligment to use for ZIP entries with the STORE compression method, in bytes.
 // 4 is the standard value.
 const STORE_ALIGNMENT: u16 = 4;
+// Alignment for libraries that are loaded directly from the APK, when `extractNativeLibs` is false.
+// These must be aligned to a page, so they can be mapped into memory.
+const NATIVE_LIB_ALIGNMENT: u16 = 4096;
+
+static REPACK_TUNING: OnceLock<RepackTuning> = OnceLock::new();
+
+/// Sets how file data is copied into the APK when it is repacked.
+/// As each agent process handles a single request, this only needs setting once, before patching.
+/// If it is never set, [RepackTuning::default] is used.
+pub fn set_repack_tuning(tuning: RepackTuning) {
+    if REPACK_TUNING.set(tuning).is_err() {
+        warn!("Repack tuning was already set, so ignoring the new value");
+    }
+}
+
+static MANIFEST_FLAGS: OnceLock<ManifestFlags> = OnceLock::new();
+
+/// Sets the flags changed in the manifest when the APK is patched.
+/// As with [set_repack_tuning], this only needs setting once, before patching.
+/// If it is never set, no flags are changed.
+pub fn set_manifest_flags(flags: ManifestFlags) {
+    if MANIFEST_FLAGS.set(flags).is_err() {
+        warn!("Manifest flags were already set, so ignoring the new value");
+    }
+}
 
 // Mods the currently installed version of the given app and reinstalls it, without doing any downgrading.
 // If `manifest_only` is true, patching will only overwrite the manifest and will not add a modloader.
@@ -44,33 +98,39 @@ pub fn mod_current_apk(
     manifest_only: bool,
     vr_splash_path: Option<&str>,
     res_cache: &ResCache,
+    progress: &mut dyn FnMut(ProgressEvent),
 ) -> Result<()> {
+    start_patch_record(&app_info.version, &temp_path.join("mbf-tmp.apk"))?;
     let libunity_path = if manifest_only {
         None
     } else {
+        progress(ProgressEvent::new(
+            Stage::Downloading,
+            "Downloading libunity.so",
+        ));
         info!("Downloading unstripped libunity.so (this could take a minute)");
-        save_libunity(res_cache, temp_path, &app_info.version).context("Preparing libunity.so")?
+        let download_timer = timings::start(TimedStage::Download);
+        let libunity_path = save_libunity(res_cache, temp_path, &app_info.version)
+            .context("Preparing libunity.so")?;
+        download_timer.finish(Some(total_file_size(&libunity_path)));
+        libunity_path
     };
 
+    cancellation::token().check()?;
     kill_app().context("Killing Beat Saber")?;
 
     info!("Copying APK to temporary location");
     let temp_apk_path = temp_path.join("mbf-tmp.apk");
     std::fs::copy(&app_info.path, &temp_apk_path).context("Copying APK to temp")?;
 
-    info!("Saving OBB files");
-    let obb_backup = temp_path.join("obbs");
-    std::fs::create_dir_all(&obb_backup)?;
-    let obb_backups =
-        save_obbs(Path::new(paths::OBB_DIR), &obb_backup).context("Saving OBB files")?;
-
     patch_and_reinstall(
         libunity_path,
         &temp_apk_path,
-        obb_backups,
+        None,
         manifest_mod,
         manifest_only,
         vr_splash_path,
+        progress,
     )
     .context("Patching and reinstalling APK")?;
     Ok(())
@@ -85,9 +145,16 @@ pub fn downgrade_and_mod_apk(
     manifest_mod: String,
     vr_splash_path: Option<&str>,
     res_cache: &ResCache,
+    progress: &mut dyn FnMut(ProgressEvent),
 ) -> Result<bool> {
+    start_patch_record(&diffs.to_version, &temp_path.join("mbf-downgraded.apk"))?;
     // Download libunity.so *for the downgraded version*
+    progress(ProgressEvent::new(
+        Stage::Downloading,
+        "Downloading libunity.so",
+    ));
     info!("Downloading unstripped libunity.so (this could take a minute)");
+    let download_timer = timings::start(TimedStage::Download);
     let libunity_path =
         save_libunity(res_cache, temp_path, &diffs.to_version).context("Saving libunity.so")?;
 
@@ -95,12 +162,28 @@ pub fn downgrade_and_mod_apk(
     let diffs_path = temp_path.join("diffs");
     std::fs::create_dir_all(&diffs_path).context("Creating diffs directory")?;
     info!("Downloading diffs needed to downgrade Beat Saber (this could take a LONG time, make a cup of tea)");
-    download_diffs(&diffs_path, &diffs).context("Downloading diffs")?;
-
+    download_diffs(&diffs_path, &diffs, progress).context("Downloading diffs")?;
+    let diff_paths: Vec<PathBuf> = std::iter::once(&diffs.apk_diff)
+        .chain(&diffs.obb_diffs)
+        .map(|diff| diffs_path.join(&diff.diff_name))
+        .collect();
+    download_timer.finish(Some(
+        total_file_size(&libunity_path) + total_file_size(&diff_paths),
+    ));
+
+    cancellation::token().check()?;
     kill_app().context("Killing Beat Saber")?;
 
     // Copy the APK to temp, downgrading it in the process.
+    let diff_count = 1 + diffs.obb_diffs.len() as u64;
+    progress(ProgressEvent {
+        stage: Stage::Patching,
+        done: 0,
+        total: Some(diff_count),
+        message: "Downgrading APK".to_string(),
+    });
     info!("Downgrading APK");
+    let downgrade_timer = timings::start(TimedStage::Downgrade);
     let temp_apk_path = temp_path.join("mbf-downgraded.apk");
     apply_diff(
         Path::new(&app_info.path),
@@ -114,8 +197,15 @@ pub fn downgrade_and_mod_apk(
     let obb_backup_dir = temp_path.join("obbs");
     std::fs::create_dir_all(&obb_backup_dir).context("Creating OBB backup directory")?;
     let mut obb_backup_paths = Vec::new();
-    for obb_diff in &diffs.obb_diffs {
-        let obb_path = Path::new(paths::OBB_DIR).join(&obb_diff.file_name);
+    for (obb_idx, obb_diff) in diffs.obb_diffs.iter().enumerate() {
+        cancellation::token().check()?;
+        progress(ProgressEvent {
+            stage: Stage::Patching,
+            done: 1 + obb_idx as u64,
+            total: Some(diff_count),
+            message: format!("Downgrading obb {}", obb_diff.file_name),
+        });
+        let obb_path = Path::new(paths::default().obb_dir()).join(&obb_diff.file_name);
         if !obb_path.exists() {
             return Err(anyhow!(
                 "Obb file {} did not exist, is the Beat Saber installation corrupt",
@@ -130,23 +220,145 @@ pub fn downgrade_and_mod_apk(
             .context("Applying diff to OBB")?;
         obb_backup_paths.push(obb_backup_path);
     }
+    downgrade_timer.finish(Some(
+        total_file_size([&temp_apk_path]) + total_file_size(&obb_backup_paths),
+    ));
 
     // Beat Saber DLC asset files do not have the .obb suffix.
     // If there are any DLC, then these have been deleted by the patching process so we return true so that the user can later be informed of this.
-    let contains_dlc = has_file_with_no_extension(paths::OBB_DIR).context("Checking for DLC")?;
+    let contains_dlc =
+        has_file_with_no_extension(paths::default().obb_dir()).context("Checking for DLC")?;
 
     patch_and_reinstall(
         libunity_path,
         &temp_apk_path,
-        obb_backup_paths,
+        Some(obb_backup_paths),
         manifest_mod,
         false,
         vr_splash_path,
+        progress,
     )
     .context("Patching and reinstall APK")?;
     Ok(contains_dlc)
 }
 
+// Works out what patching the app would do, without downloading or modifying anything (other than the resource cache).
+// `diffs` gives the diffs used to downgrade the app, or `None` if the current APK is to be patched.
+pub fn plan_patch(
+    app_info: &AppInfo,
+    diffs: Option<&VersionDiffs>,
+    repatch: bool,
+    vr_splash_path: Option<&str>,
+    res_cache: &ResCache,
+) -> Result<PatchPlan> {
+    let temp_path = Path::new(paths::default().temp());
+    let obb_dir = Path::new(paths::default().obb_dir());
+    let mut plan = PatchPlan {
+        steps: Vec::new(),
+        downloads_needed: Vec::new(),
+        estimated_bytes: 0,
+    };
+    let mut step = |action: &str, target: Option<&Path>| {
+        plan.steps.push(PlannedStep {
+            action: action.to_string(),
+            target: target.map(|path| path.to_string_lossy().into_owned()),
+        })
+    };
+
+    // Only downgrading or a full patch of the current APK adds libunity.so
+    let manifest_only = diffs.is_none() && repatch;
+    let new_version = diffs
+        .map(|diffs| &diffs.to_version)
+        .unwrap_or(&app_info.version);
+    let libunity_url = if manifest_only {
+        None
+    } else {
+        external_res::get_libunity_url(res_cache, APK_ID, new_version)
+            .context("Getting libunity.so URL")?
+    };
+    if libunity_url.is_some() {
+        step(
+            "Download unstripped libunity.so",
+            Some(&temp_path.join("libunity.so")),
+        );
+    }
+
+    step("Kill Beat Saber", None);
+    let mut temp_bytes = 0;
+    let obb_backup_dir = temp_path.join("obbs");
+    match diffs {
+        Some(diffs) => {
+            let diffs_path = temp_path.join("diffs");
+            step("Download diffs", Some(&diffs_path));
+            step("Downgrade APK", Some(&temp_path.join("mbf-downgraded.apk")));
+            temp_bytes += diffs.apk_diff.output_size as u64;
+            for obb_diff in &diffs.obb_diffs {
+                step(
+                    &format!("Downgrade obb {}", obb_diff.file_name),
+                    Some(&obb_backup_dir.join(&obb_diff.output_file_name)),
+                );
+                temp_bytes += obb_diff.output_size as u64;
+            }
+        }
+        None => {
+            step("Copy APK to temp", Some(&temp_path.join("mbf-tmp.apk")));
+            temp_bytes += std::fs::metadata(&app_info.path)
+                .context("Getting APK size")?
+                .len();
+
+            if obb_dir.exists() {
+                for entry in std::fs::read_dir(obb_dir)? {
+                    temp_bytes += entry?.metadata()?.len();
+                }
+            }
+        }
+    }
+
+    step("Patch AndroidManifest.xml", None);
+    if !manifest_only {
+        step("Add libmain.so", None);
+        if libunity_url.is_some() {
+            step("Replace libunity.so", None);
+        }
+    }
+    if vr_splash_path.is_some() {
+        step("Replace VR splash screen", None);
+    }
+    step("Sign APK", None);
+    step(
+        "Back up player data",
+        Some(Path::new(paths::default().aux_data_backup())),
+    );
+    step(
+        "Move OBB files to stash",
+        Some(Path::new(paths::default().obb_stash())),
+    );
+    step("Reinstall modded APK", None);
+    step("Restore OBB files", Some(obb_dir));
+    step(
+        "Install modloader",
+        Some(&Path::new(paths::default().modloader_dir()).join(MODLOADER_NAME)),
+    );
+    if !repatch {
+        step(
+            "Wipe existing mods",
+            Some(Path::new(paths::default().modloader_dir())),
+        );
+        step("Install core mods", None);
+    }
+
+    plan.downloads_needed.extend(libunity_url);
+    if let Some(diffs) = diffs {
+        plan.downloads_needed
+            .push(external_res::get_diff_url(&diffs.apk_diff));
+        plan.downloads_needed
+            .extend(diffs.obb_diffs.iter().map(external_res::get_diff_url));
+    }
+    plan.estimated_bytes = temp_bytes;
+
+    Ok(plan)
+}
+
 // Returns true if the given folder contains any files with no file extension.
 fn has_file_with_no_extension(obb_dir: impl AsRef<Path>) -> Result<bool> {
     for err_or_stat in std::fs::read_dir(obb_dir)? {
@@ -168,44 +380,84 @@ pub fn kill_app() -> Result<()> {
     Ok(())
 }
 
+// `downgraded_obbs` gives the OBB files to install with the app after downgrading it.
+// If `None`, the OBB files currently installed are kept.
 fn patch_and_reinstall(
     libunity_path: Option<PathBuf>,
     temp_apk_path: &Path,
-    obb_paths: Vec<PathBuf>,
+    downgraded_obbs: Option<Vec<PathBuf>>,
     manifest_mod: String,
     manifest_only: bool,
     vr_splash_path: Option<&str>,
+    progress: &mut dyn FnMut(ProgressEvent),
 ) -> Result<()> {
+    let source_apk = ZipFile::open(File::open(temp_apk_path).context("Opening APK")?)
+        .context("APK was invalid ZIP archive")?;
+    // A manifest only patch adds no libraries, so can be made to an APK with libraries for any ABI, or none.
+    let abi = match choose_patch_abi(&source_apk) {
+        Ok(abi) => abi,
+        Err(_) if manifest_only => Abi::Arm64V8a,
+        Err(err) => return Err(err),
+    };
+    // Patching must never change libil2cpp.so, so its CRC-32 is recorded to check the patched APK against.
+    let source_il2cpp_crc = source_apk.recorded_crc32(&il2cpp_path(abi));
+    drop(source_apk);
+
+    progress(ProgressEvent::new(Stage::Patching, "Patching APK"));
     info!("Patching APK");
     patch_apk_in_place(
         &temp_apk_path,
+        abi,
         libunity_path,
         manifest_mod,
         manifest_only,
         vr_splash_path,
+        progress,
     )
     .context("Patching APK")?;
 
-    if Path::new(paths::PLAYER_DATA).exists() {
-        info!("Backing up player data");
-        backup_player_data().context("Backing up player data")?;
-    } else {
-        info!("No player data to backup");
+    info!("Backing up player data");
+    match backup_player_data(false).context("Backing up player data")? {
+        BackupOutcome::NoPlayerData => info!("No player data to backup"),
+        BackupOutcome::BackedUp | BackupOutcome::KeptNewerBackup => {}
     }
 
-    if Path::new(paths::DATAKEEPER_PLAYER_DATA).exists() {
+    if Path::new(paths::default().datakeeper_player_data()).exists() {
         info!("Fixing colour schemes in backed up PlayerData.dat");
-        match fix_colour_schemes(paths::DATAKEEPER_PLAYER_DATA) {
+        match fix_colour_schemes(paths::default().datakeeper_player_data()) {
             Ok(_) => {}
             Err(err) => warn!("Failed to fix colour schemes: {err}"),
         }
     }
 
-    reinstall_modded_app(&temp_apk_path).context("Reinstalling modded APK")?;
+    info!("Verifying patched APK");
+    verify_patched(temp_apk_path, abi, !manifest_only, source_il2cpp_crc)
+        .context("Verifying patched APK")?;
+
+    // This is the last point at which patching can be cancelled, as the installed app is left untouched until now.
+    cancellation::token().check()?;
+
+    // Uninstalling the app deletes its OBB files, so they are moved somewhere safe first.
+    info!("Stashing OBB files");
+    record_patch_step(PatchStep::StashingObb, temp_apk_path)?;
+    let obb_stash = match downgraded_obbs {
+        Some(obb_paths) => Some(stash_obb_files(&obb_paths)?),
+        None => stash_obb()?,
+    };
+
+    progress(ProgressEvent::new(
+        Stage::Finalizing,
+        "Reinstalling modded app",
+    ));
+    record_patch_step(PatchStep::Reinstalling, temp_apk_path)?;
+    let reinstall_timer = timings::start(TimedStage::Reinstall);
+    reinstall_app(temp_apk_path).context("Reinstalling modded APK")?;
+    reinstall_timer.finish(Some(total_file_size([temp_apk_path])));
+    record_patch_step(PatchStep::Reinstalled, temp_apk_path)?;
     std::fs::remove_file(temp_apk_path)?;
 
     info!("Restoring OBB files");
-    restore_obb_files(Path::new(paths::OBB_DIR), obb_paths).context("Restoring OBB files")?;
+    restore_obb(obb_stash).context("Restoring OBB files")?;
 
     // Player data is not restored back to the `files` directory as we cannot correctly set its permissions so that BS can access it.
     // (which causes a black screen that can only be fixed by manually deleting the file)
@@ -213,35 +465,295 @@ fn patch_and_reinstall(
     Ok(())
 }
 
-pub fn backup_player_data() -> Result<()> {
-    info!("Copying to {}", paths::AUX_DATA_BACKUP);
+/// The result of [backup_player_data].
+pub enum BackupOutcome {
+    /// `PlayerData.dat` was copied to the backup locations.
+    BackedUp,
+    /// There was no `PlayerData.dat` to back up.
+    NoPlayerData,
+    /// The existing backups were modified more recently than `PlayerData.dat`, so were not overwritten.
+    KeptNewerBackup,
+}
 
-    std::fs::create_dir_all(Path::new(paths::AUX_DATA_BACKUP).parent().unwrap())?;
-    std::fs::copy(paths::PLAYER_DATA, paths::AUX_DATA_BACKUP)?;
+/// Copies the `PlayerData.dat` of the vanilla game to [paths::Paths::player_data_bak] and to [paths::Paths::aux_data_backup],
+/// which survives the game being uninstalled, and to the datakeeper `PlayerData.dat` if there is not one there already.
+///
+/// # Arguments
+/// * `force` - Whether to overwrite the backups in [paths::Paths::player_data_bak] and [paths::Paths::aux_data_backup]
+///   even if they are newer than `PlayerData.dat`.
+pub fn backup_player_data(force: bool) -> Result<BackupOutcome> {
+    let player_data = Path::new(paths::default().player_data());
+    if !player_data.exists() {
+        return Ok(BackupOutcome::NoPlayerData);
+    }
+
+    let mut backed_up = false;
+    for backup in [
+        paths::default().player_data_bak(),
+        paths::default().aux_data_backup(),
+    ] {
+        backed_up |= copy_unless_newer(player_data, Path::new(backup), force)?;
+    }
+    if !backed_up {
+        return Ok(BackupOutcome::KeptNewerBackup);
+    }
 
-    if Path::new(paths::DATAKEEPER_PLAYER_DATA).exists() {
+    if Path::new(paths::default().datakeeper_player_data()).exists() {
         warn!("Did not backup PlayerData.dat to datakeeper folder as there was already a PlayerData.dat there. 
-            The player data is still safe in {}", paths::AUX_DATA_BACKUP);
+            The player data is still safe in {}", paths::default().aux_data_backup());
     } else {
-        info!("Copying to {}", paths::DATAKEEPER_PLAYER_DATA);
-        std::fs::create_dir_all(Path::new(paths::DATAKEEPER_PLAYER_DATA).parent().unwrap())?;
-        std::fs::copy(paths::PLAYER_DATA, paths::DATAKEEPER_PLAYER_DATA)?;
+        info!("Copying to {}", paths::default().datakeeper_player_data());
+        std::fs::create_dir_all(
+            Path::new(paths::default().datakeeper_player_data())
+                .parent()
+                .unwrap(),
+        )?;
+        std::fs::copy(player_data, paths::default().datakeeper_player_data())?;
+    }
+
+    Ok(BackupOutcome::BackedUp)
+}
+
+// Copies `player_data` to `backup`, unless `backup` was modified more recently and `force` is false.
+// Gives true if the file was copied.
+fn copy_unless_newer(player_data: &Path, backup: &Path, force: bool) -> Result<bool> {
+    if !force && backup.exists() {
+        let backup_modified = std::fs::metadata(backup)?.modified()?;
+        let player_data_modified = std::fs::metadata(player_data)?.modified()?;
+        if backup_modified > player_data_modified {
+            warn!("Did not backup PlayerData.dat as the existing backup at {backup:?} is newer");
+            return Ok(false);
+        }
+    }
+
+    info!("Copying to {backup:?}");
+    std::fs::create_dir_all(backup.parent().unwrap())?;
+    std::fs::copy(player_data, backup)?;
+    Ok(true)
+}
+
+/// Restores a backup of `PlayerData.dat` to [paths::Paths::datakeeper_player_data], after checking it is valid player data.
+///
+/// # Arguments
+/// * `source` - The backup to restore.
+/// * `overwrite_newer` - Whether to restore the backup even if the existing datakeeper `PlayerData.dat` is newer.
+///
+/// # Returns
+/// `Ok(true)` if the backup was restored, or `Ok(false)` if it wasn't as the existing `PlayerData.dat` was newer.
+pub fn restore_player_data(source: RestoreSource, overwrite_newer: bool) -> Result<bool> {
+    let source_path = Path::new(match source {
+        RestoreSource::AuxBackup => paths::default().aux_data_backup(),
+        RestoreSource::Vanilla => paths::default().player_data(),
+        RestoreSource::VanillaBak => paths::default().player_data_bak(),
+    });
+    if !source_path.exists() {
+        return Err(anyhow!(
+            "No player data found at {source_path:?} to restore"
+        ));
+    }
+    let status = validate_player_data(source_path);
+    if status != PlayerDataStatus::Valid {
+        return Err(anyhow!(
+            "Player data at {source_path:?} is {status}, so cannot be restored"
+        ));
+    }
+
+    let target_path = Path::new(paths::default().datakeeper_player_data());
+    if !overwrite_newer && target_path.exists() {
+        let target_modified = std::fs::metadata(target_path)?.modified()?;
+        let source_modified = std::fs::metadata(source_path)?.modified()?;
+        if target_modified > source_modified {
+            warn!("Did not restore player data as the existing PlayerData.dat is newer than the backup");
+            return Ok(false);
+        }
     }
 
+    info!("Copying {source_path:?} to {target_path:?}");
+    std::fs::create_dir_all(target_path.parent().unwrap())?;
+    std::fs::copy(source_path, target_path).context("Copying player data")?;
+    Ok(true)
+}
+
+/// An official release of the game, with the SHA-256 hashes of the APKs it was released with.
+pub struct KnownVersion {
+    pub version: String,
+    pub sha256: Vec<String>,
+}
+
+/// Gets the known hashes of the APK for the given version of the game from the stock APK index.
+/// A version that is not in the index gives a [KnownVersion] with no hashes, which no APK will match.
+/// An APK that does not match is still patched, but a warning is given that it may not be an official release.
+pub fn known_version(index: &StockApkIndex, version: &str) -> KnownVersion {
+    KnownVersion {
+        version: version.to_string(),
+        sha256: index.get(version).cloned().unwrap_or_default(),
+    }
+}
+
+/// Returned by [verify_stock_apk] when an APK is not an unmodified official release of the game.
+#[derive(Debug)]
+pub enum StockApkError {
+    /// The APK has already been modded.
+    AlreadyPatched,
+    /// The V2 signature of the APK is missing or does not match its contents, so the APK is corrupt or was modified.
+    InvalidSignature { reason: String },
+    /// The APK is signed, but its hash is not one of the known hashes of its version.
+    /// It may be an official release whose hash is not yet known, so the user should be warned rather than stopped.
+    UnknownApk { sha256: String },
+}
+
+impl std::error::Error for StockApkError {}
+
+impl std::fmt::Display for StockApkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AlreadyPatched => write!(f, "APK is already modded"),
+            Self::InvalidSignature { reason } => write!(f, "APK signature is invalid: {reason}"),
+            Self::UnknownApk { sha256 } => {
+                write!(f, "APK with hash {sha256} is not a known official release")
+            }
+        }
+    }
+}
+
+/// Checks that the APK at `path` is an unmodified official release of the game.
+/// The APK must not have a mod tag, must have a V2 signature that matches its contents, and its SHA-256 hash must be
+/// one of the hashes of `expected`.
+///
+/// If the signature uses an algorithm that cannot be checked, only a warning is given, as official releases may be
+/// signed differently to the APKs that MBF signs.
+/// # Returns
+/// A [StockApkError] if the APK is not a known unmodified release.
+pub fn verify_stock_apk(path: &Path, expected: &KnownVersion) -> Result<()> {
+    let mut apk = ZipFile::open(File::open(path).context("Opening APK")?)
+        .context("APK was invalid ZIP archive")?;
+    if get_modloader_installed(&mut apk)?.is_some() {
+        return Err(StockApkError::AlreadyPatched.into());
+    }
+    drop(apk);
+
+    let mut apk_file = File::open(path).context("Opening APK")?;
+    if let Err(err) = signing::verify_v2_signature(&mut apk_file) {
+        if err.is::<signing::UnsupportedSignatureAlgorithm>() {
+            warn!("Could not check the signature of the APK: {err}");
+        } else {
+            return Err(StockApkError::InvalidSignature {
+                reason: err.to_string(),
+            }
+            .into());
+        }
+    }
+
+    let sha256 = hash::sha256_file(path).context("Hashing APK")?;
+    if !expected
+        .sha256
+        .iter()
+        .any(|known| known.eq_ignore_ascii_case(&sha256))
+    {
+        return Err(StockApkError::UnknownApk { sha256 }.into());
+    }
+
+    info!("APK is the official release of {}", expected.version);
     Ok(())
 }
 
-fn reinstall_modded_app(temp_apk_path: &Path) -> Result<()> {
-    info!("Reinstalling modded app");
+/// Copies the installed APK to [paths::Paths::stock_apk_backup], so that [restore_stock_apk] can reinstall the unmodded game.
+/// This must only be called when the installed APK is unmodded.
+///
+/// Nothing is copied if there is already a backup with the same SHA-256 hash. Otherwise, any existing backup is replaced,
+/// as only the most recent unmodded APK is kept.
+pub fn backup_stock_apk(app_info: &AppInfo) -> Result<()> {
+    let apk_hash = hash::sha256_file(Path::new(&app_info.path)).context("Hashing installed APK")?;
+    let backup_dir = Path::new(paths::default().stock_apk_backup());
+    let backup_path = backup_dir.join(format!("{}-{apk_hash}.apk", app_info.version));
+    if backup_path.exists() {
+        if hash::sha256_file(&backup_path)? == apk_hash {
+            info!("Unmodded APK is already backed up");
+            return Ok(());
+        }
+        warn!("Existing backup of unmodded APK did not match its hash, so backing up again");
+    }
+
+    if backup_dir.exists() {
+        std::fs::remove_dir_all(backup_dir).context("Removing previous unmodded APK backup")?;
+    }
+    std::fs::create_dir_all(backup_dir).context("Creating unmodded APK backup directory")?;
+
+    info!("Backing up unmodded APK");
+    // Copied to a temporary name first, so that a partial copy is never mistaken for a backup.
+    let part_path = backup_path.with_extension("part");
+    std::fs::copy(&app_info.path, &part_path).context("Copying unmodded APK")?;
+    std::fs::rename(&part_path, &backup_path).context("Moving unmodded APK backup into place")?;
+    Ok(())
+}
+
+/// Reinstalls the unmodded APK backed up by [backup_stock_apk], keeping the OBB files currently installed.
+/// The player data is backed up first, as uninstalling the app deletes it.
+///
+/// # Returns
+/// The version of the game that was reinstalled.
+pub fn restore_stock_apk() -> Result<String> {
+    let (version, apk_hash, backup_path) =
+        find_stock_apk_backup()?.ok_or(anyhow!("No unmodded APK has been backed up"))?;
+    if hash::sha256_file(&backup_path)? != apk_hash {
+        return Err(anyhow!(
+            "The backup of the unmodded APK is corrupt, so cannot be restored. Reinstall the game from the store instead"
+        ));
+    }
+
+    kill_app()?;
+    match backup_player_data(false).context("Backing up player data")? {
+        BackupOutcome::NoPlayerData => info!("No player data to backup"),
+        BackupOutcome::BackedUp | BackupOutcome::KeptNewerBackup => {}
+    }
+
+    info!("Stashing OBB files");
+    let obb_stash = stash_obb()?;
+    info!("Reinstalling unmodded APK for version {version}");
+    reinstall_app(&backup_path).context("Reinstalling unmodded APK")?;
+    info!("Restoring OBB files");
+    restore_obb(obb_stash).context("Restoring OBB files")?;
+
+    Ok(version)
+}
+
+// Finds the APK saved by [backup_stock_apk], giving its version and SHA-256 hash as well as its path.
+fn find_stock_apk_backup() -> Result<Option<(String, String, PathBuf)>> {
+    let backup_dir = Path::new(paths::default().stock_apk_backup());
+    if !backup_dir.exists() {
+        return Ok(None);
+    }
+
+    for entry in std::fs::read_dir(backup_dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "apk") {
+            let file_stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            // Versions may contain dashes, but hashes do not.
+            if let Some((version, apk_hash)) = file_stem.rsplit_once('-') {
+                return Ok(Some((
+                    version.to_string(),
+                    apk_hash.to_string(),
+                    path.clone(),
+                )));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+// Replaces the installed app with the given APK.
+fn reinstall_app(apk_path: &Path) -> Result<()> {
+    info!("Reinstalling app");
     Command::new("pm")
         .args(["uninstall", APK_ID])
         .output()
-        .context("Uninstalling vanilla APK")?;
+        .context("Uninstalling existing APK")?;
 
     Command::new("pm")
-        .args(["install", &temp_apk_path.to_string_lossy()])
+        .args(["install", &apk_path.to_string_lossy()])
         .output()
-        .context("Installing modded APK")?;
+        .context("Installing new APK")?;
 
     info!("Granting external storage permission");
     Command::new("appops")
@@ -263,7 +775,8 @@ fn read_file_vec(path: impl AsRef<Path>) -> Result<Vec<u8>> {
 }
 
 // Loads the file from from_path into memory, verifies it matches the checksum of the given diff,
-// applies the diff and then outputs it to to_path
+// applies the diff and then outputs it to to_path.
+// The output is then checked against the expected size and checksum of the diff, and deleted if it does not match.
 fn apply_diff(from_path: &Path, to_path: &Path, diff: &Diff, diffs_path: &Path) -> Result<()> {
     let diff_content = read_file_vec(diffs_path.join(&diff.diff_name))
         .context("Diff could not be opened. Was it downloaded")?;
@@ -292,20 +805,80 @@ fn apply_diff(from_path: &Path, to_path: &Path, diff: &Diff, diffs_path: &Path)
             .open(to_path)?,
     );
     patch.apply(&file_content, &mut output_handle)?;
+    output_handle.flush()?;
+    drop(output_handle);
+
+    info!("Verifying downgraded file");
+    if let Err(err) = verify_diff_output(to_path, diff) {
+        // Do not leave the invalid file behind, as it could otherwise be mistaken for a valid downgrade.
+        std::fs::remove_file(to_path).context("Deleting invalid downgraded file")?;
+        return Err(err);
+    }
+
+    Ok(())
+}
 
-    // TODO: Verify checksum on the result of downgrading?
+// Checks that the size and CRC32 hash of the file at `path` match the expected output of the given diff.
+fn verify_diff_output(path: &Path, diff: &Diff) -> Result<()> {
+    let mut reader = BufReader::new(File::open(path).context("Opening downgraded file")?);
+    let mut digest = ZIP_CRC.digest();
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut output_size = 0;
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+
+        digest.update(&buffer[0..bytes_read]);
+        output_size += bytes_read;
+    }
+
+    if output_size != diff.output_size {
+        return Err(anyhow!(
+            "Downgraded {} was {output_size} bytes, but expected {} bytes. The diff did not apply correctly",
+            diff.output_file_name,
+            diff.output_size
+        ));
+    }
+
+    let output_crc = digest.finalize();
+    if output_crc != diff.output_crc {
+        return Err(anyhow!(
+            "Downgraded {} had CRC {output_crc}, but expected {}. The diff did not apply correctly",
+            diff.output_file_name,
+            diff.output_crc
+        ));
+    }
 
     Ok(())
 }
 
 // Downloads the deltas needed for downgrading with the given version_diffs.
 // The diffs are saved with names matching `diff_name` in the `Diff` struct.
-fn download_diffs(to_path: impl AsRef<Path>, version_diffs: &VersionDiffs) -> Result<()> {
-    for diff in version_diffs.obb_diffs.iter() {
+fn download_diffs(
+    to_path: impl AsRef<Path>,
+    version_diffs: &VersionDiffs,
+    progress: &mut dyn FnMut(ProgressEvent),
+) -> Result<()> {
+    let diff_count = 1 + version_diffs.obb_diffs.len() as u64;
+    for (diff_idx, diff) in version_diffs.obb_diffs.iter().enumerate() {
+        progress(ProgressEvent {
+            stage: Stage::Downloading,
+            done: diff_idx as u64,
+            total: Some(diff_count),
+            message: format!("Downloading diff for OBB {}", diff.file_name),
+        });
         info!("Downloading diff for OBB {}", diff.file_name);
         download_diff_retry(diff, &to_path)?;
     }
 
+    progress(ProgressEvent {
+        stage: Stage::Downloading,
+        done: diff_count - 1,
+        total: Some(diff_count),
+        message: "Downloading diff for APK".to_string(),
+    });
     info!("Downloading diff for APK");
     download_diff_retry(&version_diffs.apk_diff, to_path)?;
 
@@ -335,49 +908,317 @@ fn save_libunity(
     let libunity_path = temp_path.as_ref().join("libunity.so");
     downloads::download_file_with_attempts(&crate::get_dl_cfg(), &libunity_path, &url)
         .context("Downloading unstripped libunity.so")?;
+    if let Err(err) =
+        downloads::check_file_content(&url, &libunity_path, downloads::ExpectedContent::Elf)
+    {
+        std::fs::remove_file(&libunity_path)?;
+        return Err(err);
+    }
 
     Ok(Some(libunity_path))
 }
 
-// Moves the OBB file to a backup location and returns the path that the OBB needs to be restored to
-fn save_obbs(obb_dir: &Path, obb_backups_path: &Path) -> Result<Vec<PathBuf>> {
-    let mut paths = Vec::new();
-    for err_or_stat in std::fs::read_dir(obb_dir)? {
-        if let Ok(stat) = err_or_stat {
-            let path = stat.path();
+/// Where OBB files were stashed, saved to [paths::Paths::obb_stash_record] so that the files can be recovered
+/// by a later request if the agent is interrupted before restoring them.
+#[derive(Serialize, Deserialize)]
+struct ObbStashRecord {
+    stash_dir: PathBuf,
+    restore_dir: PathBuf,
+}
 
-            // Rename doesn't work due to different mount points
-            let obb_backup_path = obb_backups_path.join(path.file_name().unwrap());
-            std::fs::copy(&path, &obb_backup_path)?;
-            std::fs::remove_file(&path)?;
+/// Moves all files in the OBB directory (including DLC) to [paths::Paths::obb_stash], so that they are not deleted when the app is uninstalled.
+///
+/// # Returns
+/// The folder the files were moved to, or `None` if there were no files to move.
+pub fn stash_obb() -> Result<Option<PathBuf>> {
+    let obb_dir = Path::new(paths::default().obb_dir());
+    if !obb_dir.exists() {
+        return Ok(None);
+    }
 
-            paths.push(obb_backup_path);
+    let mut obb_paths = Vec::new();
+    for entry in std::fs::read_dir(obb_dir).context("Reading OBB directory")? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            obb_paths.push(entry.path());
         }
     }
 
-    Ok(paths)
+    if obb_paths.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(stash_obb_files(&obb_paths)?))
+    }
 }
 
-// Copies the contents of `obb_backups` back to `restore_dir`, creating it if it doesn't already exist.
-fn restore_obb_files(restore_dir: &Path, obb_backups: Vec<PathBuf>) -> Result<()> {
-    std::fs::create_dir_all(restore_dir)?;
-    for backup_path in obb_backups {
-        // Cannot use a `rename` since the mount points are different
-        info!("Restoring {:?}", backup_path);
-        std::fs::copy(
-            &backup_path,
-            restore_dir.join(backup_path.file_name().unwrap()),
-        )?;
-        std::fs::remove_file(backup_path)?;
+// Moves the given files to the OBB stash and records the stash, so that they are restored to the OBB directory by [restore_obb].
+fn stash_obb_files(obb_paths: &[PathBuf]) -> Result<PathBuf> {
+    let stash_dir = PathBuf::from(paths::default().obb_stash());
+    std::fs::create_dir_all(&stash_dir).context("Creating OBB stash")?;
+
+    // The record is written first so that any files moved before the agent is interrupted can be recovered.
+    let record = ObbStashRecord {
+        stash_dir: stash_dir.clone(),
+        restore_dir: PathBuf::from(paths::default().obb_dir()),
+    };
+    atomic::write_atomic(
+        paths::default().obb_stash_record(),
+        &serde_json::to_vec(&record)?,
+    )
+    .context("Recording OBB stash")?;
+
+    for obb_path in obb_paths {
+        let file_name = obb_path
+            .file_name()
+            .ok_or(anyhow!("OBB path {obb_path:?} had no file name"))?;
+        move_file(obb_path, &stash_dir.join(file_name))
+            .context(format!("Stashing {obb_path:?}"))?;
+    }
+
+    Ok(stash_dir)
+}
+
+/// Moves the files stashed by [stash_obb] back to the OBB directory, then deletes the stash.
+/// Existing files in the OBB directory are never replaced, so a partially stashed file cannot overwrite the original.
+///
+/// # Arguments
+/// * `stashed` - The folder returned by [stash_obb]. If `None`, there is nothing to restore.
+pub fn restore_obb(stashed: Option<PathBuf>) -> Result<()> {
+    if let Some(stash_dir) = stashed {
+        restore_stash(&stash_dir, Path::new(paths::default().obb_dir()))?;
+    }
+
+    let record_path = Path::new(paths::default().obb_stash_record());
+    if record_path.exists() {
+        std::fs::remove_file(record_path).context("Removing OBB stash record")?;
+    }
+
+    Ok(())
+}
+
+/// Restores any OBB files left stashed by an earlier request that was interrupted, or that failed, before they were restored.
+/// Interrupted requests are recovered from at startup by [recover_interrupted_patch], which calls this.
+///
+/// # Returns
+/// True if a stash was found and restored.
+pub fn recover_orphaned_obb() -> Result<bool> {
+    let record_path = Path::new(paths::default().obb_stash_record());
+    if !record_path.exists() {
+        return Ok(false);
+    }
+
+    let record: ObbStashRecord =
+        serde_json::from_slice(&std::fs::read(record_path).context("Reading OBB stash record")?)
+            .context("Parsing OBB stash record")?;
+    warn!("Found OBB files stashed by an earlier request, restoring them");
+
+    if record.stash_dir.exists() {
+        restore_stash(&record.stash_dir, &record.restore_dir)?;
+    }
+    std::fs::remove_file(record_path).context("Removing OBB stash record")?;
+    Ok(true)
+}
+
+/// The step that patching has reached and the patched APK being produced.
+#[derive(Serialize, Deserialize)]
+struct PatchProgress {
+    step: PatchStep,
+    temp_apk_path: PathBuf,
+    /// The version of the game being patched to. Records made by older agents do not have this.
+    #[serde(default)]
+    target_version: Option<String>,
+}
+
+// The version of the game that this agent process is patching to, given by [start_patch_record].
+static PATCH_TARGET_VERSION: OnceLock<String> = OnceLock::new();
+
+// Records that patching to the given version has begun.
+fn start_patch_record(target_version: &str, temp_apk_path: &Path) -> Result<()> {
+    PATCH_TARGET_VERSION.get_or_init(|| target_version.to_string());
+    record_patch_step(PatchStep::Preparing, temp_apk_path)
+}
+
+// Records that patching has reached the given step.
+fn record_patch_step(step: PatchStep, temp_apk_path: &Path) -> Result<()> {
+    let progress = PatchProgress {
+        step,
+        temp_apk_path: temp_apk_path.to_path_buf(),
+        target_version: PATCH_TARGET_VERSION.get().cloned(),
+    };
+    atomic::write_atomic(
+        paths::default().patch_progress(),
+        &serde_json::to_vec(&progress)?,
+    )
+    .context("Recording patch progress")
+}
+
+/// Removes the record of patch progress, once patching has finished or failed and been cleaned up after.
+pub fn clear_patch_progress() -> Result<()> {
+    let progress_path = Path::new(paths::default().patch_progress());
+    if progress_path.exists() {
+        std::fs::remove_file(progress_path).context("Removing patch progress record")?;
     }
 
     Ok(())
 }
 
+/// Gets the state left behind by a patch that was interrupted, so that the frontend can offer to resume it or roll it back.
+/// Nothing is changed by this.
+///
+/// Interrupted patches are normally recovered from as soon as the agent starts (see [recover_interrupted_patch]), so this
+/// only gives `Some` if recovery failed, or another agent process is still patching.
+pub fn patch_recovery_state() -> Option<PatchRecovery> {
+    let progress: Option<PatchProgress> = std::fs::read(paths::default().patch_progress())
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok());
+    let obb_stashed = Path::new(paths::default().obb_stash_record()).exists();
+    if progress.is_none() && !obb_stashed {
+        return None;
+    }
+
+    Some(match progress {
+        Some(progress) => PatchRecovery {
+            step: Some(progress.step),
+            target_version: progress.target_version,
+            obb_stashed,
+        },
+        None => PatchRecovery {
+            step: None,
+            target_version: None,
+            obb_stashed,
+        },
+    })
+}
+
+/// What [recover_interrupted_patch] did to recover from an interrupted patch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryAction {
+    /// Patching stopped before the app was reinstalled.
+    /// The partially patched files were deleted and any stashed OBB files restored, leaving the app as it was.
+    RolledBack,
+    /// Patching stopped after the app was uninstalled but before the patched APK was installed.
+    /// The patched APK was complete, so it was installed and the OBB files restored.
+    CompletedReinstall,
+    /// The app was installed, but the OBB files stashed while doing so had not been restored, so they were restored.
+    RestoredObb,
+    /// There was no record of an interrupted patch, but temporary files were left behind, so they were deleted.
+    RemovedTempFiles,
+}
+
+/// Recovers from a patch that was interrupted by the agent being killed, e.g. because the headset went to sleep.
+/// Patching is completed if the app was uninstalled with the patched APK ready to install, and rolled back otherwise.
+/// In either case, any stashed OBB files are restored and the temporary files are deleted.
+///
+/// This should be called whenever the agent starts, but only while holding the [agent lock](crate::lock),
+/// as otherwise the files of a patch that is still in progress would be removed.
+///
+/// # Returns
+/// The action taken, or `None` if there was no interrupted patch or recovery failed, in which case the error is logged.
+pub fn recover_interrupted_patch() -> Option<RecoveryAction> {
+    match try_recover_interrupted_patch() {
+        Ok(action) => action,
+        Err(err) => {
+            error!("Failed to recover from interrupted patch: {err:?}");
+            None
+        }
+    }
+}
+
+fn try_recover_interrupted_patch() -> Result<Option<RecoveryAction>> {
+    let progress_path = Path::new(paths::default().patch_progress());
+    let progress: Option<PatchProgress> = if progress_path.exists() {
+        match serde_json::from_slice(&std::fs::read(progress_path)?) {
+            Ok(progress) => Some(progress),
+            Err(err) => {
+                // The record was written part way, so patching had not got far enough to modify the app.
+                warn!("Patch progress record was unreadable: {err}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let obb_stashed = Path::new(paths::default().obb_stash_record()).exists();
+    let temp_path = Path::new(paths::default().temp());
+    if !progress_path.exists() && !obb_stashed && !temp_path.exists() {
+        return Ok(None);
+    }
+
+    let action = match progress {
+        Some(PatchProgress {
+            step: PatchStep::Reinstalling,
+            temp_apk_path,
+            ..
+        }) if crate::get_apk_path()?.is_none() && temp_apk_path.exists() => {
+            warn!("The app was uninstalled part way through patching, installing the patched APK");
+            reinstall_app(&temp_apk_path).context("Installing patched APK")?;
+            // The loader is usually installed once the APK is, so patching would have been interrupted first.
+            install_modloader().context("Installing modloader")?;
+            RecoveryAction::CompletedReinstall
+        }
+        Some(PatchProgress {
+            step: PatchStep::Reinstalling | PatchStep::Reinstalled,
+            ..
+        }) => RecoveryAction::RestoredObb,
+        Some(_) => {
+            warn!("Rolling back a patch that was interrupted before the app was reinstalled");
+            RecoveryAction::RolledBack
+        }
+        None if obb_stashed => RecoveryAction::RestoredObb,
+        None => {
+            info!("Removing temporary files left by an earlier request");
+            RecoveryAction::RemovedTempFiles
+        }
+    };
+
+    recover_orphaned_obb()?;
+    if temp_path.exists() {
+        std::fs::remove_dir_all(temp_path).context("Removing temporary files")?;
+    }
+    clear_patch_progress()?;
+    Ok(Some(action))
+}
+
+// Moves each file in `stash_dir` to `restore_dir` unless a file with the same name exists there already, then deletes `stash_dir`.
+fn restore_stash(stash_dir: &Path, restore_dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(restore_dir).context("Creating OBB directory")?;
+    for entry in std::fs::read_dir(stash_dir).context("Reading OBB stash")? {
+        let stashed_path = entry?.path();
+        let restore_path = restore_dir.join(stashed_path.file_name().unwrap());
+        if restore_path.exists() {
+            warn!("{restore_path:?} already exists, discarding stashed copy");
+        } else {
+            info!("Restoring {:?}", stashed_path);
+            move_file(&stashed_path, &restore_path)
+                .context(format!("Restoring {stashed_path:?}"))?;
+        }
+    }
+
+    std::fs::remove_dir_all(stash_dir).context("Removing OBB stash")?;
+    Ok(())
+}
+
+// Moves a file, using a rename if possible.
+// Otherwise, e.g. if the source and destination are on different mounts, the file is copied to a temporary file
+// beside the destination, which is then renamed into place, so the destination never contains a partial file.
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    let mut part_name = to.as_os_str().to_owned();
+    part_name.push(".part");
+    let part_path = PathBuf::from(part_name);
+    std::fs::copy(from, &part_path).context("Copying file")?;
+    std::fs::rename(&part_path, to).context("Moving copied file into place")?;
+    std::fs::remove_file(from).context("Removing original file")?;
+    Ok(())
+}
+
 pub fn get_modloader_path() -> Result<PathBuf> {
-    let modloaders_path = format!("/sdcard/ModData/{APK_ID}/Modloader/");
+    let modloaders_path = paths::default().modloader_dir();
 
-    std::fs::create_dir_all(&modloaders_path)?;
+    std::fs::create_dir_all(modloaders_path)?;
     Ok(PathBuf::from(modloaders_path).join(MODLOADER_NAME))
 }
 
@@ -395,59 +1236,354 @@ pub fn install_modloader() -> Result<()> {
     Ok(())
 }
 
+/// Creates the `.nomedia` file in ModData, and ModData itself, if they do not already exist.
+/// Without this file, the media scanner indexes every mod and song file, which slows down the headset.
+///
+/// # Returns
+/// True if the file was created, false if it already existed.
+pub fn ensure_nomedia() -> Result<bool> {
+    create_nomedia(Path::new(paths::default().moddata_nomedia()))
+}
+
+// Creates the `.nomedia` file at `nomedia_path`, and its parent folders, unless it already exists.
+fn create_nomedia(nomedia_path: &Path) -> Result<bool> {
+    if nomedia_path.exists() {
+        return Ok(false);
+    }
+
+    if let Some(moddata_path) = nomedia_path.parent() {
+        std::fs::create_dir_all(moddata_path).context("Creating ModData directory")?;
+    }
+    atomic::write_atomic(nomedia_path, &[]).context("Creating .nomedia file")?;
+    Ok(true)
+}
+
+/// Records whether core mods were deliberately not installed when the game was patched,
+/// so that the mod status can explain why they are missing.
+pub fn set_core_mods_skipped(skipped: bool) -> Result<()> {
+    let marker_path = Path::new(paths::default().core_mods_skipped());
+    if skipped {
+        std::fs::create_dir_all(paths::default().modloader_dir())
+            .context("Creating modloader directory")?;
+        atomic::write_atomic(marker_path, &[]).context("Creating core mods skipped marker")
+    } else if marker_path.exists() {
+        std::fs::remove_file(marker_path).context("Removing core mods skipped marker")
+    } else {
+        Ok(())
+    }
+}
+
+/// Gets whether core mods were deliberately not installed when the game was last patched.
+pub fn core_mods_skipped() -> bool {
+    Path::new(paths::default().core_mods_skipped()).exists()
+}
+
+// The modes that files and directories in ModData need for the game to be able to read them.
+const MODDATA_DIR_MODE: u32 = 0o755;
+const MODDATA_FILE_MODE: u32 = 0o644;
+
+/// Makes the modloader, mod and custom level directories (and their contents) readable by the game.
+/// On some rooted setups, these end up with modes that stop the game from reading them, so mods silently fail to load.
+///
+/// Directories are given mode `755` and files mode `644`. Symbolic links are not followed.
+/// If the mode of a file cannot be changed as MBF isn't permitted to, a warning is logged and the file is skipped.
+pub fn repair_moddata_permissions() -> Result<()> {
+    let paths = paths::default();
+    let mut repaired_roots: Vec<&Path> = Vec::new();
+    for root in [
+        paths.modloader_dir(),
+        paths.libs(),
+        paths.early_mods(),
+        paths.late_mods(),
+        paths.custom_levels(),
+    ]
+    .map(Path::new)
+    {
+        // The mod directories are within the modloader directory, so their contents have already been repaired.
+        if !root.exists()
+            || repaired_roots
+                .iter()
+                .any(|repaired| root.starts_with(repaired))
+        {
+            continue;
+        }
+
+        let changed = repair_permissions_recursive(root)
+            .with_context(|| format!("Repairing permissions of {root:?}"))?;
+        if changed > 0 {
+            info!("Fixed the permissions of {changed} files/directories in {root:?}");
+        }
+        repaired_roots.push(root);
+    }
+
+    Ok(())
+}
+
+// Sets the mode of `path` and everything within it to MODDATA_DIR_MODE or MODDATA_FILE_MODE.
+// Gives the number of files and directories whose mode was changed.
+fn repair_permissions_recursive(path: &Path) -> Result<u32> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if metadata.file_type().is_symlink() {
+        return Ok(0);
+    }
+
+    let expected_mode = if metadata.is_dir() {
+        MODDATA_DIR_MODE
+    } else {
+        MODDATA_FILE_MODE
+    };
+    let mut changed = 0;
+    if metadata.permissions().mode() & 0o777 != expected_mode {
+        match std::fs::set_permissions(path, Permissions::from_mode(expected_mode)) {
+            Ok(_) => changed += 1,
+            Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+                warn!("Not permitted to change the mode of {path:?}: {err}")
+            }
+            Err(err) => return Err(err).context(format!("Setting mode of {path:?}")),
+        }
+    }
+
+    if metadata.is_dir() {
+        let entries = match std::fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+                warn!("Not permitted to read {path:?}: {err}");
+                return Ok(changed);
+            }
+            Err(err) => return Err(err).context(format!("Reading {path:?}")),
+        };
+        for entry in entries {
+            changed += repair_permissions_recursive(&entry?.path())?;
+        }
+    }
+
+    Ok(changed)
+}
+
 /// Checks the installed libsl2.so to see if it is present and up to date.
 pub fn get_modloader_status() -> Result<InstallStatus> {
+    info!("Checking if modloader is up to date");
     let loader_path = get_modloader_path()?;
+    if !loader_path.exists() {
+        return Ok(InstallStatus::Missing);
+    }
 
-    info!("Checking if modloader is up to date");
-    if loader_path.exists() {
-        // Load the existing modloader into memory
-        let mut existing_loader_bytes = Vec::<u8>::new();
-        std::fs::File::open(loader_path)
-            .context("Opening existing modloader (to read) to check if up to date")?
-            .read_to_end(&mut existing_loader_bytes)
-            .context("Reading existing modloader")?;
-
-        // Check if it's all up-to-date
-        if existing_loader_bytes == MODLOADER {
-            Ok(InstallStatus::Ready)
-        } else {
-            Ok(InstallStatus::NeedUpdate)
-        }
+    let installed_version =
+        hash::sha256_file(&loader_path).context("Hashing existing modloader")?;
+    if installed_version == bundled_modloader_version() {
+        Ok(InstallStatus::Ready)
     } else {
-        Ok(InstallStatus::Missing)
+        Ok(InstallStatus::NeedUpdate)
+    }
+}
+
+/// Gets the version of the modloader that MBF installs.
+/// Scotland2 binaries contain no version number, so the version is identified by the SHA-256 hash of the binary.
+pub fn bundled_modloader_version() -> String {
+    hash::sha256_reader(MODLOADER).expect("Reading from a slice cannot fail")
+}
+
+/// Gets the version of the installed modloader, in the same format as [bundled_modloader_version].
+///
+/// # Returns
+/// The version, or `None` if no modloader is installed or it could not be read.
+pub fn modloader_version() -> Option<String> {
+    let loader_path = get_modloader_path().ok()?;
+    if !loader_path.exists() {
+        return None;
+    }
+
+    match hash::sha256_file(&loader_path) {
+        Ok(version) => Some(version),
+        Err(err) => {
+            warn!("Failed to read installed modloader: {err:?}");
+            None
+        }
+    }
+}
+
+/// Gets the SHA-256 hash of the libsl2.so of each earlier release of Scotland2, along with the release.
+/// Releases that cannot be fetched are left out, with a warning, so their modloaders will not be recognised.
+pub fn earlier_modloader_releases(res_cache: &ResCache) -> Vec<(String, Version)> {
+    EARLIER_MODLOADER_RELEASES
+        .iter()
+        .filter_map(|release| {
+            match external_res::get_scotland2_release(res_cache, release)
+                .and_then(hash::sha256_reader)
+            {
+                Ok(sha256) => Some((sha256, release.clone())),
+                Err(err) => {
+                    warn!(
+                        "Failed to get Scotland2 v{release}, so it cannot be recognised: {err:?}"
+                    );
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Gets the release of Scotland2 with the given SHA-256 hash, in the format given by [modloader_version].
+///
+/// # Arguments
+/// * `earlier_releases` - The hashes of earlier releases, as given by [earlier_modloader_releases].
+/// # Returns
+/// The release, or `None` if the hash is not of the bundled release or one of `earlier_releases`.
+pub fn modloader_release(sha256: &str, earlier_releases: &[(String, Version)]) -> Option<Version> {
+    if sha256.eq_ignore_ascii_case(&bundled_modloader_version()) {
+        return Some(BUNDLED_MODLOADER_RELEASE);
+    }
+
+    earlier_releases
+        .iter()
+        .find(|(hash, _)| hash.eq_ignore_ascii_case(sha256))
+        .map(|(_, release)| release.clone())
+}
+
+/// Gets the release of Scotland2 with the given SHA-256 hash, like [modloader_release].
+/// The earlier releases are only fetched if the hash is not of the bundled release.
+pub fn find_modloader_release(sha256: &str) -> Option<Version> {
+    if sha256.eq_ignore_ascii_case(&bundled_modloader_version()) {
+        return Some(BUNDLED_MODLOADER_RELEASE);
+    }
+
+    match crate::load_res_cache() {
+        Ok(res_cache) => modloader_release(sha256, &earlier_modloader_releases(&res_cache)),
+        Err(err) => {
+            warn!("Failed to load resource cache to find modloader release: {err:?}");
+            None
+        }
+    }
+}
+
+// Whether an installed modloader of the given release, or of an unknown release if `None`, should be replaced with
+// the bundled one. A modloader of an unknown release may be newer, e.g. if it was installed by hand, so is kept.
+fn should_replace_modloader(installed: Option<&Version>, bundled: &Version) -> bool {
+    installed.is_some_and(|installed| installed < bundled)
+}
+
+/// Replaces the installed modloader with the one bundled with MBF if it is a known, older release of Scotland2.
+/// An old modloader may not be able to load mods made for newer releases.
+/// A modloader that is not a known release is left alone, as it may be newer than the bundled one.
+///
+/// Does nothing if no modloader is installed, since the game has not been patched.
+///
+/// # Returns
+/// True if the modloader was upgraded.
+pub fn upgrade_modloader_if_needed() -> Result<bool> {
+    let installed_version = match modloader_version() {
+        Some(version) => version,
+        None => return Ok(false),
+    };
+    let installed_release = find_modloader_release(&installed_version);
+    if installed_release.is_none() {
+        warn!("Installed modloader {installed_version} is not a known release of Scotland2, so it will not be replaced");
     }
+    if !should_replace_modloader(installed_release.as_ref(), &BUNDLED_MODLOADER_RELEASE) {
+        return Ok(false);
+    }
+
+    crate::report_progress(ProgressEvent::new(Stage::Finalizing, "Upgrading modloader"));
+    info!(
+        "Upgrading modloader from v{} to v{BUNDLED_MODLOADER_RELEASE}",
+        installed_release.expect("Only known releases are replaced")
+    );
+    install_modloader().context("Installing newer modloader")?;
+    Ok(true)
 }
 
 fn patch_apk_in_place(
     path: impl AsRef<Path>,
+    abi: Abi,
     libunity_path: Option<PathBuf>,
     manifest_mod: String,
     manifest_only: bool,
     vr_splash_path: Option<&str>,
+    progress: &mut dyn FnMut(ProgressEvent),
 ) -> Result<()> {
+    let repack_timer = timings::start(TimedStage::Repack);
     let file = OpenOptions::new()
         .read(true)
         .write(true)
-        .open(path)
+        .open(&path)
         .context("Opening temporary APK for writing")?;
+    let original_len = file.metadata()?.len();
 
     let mut zip = ZipFile::open(file).unwrap();
     zip.set_store_alignment(STORE_ALIGNMENT);
+    let tuning = REPACK_TUNING.get().copied().unwrap_or_default();
+    info!(
+        "Repacking with a {} KiB copy buffer{}",
+        tuning.buffer_bytes() / 1024,
+        if tuning.verify {
+            ", verifying each file"
+        } else {
+            ""
+        }
+    );
+    zip.set_copy_buffer_size(tuning.buffer_bytes());
+    zip.set_verify_writes(tuning.verify);
+
+    // The binary manifest is not known until it has been converted, but is close in size to the XML.
+    let manifest_size = manifest_mod.len() as u64;
+    let lib_main = match (manifest_only, lib_main_for(abi)) {
+        (true, _) => None,
+        (false, Some(lib_main)) => Some(lib_main),
+        (false, None) => {
+            return Err(anyhow!(
+                "MBF does not include a modloader for {} devices, so this APK cannot be modded",
+                abi.name()
+            ))
+        }
+    };
+    let libunity_size = match (&libunity_path, lib_main) {
+        (Some(unity_path), Some(_)) => total_file_size([unity_path]),
+        _ => 0,
+    };
+    let mut repack_progress = RepackProgress {
+        done: 0,
+        total: manifest_size
+            + lib_main.map(|lib_main| lib_main.len() as u64).unwrap_or(0)
+            + libunity_size
+            + vr_splash_path
+                .map(|path| total_file_size([path]))
+                .unwrap_or(0),
+        progress,
+    };
 
     info!("Applying manifest mods");
-    patch_manifest(&mut zip, manifest_mod).context("Patching manifest")?;
+    let flags = patch_manifest(&mut zip, manifest_mod).context("Patching manifest")?;
+    repack_progress.written("AndroidManifest.xml", manifest_size);
 
-    let (priv_key, cert) = signing::load_cert_and_priv_key(DEBUG_CERT_PEM);
+    // Libraries that are not extracted are loaded directly from the APK, so cannot be compressed.
+    let extract_native_libs = flags.extract_native_libs != Some(false);
+    let lib_compression = if extract_native_libs {
+        FileCompression::Deflate
+    } else {
+        zip.set_store_alignment(NATIVE_LIB_ALIGNMENT);
+        FileCompression::Store
+    };
+
+    let (cert, priv_key) = signing::load_cert_and_priv_key(DEBUG_CERT_PEM);
+
+    if let Some(lib_main) = lib_main {
+        info!("Adding libmainloader for {}", abi.name());
+        let loader_path = lib_main_path(abi);
+        zip.delete_file(&loader_path);
+        zip.write_file(&loader_path, &mut Cursor::new(lib_main), lib_compression)?;
+        repack_progress.written(&loader_path, lib_main.len() as u64);
+
+        // Only one ABI is modded, so any loader left in the other ABI's libraries by an earlier patch would never be used.
+        for other_abi in get_apk_abis(&zip).into_iter().filter(|&other| other != abi) {
+            if lib_main_for(other_abi).is_some_and(|other_lib_main| {
+                zip.compute_crc32(&lib_main_path(other_abi)).ok()
+                    == Some(mbf_zip::crc_bytes(other_lib_main))
+            }) {
+                info!("Removing unused libmainloader for {}", other_abi.name());
+                zip.delete_file(&lib_main_path(other_abi));
+            }
+        }
 
-    if !manifest_only {
-        info!("Adding libmainloader");
-        zip.delete_file(LIB_MAIN_PATH);
-        zip.write_file(
-            LIB_MAIN_PATH,
-            &mut Cursor::new(LIB_MAIN),
-            FileCompression::Deflate,
-        )?;
         add_modded_tag(
             &mut zip,
             ModTag {
@@ -458,12 +1594,14 @@ fn patch_apk_in_place(
             },
         )?;
 
+        cancellation::token().check()?;
         info!("Adding unstripped libunity.so (this may take up to a minute)");
         match libunity_path {
             Some(unity_path) => {
                 let mut unity_stream =
                     File::open(unity_path).context("Opening unstripped libunity.so")?;
-                zip.write_file(LIB_UNITY_PATH, &mut unity_stream, FileCompression::Deflate)?;
+                zip.write_file(LIB_UNITY_PATH, &mut unity_stream, lib_compression)?;
+                repack_progress.written(LIB_UNITY_PATH, libunity_size);
             }
             None => warn!("No unstripped unity added to the APK! This might cause issues later"),
         }
@@ -479,15 +1617,162 @@ fn patch_apk_in_place(
             &mut vr_splash_file,
             FileCompression::Store,
         )?;
+        repack_progress.written("assets/vr_splash.png", total_file_size([splash_path]));
     }
 
+    if !extract_native_libs {
+        check_libs_stored(&zip)?;
+    }
+
+    repack_timer.finish(Some(original_len));
+
+    cancellation::token().check()?;
+    (repack_progress.progress)(ProgressEvent::new(Stage::Signing, "Signing APK"));
     info!("Signing");
-    zip.save_and_sign_v2(&cert, &priv_key)
+    let sign_timer = timings::start(TimedStage::Sign);
+    zip.save_and_sign_v2(&priv_key, &cert)
         .context("Saving/signing APK")?;
+    sign_timer.finish(Some(total_file_size([path.as_ref()])));
 
     Ok(())
 }
 
+// Reports how many bytes of files have been written to the APK while repacking it.
+struct RepackProgress<'a> {
+    done: u64,
+    // The total size of the files to write, which is an estimate for files that are converted as they are written.
+    total: u64,
+    progress: &'a mut dyn FnMut(ProgressEvent),
+}
+
+impl RepackProgress<'_> {
+    // Records that the file `name`, of `size` bytes, has been written.
+    // The bytes done are never more than the total, in case a file was larger than estimated.
+    fn written(&mut self, name: &str, size: u64) {
+        self.done = (self.done + size).min(self.total);
+        (self.progress)(ProgressEvent {
+            stage: Stage::Patching,
+            done: self.done,
+            total: Some(self.total),
+            message: format!("Added {name}"),
+        });
+    }
+}
+
+/// Returned when a patched APK is missing something that patching should have added to it.
+#[derive(Debug)]
+pub struct PatchVerificationFailed {
+    /// Descriptions of each thing that was missing or invalid.
+    pub missing: Vec<String>,
+}
+
+impl std::error::Error for PatchVerificationFailed {}
+
+impl std::fmt::Display for PatchVerificationFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The patched APK was not patched correctly, so it has not been installed. Problems found: {}",
+            self.missing.join(", ")
+        )
+    }
+}
+
+/// Re-opens an APK once it has been patched and signed, and checks that it is ready to be installed.
+/// This catches a patching step failing silently, rather than installing an APK that would not load mods.
+///
+/// # Arguments
+/// * `path` - The path of the patched APK.
+/// * `expected_abi` - The ABI that the modloader was added for.
+/// * `expect_modloader` - Whether the modloader should have been added, i.e. patching was not just of the manifest.
+/// * `source_il2cpp_crc` - The CRC-32 of `libil2cpp.so` in the APK before it was patched, or `None` if it had none.
+///   Patching must leave this library untouched, as the game cannot start without it.
+pub fn verify_patched(
+    path: &Path,
+    expected_abi: Abi,
+    expect_modloader: bool,
+    source_il2cpp_crc: Option<u32>,
+) -> Result<()> {
+    let mut missing = Vec::new();
+    let mut apk_file = File::open(path).context("Opening patched APK")?;
+    if let Err(err) = signing::verify_v2_signature(&mut apk_file) {
+        missing.push(format!("valid signature ({err})"));
+    }
+
+    let mut apk = ZipFile::open(apk_file).context("Patched APK was invalid ZIP archive")?;
+    if expect_modloader {
+        for entry in [
+            format!("lib/{}/libmain.so", expected_abi.name()),
+            MOD_TAG_PATH.to_string(),
+        ] {
+            if !apk.contains_file(&entry) {
+                missing.push(entry);
+            }
+        }
+    }
+
+    if let Some(source_crc) = source_il2cpp_crc {
+        let il2cpp_path = il2cpp_path(expected_abi);
+        if !apk.contains_file(&il2cpp_path) {
+            missing.push("libil2cpp.so".to_string());
+        } else {
+            match apk.compute_crc32(&il2cpp_path) {
+                Ok(crc) if crc == source_crc => {}
+                Ok(crc) => missing.push(format!(
+                    "unmodified libil2cpp.so (expected CRC-32 {source_crc:08x}, got {crc:08x})"
+                )),
+                Err(err) => missing.push(format!("readable libil2cpp.so ({err})")),
+            }
+        }
+    }
+
+    match ManifestInfo::read_from_apk(&mut apk) {
+        Ok(manifest) => {
+            if !manifest
+                .permissions
+                .iter()
+                .any(|permission| permission == MANAGE_EXTERNAL_STORAGE)
+            {
+                missing.push(format!("{MANAGE_EXTERNAL_STORAGE} permission"));
+            }
+        }
+        Err(err) => missing.push(format!("readable manifest ({err})")),
+    }
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(PatchVerificationFailed { missing }.into())
+    }
+}
+
+// Gets the libmainloader binary for the given ABI, or `None` if MBF does not include one.
+fn lib_main_for(abi: Abi) -> Option<&'static [u8]> {
+    match abi {
+        Abi::Arm64V8a => Some(LIB_MAIN),
+        Abi::ArmeabiV7a => None,
+    }
+}
+
+// Gets the path of libmain.so, which libmainloader replaces, within an APK with the given ABI.
+fn lib_main_path(abi: Abi) -> String {
+    format!("lib/{}/libmain.so", abi.name())
+}
+
+// Gets the path of libil2cpp.so within an APK with the given ABI.
+fn il2cpp_path(abi: Abi) -> String {
+    format!("lib/{}/libil2cpp.so", abi.name())
+}
+
+// Gets the total size of the given files, in bytes. Files that do not exist are counted as empty.
+fn total_file_size<P: AsRef<Path>>(paths: impl IntoIterator<Item = P>) -> u64 {
+    paths
+        .into_iter()
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
 fn add_modded_tag(to: &mut ZipFile<File>, tag: ModTag) -> Result<()> {
     let saved_tag = serde_json::to_vec_pretty(&tag)?;
     to.write_file(
@@ -498,6 +1783,74 @@ fn add_modded_tag(to: &mut ZipFile<File>, tag: ModTag) -> Result<()> {
     Ok(())
 }
 
+/// Works out the ABI of the native libraries within an APK from the names of the folders within `lib/`.
+///
+/// # Returns
+/// The ABI, or `None` if the APK contains no native libraries for a known ABI.
+pub fn get_apk_abi(apk: &ZipFile<File>) -> Option<Abi> {
+    apk.iter_entry_names()
+        .filter_map(|name| name.strip_prefix("lib/")?.split('/').next())
+        .find_map(Abi::from_name)
+}
+
+// Gets every known ABI that an APK has native libraries for.
+fn get_apk_abis(apk: &ZipFile<File>) -> Vec<Abi> {
+    let mut abis: Vec<Abi> = apk
+        .iter_entry_names()
+        .filter_map(|name| name.strip_prefix("lib/")?.split('/').next())
+        .filter_map(Abi::from_name)
+        .collect();
+    abis.sort_by_key(|abi| abi.name());
+    abis.dedup();
+    abis
+}
+
+/// Gets the preferred ABI of the device, i.e. the ABI that apps with libraries for more than one ABI will use.
+pub fn detect_abi() -> Result<Abi> {
+    let output = Command::new("getprop")
+        .arg("ro.product.cpu.abilist")
+        .output()
+        .context("Running getprop")?;
+    let abi_list = String::from_utf8(output.stdout).context("ABI list was not valid UTF-8")?;
+    abi_list
+        .trim()
+        .split(',')
+        .find_map(Abi::from_name)
+        .ok_or(anyhow!(
+            "Device supports no known ABI: `{}`",
+            abi_list.trim()
+        ))
+}
+
+/// Chooses the ABI to mod an APK for.
+/// If the APK has libraries for more than one ABI, the ABI that the device runs is chosen, as given by [detect_abi].
+///
+/// # Returns
+/// The ABI, or an `Err` if the APK contains no libraries for a known ABI.
+pub fn choose_patch_abi(apk: &ZipFile<File>) -> Result<Abi> {
+    let abis = get_apk_abis(apk);
+    match abis.as_slice() {
+        [] => Err(anyhow!(
+            "APK contains no native libraries for a known ABI, so it cannot be modded. Is this the Quest version of the game?"
+        )),
+        [abi] => Ok(*abi),
+        _ => {
+            let device_abi = detect_abi().unwrap_or_else(|err| {
+                warn!("Could not detect the ABI of the device, assuming arm64-v8a: {err:?}");
+                Abi::Arm64V8a
+            });
+            if abis.contains(&device_abi) {
+                Ok(device_abi)
+            } else {
+                Err(anyhow!(
+                    "APK has no libraries for {}, the ABI of this device",
+                    device_abi.name()
+                ))
+            }
+        }
+    }
+}
+
 pub fn get_modloader_installed(apk: &mut ZipFile<File>) -> Result<Option<ModLoader>> {
     if apk.contains_file(MOD_TAG_PATH) {
         let tag_data = apk.read_file(MOD_TAG_PATH).context("Reading mod tag")?;
@@ -532,22 +1885,80 @@ pub fn get_modloader_installed(apk: &mut ZipFile<File>) -> Result<Option<ModLoad
 ///
 /// MBF only supports BS versions >1.35.0, which all use OBBs so if the obb is not present
 /// the installation is invalid and we need to prompt the user to uninstall it.
+/// Checks that the OBB files are for the installed version of the APK, e.g. after downgrading.
+/// OBB files are named `main.<version code>.<package ID>.obb` (or `patch.` for patch OBBs), so the version code in each
+/// file name is compared with the version code in the manifest of the installed APK.
+/// OBB files with names not in this format, and DLC files, are ignored.
+pub fn check_obb_matches_apk() -> Result<ObbMatch> {
+    let apk_path = crate::get_apk_path()?.ok_or(anyhow!("Beat Saber is not installed"))?;
+    let mut apk = ZipFile::open(File::open(apk_path).context("Opening APK")?)
+        .context("Reading APK as ZIP")?;
+    let apk_code = ManifestInfo::read_from_apk(&mut apk)?
+        .version_code
+        .ok_or(anyhow!("APK manifest had no versionCode"))?;
+
+    let obb_dir = Path::new(paths::default().obb_dir());
+    if !obb_dir.exists() {
+        return Ok(ObbMatch::ObbMissing);
+    }
+
+    let mut found_obb = false;
+    for entry in std::fs::read_dir(obb_dir).context("Reading OBB directory")? {
+        let file_name = entry?.file_name();
+        let obb_code = match obb_version_code(&file_name.to_string_lossy()) {
+            Some(code) => code,
+            None => continue,
+        };
+
+        found_obb = true;
+        if obb_code != apk_code {
+            return Ok(ObbMatch::Mismatch {
+                apk: apk_code,
+                obb: obb_code,
+            });
+        }
+    }
+
+    Ok(if found_obb {
+        ObbMatch::Match
+    } else {
+        ObbMatch::ObbMissing
+    })
+}
+
+// Gets the version code from an OBB file name of the form `main.<version code>.<package ID>.obb`.
+fn obb_version_code(file_name: &str) -> Option<u32> {
+    let rest = file_name
+        .strip_prefix("main.")
+        .or_else(|| file_name.strip_prefix("patch."))?;
+    if !rest.to_ascii_lowercase().ends_with(".obb") {
+        return None;
+    }
+
+    rest.split('.').next()?.parse().ok()
+}
+
 pub fn check_obb_present() -> Result<bool> {
-    if !Path::new(paths::OBB_DIR).exists() {
+    if !Path::new(paths::default().obb_dir()).exists() {
         return Ok(false);
     }
 
     // Check if any of the files in the OBB directory have extension OBB
-    Ok(std::fs::read_dir(paths::OBB_DIR)?.any(|stat_res| {
-        stat_res.is_ok_and(|path| {
-            path.path()
-                .extension()
-                .is_some_and(|ext| ext.eq_ignore_ascii_case("obb"))
-        })
-    }))
+    Ok(
+        std::fs::read_dir(paths::default().obb_dir())?.any(|stat_res| {
+            stat_res.is_ok_and(|path| {
+                path.path()
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("obb"))
+            })
+        }),
+    )
 }
 
-fn patch_manifest(zip: &mut ZipFile<File>, additional_properties: String) -> Result<()> {
+// Replaces the manifest of the APK with `additional_properties` and sets the flags given by [set_manifest_flags].
+// Gives the flags set in the new manifest.
+fn patch_manifest(zip: &mut ZipFile<File>, additional_properties: String) -> Result<ManifestFlags> {
+    check_manifest_has_application(&additional_properties)?;
     let mut xml_reader = xml::EventReader::new(Cursor::new(additional_properties.as_bytes()));
 
     let mut data_output = Cursor::new(Vec::new());
@@ -559,13 +1970,247 @@ fn patch_manifest(zip: &mut ZipFile<File>, additional_properties: String) -> Res
         .finish()
         .context("Saving AXML (binary) manifest")?;
 
+    let mut manifest = data_output.into_inner();
+    let requested_flags = MANIFEST_FLAGS.get().copied().unwrap_or_default();
+    if requested_flags != ManifestFlags::default() {
+        info!("Setting manifest flags: {requested_flags:?}");
+        manifest = crate::manifest::set_flags(&manifest, &requested_flags)
+            .context("Setting manifest flags")?;
+    }
+    // A flag set by a resource reference cannot be read, but this should not stop patching.
+    let flags = crate::manifest::read_flags(&manifest).unwrap_or_else(|err| {
+        warn!("Could not read flags of modified manifest: {err:#}");
+        ManifestFlags::default()
+    });
+
     zip.delete_file("AndroidManifest.xml");
     zip.write_file(
         "AndroidManifest.xml",
-        &mut data_output,
+        &mut Cursor::new(manifest),
         FileCompression::Deflate,
     )
     .context("Writing modified manifest")?;
 
-    Ok(())
+    Ok(flags)
+}
+
+// Checks that every library in the APK is stored uncompressed, as needed when `extractNativeLibs` is false.
+fn check_libs_stored(zip: &ZipFile<File>) -> Result<()> {
+    let compressed: Vec<&str> = zip
+        .iter_entry_names()
+        .filter(|name| name.starts_with("lib/") && name.ends_with(".so"))
+        .filter(|name| zip.compression_method(name) != Some(FileCompression::Store))
+        .collect();
+    if compressed.is_empty() {
+        return Ok(());
+    }
+
+    Err(anyhow!(
+        "extractNativeLibs is false, but these libraries in the APK are compressed, so the game could not load them: {}. Set extractNativeLibs to true instead",
+        compressed.join(", ")
+    ))
+}
+
+// Checks that the given manifest XML has an `application` element, without which the game cannot be launched.
+fn check_manifest_has_application(manifest_xml: &str) -> Result<()> {
+    for event in xml::EventReader::new(Cursor::new(manifest_xml.as_bytes())) {
+        if let xml::reader::XmlEvent::StartElement { name, .. } =
+            event.context("Parsing modified manifest XML")?
+        {
+            if name.local_name == "application" {
+                return Ok(());
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "Modified manifest had no application element, so the game would not be able to launch"
+    ))
+}
+
+/// Checks whether two manifests given as XML have the same elements, attributes and text.
+/// Whitespace, comments and the order of attributes are ignored, as these are lost when converting to and from AXML.
+/// If either manifest is not valid XML, they are treated as different.
+pub fn manifests_equivalent(a: &str, b: &str) -> bool {
+    match (manifest_events(a), manifest_events(b)) {
+        (Ok(a_events), Ok(b_events)) => a_events == b_events,
+        _ => false,
+    }
+}
+
+// Parses manifest XML into a list of the events that matter when comparing it, with the attributes of each element sorted.
+fn manifest_events(manifest_xml: &str) -> Result<Vec<xml::reader::XmlEvent>> {
+    use xml::reader::XmlEvent;
+
+    let config = xml::ParserConfig::new()
+        .trim_whitespace(true)
+        .ignore_comments(true);
+    let mut events = Vec::new();
+    for event in xml::EventReader::new_with_config(Cursor::new(manifest_xml.as_bytes()), config) {
+        match event.context("Parsing manifest XML")? {
+            XmlEvent::StartElement {
+                name,
+                mut attributes,
+                namespace,
+            } => {
+                attributes.sort_by_key(|attribute| attribute.name.to_string());
+                events.push(XmlEvent::StartElement {
+                    name,
+                    attributes,
+                    namespace,
+                });
+            }
+            XmlEvent::Whitespace(_) | XmlEvent::StartDocument { .. } => {}
+            event => events.push(event),
+        }
+    }
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, io::Cursor, path::Path};
+
+    use mbf_res_man::models::StockApkIndex;
+    use mbf_zip::{signing, FileCompression, ZipFile};
+    use semver::Version;
+
+    use super::{
+        bundled_modloader_version, create_nomedia, known_version, manifests_equivalent,
+        modloader_release, should_replace_modloader, verify_stock_apk, StockApkError,
+        BUNDLED_MODLOADER_RELEASE, DEBUG_CERT_PEM,
+    };
+    use crate::{hash, test_utils::TempDir};
+
+    // Writes a signed APK containing a single file to `path`.
+    fn write_signed_apk(path: &Path) {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .unwrap();
+        let mut zip = ZipFile::create(file).unwrap();
+        zip.write_file(
+            "classes.dex",
+            &mut Cursor::new(b"dex\n035\0"),
+            FileCompression::Deflate,
+        )
+        .unwrap();
+
+        let (cert, priv_key) = signing::load_cert_and_priv_key(DEBUG_CERT_PEM);
+        zip.save_and_sign_v2(&priv_key, &cert).unwrap();
+    }
+
+    #[test]
+    fn stock_apk_must_have_a_listed_hash() {
+        let dir = TempDir::new("stock-apk");
+        let apk_path = dir.path().join("base.apk");
+        write_signed_apk(&apk_path);
+        let apk_hash = hash::sha256_file(&apk_path).unwrap();
+
+        let index = StockApkIndex::from([
+            (
+                "1.37.0_9064817954".to_string(),
+                vec![apk_hash.to_uppercase()],
+            ),
+            (
+                "1.40.0_7867".to_string(),
+                vec![
+                    "0000000000000000000000000000000000000000000000000000000000000000".to_string(),
+                ],
+            ),
+        ]);
+        verify_stock_apk(&apk_path, &known_version(&index, "1.37.0_9064817954")).unwrap();
+
+        for unlisted_version in ["1.40.0_7867", "1.28.0_4124311467"] {
+            let err =
+                verify_stock_apk(&apk_path, &known_version(&index, unlisted_version)).unwrap_err();
+            match err.downcast_ref::<StockApkError>() {
+                Some(StockApkError::UnknownApk { sha256 }) => assert_eq!(sha256, &apk_hash),
+                _ => panic!("Expected an unknown APK, got {err}"),
+            }
+        }
+    }
+
+    #[test]
+    fn replaces_only_older_known_modloaders() {
+        let bundled = Version::new(0, 1, 6);
+        assert!(should_replace_modloader(
+            Some(&Version::new(0, 1, 5)),
+            &bundled
+        ));
+        assert!(!should_replace_modloader(Some(&bundled), &bundled));
+        assert!(!should_replace_modloader(
+            Some(&Version::new(0, 2, 0)),
+            &bundled
+        ));
+        assert!(!should_replace_modloader(None, &bundled));
+    }
+
+    #[test]
+    fn earlier_modloader_releases_are_recognised_and_replaced() {
+        let bundled_hash = bundled_modloader_version();
+        let old_hash = hash::sha256_reader(&b"\x7fELF libsl2.so v0.1.4"[..]).unwrap();
+        let earlier = [
+            (old_hash.to_uppercase(), Version::new(0, 1, 4)),
+            (
+                "1111111111111111111111111111111111111111111111111111111111111111".to_string(),
+                Version::new(0, 1, 5),
+            ),
+        ];
+
+        let old_release = modloader_release(&old_hash, &earlier);
+        assert_eq!(old_release, Some(Version::new(0, 1, 4)));
+        assert!(should_replace_modloader(
+            old_release.as_ref(),
+            &BUNDLED_MODLOADER_RELEASE
+        ));
+
+        let bundled_release = modloader_release(&bundled_hash, &earlier);
+        assert_eq!(bundled_release, Some(BUNDLED_MODLOADER_RELEASE));
+        assert!(!should_replace_modloader(
+            bundled_release.as_ref(),
+            &BUNDLED_MODLOADER_RELEASE
+        ));
+
+        let unknown_hash = hash::sha256_reader(&b"\x7fELF hand-built libsl2.so"[..]).unwrap();
+        assert_eq!(modloader_release(&unknown_hash, &earlier), None);
+        assert!(!should_replace_modloader(None, &BUNDLED_MODLOADER_RELEASE));
+    }
+
+    #[test]
+    fn nomedia_is_created_once_with_its_moddata_folder() {
+        let moddata_root = TempDir::new("nomedia");
+        let nomedia_path = moddata_root
+            .path()
+            .join("com.beatgames.beatsaber")
+            .join(".nomedia");
+
+        assert!(create_nomedia(&nomedia_path).unwrap());
+        assert!(nomedia_path.is_file());
+        assert!(!create_nomedia(&nomedia_path).unwrap());
+    }
+
+    #[test]
+    fn manifests_differing_only_in_formatting_are_equivalent() {
+        let original = r#"<?xml version="1.0" encoding="utf-8"?>
+<manifest xmlns:android="http://schemas.android.com/apk/res/android" package="com.beatgames.beatsaber">
+    <!-- The permissions of the app -->
+    <uses-permission android:name="android.permission.INTERNET"/>
+    <application android:label="Beat Saber" android:debuggable="false"/>
+</manifest>"#;
+        let reformatted = r#"<manifest package="com.beatgames.beatsaber" xmlns:android="http://schemas.android.com/apk/res/android"><uses-permission android:name="android.permission.INTERNET"></uses-permission><application android:debuggable="false" android:label="Beat Saber"/></manifest>"#;
+        assert!(manifests_equivalent(original, reformatted));
+    }
+
+    #[test]
+    fn manifests_with_added_permissions_are_not_equivalent() {
+        let original = r#"<manifest package="com.beatgames.beatsaber"><application/></manifest>"#;
+        let modified = r#"<manifest package="com.beatgames.beatsaber"><uses-permission name="android.permission.RECORD_AUDIO"/><application/></manifest>"#;
+        assert!(!manifests_equivalent(original, modified));
+        assert!(!manifests_equivalent(original, "<manifest>"));
+    }
 }
@@ -1,50 +1,651 @@
-//! Module containing all of the fixed file paths used by MBF, for easy changing throughout the project.
-
-use crate::APK_ID;
-use const_format::formatcp;
-
-/// Directory that QMOD files are stored in.
-/// `$` is replaced with the game version
-pub const QMODS: &str = formatcp!("/sdcard/ModData/{APK_ID}/Packages/$");
-/// The legacy directory used to contain QMOD files in older builds of MBF.
-pub const OLD_QMODS: &str = "/sdcard/ModsBeforeFriday/Mods";
-/// The path of the `.nomedia` file added to ModData.
-pub const MODDATA_NOMEDIA: &str = formatcp!("/sdcard/ModData/{APK_ID}/.nomedia");
-/// Directory containing the modloader.
-pub const MODLOADER_DIR: &str = formatcp!("/sdcard/ModData/{APK_ID}/Modloader");
-/// Directory containing installed late mod files.
-pub const LATE_MODS: &str = formatcp!("{MODLOADER_DIR}/mods");
-/// Directory containing installed early mod files.
-pub const EARLY_MODS: &str = formatcp!("{MODLOADER_DIR}/early_mods");
-/// Directory containing installed library files.
-pub const LIBS: &str = formatcp!("{MODLOADER_DIR}/libs");
-/// The Android `files` directory for the app being modded.
-pub const ANDROID_APP_FILES: &str = formatcp!("/sdcard/Android/data/{APK_ID}/files");
-/// Path of the `PlayerData.dat` in the vanilla game.
-pub const PLAYER_DATA: &str = formatcp!("{ANDROID_APP_FILES}/PlayerData.dat");
-/// Path of the backup `PlayerData.dat` in the vanilla game.
-pub const PLAYER_DATA_BAK: &str = formatcp!("{ANDROID_APP_FILES}/PlayerData.dat.bak");
-/// Directory containing OBBs for the app.
-pub const OBB_DIR: &str = formatcp!("/sdcard/Android/obb/{APK_ID}/");
-
-/// Path to the `PlayerData.dat` of the `datakeeper` mod.
-pub const DATAKEEPER_PLAYER_DATA: &str =
-    "/sdcard/ModData/com.beatgames.beatsaber/Mods/datakeeper/PlayerData.dat";
-/// An auxillary path that `PlayerData.dat` is copied to when modding in case it is corrupted/lost for any other reason.
-pub const AUX_DATA_BACKUP: &str = "/sdcard/ModsBeforeFriday/PlayerData.backup.dat";
-
-/// The folder that SongCore loads custom levels from.
-pub const CUSTOM_LEVELS: &str = formatcp!("/sdcard/ModData/{APK_ID}/Mods/SongCore/CustomLevels");
-/// A folder that MBF uses to download temporary files.
-pub const MBF_DOWNLOADS: &str = "/data/local/tmp/mbf/downloads";
-/// Temporary folder used by MBF during patching.
-pub const TEMP: &str = "/data/local/tmp/mbf/tmp";
-/// Path to the MBF resource cache.
-pub const RES_CACHE: &str = "/data/local/tmp/mbf/res-cache";
-/// Directories no longer used by MBF that should be deleted on startup if detected.
-pub const LEGACY_DIRS: &[&str] = &[
-    "/data/local/tmp/mbf-downloads",
-    "/data/local/tmp/mbf-res-cache",
-    "/data/local/tmp/mbf-tmp",
-    "/data/local/tmp/mbf-uploads",
-];
+//! Module containing all of the file paths used by MBF, for easy changing throughout the project.
+//!
+//! Most paths depend on the ID of the app being modded, so they are built at runtime by [init_paths].
+//! Code that only ever mods one app can use the shared instance returned by [default].
+//!
+//! On some firmware, the usual temporary files root cannot be written to. The shared instance then keeps its
+//! temporary files within ModData instead, which is recorded by [tmp_root_status].
+//!
+//! The external storage root and the ModData folder are often symlinks, so the shared instance resolves them once
+//! and builds every path from where they really are. See [check_storage_roots].
+
+use std::{fmt::Display, path::Path, sync::OnceLock};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::models::response::TmpRootStatus;
+
+/// Environment variable that can be used to override the external storage root, [PathRoots::sdcard].
+const SDCARD_ROOT_VAR: &str = "MBF_SDCARD_ROOT";
+/// Environment variable that can be used to override the temporary files root, [PathRoots::tmp].
+const TMP_ROOT_VAR: &str = "MBF_TMP_ROOT";
+/// The directory within the ModData folder of the app that is used for temporary files if [PathRoots::tmp] cannot be written to.
+const FALLBACK_TMP_DIR: &str = ".mbf-tmp";
+/// The directories that the external storage root may be a symlink into.
+/// A symlink to anywhere else is rejected, so that a bad link cannot lead MBF to write into system directories.
+const STORAGE_ROOTS: [&str; 3] = ["/storage", "/mnt", "/data/media"];
+
+/// Returned when the external storage root, or the ModData folder, is a symlink to somewhere outside of the storage
+/// that MBF expects to write to.
+#[derive(Debug, Clone)]
+pub struct UnexpectedSymlink {
+    /// The path of the symlink.
+    pub link: String,
+    /// Where the symlink resolves to.
+    pub target: String,
+}
+
+impl std::error::Error for UnexpectedSymlink {}
+
+impl Display for UnexpectedSymlink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Refusing to modify files, as {} is a symlink to {}, which is outside of the device storage",
+            self.link, self.target
+        )
+    }
+}
+
+/// The base directories that all paths used by MBF are located within.
+pub struct PathRoots {
+    /// The root of the external storage, normally `/sdcard`.
+    pub sdcard: String,
+    /// The directory that MBF is permitted to store temporary files in, normally `/data/local/tmp`.
+    pub tmp: String,
+    /// The folder that mods keep their data in. If `None`, this is `ModData` within [PathRoots::sdcard].
+    pub moddata: Option<String>,
+}
+
+impl Default for PathRoots {
+    fn default() -> Self {
+        Self {
+            sdcard: "/sdcard".to_string(),
+            tmp: "/data/local/tmp".to_string(),
+            moddata: None,
+        }
+    }
+}
+
+impl PathRoots {
+    /// Gets the roots specified by the `MBF_SDCARD_ROOT` and `MBF_TMP_ROOT` environment variables.
+    /// The default root is used for any variable that isn't set.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            sdcard: std::env::var(SDCARD_ROOT_VAR).unwrap_or(default.sdcard),
+            tmp: std::env::var(TMP_ROOT_VAR).unwrap_or(default.tmp),
+            moddata: default.moddata,
+        }
+    }
+
+    /// Gets the folder that mods keep their data in, without a trailing slash.
+    fn moddata_root(&self) -> String {
+        match &self.moddata {
+            Some(moddata) => moddata.trim_end_matches('/').to_string(),
+            None => format!("{}/ModData", self.sdcard.trim_end_matches('/')),
+        }
+    }
+
+    /// Resolves any symlinks in [PathRoots::sdcard] and the ModData folder within it, so that paths are built from
+    /// where these really are. On some devices `/sdcard` is a symlink, and users may move ModData elsewhere to save space.
+    ///
+    /// # Returns
+    /// The resolved roots, or an [UnexpectedSymlink] if the storage root is a symlink to somewhere outside
+    /// [STORAGE_ROOTS], or ModData is a symlink to somewhere outside both these and the storage root.
+    fn resolve_symlinks(self) -> Result<Self, UnexpectedSymlink> {
+        let sdcard = resolve_symlink(&self.sdcard, &[])?;
+        let moddata = resolve_symlink(
+            &format!("{}/ModData", sdcard.trim_end_matches('/')),
+            &[&sdcard],
+        )?;
+        Ok(Self {
+            sdcard,
+            tmp: self.tmp,
+            moddata: Some(moddata),
+        })
+    }
+
+    /// Checks that [PathRoots::tmp] can be written to, and if not, switches it to a directory within the ModData
+    /// folder of the given app (which must be a valid package name).
+    /// If neither can be written to, the original root is kept, as it is the most likely to work once the problem is fixed.
+    fn with_writable_tmp(self, apk_id: &str) -> (Self, TmpRootStatus) {
+        let tmp_err = match probe_writable(Path::new(&self.tmp).join("mbf")) {
+            Ok(()) => {
+                let status = TmpRootStatus {
+                    default_root: self.tmp.clone(),
+                    root: self.tmp.clone(),
+                    fallback_reason: None,
+                };
+                return (self, status);
+            }
+            Err(err) => err,
+        };
+
+        let fallback = format!("{}/{apk_id}/{FALLBACK_TMP_DIR}", self.moddata_root());
+        let (root, fallback_reason) = match probe_writable(Path::new(&fallback).join("mbf")) {
+            Ok(()) => (fallback, format!("{tmp_err:#}")),
+            Err(fallback_err) => (
+                self.tmp.clone(),
+                format!("{tmp_err:#}, and the fallback {fallback} could not be used either: {fallback_err:#}"),
+            ),
+        };
+
+        let status = TmpRootStatus {
+            default_root: self.tmp,
+            root: root.clone(),
+            fallback_reason: Some(fallback_reason),
+        };
+        (Self { tmp: root, ..self }, status)
+    }
+}
+
+// Resolves the symlinks in `path`, if it exists, checking that it ends up within [STORAGE_ROOTS] or `allowed_roots`.
+// A path that is not a symlink is always accepted, so that the storage root can be overridden during development.
+fn resolve_symlink(path: &str, allowed_roots: &[&str]) -> Result<String, UnexpectedSymlink> {
+    // A folder that doesn't exist yet, e.g. ModData before any mods are installed, has nothing to resolve.
+    let Ok(resolved) = std::fs::canonicalize(path) else {
+        return Ok(path.to_string());
+    };
+    if resolved == Path::new(path) {
+        return Ok(path.to_string());
+    }
+
+    let target = resolved.to_string_lossy().to_string();
+    if STORAGE_ROOTS
+        .iter()
+        .chain(allowed_roots)
+        .any(|root| resolved.starts_with(root))
+    {
+        Ok(target)
+    } else {
+        Err(UnexpectedSymlink {
+            link: path.to_string(),
+            target,
+        })
+    }
+}
+
+/// Checks that a file can be written, read back and deleted within `dir`, creating `dir` if it does not exist.
+pub fn probe_writable(dir: impl AsRef<Path>) -> Result<()> {
+    const PROBE_CONTENTS: &[u8] = b"mbf probe";
+
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir).context("Creating directory")?;
+    let probe_path = dir.join(".mbf-probe");
+    std::fs::write(&probe_path, PROBE_CONTENTS).context("Writing probe file")?;
+    let read_result = std::fs::read(&probe_path).context("Reading probe file");
+    std::fs::remove_file(&probe_path).context("Deleting probe file")?;
+
+    if read_result? != PROBE_CONTENTS {
+        return Err(anyhow!("Probe file contents changed after writing"));
+    }
+    Ok(())
+}
+
+/// The full set of paths used by MBF when modding a particular app.
+pub struct Paths {
+    /// Template for the per-version directory that QMOD files are stored in, within `qmods_root`.
+    /// Not a path itself: [Paths::qmods_dir_for_version] checks the version and replaces `$` with it.
+    qmods: String,
+    qmods_root: String,
+    old_qmods: String,
+    moddata_root: String,
+    moddata_nomedia: String,
+    modloader_dir: String,
+    loader_config: String,
+    mod_pins: String,
+    mod_configs: String,
+    core_mods_skipped: String,
+    mod_staging: String,
+    late_mods: String,
+    early_mods: String,
+    libs: String,
+    android_app_files: String,
+    crash_logs: String,
+    player_data: String,
+    player_data_bak: String,
+    obb_dir: String,
+    datakeeper_player_data: String,
+    aux_data_backup: String,
+    file_copy_backups: String,
+    safe_mode_record: String,
+    custom_levels: String,
+    playlists: String,
+    songcore_cache: String,
+    level_hash_cache: String,
+    game_version_cache: String,
+    mbf_downloads: String,
+    uploads: String,
+    pushed_files: String,
+    stock_apk_backup: String,
+    temp: String,
+    obb_stash: String,
+    obb_stash_record: String,
+    patch_progress: String,
+    res_cache: String,
+    agent_log: String,
+    cancel_marker: String,
+    agent_lock: String,
+    legacy_dirs: Vec<String>,
+}
+
+static DEFAULT_PATHS: OnceLock<Paths> = OnceLock::new();
+static TMP_ROOT_STATUS: OnceLock<TmpRootStatus> = OnceLock::new();
+static STORAGE_ROOT_ERROR: OnceLock<Option<UnexpectedSymlink>> = OnceLock::new();
+
+/// Gets the paths for the app that MBF manages, i.e. [crate::APK_ID].
+/// These are built the first time this is called and then reused.
+///
+/// Symlinks in the storage root and ModData are resolved before the paths are built. If either links somewhere
+/// unexpected, the unresolved roots are used instead, and [check_storage_roots] gives an error.
+///
+/// The temporary files root is then checked, and moved into ModData if it cannot be written to.
+/// Nothing is logged here, as the logger itself uses these paths. Use [tmp_root_status] to find which root was chosen.
+pub fn default() -> &'static Paths {
+    DEFAULT_PATHS.get_or_init(|| {
+        let roots = match PathRoots::from_env().resolve_symlinks() {
+            Ok(roots) => roots,
+            Err(err) => {
+                STORAGE_ROOT_ERROR.get_or_init(|| Some(err));
+                PathRoots::from_env()
+            }
+        };
+        STORAGE_ROOT_ERROR.get_or_init(|| None);
+        let (roots, status) = roots.with_writable_tmp(crate::APK_ID);
+        TMP_ROOT_STATUS.get_or_init(|| status);
+        init_paths(crate::APK_ID, Some(roots)).expect("APK_ID should be a valid package name")
+    })
+}
+
+/// Gets the temporary files root used by the paths given by [default], and why the usual root wasn't used, if it wasn't.
+pub fn tmp_root_status() -> &'static TmpRootStatus {
+    default();
+    TMP_ROOT_STATUS
+        .get()
+        .expect("Temporary root is chosen when the default paths are built")
+}
+
+/// Checks that the storage root and ModData folder used by [default] are not symlinks to somewhere outside the device
+/// storage, in which case MBF must not write to them.
+pub fn check_storage_roots() -> Result<(), UnexpectedSymlink> {
+    default();
+    match STORAGE_ROOT_ERROR
+        .get()
+        .expect("Storage roots are resolved when the default paths are built")
+    {
+        Some(err) => Err(err.clone()),
+        None => Ok(()),
+    }
+}
+
+/// Builds the paths used to mod the app with the given ID.
+///
+/// # Arguments
+/// * `apk_id` - The package ID of the app being modded.
+/// * `base_dirs` - The directories to place all paths within.
+///   If `None`, the roots are read from the environment using [PathRoots::from_env].
+///
+/// # Returns
+/// The paths, or an `Err` if `apk_id` is not a valid Android package name.
+/// This ensures that the ID cannot be used to place paths outside of the intended directories.
+pub fn init_paths(apk_id: &str, base_dirs: Option<PathRoots>) -> Result<Paths> {
+    if !is_valid_package_name(apk_id) {
+        return Err(anyhow!("`{apk_id}` is not a valid Android package name"));
+    }
+
+    let roots = base_dirs.unwrap_or_else(PathRoots::from_env);
+    let sdcard = roots.sdcard.trim_end_matches('/');
+    let tmp = roots.tmp.trim_end_matches('/');
+    let moddata_root = roots.moddata_root();
+
+    let moddata = format!("{moddata_root}/{apk_id}");
+    let modloader_dir = format!("{moddata}/Modloader");
+    let android_app_files = format!("{sdcard}/Android/data/{apk_id}/files");
+
+    Ok(Paths {
+        qmods: format!("{moddata}/Packages/$"),
+        qmods_root: format!("{moddata}/Packages"),
+        old_qmods: format!("{sdcard}/ModsBeforeFriday/Mods"),
+        moddata_root,
+        moddata_nomedia: format!("{moddata}/.nomedia"),
+        mod_staging: format!("{modloader_dir}/.mbf-staging"),
+        late_mods: format!("{modloader_dir}/mods"),
+        early_mods: format!("{modloader_dir}/early_mods"),
+        libs: format!("{modloader_dir}/libs"),
+        loader_config: format!("{modloader_dir}/loader-config.json"),
+        mod_pins: format!("{modloader_dir}/mbf-pins.json"),
+        mod_configs: format!("{moddata}/Configs"),
+        core_mods_skipped: format!("{modloader_dir}/.mbf-core-mods-skipped"),
+        modloader_dir,
+        crash_logs: format!("{android_app_files}/logs"),
+        player_data: format!("{android_app_files}/PlayerData.dat"),
+        player_data_bak: format!("{android_app_files}/PlayerData.dat.bak"),
+        android_app_files,
+        obb_dir: format!("{sdcard}/Android/obb/{apk_id}/"),
+        datakeeper_player_data: format!("{moddata}/Mods/datakeeper/PlayerData.dat"),
+        aux_data_backup: format!("{sdcard}/ModsBeforeFriday/PlayerData.backup.dat"),
+        file_copy_backups: format!("{sdcard}/ModsBeforeFriday/FileCopyBackups"),
+        safe_mode_record: format!("{sdcard}/ModsBeforeFriday/safe-mode.json"),
+        custom_levels: format!("{moddata}/Mods/SongCore/CustomLevels"),
+        playlists: format!("{moddata}/Mods/PlaylistManager/Playlists"),
+        songcore_cache: format!("{moddata}/Mods/SongCore/CachedSongData.json"),
+        level_hash_cache: format!("{tmp}/mbf/level-hashes.json"),
+        game_version_cache: format!("{tmp}/mbf/game-version.json"),
+        mbf_downloads: format!("{tmp}/mbf/downloads"),
+        uploads: format!("{tmp}/mbf/downloads/uploads"),
+        pushed_files: format!("{tmp}/mbf/uploads"),
+        stock_apk_backup: format!("{tmp}/mbf/stock-apk"),
+        temp: format!("{tmp}/mbf/tmp"),
+        obb_stash: format!("{tmp}/mbf/obb-stash"),
+        obb_stash_record: format!("{tmp}/mbf/obb-stash.json"),
+        patch_progress: format!("{tmp}/mbf/patch-progress.json"),
+        res_cache: format!("{tmp}/mbf/res-cache"),
+        agent_log: format!("{tmp}/mbf/agent-log.json"),
+        cancel_marker: format!("{tmp}/mbf/cancel"),
+        agent_lock: format!("{tmp}/mbf/agent.lock"),
+        legacy_dirs: ["mbf-downloads", "mbf-res-cache", "mbf-tmp", "mbf-uploads"]
+            .iter()
+            .map(|dir| format!("{tmp}/{dir}"))
+            .collect(),
+    })
+}
+
+// Checks that a package name consists of two or more segments of letters, digits and underscores, separated by dots.
+fn is_valid_package_name(apk_id: &str) -> bool {
+    apk_id.contains('.')
+        && apk_id.split('.').all(|segment| {
+            !segment.is_empty()
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+}
+
+impl Paths {
+    /// Gets the directory that QMOD files are stored in for a particular game version.
+    ///
+    /// # Arguments
+    /// * `version` - The full version of the game. Each version stores its QMODs separately.
+    ///
+    /// # Returns
+    /// The QMODs directory for the version, or an `Err` if the version could be used to escape the packages directory.
+    pub fn qmods_dir_for_version(&self, version: &str) -> Result<String> {
+        if version.is_empty() || version.contains(['/', '\\']) || version.contains("..") {
+            return Err(anyhow!(
+                "Game version `{version}` cannot be used as a directory name"
+            ));
+        }
+
+        Ok(self.qmods.replace('$', version))
+    }
+
+    /// The directory containing the QMODs directory of every game version.
+    pub fn qmods_root(&self) -> &str {
+        &self.qmods_root
+    }
+
+    /// The legacy directory used to contain QMOD files in older builds of MBF.
+    pub fn old_qmods(&self) -> &str {
+        &self.old_qmods
+    }
+
+    /// The ModData directory shared by all modded apps.
+    pub fn moddata_root(&self) -> &str {
+        &self.moddata_root
+    }
+
+    /// The path of the `.nomedia` file added to ModData.
+    pub fn moddata_nomedia(&self) -> &str {
+        &self.moddata_nomedia
+    }
+
+    /// Directory containing the modloader.
+    pub fn modloader_dir(&self) -> &str {
+        &self.modloader_dir
+    }
+
+    /// The modloader's configuration file, read and written by [crate::loader_config].
+    pub fn loader_config(&self) -> &str {
+        &self.loader_config
+    }
+
+    /// The list of mods pinned to a particular version, read and written by [crate::mod_man::pinned_mods].
+    pub fn mod_pins(&self) -> &str {
+        &self.mod_pins
+    }
+
+    /// Directory containing the JSON configuration file of each mod, read and written by [crate::mod_config].
+    pub fn mod_configs(&self) -> &str {
+        &self.mod_configs
+    }
+
+    /// Marker file that exists if the game was last patched without installing core mods.
+    pub fn core_mods_skipped(&self) -> &str {
+        &self.core_mods_skipped
+    }
+
+    /// Directory that mod files are copied to while installing a mod, before being moved into the modloader directories.
+    /// This is within the modloader directory so that files can be renamed, rather than copied, into place.
+    pub fn mod_staging(&self) -> &str {
+        &self.mod_staging
+    }
+
+    /// Directory containing installed late mod files.
+    pub fn late_mods(&self) -> &str {
+        &self.late_mods
+    }
+
+    /// Directory containing installed early mod files.
+    pub fn early_mods(&self) -> &str {
+        &self.early_mods
+    }
+
+    /// Directory containing installed library files.
+    pub fn libs(&self) -> &str {
+        &self.libs
+    }
+
+    /// The files directory of the app in Android's external storage.
+    pub fn android_app_files(&self) -> &str {
+        &self.android_app_files
+    }
+
+    /// Directory that the modloader and mods write their logs to, including those from when the game crashes.
+    pub fn crash_logs(&self) -> &str {
+        &self.crash_logs
+    }
+
+    /// Path of the `PlayerData.dat` in the vanilla game.
+    pub fn player_data(&self) -> &str {
+        &self.player_data
+    }
+
+    /// Path of the backup `PlayerData.dat` in the vanilla game.
+    pub fn player_data_bak(&self) -> &str {
+        &self.player_data_bak
+    }
+
+    /// Directory containing OBBs for the app.
+    pub fn obb_dir(&self) -> &str {
+        &self.obb_dir
+    }
+
+    /// Path to the `PlayerData.dat` of the `datakeeper` mod.
+    pub fn datakeeper_player_data(&self) -> &str {
+        &self.datakeeper_player_data
+    }
+
+    /// An auxillary path that `PlayerData.dat` is copied to when modding in case it is corrupted/lost for any other reason.
+    pub fn aux_data_backup(&self) -> &str {
+        &self.aux_data_backup
+    }
+
+    /// Directory that files overwritten by mod file copies are moved to, so that they can be restored when the mod is uninstalled.
+    /// Contains a folder for each mod.
+    pub fn file_copy_backups(&self) -> &str {
+        &self.file_copy_backups
+    }
+
+    /// File recording which mods were enabled before safe mode was entered, so that they can be re-enabled afterwards.
+    /// This exists only while safe mode is active.
+    pub fn safe_mode_record(&self) -> &str {
+        &self.safe_mode_record
+    }
+
+    /// The folder that SongCore loads custom levels from.
+    pub fn custom_levels(&self) -> &str {
+        &self.custom_levels
+    }
+
+    /// The folder that PlaylistManager loads playlists (`.bplist` files) from.
+    pub fn playlists(&self) -> &str {
+        &self.playlists
+    }
+
+    /// File that SongCore caches the hashes and details of custom levels in.
+    /// This is separate to [Paths::level_hash_cache], which is MBF's own cache.
+    pub fn songcore_cache(&self) -> &str {
+        &self.songcore_cache
+    }
+
+    /// File that the hashes of installed custom levels are cached in.
+    pub fn level_hash_cache(&self) -> &str {
+        &self.level_hash_cache
+    }
+
+    /// A folder that MBF uses to download temporary files.
+    pub fn mbf_downloads(&self) -> &str {
+        &self.mbf_downloads
+    }
+
+    /// Folder that files uploaded in chunks by the frontend are received into, with a folder for each upload.
+    pub fn uploads(&self) -> &str {
+        &self.uploads
+    }
+
+    /// Folder that the frontend pushes files to over ADB before asking for them to be imported.
+    pub fn pushed_files(&self) -> &str {
+        &self.pushed_files
+    }
+
+    /// Folder containing a copy of the unmodded APK, made before it was patched, so that it can be reinstalled.
+    /// This is kept apart from [Paths::mbf_downloads] so that it survives the caches being cleared.
+    pub fn stock_apk_backup(&self) -> &str {
+        &self.stock_apk_backup
+    }
+
+    /// Temporary folder used by MBF during patching.
+    pub fn temp(&self) -> &str {
+        &self.temp
+    }
+
+    /// File caching the version of the installed APK, along with the APK path and modification time it was read from.
+    pub fn game_version_cache(&self) -> &str {
+        &self.game_version_cache
+    }
+
+    /// Path to the MBF resource cache.
+    pub fn res_cache(&self) -> &str {
+        &self.res_cache
+    }
+
+    /// A folder that OBB files are moved to while the app is reinstalled.
+    /// This is outside of [Paths::temp] so that the OBB files are not lost if the temporary files are deleted after a failed patch.
+    pub fn obb_stash(&self) -> &str {
+        &self.obb_stash
+    }
+
+    /// File recording that OBB files have been moved to [Paths::obb_stash] and where they must be restored to.
+    pub fn obb_stash_record(&self) -> &str {
+        &self.obb_stash_record
+    }
+
+    /// File recording the step that patching has reached, so that an interrupted patch can be recovered from.
+    /// This is outside of [Paths::temp] as the temporary folder is deleted after patching.
+    pub fn patch_progress(&self) -> &str {
+        &self.patch_progress
+    }
+
+    /// File that the most recent log messages are saved to between requests.
+    pub fn agent_log(&self) -> &str {
+        &self.agent_log
+    }
+
+    /// File created by a `Cancel` request to ask the operation running in another agent process to stop.
+    pub fn cancel_marker(&self) -> &str {
+        &self.cancel_marker
+    }
+
+    /// File locked by the agent while it carries out a request that modifies the game or its mods.
+    /// This is outside of [Paths::temp] as the temporary folder is deleted after patching.
+    pub fn agent_lock(&self) -> &str {
+        &self.agent_lock
+    }
+
+    /// Directories no longer used by MBF that should be deleted on startup if detected.
+    pub fn legacy_dirs(&self) -> &[String] {
+        &self.legacy_dirs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{init_paths, is_valid_package_name, PathRoots};
+
+    #[test]
+    fn paths_follow_a_custom_package_id() {
+        let paths = init_paths("com.beatgames.beatsaberdemo", Some(PathRoots::default())).unwrap();
+        assert_eq!(
+            paths.obb_dir(),
+            "/sdcard/Android/obb/com.beatgames.beatsaberdemo/"
+        );
+        assert_eq!(
+            paths.qmods_dir_for_version("1.0.0").unwrap(),
+            "/sdcard/ModData/com.beatgames.beatsaberdemo/Packages/1.0.0"
+        );
+    }
+
+    #[test]
+    fn package_names_are_checked() {
+        for valid in [
+            "com.beatgames.beatsaber",
+            "com.beatgames.beatsaber_beta",
+            "a.b2",
+        ] {
+            assert!(is_valid_package_name(valid), "{valid:?} was refused");
+        }
+        for invalid in [
+            "",
+            "beatsaber",
+            "com..beatsaber",
+            "com.beatgames.",
+            "../evil.app",
+            "com.beat/games",
+            "com.beat games",
+        ] {
+            assert!(!is_valid_package_name(invalid), "{invalid:?} was allowed");
+            assert!(init_paths(invalid, Some(PathRoots::default())).is_err());
+        }
+    }
+
+    #[test]
+    fn qmods_dir_contains_the_version() {
+        let paths = init_paths("com.beatgames.beatsaber", Some(PathRoots::default())).unwrap();
+        assert_eq!(
+            paths.qmods_dir_for_version("1.37.0_9064817954").unwrap(),
+            "/sdcard/ModData/com.beatgames.beatsaber/Packages/1.37.0_9064817954"
+        );
+    }
+
+    #[test]
+    fn qmods_dir_refuses_versions_that_escape_the_packages_dir() {
+        let paths = init_paths("com.beatgames.beatsaber", Some(PathRoots::default())).unwrap();
+        for version in ["../evil", "1.37.0/../../evil", "..", "1.37.0\\evil", ""] {
+            assert!(
+                paths.qmods_dir_for_version(version).is_err(),
+                "{version:?} was allowed"
+            );
+        }
+    }
+}
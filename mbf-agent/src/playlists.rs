@@ -0,0 +1,206 @@
+//! Reads and writes the playlists (`.bplist` files) that PlaylistManager loads from [crate::paths::Paths::playlists].
+//!
+//! Fields of a playlist that MBF does not understand, e.g. the `customData` used by other tools, are kept when it is saved.
+
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    fs::File,
+    io::BufReader,
+    path::{Component, Path},
+};
+
+use anyhow::{Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    atomic, custom_levels,
+    models::response::{Playlist, PlaylistSong},
+    paths,
+};
+
+/// The extension of playlist files.
+const PLAYLIST_EXTENSION: &str = "bplist";
+
+/// Returned when saving a playlist with a file name that is not a single `.bplist` file within the playlists folder.
+#[derive(Debug)]
+pub struct InvalidPlaylistName {
+    pub file_name: String,
+}
+
+impl std::error::Error for InvalidPlaylistName {}
+
+impl Display for InvalidPlaylistName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} is not a valid playlist file name. It must end with .{PLAYLIST_EXTENSION} and cannot contain a folder",
+            self.file_name
+        )
+    }
+}
+
+/// The JSON within a `.bplist` file.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlaylistFile {
+    playlist_title: String,
+    #[serde(default)]
+    playlist_author: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    playlist_description: Option<String>,
+    // The cover image, as base64, optionally prefixed with `data:image/png;base64,`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    #[serde(default)]
+    songs: Vec<PlaylistFileSong>,
+    #[serde(flatten)]
+    other: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlaylistFileSong {
+    hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    song_name: Option<String>,
+    #[serde(flatten)]
+    other: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Lists the playlists in the playlists folder, sorted by file name.
+/// Each song is marked with whether a level with its hash is installed. Files that are not valid playlists are skipped.
+pub fn list_playlists() -> Result<Vec<Playlist>> {
+    let playlists_dir = Path::new(paths::default().playlists());
+    if !playlists_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let installed = installed_level_hashes();
+    let mut playlists = Vec::new();
+    for entry in std::fs::read_dir(playlists_dir).context("Reading playlists folder")? {
+        let path = entry?.path();
+        if !path.is_file()
+            || !path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case(PLAYLIST_EXTENSION))
+        {
+            continue;
+        }
+
+        match read_playlist_file(&path) {
+            Ok(playlist_file) => playlists.push(to_playlist(
+                path.file_name()
+                    .expect("Playlist path has a file name")
+                    .to_string_lossy()
+                    .into_owned(),
+                playlist_file,
+                &installed,
+            )),
+            Err(err) => warn!("Skipping invalid playlist {path:?}: {err:?}"),
+        }
+    }
+
+    playlists.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+    Ok(playlists)
+}
+
+/// Saves the given playlist to `playlist.file_name` within the playlists folder, replacing any playlist already there.
+/// If a playlist is replaced, the fields of it that MBF does not understand are kept.
+pub fn save_playlist(playlist: &Playlist) -> Result<()> {
+    let file_name = Path::new(&playlist.file_name);
+    let is_valid_name = matches!(
+        file_name.components().collect::<Vec<_>>().as_slice(),
+        [Component::Normal(_)]
+    ) && file_name
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(PLAYLIST_EXTENSION));
+    if !is_valid_name {
+        return Err(InvalidPlaylistName {
+            file_name: playlist.file_name.clone(),
+        }
+        .into());
+    }
+
+    let playlists_dir = Path::new(paths::default().playlists());
+    std::fs::create_dir_all(playlists_dir).context("Creating playlists folder")?;
+    let path = playlists_dir.join(file_name);
+    let mut existing = if path.exists() {
+        match read_playlist_file(&path) {
+            Ok(existing) => Some(existing),
+            Err(err) => {
+                warn!("Overwriting invalid playlist {path:?}: {err:?}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let songs = playlist
+        .songs
+        .iter()
+        .map(|song| PlaylistFileSong {
+            hash: song.hash.clone(),
+            song_name: song.song_name.clone(),
+            // Each song keeps the fields it had before, if it was already in the playlist.
+            other: existing
+                .as_mut()
+                .and_then(|existing| {
+                    existing
+                        .songs
+                        .iter_mut()
+                        .find(|existing_song| existing_song.hash.eq_ignore_ascii_case(&song.hash))
+                })
+                .map(|existing_song| std::mem::take(&mut existing_song.other))
+                .unwrap_or_default(),
+        })
+        .collect();
+
+    let playlist_file = PlaylistFile {
+        playlist_title: playlist.title.clone(),
+        playlist_author: playlist.author.clone(),
+        playlist_description: playlist.description.clone(),
+        image: playlist.image.clone(),
+        songs,
+        other: existing.map(|existing| existing.other).unwrap_or_default(),
+    };
+    atomic::write_atomic(&path, &serde_json::to_vec_pretty(&playlist_file)?)
+        .context("Writing playlist")
+}
+
+fn read_playlist_file(path: &Path) -> Result<PlaylistFile> {
+    Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+}
+
+// Gets the hashes of the installed custom levels, in upper case.
+fn installed_level_hashes() -> HashSet<String> {
+    custom_levels::list_custom_levels()
+        .into_iter()
+        .filter_map(|level| level.hash)
+        .collect()
+}
+
+fn to_playlist(
+    file_name: String,
+    playlist_file: PlaylistFile,
+    installed: &HashSet<String>,
+) -> Playlist {
+    Playlist {
+        file_name,
+        title: playlist_file.playlist_title,
+        author: playlist_file.playlist_author,
+        description: playlist_file.playlist_description,
+        image: playlist_file.image,
+        songs: playlist_file
+            .songs
+            .into_iter()
+            .map(|song| PlaylistSong {
+                installed: installed.contains(&song.hash.to_uppercase()),
+                hash: song.hash,
+                song_name: song.song_name,
+            })
+            .collect(),
+    }
+}
@@ -1,7 +0,0 @@
-use std::collections::HashMap;
-
-use semver::Version;
-use serde::{Deserialize, Serialize};
-
-use crate::mod_man::Mod;
-
@@ -0,0 +1,145 @@
+//! Checks for the free space on the file systems that MBF writes temporary files to, so that
+//! patching can fail early rather than running out of space part way through.
+//! Also works out how much space is used by mods, custom levels and MBF's own files.
+
+use std::{ffi::CString, fmt::Display, os::unix::ffi::OsStrExt, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+
+use crate::{
+    models::response::{CategoryUsage, StorageUsage},
+    paths,
+};
+
+/// Returned when there is not enough free space to carry out an operation.
+#[derive(Debug)]
+pub struct InsufficientSpace {
+    /// The number of bytes needed.
+    pub needed: u64,
+    /// The number of bytes available.
+    pub available: u64,
+    /// The path that there is not enough space for.
+    pub path: String,
+}
+
+impl std::error::Error for InsufficientSpace {}
+
+impl Display for InsufficientSpace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Not enough free space for {}: {} MB is needed but only {} MB is available. Free up some space on your Quest and try again",
+            self.path,
+            self.needed / (1024 * 1024),
+            self.available / (1024 * 1024)
+        )
+    }
+}
+
+/// The result of [check_free_space].
+pub enum SpaceStatus {
+    /// There is enough space. Contains the smallest amount of space available in any of the checked locations, in bytes.
+    Sufficient {
+        available: u64,
+    },
+    Insufficient(InsufficientSpace),
+}
+
+/// Gets the number of bytes available to MBF on the file system containing `path`.
+/// If `path` does not exist yet, the file system of its closest existing parent is used.
+pub fn available_space(path: impl AsRef<Path>) -> Result<u64> {
+    let existing_path = path
+        .as_ref()
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .ok_or(anyhow!("No part of {:?} exists", path.as_ref()))?;
+
+    let c_path =
+        CString::new(existing_path.as_os_str().as_bytes()).context("Path contained a null byte")?;
+    // SAFETY: `c_path` is a valid null-terminated string and `stat` is only read if `statvfs` succeeds.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .context(format!("Getting free space of {existing_path:?}"));
+    }
+
+    #[allow(clippy::unnecessary_cast)] // The field types differ between platforms.
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Checks that the temporary and downloads folders both have at least `required` bytes of free space.
+pub fn check_free_space(required: u64) -> Result<SpaceStatus> {
+    let mut smallest_available = u64::MAX;
+    for path in [paths::default().temp(), paths::default().mbf_downloads()] {
+        let available = available_space(path)?;
+        if available < required {
+            return Ok(SpaceStatus::Insufficient(InsufficientSpace {
+                needed: required,
+                available,
+                path: path.to_string(),
+            }));
+        }
+
+        smallest_available = smallest_available.min(available);
+    }
+
+    Ok(SpaceStatus::Sufficient {
+        available: smallest_available,
+    })
+}
+
+/// Works out the space used by each category of files that MBF manages, so that users can see what is using their storage.
+/// Each directory is walked once. Files that cannot be read are skipped with a warning, so the totals may be slightly low.
+pub fn storage_usage() -> StorageUsage {
+    let paths = paths::default();
+    let categories: Vec<CategoryUsage> = [
+        ("custom_levels", vec![paths.custom_levels()]),
+        ("libs", vec![paths.libs()]),
+        ("mods", vec![paths.early_mods(), paths.late_mods()]),
+        ("qmods", vec![paths.qmods_root()]),
+        ("downloads", vec![paths.mbf_downloads()]),
+        ("stock_apk", vec![paths.stock_apk_backup()]),
+        ("res_cache", vec![paths.res_cache()]),
+        ("obb", vec![paths.obb_dir()]),
+    ]
+    .into_iter()
+    .map(|(name, roots)| CategoryUsage {
+        name: name.to_string(),
+        bytes: roots
+            .into_iter()
+            .map(|root| directory_size(Path::new(root)))
+            .sum(),
+    })
+    .collect();
+
+    StorageUsage {
+        total_bytes: categories.iter().map(|category| category.bytes).sum(),
+        categories,
+    }
+}
+
+/// Gets the total size of the files within `dir`, including those in subdirectories, or 0 if `dir` does not exist.
+/// Symbolic links are not followed, so no file is counted twice.
+pub fn directory_size(dir: &Path) -> u64 {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return 0,
+        Err(err) => {
+            warn!("Failed to read {dir:?} when working out its size: {err}");
+            return 0;
+        }
+    };
+
+    let mut total = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => total += directory_size(&path),
+            Ok(metadata) => total += metadata.len(),
+            Err(err) => warn!("Failed to get size of {path:?}: {err}"),
+        }
+    }
+
+    total
+}
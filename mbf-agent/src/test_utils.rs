@@ -0,0 +1,35 @@
+//! Helpers shared by the tests of several modules.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A new, empty directory within the system temporary directory, which is deleted along with its contents when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates a directory whose name starts with `name`, and is unique to this test run.
+    pub fn new(name: &str) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path =
+            std::env::temp_dir().join(format!("mbf-test-{name}-{}-{id}", std::process::id()));
+        if path.exists() {
+            std::fs::remove_dir_all(&path).expect("Failed to remove old test directory");
+        }
+        std::fs::create_dir_all(&path).expect("Failed to create test directory");
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,63 @@
+//! Records how long each major stage of patching takes, so that users on slow devices can share where the time went.
+//!
+//! Stages are recorded into a list shared by the whole process, as each agent process handles a single request.
+//! The handler takes the recorded timings with [take] once the operation has finished.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::models::response::{StageTiming, TimedStage, Timings};
+
+static RECORDED: Mutex<Vec<StageTiming>> = Mutex::new(Vec::new());
+
+/// Measures one stage, from when it is started until [StageTimer::finish] is called.
+/// A stage that fails part way through is never finished, so is not recorded.
+pub struct StageTimer {
+    stage: TimedStage,
+    started: Instant,
+}
+
+/// Starts timing the given stage.
+pub fn start(stage: TimedStage) -> StageTimer {
+    StageTimer {
+        stage,
+        started: Instant::now(),
+    }
+}
+
+impl StageTimer {
+    /// Records the time taken since the stage started.
+    ///
+    /// # Arguments
+    /// * `bytes` - The number of bytes processed by the stage (e.g. downloaded or written), if relevant.
+    pub fn finish(self, bytes: Option<u64>) {
+        let timing = StageTiming {
+            stage: self.stage,
+            millis: duration_millis(self.started.elapsed()),
+            bytes,
+        };
+        RECORDED
+            .lock()
+            .expect("Timings lock should not be poisoned")
+            .push(timing);
+    }
+}
+
+/// Takes all of the stages recorded so far, in the order they finished.
+pub fn take() -> Timings {
+    let stages = std::mem::take(
+        &mut *RECORDED
+            .lock()
+            .expect("Timings lock should not be poisoned"),
+    );
+    Timings {
+        total_millis: stages.iter().map(|stage| stage.millis).sum(),
+        stages,
+    }
+}
+
+fn duration_millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
@@ -0,0 +1,235 @@
+//! Receives files from the frontend in chunks, so that a large upload that fails part way through can be resumed
+//! from where it stopped instead of starting again.
+//!
+//! Each upload is identified by the SHA-256 hash of the file being uploaded, and is saved within its own folder in
+//! [crate::paths::Paths::uploads]. Starting an upload of the same file again resumes the existing upload.
+
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{atomic, hash, models::response::UploadStatus, paths};
+
+/// The file within an upload's folder that records the details of the upload.
+const RECORD_NAME: &str = "upload.json";
+/// The file within an upload's folder that the received data is written to.
+const PART_NAME: &str = "data.part";
+
+/// Returned when a chunk does not start where the previous chunk finished.
+/// The upload should be resumed from `received`, which can also be found by beginning the upload again.
+#[derive(Debug)]
+pub struct UploadOffsetMismatch {
+    pub id: String,
+    pub offset: u64,
+    pub received: u64,
+}
+
+impl std::error::Error for UploadOffsetMismatch {}
+
+impl Display for UploadOffsetMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Chunk for upload {} started at byte {}, but {} bytes have been received",
+            self.id, self.offset, self.received
+        )
+    }
+}
+
+/// The details of an upload, given when it was started.
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+struct UploadRecord {
+    name: String,
+    size: u64,
+}
+
+/// Starts the upload of a file with the given name, size and SHA-256 hash, or resumes it if it was already started.
+///
+/// # Returns
+/// The status of the upload, including the number of bytes already received, which is where the next chunk should start.
+pub fn begin_upload(name: &str, size: u64, sha256: &str) -> Result<UploadStatus> {
+    if !is_valid_upload_name(name) {
+        return Err(anyhow!("{name} is not a valid file name to upload"));
+    }
+    if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("{sha256} is not a valid SHA-256 hash"));
+    }
+
+    let id = sha256.to_ascii_lowercase();
+    let upload_dir = upload_dir(&id);
+    let record = UploadRecord {
+        name: name.to_string(),
+        size,
+    };
+    if read_record(&id).ok().as_ref() == Some(&record) {
+        let received = part_len(&id)?;
+        info!("Resuming upload of {name} from byte {received}");
+        return Ok(UploadStatus { id, received, size });
+    }
+
+    // Any previous upload with the same hash but different details cannot be resumed.
+    if upload_dir.exists() {
+        std::fs::remove_dir_all(&upload_dir).context("Removing previous upload")?;
+    }
+    std::fs::create_dir_all(&upload_dir).context("Creating upload directory")?;
+    atomic::write_atomic(upload_dir.join(RECORD_NAME), &serde_json::to_vec(&record)?)
+        .context("Saving upload record")?;
+    File::create(upload_dir.join(PART_NAME)).context("Creating upload file")?;
+
+    info!("Starting upload of {name} ({size} bytes)");
+    Ok(UploadStatus {
+        id,
+        received: 0,
+        size,
+    })
+}
+
+/// Writes a chunk of an upload, which must start at the byte after the previously received chunk.
+///
+/// # Arguments
+/// * `offset` - The position of the chunk within the file.
+/// * `data` - The contents of the chunk, encoded with base64.
+pub fn append_chunk(id: &str, offset: u64, data: &str) -> Result<UploadStatus> {
+    let record = read_record(id)?;
+    let received = part_len(id)?;
+    if offset != received {
+        return Err(UploadOffsetMismatch {
+            id: id.to_string(),
+            offset,
+            received,
+        }
+        .into());
+    }
+
+    let chunk = STANDARD.decode(data).context("Chunk was invalid base64")?;
+    let new_received = received + chunk.len() as u64;
+    if new_received > record.size {
+        return Err(anyhow!(
+            "Chunk would make upload {id} larger than its size of {} bytes",
+            record.size
+        ));
+    }
+
+    OpenOptions::new()
+        .append(true)
+        .open(upload_dir(id).join(PART_NAME))
+        .and_then(|mut part| part.write_all(&chunk))
+        .context("Writing chunk")?;
+
+    Ok(UploadStatus {
+        id: id.to_string(),
+        received: new_received,
+        size: record.size,
+    })
+}
+
+/// Checks that all of an upload has been received and that its hash matches, then moves it into place.
+/// If the hash does not match, the received data is deleted so that the upload can be started again.
+///
+/// # Returns
+/// The path of the uploaded file, which has the name given when the upload was started.
+pub fn finish_upload(id: &str) -> Result<PathBuf> {
+    let record = read_record(id)?;
+    let received = part_len(id)?;
+    if received != record.size {
+        return Err(anyhow!(
+            "Upload {id} is incomplete: {received} of {} bytes have been received",
+            record.size
+        ));
+    }
+
+    let upload_dir = upload_dir(id);
+    let part_path = upload_dir.join(PART_NAME);
+    let actual_hash = hash::sha256_file(&part_path)?;
+    if actual_hash != id {
+        warn!(
+            "Upload of {} had hash {actual_hash}, deleting it",
+            record.name
+        );
+        std::fs::remove_dir_all(&upload_dir).context("Removing corrupt upload")?;
+        return Err(anyhow!(
+            "Uploaded {} did not match the expected hash, so it must be uploaded again",
+            record.name
+        ));
+    }
+
+    let dest = upload_dir.join(&record.name);
+    std::fs::rename(&part_path, &dest).context("Moving upload into place")?;
+    std::fs::remove_file(upload_dir.join(RECORD_NAME)).context("Removing upload record")?;
+    Ok(dest)
+}
+
+// Checks that `name` can be used as the name of the finished file within the upload's folder.
+// It must be a single path component, so cannot contain a separator or be `.` or `..`, and must not be the name of
+// one of the files the upload uses, otherwise finishing the upload would overwrite or delete it.
+fn is_valid_upload_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.contains(['/', '\\'])
+        && name != RECORD_NAME
+        && name != PART_NAME
+        && Path::new(name)
+            .file_name()
+            .is_some_and(|file_name| file_name == name)
+}
+
+fn upload_dir(id: &str) -> PathBuf {
+    Path::new(paths::default().uploads()).join(id)
+}
+
+fn read_record(id: &str) -> Result<UploadRecord> {
+    // IDs are hashes, so are checked before being used in a path.
+    if id.len() != 64 || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("{id} is not a valid upload ID"));
+    }
+
+    let record_bytes = std::fs::read(upload_dir(id).join(RECORD_NAME))
+        .with_context(|| format!("No upload with ID {id} has been started"))?;
+    serde_json::from_slice(&record_bytes).context("Parsing upload record")
+}
+
+fn part_len(id: &str) -> Result<u64> {
+    match std::fs::metadata(upload_dir(id).join(PART_NAME)) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err).context("Getting size of upload"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_valid_upload_name, PART_NAME, RECORD_NAME};
+
+    #[test]
+    fn plain_file_names_are_allowed() {
+        assert!(is_valid_upload_name("song.zip"));
+        assert!(is_valid_upload_name("my mod v1.0.0.qmod"));
+        assert!(is_valid_upload_name("..hidden"));
+    }
+
+    #[test]
+    fn paths_and_reserved_names_are_refused() {
+        for name in [
+            "",
+            ".",
+            "..",
+            "../song.zip",
+            "folder/song.zip",
+            "/song.zip",
+            "folder\\song.zip",
+            RECORD_NAME,
+            PART_NAME,
+        ] {
+            assert!(!is_valid_upload_name(name), "{name:?} was allowed");
+        }
+    }
+}
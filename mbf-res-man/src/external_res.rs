@@ -1,11 +1,13 @@
 //! Collection of types used to read the BMBF resources repository to fetch core mod information.
 use crate::{
-    models::{Diff, DiffIndex, ModRepo, VersionedCoreMods},
+    models::{CoreModSet, Diff, DiffIndex, ModRepo, StockApkIndex, VersionedCoreMods},
     res_cache::{JsonPullError, ResCache},
+    version,
 };
 use anyhow::{anyhow, Context, Result};
 use log::info;
-use std::collections::HashMap;
+use semver::Version;
+use std::{collections::HashMap, fs::File};
 
 pub type CoreModIndex = HashMap<String, VersionedCoreMods>;
 
@@ -26,6 +28,32 @@ pub fn fetch_core_mods(
     }
 }
 
+/// Fetches the core mods for `game_version`.
+/// If the core mod index has no entry for `game_version`, this gives [CoreModSet::Unsupported] rather than an error,
+/// so that the caller can warn that mods may not work and offer to downgrade instead.
+pub fn load_core_mods(
+    res_cache: &ResCache,
+    override_core_mod_url: Option<String>,
+    game_version: &str,
+) -> Result<CoreModSet, JsonPullError> {
+    let mut core_mod_index = fetch_core_mods(res_cache, override_core_mod_url)?;
+    Ok(match core_mod_index.remove(game_version) {
+        Some(core_mods) => CoreModSet::Supported(core_mods),
+        None => CoreModSet::Unsupported {
+            latest_supported: latest_version(core_mod_index.into_keys()),
+        },
+    })
+}
+
+// Finds the newest of the given Beat Saber versions, ignoring any that aren't valid semver before the build number suffix.
+fn latest_version(versions: impl Iterator<Item = String>) -> Option<String> {
+    versions
+        .map(|version| (version::parse_bs_version(&version), version))
+        .filter(|(parsed, _)| parsed.semver.is_some())
+        .max_by(|(parsed_a, _), (parsed_b, _)| parsed_a.cmp(parsed_b))
+        .map(|(_, version)| version)
+}
+
 const UNITY_INDEX_URL: &str =
     "https://raw.githubusercontent.com/Lauriethefish/QuestUnstrippedUnity/main/index.json";
 const UNITY_VER_FORMAT: &str =
@@ -66,6 +94,25 @@ pub fn get_diff_url(diff: &Diff) -> String {
     format!("{DIFF_INDEX_STEM}/{}", diff.diff_name)
 }
 
+/// Gets the SHA-256 hashes of the official APK of each version, which is published in the same release as the diff index.
+pub fn get_stock_apk_index(res_cache: &ResCache) -> Result<StockApkIndex, JsonPullError> {
+    res_cache.get_json_cached(
+        &format!("{DIFF_INDEX_STEM}/stock_apks.json"),
+        "stock_apk_index.json",
+    )
+}
+
+const SCOTLAND2_RELEASE_FORMAT: &str =
+    "https://github.com/sc2ad/scotland2/releases/download/v{0}/libsl2.so";
+
+/// Gets the libsl2.so published with the given release of Scotland2, which is cached from then on as release assets do not change.
+pub fn get_scotland2_release(res_cache: &ResCache, release: &Version) -> Result<File> {
+    res_cache.get_immutable_cached(
+        &SCOTLAND2_RELEASE_FORMAT.replace("{0}", &release.to_string()),
+        &format!("libsl2-{release}.so"),
+    )
+}
+
 const MANIFEST_FORMAT: &str =
     "https://github.com/Lauriethefish/mbf-manifests/releases/download/1.0.0/{0}.xml";
 
@@ -96,3 +143,13 @@ const MOD_REPO_URL: &str = "https://mods.bsquest.xyz/mods.json";
 pub fn get_mod_repo(res_cache: &ResCache) -> Result<ModRepo> {
     Ok(res_cache.get_json_cached(MOD_REPO_URL, "mod_repo.json")?)
 }
+
+/// Gets a name for each server that resources are fetched from, along with a URL on that server,
+/// so that the servers can be checked to be reachable before starting an operation that needs them.
+pub fn resource_hosts() -> [(&'static str, String); 3] {
+    [
+        ("core_mod_index", CORE_MODS_URL.to_string()),
+        ("downloads", format!("{DIFF_INDEX_STEM}/index.json")),
+        ("mod_repo", MOD_REPO_URL.to_string()),
+    ]
+}
@@ -2,3 +2,5 @@ pub mod default_agent;
 pub mod external_res;
 pub mod models;
 pub mod res_cache;
+pub mod retry;
+pub mod version;
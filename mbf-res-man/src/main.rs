@@ -8,11 +8,12 @@ use const_format::formatcp;
 use hash_cache::HashCache;
 use log::{info, warn};
 use mbf_zip::ZipFile;
-use models::{DiffIndex, VersionDiffs};
+use models::{DiffIndex, StockApkIndex, VersionDiffs};
 use oculus_db::{get_obb_binary, AndroidBinary};
 use release_editor::Repo;
 use res_cache::ResCache;
 use semver::{Op, Version};
+use sha2::{Digest, Sha256};
 use std::{
     cell::LazyCell,
     collections::HashMap,
@@ -33,6 +34,8 @@ mod models;
 mod oculus_db;
 mod release_editor;
 mod res_cache;
+mod retry;
+mod version;
 mod version_grabber;
 
 const APK_ID: &str = "com.beatgames.beatsaber";
@@ -42,6 +45,8 @@ const DIFFS_PATH: &str = formatcp!("{APK_DATA_DIR}/diffs");
 const RES_CACHE_PATH: &str = formatcp!("res_cache");
 const MANIFESTS_PATH: &str = formatcp!("{APK_DATA_DIR}/manifests");
 const DIFF_INDEX_PATH: &str = formatcp!("{DIFFS_PATH}/index.json");
+// Uploaded with the diffs, so that the agent can check an APK is an official release before patching it.
+const STOCK_APK_INDEX_PATH: &str = formatcp!("{DIFFS_PATH}/stock_apks.json");
 
 const CRC32_CACHE_PATH: &str = formatcp!("{APK_DATA_DIR}/crc_cache.json");
 const META_TOKEN_PATH: &str = "META_TOKEN.txt";
@@ -179,6 +184,54 @@ fn save_diff_index(index: DiffIndex) -> Result<()> {
     Ok(())
 }
 
+// Adds the SHA-256 hash of the APK of each Beat Saber version in the local version list to the stock APK index.
+// Hashes already in the index are kept, even if their version is no longer stored locally.
+fn update_stock_apk_index() -> Result<()> {
+    info!("Updating stock APK index");
+    let mut index: StockApkIndex = if Path::new(STOCK_APK_INDEX_PATH).exists() {
+        let handle = std::fs::File::open(STOCK_APK_INDEX_PATH)?;
+        serde_json::from_reader(handle).context("Existing stock APK index was invalid JSON")?
+    } else {
+        HashMap::new()
+    };
+
+    for entry_res in
+        std::fs::read_dir(Path::new(BS_VERSIONS_PATH)).context("Reading apk_data folder")?
+    {
+        let entry = entry_res?;
+        let apk_path = entry.path().join(format!("{APK_ID}.apk"));
+        if !apk_path.exists() {
+            continue;
+        }
+
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut std::fs::File::open(&apk_path)?, &mut hasher).context("Hashing APK")?;
+        let apk_hash: String = hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        let version = entry.file_name().to_string_lossy().to_string();
+        let version_hashes = index.entry(version.clone()).or_default();
+        if !version_hashes.contains(&apk_hash) {
+            info!("Adding APK hash for {version}");
+            version_hashes.push(apk_hash);
+        }
+    }
+
+    std::fs::create_dir_all(DIFFS_PATH)?;
+    let mut handle = std::io::BufWriter::new(
+        std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(STOCK_APK_INDEX_PATH)?,
+    );
+    serde_json::to_writer_pretty(&mut handle, &index)?;
+    Ok(())
+}
+
 // Gets the path to the OBB and APK for the given Beat Saber version.
 // Will error if multiple OBBs exist, or if the version is not stored locally.
 fn get_obb_and_apk_path(version: &str, fuzzy_lookup: bool) -> Result<(PathBuf, Option<PathBuf>)> {
@@ -316,16 +369,6 @@ fn add_diff_to_index(
     Ok(())
 }
 
-// Converts a Beat Saber version string to semver.
-fn bs_ver_to_semver(bs_ver: &str) -> semver::Version {
-    let semver_portion = bs_ver
-        .split('_')
-        .next()
-        .expect("Beat Saber version should not be blank");
-
-    semver::Version::parse(semver_portion).expect("BS version was invalid semver")
-}
-
 // Gets the latest moddable version of Beat Saber
 fn get_latest_moddable_bs() -> Result<String> {
     info!("Working out latest moddable version");
@@ -333,9 +376,9 @@ fn get_latest_moddable_bs() -> Result<String> {
     let core_mods = crate::external_res::fetch_core_mods(&get_res_cache()?, None)
         .context("Downloading (HTTP GET) core mod index")?;
 
-    let latest_ver = core_mods.into_keys().max_by(|version_a, version_b| {
-        bs_ver_to_semver(version_a).cmp(&bs_ver_to_semver(version_b))
-    });
+    let latest_ver = core_mods
+        .into_keys()
+        .max_by(|version_a, version_b| version::compare_bs_versions(version_a, version_b));
 
     latest_ver.ok_or(anyhow!("No Beat Saber versions were moddable"))
 }
@@ -449,6 +492,7 @@ fn upload_manifests() -> Result<()> {
 // Based on the current Beat Saber versions in the index, does a few key actions:
 // - Ensures all manifests are available on the manifests repo.
 // - Ensures a diff exists from the latest version to the latest moddable version.
+// - Ensures the hash of every APK is in the stock APK index.
 fn update_all_repositories(latest_bs_version: String) -> Result<()> {
     // First check all manifests are available
     update_manifests()?;
@@ -461,9 +505,10 @@ fn update_all_repositories(latest_bs_version: String) -> Result<()> {
     } else {
         info!("Ensuring diff exists from latest to latest moddable");
         add_diff_to_index(latest_bs_version, latest_moddable, false)?;
-        upload_diff_index()?;
     }
 
+    update_stock_apk_index()?;
+    upload_diff_index()?;
     Ok(())
 }
 
@@ -541,12 +586,14 @@ enum Commands {
     InstallLatestModdable,
     /// Uploads any changes made to the mbf diffs index.
     UpdateDiffIndex,
+    /// Adds the hash of each APK in the local version list to the stock APK index, then uploads it with the diff index.
+    UpdateStockApkIndex,
     /// Extracts all AndroidManifest.xml files from APKs and uploads them to the MBF manifests repo.
     UpdateManifestsRepo,
     /// Convenience command for use when a Beat Saber update releases.
     /// - Pulls the new update from the quest.
     /// - Generates a diff from this version to the latest moddable version.
-    /// - Uploads the diff to the mbf-diffs repo.
+    /// - Uploads the diff and the hash of the APK to the mbf-diffs repo.
     /// - Extracts the manifest from this version's APK.
     /// - Uploads the manifest to the manifests repo.
     AcceptNewVersion,
@@ -570,7 +617,7 @@ enum Commands {
     /// Fetches Beat Saber versions from the oculus database, then:
     /// - Ensures all manifests are available on the manifests repo.
     /// - Ensures the latest version has a diff to the latest moddable version (if not the same)
-    /// - Uploads the diff to the mbf-diffs repo.
+    /// - Uploads the diff and the hashes of the APKs to the mbf-diffs repo.
     UpdateReposFromOculusApi {
         #[arg(short, long)]
         min_version: Option<String>,
@@ -656,6 +703,10 @@ fn main() -> Result<()> {
             add_diff_to_index(from_version, latest_moddable, overwrite)?;
         }
         Commands::UpdateDiffIndex => upload_diff_index()?,
+        Commands::UpdateStockApkIndex => {
+            update_stock_apk_index()?;
+            upload_diff_index()?;
+        }
         // Enable fuzzy lookup so that if e.g. 1.28.0 is selected, the command will find the full version string with the build suffix and install that
         Commands::InstallVersion { version } => install_bs_version(&version, true)?,
         Commands::InstallLatestModdable => install_bs_version(&get_latest_moddable_bs()?, false)?,
@@ -21,6 +21,15 @@ pub struct VersionedCoreMods {
     pub mods: Vec<CoreMod>,
 }
 
+/// The core mods available for a particular Beat Saber version.
+pub enum CoreModSet {
+    /// The version has core mods, which are contained.
+    Supported(VersionedCoreMods),
+    /// The version has no core mods, e.g. because it was only just released.
+    /// Contains the newest version that does have core mods, if any.
+    Unsupported { latest_supported: Option<String> },
+}
+
 /// The diffs needed to downgrade between two particular Beat Saber versions.
 #[derive(Clone, Deserialize, Serialize)]
 pub struct VersionDiffs {
@@ -43,6 +52,10 @@ pub struct Diff {
     pub output_size: usize,
 }
 
+/// The SHA-256 hashes of the APKs of official releases of the game, published alongside the diff index.
+/// Key is full Beat Saber version incl. non-semver portion, value is the lowercase hex hashes of the APKs released for that version.
+pub type StockApkIndex = HashMap<String, Vec<String>>;
+
 /// The mod repo served on mods.bsquest.xyz
 /// Key is full Beat Saber version incl. non-semver portion, value is a list of mods for the version.
 pub type ModRepo = HashMap<String, Vec<ModRepoMod>>;
@@ -50,14 +63,19 @@ pub type ModRepo = HashMap<String, Vec<ModRepoMod>>;
 /// A particular mod within the mod repo.
 #[derive(Clone, Deserialize)]
 pub struct ModRepoMod {
-    //name: String,
+    #[serde(default)]
+    pub name: String,
     pub id: String,
     pub version: Version,
     pub download: String,
-    // Fields not currently needed, as the mods repo is used just for fetching dependencies. The frontend, however, also uses the mod repo, but fetches it separately.
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub description: String,
+    // URL of the cover image of the mod, if it has one.
+    #[serde(default)]
+    pub cover: Option<String>,
+    // Fields not currently needed.
     //source: String,
-    //author: String,
-    //cover: Option<String>,
     //modloader: String,
-    //description: String
 }
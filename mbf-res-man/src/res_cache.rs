@@ -11,6 +11,8 @@ use anyhow::{Context, Result};
 use log::{debug, warn};
 use serde::de::DeserializeOwned;
 
+use crate::retry::RetryConfig;
+
 /// We separate this out into an enum as if a file can't be fetched,
 /// then it is useful to know that the *fetching* was the problem and not the *parsing*
 /// so that the user can be warned of their failing internet connection.
@@ -31,6 +33,33 @@ impl Display for JsonPullError {
     }
 }
 
+/// Returned when a file is needed while in offline mode, but no cached copy of it exists.
+#[derive(Debug)]
+pub struct OfflineCacheMiss {
+    /// The URL of the file that is missing from the cache.
+    pub url: String,
+}
+
+impl std::error::Error for OfflineCacheMiss {}
+
+impl Display for OfflineCacheMiss {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Cannot complete offline, missing {} as it has not been downloaded before",
+            self.url
+        )
+    }
+}
+
+/// The result of [ResCache::enforce_cache_limit].
+pub struct EvictionReport {
+    /// The total size of the files that were deleted, in bytes.
+    pub freed_bytes: u64,
+    /// The paths of the files that were deleted.
+    pub removed: Vec<PathBuf>,
+}
+
 /// The file used to store a cache of the ETags of the other files, if given in the response
 /// This filename cannot be used as the name of a cache file.
 pub const ETAG_CACHE_FILENAME: &str = "etag_cache.json";
@@ -44,6 +73,10 @@ pub struct ResCache<'agent> {
     // If this is none, then the ETag cache is yet to be loaded.
     etag_cache: RefCell<Option<HashMap<String, String>>>,
     etag_cache_path: PathBuf,
+    // If true, no requests are made and only cached files are used.
+    offline: bool,
+    // Used to retry requests that fail due to a temporary problem.
+    retry: RetryConfig,
 }
 
 impl<'agent> ResCache<'agent> {
@@ -56,9 +89,24 @@ impl<'agent> ResCache<'agent> {
             cache_root,
             agent,
             etag_cache: RefCell::new(None),
+            offline: false,
+            retry: RetryConfig::default(),
         }
     }
 
+    /// Sets how requests that fail due to connection problems or server errors are retried.
+    /// By default, [RetryConfig::default] is used.
+    pub fn set_retry_config(&mut self, retry: RetryConfig) {
+        self.retry = retry;
+    }
+
+    /// Sets whether the cache is in offline mode.
+    /// When offline, cached files are always used even if they may be out of date, and getting a file
+    /// that has no cached copy fails with [OfflineCacheMiss].
+    pub fn set_offline(&mut self, offline: bool) {
+        self.offline = offline;
+    }
+
     fn load_etag_cache(&self) -> Result<()> {
         let mut etag_ref = self.etag_cache.borrow_mut();
         if etag_ref.is_none() {
@@ -115,10 +163,22 @@ impl<'agent> ResCache<'agent> {
     ///
     /// If the cached copy is found to be in date, this copy will be returned instead.
     pub fn get_cached(&self, url: &str, cached_file_name: &str) -> Result<File> {
+        let cached_path = self.cache_root.join(cached_file_name);
+        if self.offline {
+            return if cached_path.exists() {
+                debug!("Offline, so using cached file {cached_file_name} for {url}");
+                Ok(std::fs::File::open(cached_path)?)
+            } else {
+                Err(OfflineCacheMiss {
+                    url: url.to_string(),
+                }
+                .into())
+            };
+        }
+
         let mut request = self.agent.get(url);
         self.load_etag_cache()?;
 
-        let cached_path = self.cache_root.join(cached_file_name);
         if cached_path.exists() {
             let cache_last_modified = std::fs::metadata(&cached_path)
                 .context("Getting metadata on cached file")?
@@ -138,7 +198,10 @@ impl<'agent> ResCache<'agent> {
             request = request.set("If-None-Match", &cached_etag);
         }
 
-        let resp = request.call().context("HTTP GET to get file to cache")?;
+        let resp = self
+            .retry
+            .run(url, || request.clone().call().map_err(Box::new))
+            .context("HTTP GET to get file to cache")?;
         if resp.status() != 304 {
             // If cached file out of date. (or no cache)
             if let Some(etag) = resp.header("ETag") {
@@ -167,6 +230,40 @@ impl<'agent> ResCache<'agent> {
         Ok(std::fs::File::open(cached_path)?)
     }
 
+    /// Gets a file whose contents at the provided URL never change, such as a release asset, and caches it at
+    /// `cached_file_name` within the `cache_root`.
+    /// Unlike [ResCache::get_cached], no request is made if there is already a cached copy.
+    pub fn get_immutable_cached(&self, url: &str, cached_file_name: &str) -> Result<File> {
+        let cached_path = self.cache_root.join(cached_file_name);
+        if cached_path.exists() {
+            debug!("Using cached file {cached_file_name} for {url}");
+            return Ok(std::fs::File::open(cached_path)?);
+        }
+        if self.offline {
+            return Err(OfflineCacheMiss {
+                url: url.to_string(),
+            }
+            .into());
+        }
+
+        let resp = self
+            .retry
+            .run(url, || self.agent.get(url).call().map_err(Box::new))
+            .context("HTTP GET to get file to cache")?;
+
+        debug!("No cache, downloading {url} to {cached_file_name}");
+        // Downloaded to a temporary name first, as a partial download would otherwise be used from then on.
+        let part_path = cached_path.with_extension("part");
+        let mut part_handle = std::fs::File::create(&part_path)
+            .context("Opening cache file for writing: is the directory writable?")?;
+        std::io::copy(&mut resp.into_reader(), &mut part_handle)
+            .context("Copying response to cache")?;
+        drop(part_handle);
+        std::fs::rename(&part_path, &cached_path).context("Moving downloaded file into cache")?;
+
+        Ok(std::fs::File::open(cached_path)?)
+    }
+
     /// Gets a file from the provided URL and caches it at `cached_file_name` within the `cache_root`,
     /// if there is no cached copy already or the cached copy is out of date.
     /// Returns the contents of the file as a byte array.
@@ -199,4 +296,66 @@ impl<'agent> ResCache<'agent> {
             Err(parse_err) => Err(JsonPullError::ParseError(parse_err)),
         }
     }
+
+    /// Deletes the least recently updated files in the cache until the total size of the cache is no more than `max_bytes`.
+    ///
+    /// # Arguments
+    /// * `max_bytes` - The maximum total size of the cached files, in bytes.
+    /// * `pinned` - Paths of files that are in use and must not be deleted, even if this means the cache stays above the limit.
+    pub fn enforce_cache_limit(
+        &self,
+        max_bytes: u64,
+        pinned: &[PathBuf],
+    ) -> Result<EvictionReport> {
+        let mut cached_files = Vec::new();
+        let mut total_bytes = 0;
+        for stat_result in std::fs::read_dir(&self.cache_root).context("Reading cache directory")? {
+            let entry = stat_result?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() || entry.file_name() == ETAG_CACHE_FILENAME {
+                continue;
+            }
+
+            total_bytes += metadata.len();
+            cached_files.push((entry.path(), metadata.modified()?, metadata.len()));
+        }
+
+        let mut report = EvictionReport {
+            freed_bytes: 0,
+            removed: Vec::new(),
+        };
+        if total_bytes <= max_bytes {
+            return Ok(report);
+        }
+
+        // Files are updated whenever they are re-downloaded, so the oldest files are deleted first.
+        cached_files.sort_by_key(|(_, modified, _)| *modified);
+        self.load_etag_cache()?;
+        for (path, _, size) in cached_files {
+            if total_bytes <= max_bytes {
+                break;
+            }
+            if pinned.contains(&path) {
+                continue;
+            }
+
+            debug!("Evicting {path:?} from cache");
+            std::fs::remove_file(&path).context("Deleting cached file")?;
+            // Otherwise, the server could respond that the (now deleted) cached copy is up to date.
+            if let Some(file_name) = path.file_name() {
+                self.etag_cache
+                    .borrow_mut()
+                    .as_mut()
+                    .expect("ETag cache should have been loaded by now")
+                    .remove(&*file_name.to_string_lossy());
+            }
+
+            total_bytes -= size;
+            report.freed_bytes += size;
+            report.removed.push(path);
+        }
+
+        self.save_etag_cache()?;
+        Ok(report)
+    }
 }
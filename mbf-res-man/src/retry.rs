@@ -0,0 +1,97 @@
+//! The policy used to retry HTTP requests that fail due to a temporary problem, such as a brief loss of WiFi.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use log::warn;
+
+/// Controls how failed HTTP requests are retried.
+/// Requests are retried with an exponentially increasing delay, with random jitter so that many requests failing
+/// at once do not all retry at the same moment.
+#[derive(Clone, Copy)]
+pub struct RetryConfig {
+    /// The maximum number of attempts at a request, including the first.
+    pub max_attempts: u32,
+    /// The delay before the first retry. Each subsequent delay is double the last.
+    pub initial_delay: Duration,
+    /// The maximum delay between two attempts.
+    pub max_delay: Duration,
+    /// If `Some`, called before each retry with the URL, the number of the attempt about to be made and the delay before it.
+    /// This can be used to let the user know that the request hasn't hung.
+    pub on_retry: Option<fn(&str, u32, Duration)>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            on_retry: None,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Gets the delay before making the given attempt, which must be at least 2.
+    /// This is between half and all of the exponential delay for the attempt.
+    pub fn delay_before_attempt(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(2).min(16);
+        let full_delay = self
+            .initial_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+
+        // A RandomState is seeded randomly each time it is created, so gives a random number without another dependency.
+        let random = RandomState::new().build_hasher().finish();
+        let jitter_fraction = (random % 1000) as f64 / 2000.0;
+        full_delay.mul_f64(1.0 - jitter_fraction)
+    }
+
+    /// Waits before making the given attempt at downloading `url`, calling [RetryConfig::on_retry] first.
+    pub fn wait_before_attempt(&self, url: &str, attempt: u32) {
+        let delay = self.delay_before_attempt(attempt);
+        if let Some(on_retry) = self.on_retry {
+            on_retry(url, attempt, delay);
+        }
+
+        std::thread::sleep(delay);
+    }
+
+    /// Makes a request, retrying it if it fails with an error that [is_retryable].
+    ///
+    /// # Arguments
+    /// * `url` - The URL being requested, used when notifying about retries.
+    /// * `request` - Called to make each attempt at the request. The error is boxed as [ureq::Error] is large.
+    pub fn run<T>(
+        &self,
+        url: &str,
+        mut request: impl FnMut() -> Result<T, Box<ureq::Error>>,
+    ) -> Result<T, Box<ureq::Error>> {
+        let mut attempt = 1;
+        loop {
+            match request() {
+                Ok(result) => return Ok(result),
+                Err(err) if is_retryable(&err) && attempt < self.max_attempts => {
+                    warn!("Request to {url} failed ({err}), retrying");
+                    attempt += 1;
+                    self.wait_before_attempt(url, attempt);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Gets whether a request that failed with `err` may succeed if made again.
+/// This is true for connection problems, including timeouts, and server errors (5xx status codes).
+/// Other status codes, such as 404, will not change by retrying.
+pub fn is_retryable(err: &ureq::Error) -> bool {
+    match err {
+        ureq::Error::Status(code, _) => *code >= 500,
+        ureq::Error::Transport(_) => true,
+    }
+}
@@ -0,0 +1,83 @@
+//! Parsing and comparison of Beat Saber versions.
+//!
+//! Beat Saber versions are semver followed by a build number, e.g. `1.37.0_9064817954`, so neither comparing them
+//! as strings nor parsing them as semver gives the correct result.
+
+use std::cmp::Ordering;
+
+use semver::Version;
+
+/// A parsed Beat Saber version.
+///
+/// Versions are ordered by their semver portion first, then by build number.
+/// A version without a valid semver portion is older than all versions with one, and likewise a version without
+/// a build number is older than the same version with a build number.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BsVersion {
+    /// The portion of the version before the underscore, or `None` if it is not valid semver.
+    /// Version requirements for the game should be matched against this alone.
+    pub semver: Option<Version>,
+    /// The build number after the underscore, or `None` if there is no build number or it is not a number.
+    pub build: Option<u64>,
+}
+
+/// Parses a Beat Saber version, e.g. `1.37.0_9064817954`.
+/// Parsing never fails. Instead, any part of the version that is invalid is given as `None`.
+pub fn parse_bs_version(s: &str) -> BsVersion {
+    let (semver_portion, build_portion) = match s.split_once('_') {
+        Some((semver_portion, build_portion)) => (semver_portion, Some(build_portion)),
+        None => (s, None),
+    };
+
+    BsVersion {
+        semver: Version::parse(semver_portion).ok(),
+        build: build_portion.and_then(|build| build.parse().ok()),
+    }
+}
+
+/// Compares two Beat Saber versions, as described in [BsVersion].
+pub fn compare_bs_versions(a: &str, b: &str) -> Ordering {
+    parse_bs_version(a).cmp(&parse_bs_version(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use semver::Version;
+
+    use super::{compare_bs_versions, parse_bs_version};
+
+    #[test]
+    fn versions_are_split_into_semver_and_build() {
+        let version = parse_bs_version("1.37.0_9064817954");
+        assert_eq!(version.semver, Some(Version::new(1, 37, 0)));
+        assert_eq!(version.build, Some(9064817954));
+
+        let invalid = parse_bs_version("not a version_abc");
+        assert_eq!(invalid.semver, None);
+        assert_eq!(invalid.build, None);
+    }
+
+    #[test]
+    fn builds_are_compared_as_numbers() {
+        assert_eq!(
+            compare_bs_versions("1.37.0_900", "1.37.0_1000"),
+            Ordering::Less
+        );
+        assert_eq!(
+            compare_bs_versions("1.37.0_1000", "1.37.0_1000"),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn semver_is_compared_before_build() {
+        assert_eq!(compare_bs_versions("1.35.0", "1.37.0"), Ordering::Less);
+        assert_eq!(
+            compare_bs_versions("1.35.0_9000", "1.37.0_1"),
+            Ordering::Less
+        );
+        assert_eq!(compare_bs_versions("1.37.0", "1.37.0_1"), Ordering::Less);
+    }
+}
@@ -1,6 +1,6 @@
 import { AdbSync, AdbSyncWriteOptions, Adb, encodeUtf8 } from '@yume-chan/adb';
 import { Consumable, ConcatStringStream, TextDecoderStream, MaybeConsumable, ReadableStream } from '@yume-chan/stream-extra';
-import { Request, Response, LogMsg, ModStatus, Mods, FixedPlayerData, ImportResult, DowngradedManifest, Patched, ModSyncResult } from "./Messages";
+import { Request, Response, LogMsg, ModStatus, Mods, QuickFixed, FixedPlayerData, ImportResult, DowngradedManifest, Patched, ModSyncResult, ErrorCode } from "./Messages";
 import { AGENT_SHA1 } from './agent_manifest';
 import { toast } from 'react-toastify';
 import { Log } from './Logging';
@@ -137,6 +137,16 @@ async function downloadAgent(): Promise<Uint8Array> {
   throw new Error("Failed to fetch agent after multiple attempts.\nDid you lose internet connection just after you loaded the site?\n\nIf not, then please report this issue, including a screenshot of the browser console window!");
 }
 
+// Thrown when the agent gives an `Error` response, so that callers can react to specific kinds of failure.
+export class AgentError extends Error {
+  code: ErrorCode;
+
+  constructor(code: ErrorCode, message: string) {
+    super(message);
+    this.code = code;
+  }
+}
+
 async function sendRequest(adb: Adb, request: Request): Promise<Response> {
   let command_buffer = encodeUtf8(JSON.stringify(request) + "\n");
 
@@ -191,8 +201,11 @@ async function sendRequest(adb: Adb, request: Request): Promise<Response> {
         if(msg_obj.level === 'Error') {
           response = msg_obj;
         }
+      } else if(msg_obj.type === "Progress") {
+        // Progress updates are sent during the operation, so are never the final message.
+        console.log("Progress (" + msg_obj.stage + "): " + msg_obj.message);
       } else  {
-        // The final message is the only one that isn't of type `log`.
+        // The final message is the only one that isn't of type `log` or `progress`.
         // This contains the actual response data
         response = msg_obj;
       }
@@ -203,9 +216,23 @@ async function sendRequest(adb: Adb, request: Request): Promise<Response> {
   if((await agentProcess.exit) === 0) {
     if(response === null) {
       throw new Error("Received error response from agent");
+    } else if(response.type === 'Error') {
+      throw new AgentError(response.code, "`" + response.detail + "`");
     } else if(response.type === 'LogMsg') {
       const log = response as LogMsg;
       throw new Error("`" + log.message + "`");
+    } else if(response.type === 'AppNotInstalled') {
+      throw new Error("Beat Saber (" + response.apk_id + ") is not installed. Install it from the Meta store and try again.");
+    } else if(response.type === 'Busy') {
+      throw new Error("MBF is still carrying out another operation. Wait for it to finish and try again.");
+    } else if(response.type === 'IncompatibleMod') {
+      const compat = response.compatibility;
+      if(compat.type === 'WrongPackage') {
+        throw new Error("Mod " + response.mod_id + " is not made for " + compat.expected + ".");
+      } else if(compat.type === 'VersionMismatch') {
+        throw new Error("Mod " + response.mod_id + " is made for Beat Saber v" + compat.mod_target + ", but v" + compat.game + " is installed.");
+      }
+      throw new Error("Mod " + response.mod_id + " could not be installed.");
     } else  {
       return response;
     }
@@ -359,7 +386,8 @@ export async function patchApp(device: Adb,
           core_mod_install_status: "Ready",
           supported_versions: beforePatch.core_mods!.supported_versions,
           downgrade_versions: [],
-          is_awaiting_diff: false
+          is_awaiting_diff: false,
+          missing_core_mods: []
       },
       modloader_install_status: "Ready",
       installed_mods: response.installed_mods
@@ -375,7 +403,7 @@ export async function quickFix(device: Adb,
       type: 'QuickFix',
       override_core_mod_url: CORE_MOD_OVERRIDE_URL,
       wipe_existing_mods
-  });
+  }) as QuickFixed;
 
   // Update the mod status to reflect the fixed installation
   return {
@@ -385,9 +413,10 @@ export async function quickFix(device: Adb,
           core_mod_install_status: "Ready",
           supported_versions: beforeFix.core_mods!.supported_versions,
           downgrade_versions: beforeFix.core_mods!.downgrade_versions,
-          is_awaiting_diff: beforeFix.core_mods!.is_awaiting_diff
+          is_awaiting_diff: beforeFix.core_mods!.is_awaiting_diff,
+          missing_core_mods: []
       },
-      installed_mods: (response as Mods).installed_mods,
+      installed_mods: response.installed_mods,
       modloader_install_status: "Ready"
   }
 }
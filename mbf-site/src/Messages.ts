@@ -13,16 +13,72 @@ export interface Patch {
     override_core_mod_url: string | null,
     // Path to a file containing the splash image, as a PNG
     vr_splash_path: string | null,
-    remodding: boolean
+    remodding: boolean,
+    // If true, nothing is patched and a PatchPlan is returned instead.
+    dry_run?: boolean,
+    // If set, patching is stopped if it takes longer than this many seconds
+    timeout_secs?: number | null,
+    // Controls how files are copied into the APK. Defaults to a 64 KiB buffer without verification.
+    repack_tuning?: RepackTuning,
+    // If true, the modloader is added but core mods are not installed
+    skip_core_mods?: boolean,
+    // Flags to set in the manifest after applying manifest_mod. Flags that are null or missing are left unchanged.
+    manifest_flags?: ManifestFlags
+}
+
+export interface ManifestFlags {
+    // Each flag is null if not set in the manifest, in which case Android uses its default
+    debuggable?: boolean | null,
+    // If false, libraries must be stored uncompressed in the APK
+    extract_native_libs?: boolean | null,
+    // android:requestLegacyExternalStorage
+    legacy_storage?: boolean | null
+}
+
+export interface RepackTuning {
+    copy_buffer_bytes: number,
+    // If true, each file is read back after being copied and its CRC-32 checked
+    verify: boolean
+}
+
+// Reinstalls the unmodded APK backed up when the game was last patched
+export interface RestoreStockApk {
+    type: 'RestoreStockApk'
 }
 
 export interface FixPlayerData {
     type: 'FixPlayerData',
 }
 
+export type RestoreSource = 'AuxBackup' | 'Vanilla' | 'VanillaBak';
+
+export interface RestorePlayerData {
+    type: 'RestorePlayerData',
+    source: RestoreSource,
+    overwrite_newer: boolean
+}
+
 export interface SetModsEnabled {
     type: 'SetModsEnabled',
-    statuses: { [id: string]: boolean } 
+    statuses: { [id: string]: boolean },
+    // Must be true to enable mods made for a different game version
+    allow_version_mismatch?: boolean
+}
+
+export interface DesiredMod {
+    id: string,
+    version: string,
+    enabled: boolean,
+    source_url?: string | null
+}
+
+// Makes the installed mods match the given set, removing any mods not in it that are not needed by another mod
+export interface SyncMods {
+    type: 'SyncMods',
+    mods: DesiredMod[],
+    allow_version_mismatch?: boolean,
+    // If true, a SyncPlan is given instead of making any changes
+    dry_run?: boolean
 }
 
 export interface QuickFix {
@@ -31,11 +87,192 @@ export interface QuickFix {
     wipe_existing_mods: boolean
 }
 
+export interface GetCustomLevels {
+    type: 'GetCustomLevels'
+}
+
+export interface DeleteCustomLevel {
+    type: 'DeleteCustomLevel',
+    hash: string
+}
+
+export interface FindDuplicateLevels {
+    type: 'FindDuplicateLevels'
+}
+
+// Removes duplicate level folders, keeping the first folder (by name) for each level
+export interface DedupeLevels {
+    type: 'DedupeLevels'
+}
+
+export interface GetPlaylists {
+    type: 'GetPlaylists'
+}
+
+// Saves a playlist, replacing any existing playlist with the same file name
+export interface SavePlaylist {
+    type: 'SavePlaylist',
+    playlist: Playlist
+}
+
+export interface RunDiagnostics {
+    type: 'RunDiagnostics'
+}
+
+export interface GetLogs {
+    type: 'GetLogs'
+}
+
+// Gets the most recent log files written by the modloader and mods
+export interface GetCrashLogs {
+    type: 'GetCrashLogs',
+    max: number
+}
+
+export interface ExportConfig {
+    type: 'ExportConfig'
+}
+
+export interface ImportConfig {
+    type: 'ImportConfig',
+    config: ConfigExport
+}
+
+// Deletes all mods without unpatching, optionally reinstalling the core mods
+export interface ResetMods {
+    type: 'ResetMods',
+    keep_core: boolean
+}
+
+// Saves the modloader folder, QMODs, custom levels and player data to a ZIP archive on the quest
+export interface ExportModData {
+    type: 'ExportModData',
+    to_path: string
+}
+
+// Restores an archive made by ExportModData
+export interface ImportModData {
+    type: 'ImportModData',
+    from_path: string
+}
+
+// Disables all mods other than the core mods and their dependencies
+export interface EnterSafeMode {
+    type: 'EnterSafeMode'
+}
+
+// Puts the mods changed by EnterSafeMode back how they were
+export interface ExitSafeMode {
+    type: 'ExitSafeMode'
+}
+
+export interface GetStorageUsage {
+    type: 'GetStorageUsage'
+}
+
+// Gets the latest version of each mod in the mod repo for the installed game version
+export interface GetAvailableMods {
+    type: 'GetAvailableMods'
+}
+
+// Deletes the contents of the selected caches
+export interface ClearCaches {
+    type: 'ClearCaches',
+    downloads: boolean,
+    temp: boolean,
+    res_cache: boolean,
+    // SongCore's cache of the installed levels, which can stop levels from appearing if out of date
+    songcore?: boolean
+}
+
+export interface GetGameVersion {
+    type: 'GetGameVersion'
+}
+
+// Checks for a patch that was interrupted part way through, without changing anything
+export interface GetRecoveryState {
+    type: 'GetRecoveryState'
+}
+
+// Reads the flags set in the manifest of the installed APK
+export interface GetManifestFlags {
+    type: 'GetManifestFlags'
+}
+
+// Runs every check of the installed game at once, without repairing anything
+export interface VerifyInstallation {
+    type: 'VerifyInstallation'
+}
+
+// Checks that the servers used for downloads can be reached, e.g. before patching
+export interface NetworkPreflight {
+    type: 'NetworkPreflight'
+}
+
+export interface GetLoaderConfig {
+    type: 'GetLoaderConfig'
+}
+
+export interface SetLoaderConfig {
+    type: 'SetLoaderConfig',
+    config: LoaderConfig
+}
+
+export interface GetModConfig {
+    type: 'GetModConfig',
+    id: string
+}
+
+// Saves a mod's configuration. `config` is the JSON text to save, and is refused if it isn't valid JSON.
+export interface SetModConfig {
+    type: 'SetModConfig',
+    id: string,
+    config: string
+}
+
+// Asks the operation running in another request to stop
+export interface Cancel {
+    type: 'Cancel'
+}
+
 export interface RemoveMod {
     type: 'RemoveMod',
     id: string
 }
 
+// Pins a mod so that syncing and core mod updates won't change its version.
+// If no version is given, the mod is pinned to the version currently loaded.
+export interface PinMod {
+    type: 'PinMod',
+    id: string,
+    version?: string | null
+}
+
+export interface UnpinMod {
+    type: 'UnpinMod',
+    id: string
+}
+
+// Reads the manifest of a QMOD without installing it
+export interface PreviewMod {
+    type: 'PreviewMod',
+    from_path: string
+}
+
+// Checks a QMOD for problems without installing it, e.g. when it is dropped onto the page
+export interface ValidateQmod {
+    type: 'ValidateQmod',
+    from_path: string
+}
+
+// Installs several QMODs at once, resolving their dependencies together
+export interface InstallMods {
+    type: 'InstallMods',
+    from_paths: string[],
+    // Must be true to install mods made for a different game version
+    allow_version_mismatch?: boolean
+}
+
 export interface Import {
     type: 'Import',
     from_path: string
@@ -46,6 +283,39 @@ export interface ImportUrl {
     from_url: string
 }
 
+// Starts or resumes uploading a file in chunks
+export interface BeginUpload {
+    type: 'BeginUpload',
+    name: string,
+    size: number,
+    sha256: string
+}
+
+export interface AppendChunk {
+    type: 'AppendChunk',
+    id: string,
+    // Must be the number of bytes received so far
+    offset: number,
+    // Base64 encoded
+    data: string
+}
+
+export interface FinishUpload {
+    type: 'FinishUpload',
+    id: string
+}
+
+export interface InstallModFromUrl {
+    type: 'InstallModFromUrl',
+    url: string,
+    // Hex SHA-256 of the QMOD, checked after downloading
+    expected_sha256: string | null,
+    // Must be true to download from a URL that isn't HTTPS
+    allow_insecure?: boolean,
+    // Must be true to install a mod made for a different game version
+    allow_version_mismatch?: boolean
+}
+
 export interface GetDowngradedManifest {
     type: 'GetDowngradedManifest',
     version: string
@@ -53,19 +323,75 @@ export interface GetDowngradedManifest {
 
 export type Request = GetModStatus | 
     Patch | 
+    RestoreStockApk |
     SetModsEnabled | 
+    SyncMods |
     QuickFix | 
     RemoveMod | 
+    PinMod |
+    UnpinMod |
+    PreviewMod |
+    ValidateQmod |
+    InstallMods |
     Import | 
     ImportUrl | 
+    BeginUpload |
+    AppendChunk |
+    FinishUpload |
+    InstallModFromUrl |
     FixPlayerData |
-    GetDowngradedManifest;
+    RestorePlayerData |
+    GetDowngradedManifest |
+    GetCustomLevels |
+    DeleteCustomLevel |
+    FindDuplicateLevels |
+    DedupeLevels |
+    GetPlaylists |
+    SavePlaylist |
+    RunDiagnostics |
+    GetLogs |
+    GetCrashLogs |
+    ExportConfig |
+    ImportConfig |
+    ResetMods |
+    ExportModData |
+    ImportModData |
+    EnterSafeMode |
+    ExitSafeMode |
+    GetStorageUsage |
+    ClearCaches |
+    GetGameVersion |
+    GetRecoveryState |
+    GetManifestFlags |
+    VerifyInstallation |
+    NetworkPreflight |
+    GetAvailableMods |
+    GetLoaderConfig |
+    SetLoaderConfig |
+    GetModConfig |
+    SetModConfig |
+    Cancel;
 
 export interface Mods {
     type: 'Mods',
     installed_mods: Mod[]
 }
 
+export interface QuickFixed {
+    type: 'QuickFixed',
+    installed_mods: Mod[],
+    // A description of each repair carried out, empty if nothing needed fixing.
+    repairs: string[],
+    // Problems with mod binaries that could not be repaired. These mods should be reinstalled.
+    binary_issues: BinaryIssue[]
+}
+
+export interface BinaryIssue {
+    mod_id: string,
+    path: string,
+    problem: string
+}
+
 export interface ModSyncResult {
     type: 'ModSyncResult',
     installed_mods: Mod[],
@@ -75,13 +401,57 @@ export interface ModSyncResult {
 export interface Patched {
     type: 'Patched',
     installed_mods: Mod[],
-    did_remove_dlc: boolean
+    did_remove_dlc: boolean,
+    // How long each stage of patching took
+    timings: Timings
+}
+
+// Given instead of Patched if the game was already patched, in which case only the mods were fixed
+export interface AlreadyPatched {
+    type: 'AlreadyPatched',
+    installed_mods: Mod[],
+    mods_fixed: string[],
+    binary_issues: BinaryIssue[]
+}
+
+export interface Upload {
+    type: 'Upload',
+    id: string,
+    // The offset the next chunk should start at
+    received: number,
+    size: number
+}
+
+export interface UploadFinished {
+    type: 'UploadFinished',
+    // Can be given to Import
+    path: string
+}
+
+export interface StockApkRestored {
+    type: 'StockApkRestored',
+    // The version of the game that was reinstalled
+    version: string
+}
+
+export interface Timings {
+    stages: StageTiming[],
+    total_millis: number
+}
+
+export interface StageTiming {
+    stage: "Download" | "Downgrade" | "Repack" | "Sign" | "Reinstall" | "InstallCoreMods",
+    millis: number,
+    // The number of bytes downloaded or written, null if not relevant
+    bytes: number | null
 }
 
 export interface ImportedMod {
     type: 'ImportedMod',
     installed_mods: Mod[],
-    imported_id: string
+    imported_id: string,
+    // Whether the mod is made for the installed game
+    compatibility: Compatibility
 }
 
 export interface ImportedFileCopy {
@@ -90,8 +460,12 @@ export interface ImportedFileCopy {
     mod_id: string
 }
 
-export interface ImportedSong {
-    type: 'ImportedSong'
+export interface ImportedLevel {
+    type: 'ImportedLevel',
+    hash: string,
+    path: string,
+    // True if the level was already installed, so was not copied again.
+    already_existed: boolean
 }
 
 export interface NonQuestModDetected {
@@ -103,13 +477,66 @@ export interface FixedPlayerData {
     existed: boolean
 }
 
+export interface RestoredPlayerData {
+    type: 'RestoredPlayerData',
+    restored: boolean
+}
+
 export interface ImportResult {
     result: ImportResultType,
     used_filename: string,
     type: 'ImportResult'
 }
 
-export type ImportResultType = ImportedMod | ImportedFileCopy | ImportedSong | NonQuestModDetected;
+export type Compatibility = { type: 'Compatible' } |
+    { type: 'WrongPackage', expected: string } |
+    { type: 'VersionMismatch', mod_target: string, game: string };
+
+export type QmodIssue = { type: 'MissingEntry', entry: string } |
+    { type: 'BadManifest', error: string } |
+    { type: 'NoAbiMatch', entry: string, problem: string };
+
+export interface QmodValidation {
+    type: 'QmodValidation',
+    // Null if the manifest couldn't be read
+    id: string | null,
+    version: string | null,
+    // The QMOD can be imported if there are no issues
+    issues: QmodIssue[]
+}
+
+export interface BatchInstallResult {
+    path: string,
+    // Null if the manifest couldn't be read
+    id: string | null,
+    version: string | null,
+    // Null if the mod was installed
+    error: string | null
+}
+
+export interface ModsInstalled {
+    type: 'ModsInstalled',
+    report: {
+        // In the same order as the paths in the request
+        results: BatchInstallResult[]
+    },
+    installed_mods: Mod[]
+}
+
+export interface ModPreview {
+    type: 'ModPreview',
+    id: string,
+    name: string,
+    version: string,
+    author: string,
+    description: string | null,
+    game_version: string | null,
+    compatibility: Compatibility,
+    // IDs of the mods this mod depends on
+    dependencies: string[]
+}
+
+export type ImportResultType = ImportedMod | ImportedFileCopy | ImportedLevel | NonQuestModDetected;
 
 // Represents whether a particular part of the modded game is installed and up to date.
 export type InstallStatus = "Ready" | "NeedUpdate" | "Missing";
@@ -118,8 +545,17 @@ export interface ModStatus {
     type: 'ModStatus',
     app_info: AppInfo | null,
     core_mods: CoreModsInfo | null,
+    // True if the game was patched with skip_core_mods, so missing core mods are expected
+    core_mods_intentionally_skipped: boolean,
     modloader_install_status: InstallStatus,
     installed_mods: Mod[],
+    // Libraries provided by more than one enabled mod with differing contents
+    conflicts: Conflict[]
+}
+
+export interface Conflict {
+    library: string,
+    mods: { id: string, version: string }[]
 }
 
 export interface LogMsg {
@@ -133,13 +569,409 @@ export interface DowngradedManifest {
     manifest_xml: string
 }
 
-export type Response = LogMsg | ModStatus | Mods | ImportResult | FixedPlayerData | DowngradedManifest | Patched | ModSyncResult;
+export type Stage = 'Downloading' | 'Patching' | 'Signing' | 'InstallingMods' | 'Finalizing';
+
+export interface Progress {
+    type: 'Progress',
+    stage: Stage,
+    done: number,
+    total: number | null,
+    message: string
+}
+
+export interface PlannedStep {
+    action: string,
+    target: string | null
+}
+
+export interface PatchPlan {
+    type: 'PatchPlan',
+    steps: PlannedStep[],
+    downloads_needed: string[],
+    estimated_bytes: number
+}
+
+export interface LevelDifficultySet {
+    characteristic: string,
+    difficulties: string[]
+}
+
+export interface LevelMetadata {
+    song_name: string,
+    song_author: string,
+    level_author: string,
+    bpm: number,
+    difficulty_sets: LevelDifficultySet[]
+}
+
+// A folder within the SongCore custom levels folder.
+// If the folder isn't a valid level, `hash` and `metadata` are null and `error` gives the reason.
+export interface CustomLevel {
+    folder_name: string,
+    hash: string | null,
+    metadata: LevelMetadata | null,
+    error: string | null
+}
+
+export interface CustomLevels {
+    type: 'CustomLevels',
+    levels: CustomLevel[]
+}
+
+// Level folders with the same hash. The first folder is the one kept when deduping.
+export interface DuplicateGroup {
+    hash: string,
+    folder_names: string[]
+}
+
+export interface DuplicateLevels {
+    type: 'DuplicateLevels',
+    groups: DuplicateGroup[]
+}
+
+export interface LevelsDeduped {
+    type: 'LevelsDeduped',
+    removed_folders: number,
+    freed_bytes: number,
+    // The levels that remain installed
+    levels: CustomLevel[]
+}
+
+export interface Playlist {
+    // Including the .bplist extension
+    file_name: string,
+    title: string,
+    author: string,
+    description: string | null,
+    // Base64 cover image
+    image: string | null,
+    songs: PlaylistSong[]
+}
+
+export interface PlaylistSong {
+    hash: string,
+    song_name: string | null,
+    // Whether a level with this hash is installed. Ignored when saving.
+    installed: boolean
+}
+
+export interface Playlists {
+    type: 'Playlists',
+    // Sorted by file name
+    playlists: Playlist[]
+}
+
+export interface PathCheck {
+    name: string,
+    path: string,
+    writable: boolean,
+    error: string | null,
+    // Free space on the file system containing the path, in bytes
+    free_space: number | null
+}
+
+export interface Diagnostics {
+    type: 'Diagnostics',
+    apk_id: string,
+    game_version: string | null,
+    paths: PathCheck[],
+    tmp_root: TmpRootStatus,
+    datakeeper: DatakeeperStatus,
+    songcore_cache: SongCoreCacheStatus
+}
+
+export interface HostReachability {
+    // What the server is used for, e.g. `core_mod_index`
+    name: string,
+    url: string,
+    reachable: boolean,
+    latency_millis: number | null,
+    error: string | null
+}
+
+export interface NetworkStatus {
+    type: 'NetworkStatus',
+    // Online if at least one server could be reached
+    state: 'Online' | 'Offline',
+    hosts: HostReachability[]
+}
+
+export interface TmpRootStatus {
+    root: string,
+    default_root: string,
+    // Why the default root (/data/local/tmp) couldn't be used, in which case files are kept in ModData instead
+    fallback_reason: string | null
+}
+
+export interface SongCoreCacheStatus {
+    exists: boolean,
+    // Seconds since the cache was last updated
+    age_secs: number | null
+}
+
+export interface DatakeeperStatus {
+    installed: boolean,
+    player_data_exists: boolean,
+    // How recently datakeeper's PlayerData.dat was modified compared with the game's, null if either is missing
+    compared_to_live: "Newer" | "Older" | "Same" | null
+}
+
+export interface LogEntry {
+    // Milliseconds since the UNIX epoch
+    timestamp: number,
+    level: LogLevel,
+    stage: string,
+    message: string
+}
+
+export interface Logs {
+    type: 'Logs',
+    entries: LogEntry[]
+}
+
+export interface LogFile {
+    // Relative to the logs directory
+    name: string,
+    contents: string,
+    // True if only the end of the file is given
+    truncated: boolean,
+    // Milliseconds since the UNIX epoch
+    modified: number
+}
+
+export interface CrashLogs {
+    type: 'CrashLogs',
+    // Most recently modified first
+    files: LogFile[]
+}
+
+export interface ExportedMod {
+    id: string,
+    version: string,
+    enabled: boolean,
+    // null if no download was found for this version of the mod
+    source_url: string | null
+}
+
+export interface ConfigExport {
+    game_version: string,
+    mods: ExportedMod[],
+    custom_level_hashes: string[]
+}
+
+export interface ConfigExportResponse extends ConfigExport {
+    type: 'ConfigExport'
+}
+
+export interface AppNotInstalled {
+    type: 'AppNotInstalled',
+    apk_id: string
+}
+
+export interface ConfigImported {
+    type: 'ConfigImported',
+    installed_mods: Mod[],
+    failed_mods: string[],
+    // Hashes of the custom levels that the user still needs to supply
+    missing_levels: string[]
+}
+
+export interface ModsReset {
+    type: 'ModsReset',
+    installed_mods: Mod[],
+    // The number of mod, library and QMOD files deleted
+    removed_files: number,
+    reinstalled_core_mods: number
+}
+
+export interface ModDataExported {
+    type: 'ModDataExported',
+    // The size of the archive, in bytes
+    bytes: number
+}
+
+export interface AvailableMods {
+    type: 'AvailableMods',
+    // Sorted by name
+    mods: AvailableMod[]
+}
+
+export interface AvailableMod {
+    id: string,
+    name: string,
+    version: string,
+    author: string,
+    description: string,
+    cover: string | null,
+    download_url: string,
+    status: "NotInstalled" | "Installed" | "Outdated",
+    installed_version: string | null
+}
+
+export interface SafeMode {
+    type: 'SafeMode',
+    active: boolean,
+    // The IDs of the mods disabled on entering safe mode, or re-enabled on exiting it
+    changed_mods: string[],
+    installed_mods: Mod[],
+    failures: string | null
+}
+
+// Given instead of the usual response when a mod is not made for the installed game
+export interface IncompatibleMod {
+    type: 'IncompatibleMod',
+    mod_id: string,
+    compatibility: Compatibility
+}
+
+export interface CategoryUsage {
+    name: string,
+    bytes: number
+}
+
+export interface StorageUsage {
+    type: 'StorageUsage',
+    categories: CategoryUsage[],
+    total_bytes: number
+}
+
+export interface ClearedCache {
+    name: string,
+    path: string,
+    files_removed: number,
+    bytes_freed: number
+}
+
+export interface CachesCleared {
+    type: 'CachesCleared',
+    caches: ClearedCache[]
+}
+
+export type SyncStep = { type: 'Disable', id: string } |
+    { type: 'Remove', id: string } |
+    { type: 'Install', id: string, version: string, source_url: string | null } |
+    { type: 'Enable', id: string };
+
+export type Skipped = { type: 'Pinned', version: string };
+
+// A mod that was left as it is, rather than being made to match the desired mods
+export interface SkippedMod {
+    id: string,
+    reason: Skipped
+}
+
+export interface SyncPlan {
+    type: 'SyncPlan',
+    steps: SyncStep[],
+    skipped: SkippedMod[]
+}
+
+export type PatchStep = 'Preparing' | 'StashingObb' | 'Reinstalling' | 'Reinstalled';
+
+export interface PatchRecovery {
+    // null if the record of the step reached is missing
+    step: PatchStep | null,
+    target_version: string | null,
+    // If true, the OBB files are missing from the OBB directory until restored
+    obb_stashed: boolean
+}
+
+export interface RecoveryState {
+    type: 'RecoveryState',
+    // null if there is no interrupted patch
+    recovery: PatchRecovery | null
+}
+
+export type ObbMatch = { state: 'Match' } | { state: 'Mismatch', apk: number, obb: number } | { state: 'ObbMissing' };
+
+export interface InstallationReport {
+    type: 'InstallationReport',
+    // True if every check passed and none failed to run
+    healthy: boolean,
+    game_version: string,
+    // Each check that could not be run is null, with the reason given in errors
+    modded: boolean | null,
+    // Parts of the patched APK that are missing or invalid
+    patch_issues: string[],
+    modloader_status: InstallStatus | null,
+    modloader_version: string | null,
+    // The Scotland2 release of the installed modloader, null if missing or unknown
+    modloader_release: string | null,
+    missing_core_mods: string[] | null,
+    binary_issues: BinaryIssue[],
+    conflicts: Conflict[],
+    obb: ObbMatch | null,
+    errors: string[]
+}
+
+export interface ManifestFlagsResponse {
+    type: 'ManifestFlags',
+    // null if Beat Saber is not installed
+    flags: ManifestFlags | null
+}
+
+export interface GameVersion {
+    type: 'GameVersion',
+    // null if Beat Saber is not installed
+    version: string | null,
+    semver: string | null,
+    build: number | null
+}
+
+export type LoaderLogLevel = 'Error' | 'Warn' | 'Info' | 'Debug' | 'Verbose';
+
+export interface LoaderConfig {
+    log_level: LoaderLogLevel,
+    wait_for_debugger: boolean
+}
+
+export interface ModConfig {
+    type: 'ModConfig',
+    id: string,
+    // Null if the mod hasn't saved a configuration
+    config: any | null
+}
+
+export interface LoaderConfigResponse extends LoaderConfig {
+    type: 'LoaderConfig'
+}
+
+export type ErrorCode = 'Network' |
+    'InsufficientSpace' |
+    'AppNotInstalled' |
+    'UnknownApk' |
+    'DependencyError' |
+    'IncompatibleMod' |
+    'Io' |
+    'Cancelled' |
+    'Other';
+
+// Given instead of the usual response if the request failed
+export interface ErrorResponse {
+    type: 'Error',
+    code: ErrorCode,
+    message: string,
+    // The full chain of errors that caused the failure
+    detail: string
+}
+
+// Given instead of the usual response if another operation is still in progress
+export interface Busy {
+    type: 'Busy'
+}
+
+export interface CancelRequested {
+    type: 'CancelRequested'
+}
+
+export type Response = LogMsg | Progress | ModStatus | Mods | QuickFixed | ImportResult | FixedPlayerData | RestoredPlayerData | DowngradedManifest | Patched | AlreadyPatched | StockApkRestored | Upload | UploadFinished | ModPreview | QmodValidation | ModsInstalled | PatchPlan | CustomLevels | DuplicateLevels | LevelsDeduped | Playlists | Diagnostics | Logs | CrashLogs | AppNotInstalled | ConfigExportResponse | ConfigImported | ModsReset | SafeMode | AvailableMods | ModDataExported | CachesCleared | StorageUsage | SyncPlan | LoaderConfigResponse | ModConfig | NetworkStatus | RecoveryState | ManifestFlagsResponse | InstallationReport | GameVersion | ErrorResponse | IncompatibleMod | CancelRequested | Busy | ModSyncResult;
 
 export interface CoreModsInfo {
     supported_versions: string[],
     downgrade_versions: string[],
     core_mod_install_status: InstallStatus,
-    is_awaiting_diff: boolean
+    is_awaiting_diff: boolean,
+    missing_core_mods: string[]
 }
 
 export type ModLoader = "Scotland2" | "QuestLoader" | "Unknown";
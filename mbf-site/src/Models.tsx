@@ -5,7 +5,9 @@ interface Mod {
     version: string,
     is_enabled: boolean,
     game_version: string | null
-    is_core: boolean
+    is_core: boolean,
+    // Pinned mods are not updated by syncing or by updating core mods
+    is_pinned: boolean
 }
 
 interface CoreMod {
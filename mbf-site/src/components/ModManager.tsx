@@ -235,8 +235,12 @@ function AddModsMenu(props: ModMenuProps) {
         if(typedResult.type === 'ImportedFileCopy') {
             Log.info("Successfully copied " + filename + " to " + typedResult.copied_to + " due to request from " + typedResult.mod_id);
             toast.success("Successfully copied " + filename + " to the path specified by " + typedResult.mod_id);
-        }   else if(typedResult.type === 'ImportedSong') {
-            toast.success("Successfully imported song " + filename);
+        }   else if(typedResult.type === 'ImportedLevel') {
+            if(typedResult.already_existed) {
+                toast.info("Song " + filename + " was already installed");
+            }   else    {
+                toast.success("Successfully imported song " + filename);
+            }
         }   else if(typedResult.type === 'NonQuestModDetected')  {
             toast.error(`${importResult.used_filename} is a PC mod, with the .DLL file extension. You can only install Quest mods with the .QMOD file extension. Get these from the 'Add Mods' tab.`, { autoClose: false })
         }   else    {
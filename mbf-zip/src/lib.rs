@@ -8,7 +8,7 @@ use std::{
     collections::HashMap,
     fs::File,
     io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
-    path::Path,
+    path::{Component, Path},
 };
 
 use self::data::{CentDirHeader, EndOfCentDir, LocalFileHeader};
@@ -19,6 +19,9 @@ pub mod signing;
 /// Minimum version needed to extract ZIP files made by this module
 pub const VERSION_NEEDED_TO_EXTRACT: u16 = 0x0002;
 
+/// The size of the buffer used to copy file data into the archive, unless changed with [ZipFile::set_copy_buffer_size].
+pub const DEFAULT_COPY_BUFFER_SIZE: usize = 4096;
+
 /// The CRC-32 algorithm used by the ZIP file format.
 pub const ZIP_CRC: Crc<u32> = Crc::<u32>::new(&Algorithm {
     width: 32,
@@ -62,6 +65,22 @@ pub enum FileCompression {
     Unsupported(u16),
 }
 
+// Seeks the stream to the start of the end of central directory record.
+fn seek_to_eocd(stream: &mut (impl Read + Seek)) -> Result<()> {
+    stream.seek(SeekFrom::End(-22))?; // Assuming zero comment, this is the latest position for the EOCD header
+
+    // Read backwards until an EOCD header is found.
+    while stream.read_u32::<LE>()? != EndOfCentDir::HEADER {
+        if stream.stream_position()? == 4 {
+            return Err(anyhow!("No EOCD found in APK"));
+        }
+
+        stream.seek(SeekFrom::Current(-8))?;
+    }
+    stream.seek(SeekFrom::Current(-4))?; // Seek back before the LFH
+    Ok(())
+}
+
 pub struct ZipFile<T: Read + Seek> {
     file: T,
     entries: HashMap<String, CentDirHeader>,
@@ -71,24 +90,17 @@ pub struct ZipFile<T: Read + Seek> {
     // memory, improving performance.
     // Typically, already-compressed media files like PNG use the STORE compression method.
     store_aligment: u16,
+    // Size of the buffer used when copying data into new entries.
+    copy_buffer_size: usize,
+    // If true, each written entry is read back and its CRC-32 checked against that of the source data.
+    verify_writes: bool,
 }
 
 impl<T: Read + Seek> ZipFile<T> {
     /// Opens a ZIP archive from a readable stream.
     pub fn open(mut file: T) -> Result<Self> {
         let mut buf_file = BufReader::new(&mut file);
-
-        buf_file.seek(SeekFrom::End(-22))?; // Assuming zero comment, this is the latest position for the EOCD header
-
-        // Read backwards until an EOCD header is found.
-        while buf_file.read_u32::<LE>()? != EndOfCentDir::HEADER {
-            if buf_file.stream_position()? == 4 {
-                return Err(anyhow!("No EOCD found in APK"));
-            }
-
-            buf_file.seek(SeekFrom::Current(-8))?;
-        }
-        buf_file.seek(SeekFrom::Current(-4))?; // Seek back before the LFH
+        seek_to_eocd(&mut buf_file)?;
 
         let eocd: EndOfCentDir = EndOfCentDir::read(&mut buf_file).context("Invalid EOCD")?;
         buf_file.seek(SeekFrom::Start(eocd.cent_dir_offset as u64))?;
@@ -118,6 +130,8 @@ impl<T: Read + Seek> ZipFile<T> {
             file,
             entries,
             store_aligment: 1,
+            copy_buffer_size: DEFAULT_COPY_BUFFER_SIZE,
+            verify_writes: false,
         })
     }
 
@@ -143,6 +157,7 @@ impl<T: Read + Seek> ZipFile<T> {
     }
 
     /// Extracts all of the files in the ZIP file to the given directory.
+    /// Gives an Err if any entry would be extracted outside of the directory, e.g. if its name contains `..`.
     pub fn extract_to_directory(&mut self, to: impl AsRef<Path>) -> Result<()> {
         let to = to.as_ref();
 
@@ -154,7 +169,24 @@ impl<T: Read + Seek> ZipFile<T> {
             .map(|(key, _)| key.clone())
             .collect::<Vec<_>>();
         for entry_name in entries.iter() {
+            // Prevent entries from escaping the destination directory ("zip slip")
+            if Path::new(entry_name)
+                .components()
+                .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+            {
+                return Err(anyhow!(
+                    "Entry {entry_name} would be extracted outside of the destination directory"
+                ));
+            }
+
             let extract_path = to.join(entry_name);
+            // Some archives contain entries for directories, which have no contents to extract.
+            if entry_name.ends_with('/') {
+                std::fs::create_dir_all(&extract_path)
+                    .context("Creating directory to extract ZIP file")?;
+                continue;
+            }
+
             if let Some(parent) = extract_path.parent() {
                 std::fs::create_dir_all(parent)
                     .context("Creating directory to extract ZIP file")?;
@@ -309,12 +341,31 @@ impl<T: Read + Seek> ZipFile<T> {
     pub fn contains_file(&self, name: &str) -> bool {
         self.entries.contains_key(name)
     }
+
+    /// Gets the CRC-32 recorded in the central directory for the file with name `name`, or `None` if it does not exist.
+    pub fn recorded_crc32(&self, name: &str) -> Option<u32> {
+        self.entries.get(name).map(|header| header.crc32)
+    }
+
+    /// Gets the compression method of the file with name `name`, or `None` if it does not exist.
+    pub fn compression_method(&self, name: &str) -> Option<FileCompression> {
+        self.entries
+            .get(name)
+            .map(|header| header.compression_method)
+    }
+
+    /// Calculates the CRC-32 of the contents of the file with name `name` by reading the whole file.
+    /// Unlike [ZipFile::recorded_crc32], this detects contents that were corrupted after the CRC-32 was recorded.
+    pub fn compute_crc32(&mut self, name: &str) -> Result<u32> {
+        let mut crc = CrcWriter(ZIP_CRC.digest());
+        self.read_file_contents(name, &mut crc)?;
+        Ok(crc.0.finalize())
+    }
 }
 
-// Copies the contents of `from` to `to`, calculating the ZIP CRC-32 of the copied data.
-fn copy_to_with_crc(from: &mut impl Read, to: &mut impl Write) -> Result<u32> {
-    const BUFFER_SIZE: usize = 4096;
-    let mut buffer = vec![0; BUFFER_SIZE];
+// Copies the contents of `from` to `to` using a buffer of `buffer_size` bytes, calculating the ZIP CRC-32 of the copied data.
+fn copy_to_with_crc(from: &mut impl Read, to: &mut impl Write, buffer_size: usize) -> Result<u32> {
+    let mut buffer = vec![0; buffer_size.max(1)];
 
     let mut crc = ZIP_CRC.digest();
     loop {
@@ -328,12 +379,52 @@ fn copy_to_with_crc(from: &mut impl Read, to: &mut impl Write) -> Result<u32> {
     }
 }
 
+// Calculates the ZIP CRC-32 of the data written to it, discarding the data.
+struct CrcWriter<'a>(crc::Digest<'a, u32>);
+
+impl Write for CrcWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 impl ZipFile<File> {
+    /// Creates a new ZIP archive with no entries, replacing any existing contents of `file`.
+    /// [ZipFile::save] must be called once all files have been written.
+    pub fn create(file: File) -> Result<Self> {
+        file.set_len(0).context("Truncating new ZIP file")?;
+        Ok(Self {
+            file,
+            entries: HashMap::new(),
+            end_of_entries_offset: 0,
+            store_aligment: 1,
+            copy_buffer_size: DEFAULT_COPY_BUFFER_SIZE,
+            verify_writes: false,
+        })
+    }
+
     /// Sets the alignment for files written with the STORE compression method.
     pub fn set_store_alignment(&mut self, alignment: u16) {
         self.store_aligment = alignment;
     }
 
+    /// Sets the size of the buffer, in bytes, used to copy data into files written with [ZipFile::write_file].
+    /// Larger buffers mean fewer reads and writes, at the cost of memory.
+    pub fn set_copy_buffer_size(&mut self, size: usize) {
+        self.copy_buffer_size = size;
+    }
+
+    /// Sets whether each file written with [ZipFile::write_file] is read back from the archive afterwards,
+    /// failing the write if the CRC-32 of its contents does not match the data that was copied in.
+    pub fn set_verify_writes(&mut self, verify: bool) {
+        self.verify_writes = verify;
+    }
+
     // Creates a field used to align the ZIP entry data to store_alignment
     // `data_offset` is what the offset in the ZIP of the first byte of the data would be,
     // with no alignment field.
@@ -405,7 +496,7 @@ impl ZipFile<File> {
                 let mut buf_writer = BufWriter::new(&mut self.file);
 
                 let mut encoder = deflate::Encoder::new(&mut buf_writer);
-                let crc = copy_to_with_crc(contents, &mut encoder)
+                let crc = copy_to_with_crc(contents, &mut encoder, self.copy_buffer_size)
                     .context("Writing/compressing file data")?;
                 encoder.finish().into_result()?;
 
@@ -418,8 +509,8 @@ impl ZipFile<File> {
                 crc
             }
             FileCompression::Store => {
-                let crc =
-                    copy_to_with_crc(contents, &mut self.file).context("Writing file data")?;
+                let crc = copy_to_with_crc(contents, &mut self.file, self.copy_buffer_size)
+                    .context("Writing file data")?;
                 // Update the offset for the next file to be written
                 self.end_of_entries_offset = self
                     .file
@@ -479,6 +570,18 @@ impl ZipFile<File> {
 
         // Insert/replace the central directory header. (replacing the header will delete an existing file with the same name)
         self.entries.insert(name.to_string(), central_dir_header);
+
+        if self.verify_writes {
+            let written_crc = self
+                .compute_crc32(name)
+                .context("Reading back written file")?;
+            if written_crc != crc32 {
+                return Err(anyhow!(
+                    "{name} was corrupted when written: expected CRC-32 {crc32:08x}, got {written_crc:08x}"
+                ));
+            }
+        }
+
         Ok(())
     }
 
@@ -533,8 +636,6 @@ impl ZipFile<File> {
     /// Saves the ZIP central directory.
     /// If this is not called, any newly written files or deleted files will not be respected in the final archive.
     /// The CD is NOT automatically saved on drop.
-    /// Currently, this project doesn't save any ZIP files without signing them, but this is kept in-case this is needed in the future.
-    #[allow(unused)]
     pub fn save(mut self) -> Result<()> {
         // Remove existing CD and EOCD
         self.file.set_len(self.end_of_entries_offset as u64)?;
@@ -3,13 +3,14 @@
 //!
 //! V1 signatures are not supported, so this module cannot be used for APKs that will be installed on any Android version before 7.0.
 
-use anyhow::{Context, Result};
-use byteorder::{ByteOrder, WriteBytesExt, LE};
+use anyhow::{anyhow, Context, Result};
+use byteorder::{ByteOrder, ReadBytesExt, WriteBytesExt, LE};
 use rasn_pkix::Certificate;
 use rsa::{
     pkcs1::DecodeRsaPrivateKey,
+    pkcs8::DecodePublicKey,
     sha2::{Digest, Sha256},
-    Pkcs1v15Sign, RsaPrivateKey,
+    Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey,
 };
 use std::{
     fs::File,
@@ -18,6 +19,21 @@ use std::{
 
 use super::data::EndOfCentDir;
 
+/// Returned by [verify_v2_signature] when the APK is signed with an algorithm that it cannot check.
+/// The signature may still be valid.
+#[derive(Debug)]
+pub struct UnsupportedSignatureAlgorithm {
+    pub algorithm: u32,
+}
+
+impl std::error::Error for UnsupportedSignatureAlgorithm {}
+
+impl std::fmt::Display for UnsupportedSignatureAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unsupported signature algorithm {:#x}", self.algorithm)
+    }
+}
+
 /// Writes the v2 signature block to the APK.
 /// The `apk` stream should be seeked to the first byte after the contents of the last ZIP entry.
 pub(super) fn write_v2_signature(
@@ -43,11 +59,159 @@ pub(super) fn write_v2_signature(
     Ok(())
 }
 
+/// Checks that the APK has a valid V2 signature: the signed digest must match the contents of the APK, and the signature
+/// must be made by the key of the certificate it contains.
+///
+/// Only signatures using RSA PKCS#1 v1.5 with SHA-256 are supported, which is the algorithm used by [write_v2_signature].
+/// Any other algorithm gives an [UnsupportedSignatureAlgorithm] error.
+pub fn verify_v2_signature(apk: &mut File) -> Result<()> {
+    super::seek_to_eocd(apk)?;
+    let eocd = EndOfCentDir::read(apk).context("Invalid EOCD")?;
+    let cd_offset = eocd.cent_dir_offset as u64;
+
+    // The signing block ends with its length and the footer, just before the central directory.
+    let footer_offset = cd_offset
+        .checked_sub(24)
+        .ok_or(anyhow!("APK has no signing block"))?;
+    apk.seek(SeekFrom::Start(footer_offset))?;
+    let block_len = apk.read_u64::<LE>()?;
+    let mut footer = [0u8; 16];
+    apk.read_exact(&mut footer)?;
+    if footer != APK_SIG_BLOCK_FOOTER {
+        return Err(anyhow!("APK has no signing block"));
+    }
+
+    // The length does not include itself at the start of the block, but does include the length and footer at the end.
+    let invalid_len = || anyhow!("APK signing block length of {block_len} was invalid");
+    if block_len < 24 {
+        return Err(invalid_len());
+    }
+    let total_len = block_len.checked_add(8).ok_or_else(invalid_len)?;
+    let block_start = cd_offset.checked_sub(total_len).ok_or_else(invalid_len)?;
+    apk.seek(SeekFrom::Start(block_start))?;
+    let mut block = vec![0u8; total_len as usize];
+    apk.read_exact(&mut block)?;
+
+    // The ID-value pairs are between the length at the start of the block, and the length and footer at the end.
+    let mut pairs = &block[8..block.len() - 24];
+    let mut v2_signature = None;
+    while !pairs.is_empty() {
+        let mut pair = read_u64_prefixed(&mut pairs)?;
+        if read_u32(&mut pair)? == V2_SIGNATURE_ID {
+            v2_signature = Some(pair);
+        }
+    }
+    let mut v2_signature = v2_signature.ok_or(anyhow!("APK has no V2 signature"))?;
+
+    // Only the first signer is checked, as MBF only ever signs with one key.
+    let mut signers = read_len_prefixed(&mut v2_signature)?;
+    let mut signer = read_len_prefixed(&mut signers)?;
+    let signed_data = read_len_prefixed(&mut signer)?;
+    let mut signatures = read_len_prefixed(&mut signer)?;
+    let public_key_info = read_len_prefixed(&mut signer)?;
+
+    let mut signature = read_len_prefixed(&mut signatures)?;
+    let algorithm = read_u32(&mut signature)?;
+    if algorithm != RSA_PKCS1_15_SHA256 {
+        return Err(UnsupportedSignatureAlgorithm { algorithm }.into());
+    }
+    let signature = read_len_prefixed(&mut signature)?;
+
+    let public_key =
+        RsaPublicKey::from_public_key_der(public_key_info).context("Invalid public key")?;
+    public_key
+        .verify(
+            Pkcs1v15Sign::new::<Sha256>(),
+            &Sha256::digest(signed_data),
+            signature,
+        )
+        .map_err(|_| anyhow!("Signature did not match the signed data"))?;
+
+    let mut signed_data = signed_data;
+    let mut digests = read_len_prefixed(&mut signed_data)?;
+    let mut certificates = read_len_prefixed(&mut signed_data)?;
+    let mut digest = read_len_prefixed(&mut digests)?;
+    if read_u32(&mut digest)? != RSA_PKCS1_15_SHA256 {
+        return Err(anyhow!(
+            "Digest algorithm did not match signature algorithm"
+        ));
+    }
+    let signed_digest = read_len_prefixed(&mut digest)?;
+
+    let cert = rasn::der::decode::<Certificate>(read_len_prefixed(&mut certificates)?)
+        .map_err(|err| anyhow!("Invalid certificate: {err}"))?;
+    let cert_public_key_info = rasn::der::encode(&cert.tbs_certificate.subject_public_key_info)
+        .map_err(|err| anyhow!("Invalid certificate public key: {err}"))?;
+    if cert_public_key_info != public_key_info {
+        return Err(anyhow!(
+            "Public key of signature did not match the certificate"
+        ));
+    }
+
+    // The digest is calculated with the EOCD pointing to the signing block rather than the central directory.
+    let mut central_dir = vec![0u8; eocd.cent_dir_size as usize];
+    apk.seek(SeekFrom::Start(cd_offset))?;
+    apk.read_exact(&mut central_dir)?;
+    let mut signing_eocd = eocd;
+    signing_eocd.cent_dir_offset = block_start
+        .try_into()
+        .context("ZIP file too large (to verify)")?;
+    let mut eocd_bytes = Vec::new();
+    signing_eocd.write(&mut Cursor::new(&mut eocd_bytes))?;
+
+    let apk_digest = calculate_apk_digest(apk, block_start, &central_dir, &eocd_bytes)?;
+    if apk_digest != signed_digest {
+        return Err(anyhow!("APK contents did not match the signed digest"));
+    }
+
+    Ok(())
+}
+
+// Reads a little-endian u32 from the start of `data`, advancing `data` past it.
+fn read_u32(data: &mut &[u8]) -> Result<u32> {
+    data.read_u32::<LE>().context("Signing block was truncated")
+}
+
+// Reads a value prefixed with its u32 length from the start of `data`, advancing `data` past it.
+fn read_len_prefixed<'a>(data: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = read_u32(data)? as usize;
+    take_bytes(data, len)
+}
+
+// Reads a value prefixed with its u64 length from the start of `data`, advancing `data` past it.
+fn read_u64_prefixed<'a>(data: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = data
+        .read_u64::<LE>()
+        .context("Signing block was truncated")? as usize;
+    take_bytes(data, len)
+}
+
+fn take_bytes<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if data.len() < len {
+        return Err(anyhow!("Signing block was truncated"));
+    }
+
+    let (value, rest) = data.split_at(len);
+    *data = rest;
+    Ok(value)
+}
+
 /// Loads an X509 certificate and RSA private key from the given PEM data.
 /// Panics in the case of invalid PEM or an invalid key/cert, so this should be used on certificates that are known to be valid.
 /// (i.e. the debug certificate included with the agent)
 pub fn load_cert_and_priv_key(pem_data: &[u8]) -> (Certificate, RsaPrivateKey) {
-    let pem = pem::parse_many(pem_data).expect("Invalid PEM");
+    try_load_cert_and_priv_key(pem_data).expect("Invalid debug certificate PEM")
+}
+
+/// Loads an X509 certificate and RSA private key from the given PEM data,
+/// which may be supplied by the user.
+///
+/// Signatures are created with RSA PKCS#1 v1.5, so signing the same APK with the same key always gives
+/// the same signature block.
+/// # Returns
+/// The certificate and private key, or an `Err` if the PEM was invalid or did not contain both.
+pub fn try_load_cert_and_priv_key(pem_data: &[u8]) -> Result<(Certificate, RsaPrivateKey)> {
+    let pem = pem::parse_many(pem_data).context("Invalid PEM")?;
 
     let mut cert = None;
     let mut priv_key = None;
@@ -55,21 +219,23 @@ pub fn load_cert_and_priv_key(pem_data: &[u8]) -> (Certificate, RsaPrivateKey) {
     for pem_sect in pem.iter() {
         if pem_sect.tag() == "RSA PRIVATE KEY" {
             priv_key = Some(
-                RsaPrivateKey::from_pkcs1_der(pem_sect.contents()).expect("Invalid private key"),
+                RsaPrivateKey::from_pkcs1_der(pem_sect.contents())
+                    .context("Invalid private key")?,
             );
         }
 
         if pem_sect.tag() == "CERTIFICATE" {
             cert = Some(
-                rasn::der::decode::<Certificate>(pem_sect.contents()).expect("Invalid certificate"),
+                rasn::der::decode::<Certificate>(pem_sect.contents())
+                    .map_err(|err| anyhow!("Invalid certificate: {err}"))?,
             );
         }
     }
 
-    return (
-        cert.expect("No certificate"),
-        priv_key.expect("No private key"),
-    );
+    Ok((
+        cert.ok_or(anyhow!("PEM contained no certificate"))?,
+        priv_key.ok_or(anyhow!("PEM contained no RSA private key"))?,
+    ))
 }
 
 const CHUNK_SIZE: u64 = 0x100000;
@@ -247,3 +413,52 @@ fn generate_signed_data(apk_digest: &[u8], cert: &Certificate) -> Result<Vec<u8>
 
     Ok(signed_data)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Write};
+
+    use byteorder::{WriteBytesExt, LE};
+
+    use super::{verify_v2_signature, APK_SIG_BLOCK_FOOTER};
+    use crate::data::EndOfCentDir;
+
+    // Creates a file containing only a signing block footer with the given length, followed by an empty central directory.
+    fn apk_with_block_len(name: &str, block_len: u64) -> File {
+        let path = std::env::temp_dir().join(format!("mbf-zip-{name}-{}.apk", std::process::id()));
+        let mut data = Vec::new();
+        data.write_u64::<LE>(block_len).unwrap();
+        data.write_all(&APK_SIG_BLOCK_FOOTER).unwrap();
+        EndOfCentDir {
+            cent_dir_records: 0,
+            cent_dir_size: 0,
+            cent_dir_offset: data.len() as u32,
+            comment: Vec::new(),
+        }
+        .write(&mut data)
+        .unwrap();
+        std::fs::write(&path, data).unwrap();
+
+        let file = File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        file
+    }
+
+    #[test]
+    fn signing_block_shorter_than_its_footer_is_rejected() {
+        for block_len in [0, 8, 23] {
+            let mut apk = apk_with_block_len(&format!("short-{block_len}"), block_len);
+            let err = verify_v2_signature(&mut apk).unwrap_err();
+            assert!(err.to_string().contains("length"), "{err}");
+        }
+    }
+
+    #[test]
+    fn signing_block_length_that_would_overflow_is_rejected() {
+        for block_len in [u64::MAX, u64::MAX - 7, 1 << 40] {
+            let mut apk = apk_with_block_len("overflow", block_len);
+            let err = verify_v2_signature(&mut apk).unwrap_err();
+            assert!(err.to_string().contains("length"), "{err}");
+        }
+    }
+}